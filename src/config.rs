@@ -1,9 +1,16 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
 use chrono::Duration;
+use microtype::secrecy::ExposeSecret;
 use serde::Deserialize;
+use url::Url;
 
 pub use keypair::KeyPair;
 
-use crate::db::sql::DbConfig;
+use crate::{
+    db::sql::DbConfig,
+    model::types::{ClientSecret, Password, Token, UserId},
+};
 
 mod io;
 mod keypair;
@@ -11,15 +18,334 @@ mod keypair;
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
     pub jwt: JwtConfig,
+    /// Used as the token issuer
     pub hostname: String,
     pub db: DbKind,
+    #[serde(default)]
+    pub log: LogConfig,
+    /// Export traces over OTLP, disabled if absent
+    pub otlp: Option<OtlpConfig>,
+    /// Users allowed to use the `/admin` endpoints
+    #[serde(default)]
+    pub admins: Vec<UserId>,
+    #[serde(default)]
+    pub accounts: AccountConfig,
+    /// OpenID Connect providers users can sign in with, keyed by the name they're given in URLs
+    #[serde(default)]
+    pub oidc: BTreeMap<String, OidcConfig>,
+    #[serde(default)]
+    pub oauth: OAuthConfig,
+    #[serde(default)]
+    pub passwords: PasswordConfig,
+    #[serde(default)]
+    pub limits: LimitConfig,
+    /// How email is sent, without which nobody could be sent a sign in link, an invitation or
+    /// the confirmation for a new address
+    pub email: EmailConfig,
 }
 
 #[derive(Debug, Clone, Deserialize)]
+#[allow(clippy::large_enum_variant)] // only ever loaded once
 pub enum DbKind {
     Real(DbConfig),
-    #[cfg(test)]
-    InMemory,
+    /// A single SQLite file, created if it doesn't exist
+    Sqlite {
+        path: PathBuf,
+    },
+    /// Everything in memory, optionally loaded from and saved back to a JSON snapshot file
+    #[cfg(any(test, feature = "in-memory-db"))]
+    InMemory {
+        snapshot: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    /// Output format for log lines
+    pub format: LogFormat,
+    /// An `EnvFilter` directive, e.g. `info,example_backend=debug`
+    ///
+    /// Overridden by `RUST_LOG` if set
+    pub filter: String,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::Text,
+            filter: "info".into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OtlpConfig {
+    /// Full URL of the collector's OTLP/HTTP traces endpoint, e.g. `http://localhost:4318/v1/traces`
+    pub endpoint: String,
+    /// Fraction of new traces to sample, between 0 and 1
+    ///
+    /// Traces started by a caller follow the caller's sampling decision
+    #[serde(default = "default_sampling_ratio")]
+    pub sampling_ratio: f64,
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+}
+
+fn default_sampling_ratio() -> f64 {
+    1.0
+}
+
+fn default_service_name() -> String {
+    env!("CARGO_PKG_NAME").into()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AccountConfig {
+    /// How long a deleted account can still be restored, after which it's purged
+    pub deletion_grace_period_seconds: i64,
+    /// How often to look for deleted accounts to purge
+    pub purge_interval_seconds: u64,
+    /// How long the link confirming a change of email address works for
+    pub email_change_ttl_seconds: i64,
+    /// The longest an API key can be made to last for
+    pub api_key_max_ttl_seconds: i64,
+    /// How long an emailed sign in link works for
+    pub magic_link_ttl_seconds: i64,
+    /// Whether following a sign in link sent to an address nobody has yet makes them an account
+    pub magic_link_signup: bool,
+    /// How long an emailed invitation to join an organization works for
+    pub org_invitation_ttl_seconds: i64,
+    /// How long the link for choosing a new password, for someone who forgot theirs, works for
+    pub password_reset_ttl_seconds: i64,
+}
+
+impl Default for AccountConfig {
+    fn default() -> Self {
+        Self {
+            deletion_grace_period_seconds: 30 * 24 * 60 * 60,
+            purge_interval_seconds: 60 * 60,
+            email_change_ttl_seconds: 24 * 60 * 60,
+            api_key_max_ttl_seconds: 365 * 24 * 60 * 60,
+            magic_link_ttl_seconds: 15 * 60,
+            magic_link_signup: false,
+            org_invitation_ttl_seconds: 7 * 24 * 60 * 60,
+            password_reset_ttl_seconds: 60 * 60,
+        }
+    }
+}
+
+impl AccountConfig {
+    pub fn deletion_grace_period(&self) -> Duration {
+        Duration::seconds(self.deletion_grace_period_seconds)
+    }
+
+    pub fn purge_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.purge_interval_seconds)
+    }
+
+    pub fn email_change_ttl(&self) -> Duration {
+        Duration::seconds(self.email_change_ttl_seconds)
+    }
+
+    pub fn api_key_max_ttl(&self) -> Duration {
+        Duration::seconds(self.api_key_max_ttl_seconds)
+    }
+
+    pub fn magic_link_ttl(&self) -> Duration {
+        Duration::seconds(self.magic_link_ttl_seconds)
+    }
+
+    pub fn org_invitation_ttl(&self) -> Duration {
+        Duration::seconds(self.org_invitation_ttl_seconds)
+    }
+
+    pub fn password_reset_ttl(&self) -> Duration {
+        Duration::seconds(self.password_reset_ttl_seconds)
+    }
+}
+
+/// How we act as an OAuth authorization server for registered clients
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct OAuthConfig {
+    /// How long a client has to exchange the code a user is sent back to it with
+    pub authorization_code_ttl_seconds: i64,
+    /// How long a client can keep getting new access tokens without the user signing in again
+    pub refresh_token_ttl_seconds: i64,
+}
+
+impl Default for OAuthConfig {
+    fn default() -> Self {
+        Self {
+            authorization_code_ttl_seconds: 5 * 60,
+            refresh_token_ttl_seconds: 30 * 24 * 60 * 60,
+        }
+    }
+}
+
+impl OAuthConfig {
+    pub fn authorization_code_ttl(&self) -> Duration {
+        Duration::seconds(self.authorization_code_ttl_seconds)
+    }
+
+    pub fn refresh_token_ttl(&self) -> Duration {
+        Duration::seconds(self.refresh_token_ttl_seconds)
+    }
+}
+
+/// What passwords users are allowed to choose
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PasswordConfig {
+    /// In characters
+    pub min_length: usize,
+    /// In bytes, since bcrypt ignores everything past the 72nd
+    pub max_length: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    /// Anything that isn't a letter or a digit, spaces included
+    pub require_symbol: bool,
+    /// The lowest estimated strength allowed, from 0 (guessed in a few tries) to 4 (very unlikely
+    /// to be guessed)
+    pub min_strength: u8,
+    /// Whether a password can contain the account's email address, or the part before the `@`
+    pub allow_email: bool,
+    /// A directory of breached password hashes to reject on top of the bundled ones, laid out as
+    /// k-anonymity range files like the Pwned Passwords downloader writes
+    ///
+    /// Each file is named after the first 5 hex digits of a SHA-1 hash, as in `21BD1.txt`, and
+    /// has a `SUFFIX:COUNT` line for each hash starting with them. Only the file for the password
+    /// being checked is read
+    pub breached_passwords: Option<PathBuf>,
+}
+
+impl Default for PasswordConfig {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            max_length: 72,
+            require_lowercase: false,
+            require_uppercase: false,
+            require_digit: false,
+            require_symbol: false,
+            min_strength: 2,
+            allow_email: false,
+            breached_passwords: None,
+        }
+    }
+}
+
+/// How many sign in attempts are allowed before more are turned away for a while
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LimitConfig {
+    /// Failed sign ins, and sign in links sent, for one email address
+    pub per_email: usize,
+    /// The same, from one IP address, which may be shared by everyone behind it
+    pub per_ip: usize,
+    /// How long an attempt counts against the limits for
+    pub window_seconds: i64,
+}
+
+impl Default for LimitConfig {
+    fn default() -> Self {
+        Self {
+            per_email: 10,
+            per_ip: 100,
+            window_seconds: 15 * 60,
+        }
+    }
+}
+
+impl LimitConfig {
+    pub fn window(&self) -> Duration {
+        Duration::seconds(self.window_seconds)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EmailConfig {
+    /// Who messages are from, e.g. `Example <no-reply@example.com>`
+    pub from: String,
+    pub smtp: SmtpConfig,
+    /// The frontend that links sent out by email open, e.g. `https://app.example.com`
+    ///
+    /// It has to serve `/email/confirm`, `/email/cancel`, `/magic-link`, `/org-invitation` and
+    /// `/password-reset`, each of which posts the `token` it's opened with on to the matching
+    /// endpoint here
+    pub link_base_url: Url,
+}
+
+impl EmailConfig {
+    /// A link to the frontend's `path`, carrying `token`
+    pub fn link(&self, path: &str, token: &Token) -> Url {
+        let mut url = self.link_base_url.clone();
+        if let Ok(mut segments) = url.path_segments_mut() {
+            segments.pop_if_empty().extend(path.split('/'));
+        }
+        url.query_pairs_mut()
+            .append_pair("token", token.expose_secret());
+        url
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SmtpConfig {
+    pub host: String,
+    /// Defaults to the usual port for `tls`
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub tls: SmtpTls,
+    /// Only needed if the server asks us to sign in
+    pub credentials: Option<SmtpCredentials>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SmtpCredentials {
+    pub username: String,
+    pub password: Password,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpTls {
+    /// Plaintext throughout, which is only safe for a server on the same host
+    None,
+    /// A plaintext connection upgraded to TLS, normally on port 587
+    #[default]
+    Starttls,
+    /// TLS from the start, normally on port 465
+    Tls,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OidcConfig {
+    /// The provider's `/.well-known/openid-configuration` document
+    pub discovery_url: Url,
+    pub client_id: String,
+    pub client_secret: ClientSecret,
+    /// Where the provider sends users back to, which has to be registered with it
+    ///
+    /// This is normally a frontend page, which passes the `code` and `state` it's given on to us,
+    /// along with the cookie it was given when the sign in started
+    pub redirect_url: Url,
+    /// Asked for on top of `openid`, and should include whatever puts `email` in the ID token
+    #[serde(default = "default_oidc_scopes")]
+    pub scopes: Vec<String>,
+}
+
+fn default_oidc_scopes() -> Vec<String> {
+    vec!["email".into()]
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -36,7 +362,14 @@ impl JwtConfig {
 
 #[cfg(test)]
 pub mod testing {
-    use super::{Config, DbKind, JwtConfig, KeyPair};
+    use std::collections::BTreeMap;
+
+    use crate::model::types::mock::ADMIN_USER_ID;
+
+    use super::{
+        AccountConfig, Config, DbKind, EmailConfig, JwtConfig, KeyPair, LimitConfig, LogConfig,
+        OAuthConfig, PasswordConfig, SmtpConfig, SmtpTls,
+    };
 
     pub fn test_config() -> Config {
         Config {
@@ -45,7 +378,49 @@ pub mod testing {
                 ttl_seconds: 1000,
                 key: KeyPair::from_secret(b"bad secret"),
             },
-            db: DbKind::InMemory,
+            db: DbKind::InMemory { snapshot: None },
+            log: LogConfig::default(),
+            otlp: None,
+            admins: vec![*ADMIN_USER_ID],
+            accounts: AccountConfig::default(),
+            oidc: BTreeMap::new(),
+            oauth: OAuthConfig::default(),
+            passwords: PasswordConfig::default(),
+            limits: LimitConfig::default(),
+            email: EmailConfig {
+                from: "Example <no-reply@example.com>".into(),
+                smtp: SmtpConfig {
+                    host: "localhost".into(),
+                    port: None,
+                    tls: SmtpTls::None,
+                    credentials: None,
+                },
+                link_base_url: "https://app.localhost".parse().unwrap(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use microtype::SecretMicrotype;
+
+    use super::{testing::test_config, *};
+
+    #[test]
+    fn links_keep_the_base_path() {
+        let token = Token::new("abc".into());
+
+        for base in ["https://example.com/app", "https://example.com/app/"] {
+            let config = EmailConfig {
+                link_base_url: base.parse().unwrap(),
+                ..test_config().email
+            };
+
+            assert_eq!(
+                config.link("email/confirm", &token).as_str(),
+                "https://example.com/app/email/confirm?token=abc"
+            );
         }
     }
 }
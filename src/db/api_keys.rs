@@ -0,0 +1,91 @@
+use chrono::{DateTime, Utc};
+use diesel::{delete, insert_into, update, ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::RunQueryDsl;
+use futures::FutureExt;
+use uuid::Uuid;
+
+use crate::{
+    db::schema::api_keys,
+    model::{api_key::ApiKey, types::UserId},
+};
+
+use super::sql::{DbError, SqlDb};
+
+/// Access to the API keys users have made
+#[axum::async_trait]
+pub trait ApiKeyDao {
+    /// Fails with `AlreadyExists` if the user already has a key with that name, or the prefix is
+    /// taken
+    async fn create_api_key(&self, key: ApiKey) -> Result<(), DbError>;
+
+    async fn api_key_by_prefix(&self, prefix: String) -> Result<Option<ApiKey>, DbError>;
+
+    /// Every key a user has made, oldest first
+    async fn api_keys_for_user(&self, user_id: UserId) -> Result<Vec<ApiKey>, DbError>;
+
+    async fn touch_api_key(&self, id: Uuid, at: DateTime<Utc>) -> Result<(), DbError>;
+
+    /// Remove one of a user's keys, failing with `RowsModified` if they have no such key
+    async fn delete_api_key(&self, user_id: UserId, id: Uuid) -> Result<(), DbError>;
+
+    /// Remove all of a user's keys, returning how many there were
+    async fn delete_api_keys_for_user(&self, user_id: UserId) -> Result<usize, DbError>;
+}
+
+#[axum::async_trait]
+impl ApiKeyDao for SqlDb {
+    async fn create_api_key(&self, key: ApiKey) -> Result<(), DbError> {
+        let query = insert_into(api_keys::table).values(key);
+        let rows_modified = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn api_key_by_prefix(&self, prefix: String) -> Result<Option<ApiKey>, DbError> {
+        let query = api_keys::table.filter(api_keys::prefix.eq(prefix)).limit(1);
+        let key = self
+            .read(query, |query, conn| {
+                async move { query.get_result(conn).await.optional() }.boxed()
+            })
+            .await?;
+
+        Ok(key)
+    }
+
+    async fn api_keys_for_user(&self, user_id: UserId) -> Result<Vec<ApiKey>, DbError> {
+        let query = api_keys::table
+            .filter(api_keys::user_id.eq(user_id))
+            .order((api_keys::created_at, api_keys::id));
+        self.read(query, |query, conn| query.load(conn).boxed())
+            .await
+    }
+
+    async fn touch_api_key(&self, id: Uuid, at: DateTime<Utc>) -> Result<(), DbError> {
+        let query =
+            update(api_keys::table.filter(api_keys::id.eq(id))).set(api_keys::last_used_at.eq(at));
+        self.exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_api_key(&self, user_id: UserId, id: Uuid) -> Result<(), DbError> {
+        let query = delete(
+            api_keys::table
+                .filter(api_keys::id.eq(id))
+                .filter(api_keys::user_id.eq(user_id)),
+        );
+        let rows_modified = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn delete_api_keys_for_user(&self, user_id: UserId) -> Result<usize, DbError> {
+        let query = delete(api_keys::table.filter(api_keys::user_id.eq(user_id)));
+        self.exec(query, |query, conn| query.execute(conn).boxed())
+            .await
+    }
+}
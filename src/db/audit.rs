@@ -0,0 +1,126 @@
+use chrono::{DateTime, Utc};
+use diesel::{insert_into, update, BoolExpressionMethods, ExpressionMethods, QueryDsl};
+use diesel_async::RunQueryDsl;
+use futures::FutureExt;
+
+use crate::{
+    db::schema::audit_events,
+    model::{
+        audit::{AuditAction, AuditEvent},
+        types::UserId,
+    },
+};
+
+use super::sql::{DbError, SqlDb};
+
+/// Which audit events to find, where every filter that's set has to match
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    /// Events where this user is either the actor or the subject
+    pub user_id: Option<UserId>,
+    pub action: Option<AuditAction>,
+    /// Inclusive
+    pub since: Option<DateTime<Utc>>,
+    /// Exclusive
+    pub until: Option<DateTime<Utc>>,
+    pub limit: i64,
+}
+
+impl AuditFilter {
+    /// For backends that can't push the filter down into a query
+    #[cfg(any(test, feature = "in-memory-db"))]
+    pub fn matches(&self, event: &AuditEvent) -> bool {
+        let user_matches = self.user_id.is_none_or(|user_id| {
+            event.actor_id == Some(user_id) || event.subject_id == Some(user_id)
+        });
+
+        user_matches
+            && self.action.is_none_or(|action| event.action == action)
+            && self.since.is_none_or(|since| event.occurred_at >= since)
+            && self.until.is_none_or(|until| event.occurred_at < until)
+    }
+}
+
+/// Access to the audit log, which is only ever added to
+#[axum::async_trait]
+pub trait AuditDao {
+    async fn create_audit_event(&self, event: AuditEvent) -> Result<(), DbError>;
+
+    /// Events matching `filter`, most recent first
+    async fn audit_events(&self, filter: AuditFilter) -> Result<Vec<AuditEvent>, DbError>;
+
+    /// Remove a user from every event that mentions them, along with the events' client details,
+    /// returning how many events there were
+    ///
+    /// The events themselves are kept, so the log still shows what happened
+    async fn anonymize_audit_events(&self, user_id: UserId) -> Result<usize, DbError>;
+}
+
+#[axum::async_trait]
+impl AuditDao for SqlDb {
+    async fn create_audit_event(&self, event: AuditEvent) -> Result<(), DbError> {
+        let query = insert_into(audit_events::table).values(event);
+        let rows_modified = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn audit_events(&self, filter: AuditFilter) -> Result<Vec<AuditEvent>, DbError> {
+        let mut query = audit_events::table
+            .order((audit_events::occurred_at.desc(), audit_events::id.desc()))
+            .limit(filter.limit)
+            .into_boxed();
+
+        if let Some(user_id) = filter.user_id {
+            query = query.filter(
+                audit_events::actor_id
+                    .eq(user_id)
+                    .or(audit_events::subject_id.eq(user_id)),
+            );
+        }
+        if let Some(action) = filter.action {
+            query = query.filter(audit_events::action.eq(action));
+        }
+        if let Some(since) = filter.since {
+            query = query.filter(audit_events::occurred_at.ge(since));
+        }
+        if let Some(until) = filter.until {
+            query = query.filter(audit_events::occurred_at.lt(until));
+        }
+
+        // boxed queries can't be cloned, which `read` needs to be able to fall back on the
+        // primary, so this always goes to the primary
+        self.exec(query, |query, conn| query.load(conn).boxed())
+            .await
+    }
+
+    async fn anonymize_audit_events(&self, user_id: UserId) -> Result<usize, DbError> {
+        let query = update(
+            audit_events::table.filter(
+                audit_events::actor_id
+                    .eq(user_id)
+                    .or(audit_events::subject_id.eq(user_id)),
+            ),
+        )
+        .set((
+            audit_events::ip.eq(None::<String>),
+            audit_events::user_agent.eq(None::<String>),
+        ));
+        let anonymized = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        let query = update(audit_events::table.filter(audit_events::actor_id.eq(user_id)))
+            .set(audit_events::actor_id.eq(None::<UserId>));
+        self.exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+        let query = update(audit_events::table.filter(audit_events::subject_id.eq(user_id)))
+            .set(audit_events::subject_id.eq(None::<UserId>));
+        self.exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        Ok(anonymized)
+    }
+}
@@ -0,0 +1,2000 @@
+//! Behaviour every `Db` implementation must share
+//!
+//! Each test here runs against the in-memory database, SQLite and, if `DATABASE_URL` is set,
+//! Postgres, in a schema of its own that's dropped afterwards. Where backends could reasonably
+//! differ, Postgres is the reference
+
+use std::{future::Future, panic::AssertUnwindSafe, path::Path, sync::Arc};
+
+use chrono::Duration;
+use diesel::{migration::MigrationSource, pg::Pg};
+use futures::FutureExt;
+use microtype::{secrecy::ExposeSecret, SecretMicrotype};
+use uuid::Uuid;
+
+use crate::{
+    model::{
+        api_key::ApiKey,
+        audit::{AuditAction, AuditEvent},
+        email_change::EmailChange,
+        identity::UserIdentity,
+        magic_link::MagicLink,
+        oauth::{AuthorizationCode, OAuthClient, OAuthConsent, RefreshToken},
+        organization::{Membership, OrgInvitation, OrgRole, Organization},
+        password_reset::PasswordReset,
+        session::Session,
+        types::{ClientId, Email, OrgId, PasswordHash, SessionId, UserId},
+        user::{mock::default_user, ProfileChanges, User},
+    },
+    state::metrics::Metrics,
+};
+
+use super::{
+    audit::AuditFilter,
+    memory::MemoryDb,
+    sql::{test_config, DbConfig, DbError, SqlDb},
+    sqlite::SqliteDb,
+    Db, MIGRATIONS,
+};
+
+fn memory() -> Arc<dyn Db> {
+    Arc::new(MemoryDb::new())
+}
+
+fn sqlite() -> Arc<dyn Db> {
+    Arc::new(SqliteDb::connect(Path::new(":memory:")).unwrap())
+}
+
+/// A freshly migrated Postgres schema, which only exists for the length of one test
+pub(super) struct TestSchema {
+    name: String,
+    admin: SqlDb,
+    db: Arc<SqlDb>,
+}
+
+impl TestSchema {
+    /// `None` if `DATABASE_URL` isn't set
+    pub async fn create() -> Option<Self> {
+        let config = test_config()?;
+        let name = format!("test_{}", Uuid::new_v4().simple());
+
+        let admin = SqlDb::connect(config.clone(), Arc::new(Metrics::new().unwrap())).unwrap();
+        admin
+            .batch_execute(&format!("CREATE SCHEMA {name}"))
+            .await
+            .unwrap();
+
+        let config = DbConfig {
+            schema: Some(name.clone()),
+            ..config
+        };
+        let db = SqlDb::connect(config, Arc::new(Metrics::new().unwrap())).unwrap();
+        migrate(&db).await;
+
+        Some(Self {
+            name,
+            admin,
+            db: Arc::new(db),
+        })
+    }
+
+    /// Run `test` against this schema, dropping the schema afterwards even if `test` panics
+    pub async fn run<F, Fut>(self, test: F)
+    where
+        F: FnOnce(Arc<dyn Db>) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let result = AssertUnwindSafe(test(self.db.clone())).catch_unwind().await;
+
+        self.admin
+            .batch_execute(&format!("DROP SCHEMA {} CASCADE", self.name))
+            .await
+            .unwrap();
+
+        if let Err(panic) = result {
+            std::panic::resume_unwind(panic);
+        }
+    }
+}
+
+/// Apply the migrations the same way diesel would
+///
+/// diesel's migration harness needs a synchronous connection, which we don't have, so this runs
+/// the SQL from the migrations directory and keeps the bookkeeping table up to date itself
+async fn migrate(db: &SqlDb) {
+    db.batch_execute(
+        "CREATE TABLE __diesel_schema_migrations (
+            version VARCHAR(50) PRIMARY KEY NOT NULL,
+            run_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )",
+    )
+    .await
+    .unwrap();
+
+    for migration in MigrationSource::<Pg>::migrations(&MIGRATIONS).unwrap() {
+        let name = migration.name();
+        let path = format!(
+            "{}/src/db/migrations/{name}/up.sql",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        let up = std::fs::read_to_string(path).unwrap();
+
+        db.batch_execute(&up).await.unwrap();
+        db.batch_execute(&format!(
+            "INSERT INTO __diesel_schema_migrations (version) VALUES ('{}')",
+            name.version()
+        ))
+        .await
+        .unwrap();
+    }
+}
+
+/// Generate a test per backend for each of the named test functions
+macro_rules! conformance {
+    ($($test:ident),* $(,)?) => {
+        mod memory {
+            $(
+                #[tokio::test]
+                async fn $test() {
+                    super::$test(super::memory()).await;
+                }
+            )*
+        }
+
+        mod sqlite {
+            $(
+                #[tokio::test]
+                async fn $test() {
+                    super::$test(super::sqlite()).await;
+                }
+            )*
+        }
+
+        mod postgres {
+            $(
+                #[tokio::test]
+                async fn $test() {
+                    if let Some(schema) = super::TestSchema::create().await {
+                        schema.run(super::$test).await;
+                    }
+                }
+            )*
+        }
+    };
+}
+
+conformance!(
+    is_up_to_date,
+    finds_created_user,
+    missing_user_is_none,
+    duplicate_id_already_exists,
+    duplicate_email_already_exists,
+    deleted_user_is_gone,
+    deleted_user_keeps_email,
+    deleting_missing_user_modifies_nothing,
+    restores_recently_deleted_user,
+    does_not_restore_after_grace_period,
+    purges_only_expired_deletions,
+    transaction_commits_on_ok,
+    transaction_rolls_back_on_err,
+    finds_created_sessions,
+    session_needs_a_user,
+    touching_session_reorders_sessions,
+    deletes_only_own_sessions,
+    deletes_sessions_created_before,
+    purging_user_removes_sessions,
+    updates_profile,
+    updating_profile_bumps_updated_at,
+    cannot_update_deleted_users_profile,
+    finds_audit_events,
+    filters_audit_events,
+    audit_events_outlive_their_users,
+    updates_email,
+    cannot_take_another_users_email,
+    updates_password,
+    finds_email_changes_by_either_token,
+    one_email_change_per_user,
+    email_change_needs_a_user,
+    deletes_email_change_once,
+    purging_user_removes_email_changes,
+    erases_user_whether_or_not_deleted,
+    anonymizes_audit_events,
+    finds_identities,
+    identities_are_unique,
+    identity_needs_a_user,
+    deletes_only_own_identities,
+    purging_user_removes_identities,
+    finds_oauth_clients,
+    saves_oauth_consents,
+    oauth_consent_needs_a_user_and_client,
+    deletes_authorization_code_once,
+    finds_refresh_tokens,
+    deletes_expired_oauth_tokens,
+    deleting_client_removes_its_grants,
+    purging_user_removes_oauth_grants,
+    finds_api_keys,
+    api_keys_are_unique,
+    api_key_needs_a_user,
+    touches_api_key,
+    deletes_only_own_api_keys,
+    purging_user_removes_api_keys,
+    finds_magic_links,
+    one_magic_link_per_email,
+    deletes_magic_link_once,
+    deletes_expired_magic_links,
+    finds_organizations_and_members,
+    one_membership_per_user,
+    membership_needs_a_user_and_organization,
+    changes_and_removes_memberships,
+    finds_org_invitations,
+    deletes_org_invitations_for_email,
+    one_org_invitation_per_email,
+    deletes_expired_org_invitations,
+    deleting_organization_removes_members_and_invitations,
+    purging_user_removes_memberships,
+    finds_password_resets,
+    one_password_reset_per_user,
+    password_reset_needs_a_user,
+    deletes_password_reset_once,
+    deletes_expired_password_resets,
+    purging_user_removes_password_resets,
+);
+
+fn other_user() -> User {
+    User {
+        id: UserId(Uuid::new_v4()),
+        email: Email("other@email.com".into()),
+        ..default_user()
+    }
+}
+
+async fn is_up_to_date(db: Arc<dyn Db>) {
+    db.ping().await.unwrap();
+    assert!(!db.has_pending_migrations().await.unwrap());
+}
+
+async fn finds_created_user(db: Arc<dyn Db>) {
+    let user = default_user();
+    db.create_user(user.clone()).await.unwrap();
+    db.create_user(other_user()).await.unwrap();
+
+    let by_id = db.user_by_id(user.id).await.unwrap().unwrap();
+    let by_email = db.user_by_email(user.email.clone()).await.unwrap().unwrap();
+
+    assert_eq!(by_id.id, user.id);
+    assert_eq!(by_id.email, user.email);
+    assert_eq!(by_id.created_at, user.created_at);
+    assert_eq!(by_email.id, user.id);
+}
+
+async fn updates_profile(db: Arc<dyn Db>) {
+    let user = User {
+        avatar_url: Some("https://example.com/old.png".into()),
+        timezone: Some("UTC".into()),
+        ..default_user()
+    };
+    db.create_user(user.clone()).await.unwrap();
+
+    let changes = ProfileChanges {
+        display_name: Some(Some("Ada".into())),
+        avatar_url: Some(None),
+        ..ProfileChanges::default()
+    };
+    db.update_profile(user.id, changes).await.unwrap();
+
+    let found = db.user_by_id(user.id).await.unwrap().unwrap();
+    assert_eq!(found.display_name.as_deref(), Some("Ada"));
+    assert_eq!(found.avatar_url, None);
+    assert_eq!(found.timezone.as_deref(), Some("UTC"));
+    assert_eq!(found.locale, None);
+}
+
+/// The database keeps `updated_at` up to date itself, using its own clock
+async fn updating_profile_bumps_updated_at(db: Arc<dyn Db>) {
+    let user = default_user();
+    db.create_user(user.clone()).await.unwrap();
+    let found = db.user_by_id(user.id).await.unwrap().unwrap();
+    assert_eq!(found.updated_at, user.created_at);
+
+    let changes = ProfileChanges {
+        locale: Some(Some("en-GB".into())),
+        ..ProfileChanges::default()
+    };
+    db.update_profile(user.id, changes).await.unwrap();
+    let found = db.user_by_id(user.id).await.unwrap().unwrap();
+    assert!(found.updated_at > user.created_at);
+
+    db.delete_user(user.id, user.created_at).await.unwrap();
+    db.restore_user(user.id, user.created_at).await.unwrap();
+    let restored = db.user_by_id(user.id).await.unwrap().unwrap();
+    assert!(restored.updated_at >= found.updated_at);
+}
+
+async fn cannot_update_deleted_users_profile(db: Arc<dyn Db>) {
+    let user = default_user();
+    let changes = ProfileChanges {
+        display_name: Some(Some("Ada".into())),
+        ..ProfileChanges::default()
+    };
+    assert_rows_modified(db.update_profile(user.id, changes.clone()).await, 0);
+
+    db.create_user(user.clone()).await.unwrap();
+    db.delete_user(user.id, user.created_at).await.unwrap();
+    assert_rows_modified(db.update_profile(user.id, changes).await, 0);
+}
+
+async fn missing_user_is_none(db: Arc<dyn Db>) {
+    let user = default_user();
+
+    assert!(db.user_by_id(user.id).await.unwrap().is_none());
+    assert!(db.user_by_email(user.email).await.unwrap().is_none());
+}
+
+/// Only the table is compared, since backends differ in whether they can name the column
+fn assert_already_exists(result: Result<(), DbError>, expected_table: &str) {
+    match result {
+        Err(DbError::AlreadyExists { table, .. }) => {
+            assert_eq!(table.as_deref(), Some(expected_table));
+        }
+        other => panic!("expected AlreadyExists, got {other:?}"),
+    }
+}
+
+async fn duplicate_id_already_exists(db: Arc<dyn Db>) {
+    let user = default_user();
+    db.create_user(user.clone()).await.unwrap();
+
+    let duplicate = User {
+        email: Email("different@email.com".into()),
+        ..user
+    };
+    assert_already_exists(db.create_user(duplicate).await, "users");
+}
+
+async fn duplicate_email_already_exists(db: Arc<dyn Db>) {
+    let user = default_user();
+    db.create_user(user.clone()).await.unwrap();
+
+    let duplicate = User {
+        email: user.email,
+        ..other_user()
+    };
+    assert_already_exists(db.create_user(duplicate).await, "users");
+}
+
+fn assert_rows_modified(result: Result<(), DbError>, expected_actual: usize) {
+    match result {
+        Err(DbError::RowsModified { expected, actual }) => {
+            assert_eq!((expected, actual), (1, expected_actual));
+        }
+        other => panic!("expected RowsModified, got {other:?}"),
+    }
+}
+
+async fn deleted_user_is_gone(db: Arc<dyn Db>) {
+    let user = default_user();
+    db.create_user(user.clone()).await.unwrap();
+    db.delete_user(user.id, user.created_at).await.unwrap();
+
+    assert!(db.user_by_id(user.id).await.unwrap().is_none());
+    assert!(db.user_by_email(user.email).await.unwrap().is_none());
+    assert_rows_modified(db.delete_user(user.id, user.created_at).await, 0);
+
+    let found = db
+        .user_by_id_including_deleted(user.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(found.deleted_at, Some(user.created_at));
+}
+
+/// The address stays taken until the user is purged, so that they can still be restored
+async fn deleted_user_keeps_email(db: Arc<dyn Db>) {
+    let user = default_user();
+    db.create_user(user.clone()).await.unwrap();
+    db.delete_user(user.id, user.created_at).await.unwrap();
+
+    let duplicate = User {
+        email: user.email,
+        ..other_user()
+    };
+    assert_already_exists(db.create_user(duplicate).await, "users");
+}
+
+async fn deleting_missing_user_modifies_nothing(db: Arc<dyn Db>) {
+    let user = default_user();
+    assert_rows_modified(db.delete_user(user.id, user.created_at).await, 0);
+}
+
+async fn restores_recently_deleted_user(db: Arc<dyn Db>) {
+    let user = default_user();
+    let deleted_at = user.created_at + Duration::days(1);
+    db.create_user(user.clone()).await.unwrap();
+    db.delete_user(user.id, deleted_at).await.unwrap();
+
+    db.restore_user(user.id, deleted_at).await.unwrap();
+
+    let restored = db.user_by_id(user.id).await.unwrap().unwrap();
+    assert_eq!(restored.deleted_at, None);
+    assert_rows_modified(db.restore_user(user.id, deleted_at).await, 0);
+}
+
+async fn does_not_restore_after_grace_period(db: Arc<dyn Db>) {
+    let user = default_user();
+    let deleted_at = user.created_at + Duration::days(1);
+    db.create_user(user.clone()).await.unwrap();
+    db.delete_user(user.id, deleted_at).await.unwrap();
+
+    let result = db
+        .restore_user(user.id, deleted_at + Duration::seconds(1))
+        .await;
+
+    assert_rows_modified(result, 0);
+    assert!(db.user_by_id(user.id).await.unwrap().is_none());
+}
+
+async fn purges_only_expired_deletions(db: Arc<dyn Db>) {
+    let expired = default_user();
+    let recent = other_user();
+    let active = User {
+        id: UserId(Uuid::new_v4()),
+        email: Email("active@email.com".into()),
+        ..default_user()
+    };
+    let cutoff = expired.created_at + Duration::days(30);
+    for user in [&expired, &recent, &active] {
+        db.create_user(user.clone()).await.unwrap();
+    }
+    db.delete_user(expired.id, cutoff - Duration::seconds(1))
+        .await
+        .unwrap();
+    db.delete_user(recent.id, cutoff).await.unwrap();
+
+    assert_eq!(db.users_deleted_before(cutoff).await.unwrap(), [expired.id]);
+    assert_eq!(db.purge_deleted_users(cutoff).await.unwrap(), 1);
+
+    // purged users are gone for good, so their address is free again
+    db.create_user(User {
+        id: UserId(Uuid::new_v4()),
+        ..expired
+    })
+    .await
+    .unwrap();
+    db.restore_user(recent.id, cutoff).await.unwrap();
+    assert!(db.user_by_id(active.id).await.unwrap().is_some());
+}
+
+async fn transaction_commits_on_ok(db: Arc<dyn Db>) {
+    let user = default_user();
+
+    let id = db
+        .transaction(|tx| {
+            let user = user.clone();
+            async move {
+                tx.create_user(user.clone()).await?;
+                let found = tx.user_by_id(user.id).await?;
+                Ok::<_, DbError>(found.unwrap().id)
+            }
+            .boxed()
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(id, user.id);
+    assert!(db.user_by_id(user.id).await.unwrap().is_some());
+}
+
+async fn transaction_rolls_back_on_err(db: Arc<dyn Db>) {
+    let user = default_user();
+
+    let result = db
+        .transaction(|tx| {
+            let user = user.clone();
+            async move {
+                tx.create_user(user.clone()).await?;
+                tx.create_user(user).await
+            }
+            .boxed()
+        })
+        .await;
+
+    assert_already_exists(result, "users");
+    assert!(db.user_by_id(user.id).await.unwrap().is_none());
+}
+
+fn session(id: &str, user: &User) -> Session {
+    Session {
+        id: SessionId(id.into()),
+        user_id: user.id,
+        created_at: user.created_at,
+        last_seen_at: user.created_at,
+        user_agent: Some("agent".into()),
+        ip: Some("127.0.0.1".into()),
+    }
+}
+
+async fn finds_created_sessions(db: Arc<dyn Db>) {
+    let (user, other) = (default_user(), other_user());
+    db.create_user(user.clone()).await.unwrap();
+    db.create_user(other.clone()).await.unwrap();
+    db.create_session(session("a", &user)).await.unwrap();
+    db.create_session(session("b", &other)).await.unwrap();
+
+    let found = db
+        .session_by_id(SessionId("a".into()))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(found.user_id, user.id);
+    assert_eq!(found.created_at, user.created_at);
+    assert_eq!(found.user_agent.as_deref(), Some("agent"));
+    assert_eq!(found.ip.as_deref(), Some("127.0.0.1"));
+
+    let sessions = db.sessions_for_user(user.id).await.unwrap();
+    assert_eq!(sessions.len(), 1);
+    assert!(db
+        .session_by_id(SessionId("c".into()))
+        .await
+        .unwrap()
+        .is_none());
+    assert_already_exists(db.create_session(session("a", &user)).await, "sessions");
+}
+
+async fn session_needs_a_user(db: Arc<dyn Db>) {
+    assert!(db
+        .create_session(session("a", &default_user()))
+        .await
+        .is_err());
+}
+
+async fn touching_session_reorders_sessions(db: Arc<dyn Db>) {
+    let user = default_user();
+    db.create_user(user.clone()).await.unwrap();
+    db.create_session(session("a", &user)).await.unwrap();
+    db.create_session(session("b", &user)).await.unwrap();
+
+    let later = user.created_at + Duration::minutes(5);
+    db.touch_session(SessionId("a".into()), later)
+        .await
+        .unwrap();
+
+    let sessions = db.sessions_for_user(user.id).await.unwrap();
+    let ids: Vec<_> = sessions
+        .iter()
+        .map(|session| session.id.0.as_str())
+        .collect();
+    assert_eq!(ids, ["a", "b"]);
+    assert_eq!(sessions[0].last_seen_at, later);
+}
+
+async fn deletes_only_own_sessions(db: Arc<dyn Db>) {
+    let (user, other) = (default_user(), other_user());
+    db.create_user(user.clone()).await.unwrap();
+    db.create_user(other.clone()).await.unwrap();
+    for (id, owner) in [("a", &user), ("b", &user), ("c", &other)] {
+        db.create_session(session(id, owner)).await.unwrap();
+    }
+
+    assert_rows_modified(db.delete_session(other.id, SessionId("a".into())).await, 0);
+    db.delete_session(user.id, SessionId("a".into()))
+        .await
+        .unwrap();
+    assert_rows_modified(db.delete_session(user.id, SessionId("a".into())).await, 0);
+
+    assert_eq!(db.delete_sessions_for_user(user.id).await.unwrap(), 1);
+    assert_eq!(db.sessions_for_user(other.id).await.unwrap().len(), 1);
+}
+
+async fn deletes_sessions_created_before(db: Arc<dyn Db>) {
+    let user = default_user();
+    db.create_user(user.clone()).await.unwrap();
+    let cutoff = user.created_at + Duration::hours(1);
+    db.create_session(session("old", &user)).await.unwrap();
+    db.create_session(Session {
+        created_at: cutoff,
+        ..session("new", &user)
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(db.delete_sessions_created_before(cutoff).await.unwrap(), 1);
+    assert!(db
+        .session_by_id(SessionId("new".into()))
+        .await
+        .unwrap()
+        .is_some());
+}
+
+async fn purging_user_removes_sessions(db: Arc<dyn Db>) {
+    let user = default_user();
+    db.create_user(user.clone()).await.unwrap();
+    db.create_session(session("a", &user)).await.unwrap();
+    db.delete_user(user.id, user.created_at).await.unwrap();
+
+    // soft deletion leaves sessions alone, it's up to the caller to revoke them
+    assert!(db
+        .session_by_id(SessionId("a".into()))
+        .await
+        .unwrap()
+        .is_some());
+
+    db.purge_deleted_users(user.created_at + Duration::days(1))
+        .await
+        .unwrap();
+    assert!(db
+        .session_by_id(SessionId("a".into()))
+        .await
+        .unwrap()
+        .is_none());
+}
+
+fn audit_event(action: AuditAction, actor: Option<&User>, minutes: i64) -> AuditEvent {
+    let user = default_user();
+    AuditEvent {
+        id: Uuid::new_v4(),
+        occurred_at: user.created_at + Duration::minutes(minutes),
+        actor_id: actor.map(|actor| actor.id),
+        subject_id: Some(user.id),
+        action,
+        ip: Some("127.0.0.1".into()),
+        user_agent: None,
+        request_id: Some("request".into()),
+    }
+}
+
+fn audit_filter() -> AuditFilter {
+    AuditFilter {
+        limit: 10,
+        ..AuditFilter::default()
+    }
+}
+
+async fn finds_audit_events(db: Arc<dyn Db>) {
+    let event = audit_event(AuditAction::Login, Some(&default_user()), 0);
+    db.create_audit_event(event.clone()).await.unwrap();
+
+    let found = db.audit_events(audit_filter()).await.unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].id, event.id);
+    assert_eq!(found[0].occurred_at, event.occurred_at);
+    assert_eq!(found[0].actor_id, event.actor_id);
+    assert_eq!(found[0].subject_id, event.subject_id);
+    assert_eq!(found[0].action, AuditAction::Login);
+    assert_eq!(found[0].ip, event.ip);
+    assert_eq!(found[0].user_agent, None);
+    assert_eq!(found[0].request_id, event.request_id);
+
+    let result = db.create_audit_event(event).await;
+    assert_already_exists(result, "audit_events");
+}
+
+async fn filters_audit_events(db: Arc<dyn Db>) {
+    let (user, other) = (default_user(), other_user());
+    let events = [
+        audit_event(AuditAction::LoginFailed, None, 0),
+        audit_event(AuditAction::Login, Some(&user), 1),
+        audit_event(AuditAction::UserRestored, Some(&other), 2),
+        AuditEvent {
+            subject_id: Some(other.id),
+            ..audit_event(AuditAction::Login, Some(&other), 3)
+        },
+    ];
+    for event in &events {
+        db.create_audit_event(event.clone()).await.unwrap();
+    }
+    let found = |filter| {
+        let db = db.clone();
+        async move {
+            let events = db.audit_events(filter).await.unwrap();
+            events
+                .into_iter()
+                .map(|event| event.occurred_at)
+                .collect::<Vec<_>>()
+        }
+    };
+    let at = |minutes| user.created_at + Duration::minutes(minutes);
+
+    assert_eq!(found(audit_filter()).await, [at(3), at(2), at(1), at(0)]);
+
+    // either as the actor or as the subject
+    let by_user = AuditFilter {
+        user_id: Some(other.id),
+        ..audit_filter()
+    };
+    assert_eq!(found(by_user).await, [at(3), at(2)]);
+
+    let by_action = AuditFilter {
+        action: Some(AuditAction::Login),
+        ..audit_filter()
+    };
+    assert_eq!(found(by_action).await, [at(3), at(1)]);
+
+    let by_time = AuditFilter {
+        since: Some(at(1)),
+        until: Some(at(3)),
+        ..audit_filter()
+    };
+    assert_eq!(found(by_time).await, [at(2), at(1)]);
+
+    let limited = AuditFilter {
+        limit: 1,
+        ..audit_filter()
+    };
+    assert_eq!(found(limited).await, [at(3)]);
+}
+
+async fn audit_events_outlive_their_users(db: Arc<dyn Db>) {
+    let user = default_user();
+    db.create_user(user.clone()).await.unwrap();
+    db.create_audit_event(audit_event(AuditAction::UserDeleted, Some(&user), 0))
+        .await
+        .unwrap();
+    db.delete_user(user.id, user.created_at).await.unwrap();
+    db.purge_deleted_users(user.created_at + Duration::days(1))
+        .await
+        .unwrap();
+
+    assert_eq!(db.audit_events(audit_filter()).await.unwrap().len(), 1);
+}
+
+async fn updates_email(db: Arc<dyn Db>) {
+    let user = default_user();
+    let new_email = Email("new@email.com".into());
+    db.create_user(user.clone()).await.unwrap();
+
+    db.update_email(user.id, new_email.clone()).await.unwrap();
+
+    assert!(db
+        .user_by_email(user.email.clone())
+        .await
+        .unwrap()
+        .is_none());
+    let found = db.user_by_email(new_email.clone()).await.unwrap().unwrap();
+    assert_eq!(found.id, user.id);
+
+    // the old address is free for someone else to use
+    let other = User {
+        email: user.email,
+        ..other_user()
+    };
+    db.create_user(other).await.unwrap();
+
+    db.delete_user(user.id, user.created_at).await.unwrap();
+    assert_rows_modified(db.update_email(user.id, new_email).await, 0);
+}
+
+async fn cannot_take_another_users_email(db: Arc<dyn Db>) {
+    let (user, other) = (default_user(), other_user());
+    db.create_user(user.clone()).await.unwrap();
+    db.create_user(other.clone()).await.unwrap();
+
+    assert_already_exists(db.update_email(user.id, other.email).await, "users");
+    let found = db.user_by_id(user.id).await.unwrap().unwrap();
+    assert_eq!(found.email, user.email);
+
+    // changing to the address a user already has is a no-op rather than a clash
+    db.update_email(user.id, user.email).await.unwrap();
+}
+
+async fn updates_password(db: Arc<dyn Db>) {
+    let user = default_user();
+    db.create_user(user.clone()).await.unwrap();
+
+    let password_hash = PasswordHash::new("new hash".into());
+    db.update_password(user.id, password_hash).await.unwrap();
+    let found = db.user_by_id(user.id).await.unwrap().unwrap();
+    assert_eq!(found.password_hash.expose_secret(), "new hash");
+
+    db.delete_user(user.id, user.created_at).await.unwrap();
+    let password_hash = PasswordHash::new("newer hash".into());
+    assert_rows_modified(db.update_password(user.id, password_hash).await, 0);
+}
+
+fn email_change(user: &User, token: &str) -> EmailChange {
+    EmailChange {
+        id: Uuid::new_v4(),
+        user_id: user.id,
+        new_email: Email("new@email.com".into()),
+        confirm_token_hash: format!("confirm {token}"),
+        cancel_token_hash: format!("cancel {token}"),
+        created_at: user.created_at,
+        expires_at: user.created_at + Duration::days(1),
+    }
+}
+
+async fn finds_email_changes_by_either_token(db: Arc<dyn Db>) {
+    let user = default_user();
+    db.create_user(user.clone()).await.unwrap();
+    let change = email_change(&user, "a");
+    db.create_email_change(change.clone()).await.unwrap();
+
+    let found = db
+        .email_change_by_confirm_token("confirm a".into())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(found.id, change.id);
+    assert_eq!(found.user_id, user.id);
+    assert_eq!(found.new_email, change.new_email);
+    assert_eq!(found.created_at, change.created_at);
+    assert_eq!(found.expires_at, change.expires_at);
+
+    let found = db
+        .email_change_by_cancel_token("cancel a".into())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(found.id, change.id);
+
+    let found = db.email_change_for_user(user.id).await.unwrap().unwrap();
+    assert_eq!(found.id, change.id);
+
+    // each token only works for what it was sent for
+    assert!(db
+        .email_change_by_confirm_token("cancel a".into())
+        .await
+        .unwrap()
+        .is_none());
+}
+
+async fn one_email_change_per_user(db: Arc<dyn Db>) {
+    let user = default_user();
+    db.create_user(user.clone()).await.unwrap();
+    db.create_email_change(email_change(&user, "a"))
+        .await
+        .unwrap();
+
+    let result = db.create_email_change(email_change(&user, "b")).await;
+    assert_already_exists(result, "email_changes");
+
+    assert_eq!(db.delete_email_changes_for_user(user.id).await.unwrap(), 1);
+    db.create_email_change(email_change(&user, "b"))
+        .await
+        .unwrap();
+    assert!(db
+        .email_change_by_confirm_token("confirm a".into())
+        .await
+        .unwrap()
+        .is_none());
+}
+
+async fn email_change_needs_a_user(db: Arc<dyn Db>) {
+    assert!(db
+        .create_email_change(email_change(&default_user(), "a"))
+        .await
+        .is_err());
+}
+
+async fn deletes_email_change_once(db: Arc<dyn Db>) {
+    let user = default_user();
+    db.create_user(user.clone()).await.unwrap();
+    let change = email_change(&user, "a");
+    db.create_email_change(change.clone()).await.unwrap();
+
+    db.delete_email_change(change.id).await.unwrap();
+    assert_rows_modified(db.delete_email_change(change.id).await, 0);
+    assert_eq!(db.delete_email_changes_for_user(user.id).await.unwrap(), 0);
+}
+
+async fn purging_user_removes_email_changes(db: Arc<dyn Db>) {
+    let user = default_user();
+    db.create_user(user.clone()).await.unwrap();
+    db.create_email_change(email_change(&user, "a"))
+        .await
+        .unwrap();
+    db.delete_user(user.id, user.created_at).await.unwrap();
+    db.purge_deleted_users(user.created_at + Duration::days(1))
+        .await
+        .unwrap();
+
+    assert!(db
+        .email_change_by_cancel_token("cancel a".into())
+        .await
+        .unwrap()
+        .is_none());
+}
+
+async fn erases_user_whether_or_not_deleted(db: Arc<dyn Db>) {
+    let (user, other) = (default_user(), other_user());
+    db.create_user(user.clone()).await.unwrap();
+    db.create_user(other.clone()).await.unwrap();
+    db.create_session(session("a", &user)).await.unwrap();
+
+    db.erase_user(user.id).await.unwrap();
+    assert!(db.user_by_email(user.email).await.unwrap().is_none());
+    assert!(db.sessions_for_user(user.id).await.unwrap().is_empty());
+    assert_rows_modified(db.erase_user(user.id).await, 0);
+
+    db.delete_user(other.id, other.created_at).await.unwrap();
+    db.erase_user(other.id).await.unwrap();
+    assert_rows_modified(db.restore_user(other.id, other.created_at).await, 0);
+}
+
+async fn anonymizes_audit_events(db: Arc<dyn Db>) {
+    let (user, other) = (default_user(), other_user());
+    let events = [
+        audit_event(AuditAction::Login, Some(&user), 0),
+        audit_event(AuditAction::UserRestored, Some(&other), 1),
+        AuditEvent {
+            subject_id: Some(other.id),
+            ..audit_event(AuditAction::Login, Some(&other), 2)
+        },
+    ];
+    for event in &events {
+        db.create_audit_event(event.clone()).await.unwrap();
+    }
+
+    assert_eq!(db.anonymize_audit_events(user.id).await.unwrap(), 2);
+
+    let by_user = AuditFilter {
+        user_id: Some(user.id),
+        ..audit_filter()
+    };
+    assert!(db.audit_events(by_user).await.unwrap().is_empty());
+
+    // newest first, so the untouched event comes before the two that mentioned `user`
+    let found = db.audit_events(audit_filter()).await.unwrap();
+    assert_eq!(found.len(), 3);
+    assert_eq!(found[0].ip, events[2].ip);
+    assert_eq!(found[0].subject_id, Some(other.id));
+    assert_eq!(
+        (found[1].actor_id, found[1].subject_id),
+        (Some(other.id), None)
+    );
+    assert_eq!((found[2].actor_id, found[2].subject_id), (None, None));
+    assert!(found[1..].iter().all(|event| event.ip.is_none()));
+    assert_eq!(found[2].request_id, events[0].request_id);
+}
+
+fn identity(user: &User, provider: &str, subject: &str) -> UserIdentity {
+    UserIdentity {
+        id: Uuid::new_v4(),
+        user_id: user.id,
+        provider: provider.into(),
+        subject: subject.into(),
+        email: Some(user.email.clone()),
+        created_at: user.created_at,
+    }
+}
+
+async fn finds_identities(db: Arc<dyn Db>) {
+    let user = default_user();
+    db.create_user(user.clone()).await.unwrap();
+    let first = identity(&user, "a", "1");
+    let second = UserIdentity {
+        email: None,
+        created_at: user.created_at + Duration::seconds(1),
+        ..identity(&user, "b", "1")
+    };
+    db.create_identity(second.clone()).await.unwrap();
+    db.create_identity(first.clone()).await.unwrap();
+
+    let found = db
+        .identity_by_subject("b".into(), "1".into())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(found.id, second.id);
+    assert_eq!(found.user_id, user.id);
+    assert_eq!(found.email, None);
+    assert_eq!(found.created_at, second.created_at);
+    assert!(db
+        .identity_by_subject("a".into(), "2".into())
+        .await
+        .unwrap()
+        .is_none());
+
+    let found = db.identities_for_user(user.id).await.unwrap();
+    let ids: Vec<_> = found.iter().map(|identity| identity.id).collect();
+    assert_eq!(ids, [first.id, second.id]);
+    assert_eq!(found[0].email, Some(user.email));
+}
+
+async fn identities_are_unique(db: Arc<dyn Db>) {
+    let (user, other) = (default_user(), other_user());
+    db.create_user(user.clone()).await.unwrap();
+    db.create_user(other.clone()).await.unwrap();
+    db.create_identity(identity(&user, "a", "1")).await.unwrap();
+
+    // someone else's account at the same provider is fine, but not the same account again
+    db.create_identity(identity(&other, "a", "2"))
+        .await
+        .unwrap();
+    db.create_identity(identity(&other, "b", "1"))
+        .await
+        .unwrap();
+    let result = db.create_identity(identity(&other, "a", "1")).await;
+    assert_already_exists(result, "user_identities");
+
+    // nor a second account at the same provider
+    let result = db.create_identity(identity(&user, "a", "3")).await;
+    assert_already_exists(result, "user_identities");
+}
+
+async fn identity_needs_a_user(db: Arc<dyn Db>) {
+    assert!(db
+        .create_identity(identity(&default_user(), "a", "1"))
+        .await
+        .is_err());
+}
+
+async fn deletes_only_own_identities(db: Arc<dyn Db>) {
+    let (user, other) = (default_user(), other_user());
+    db.create_user(user.clone()).await.unwrap();
+    db.create_user(other.clone()).await.unwrap();
+    db.create_identity(identity(&user, "a", "1")).await.unwrap();
+    db.create_identity(identity(&other, "b", "2"))
+        .await
+        .unwrap();
+    db.create_identity(identity(&user, "c", "1")).await.unwrap();
+
+    assert_rows_modified(db.delete_identity(user.id, "b".into()).await, 0);
+    db.delete_identity(user.id, "a".into()).await.unwrap();
+    assert_rows_modified(db.delete_identity(user.id, "a".into()).await, 0);
+
+    assert_eq!(db.delete_identities_for_user(user.id).await.unwrap(), 1);
+    assert!(db.identities_for_user(user.id).await.unwrap().is_empty());
+    assert_eq!(db.identities_for_user(other.id).await.unwrap().len(), 1);
+}
+
+async fn purging_user_removes_identities(db: Arc<dyn Db>) {
+    let user = default_user();
+    db.create_user(user.clone()).await.unwrap();
+    db.create_identity(identity(&user, "a", "1")).await.unwrap();
+    db.delete_user(user.id, user.created_at).await.unwrap();
+    db.purge_deleted_users(user.created_at + Duration::days(1))
+        .await
+        .unwrap();
+
+    assert!(db
+        .identity_by_subject("a".into(), "1".into())
+        .await
+        .unwrap()
+        .is_none());
+}
+
+fn oauth_client(name: &str) -> OAuthClient {
+    OAuthClient {
+        id: ClientId(Uuid::new_v4()),
+        name: name.into(),
+        secret_hash: Some(format!("{name} secret")),
+        redirect_uri: format!("https://{name}.example.com/callback"),
+        scopes: "api email".parse().unwrap(),
+        created_at: default_user().created_at,
+    }
+}
+
+fn consent(user: &User, client: &OAuthClient) -> OAuthConsent {
+    OAuthConsent {
+        user_id: user.id,
+        client_id: client.id,
+        scopes: "email".parse().unwrap(),
+        granted_at: user.created_at,
+    }
+}
+
+fn authorization_code(user: &User, client: &OAuthClient, hash: &str) -> AuthorizationCode {
+    AuthorizationCode {
+        code_hash: hash.into(),
+        client_id: client.id,
+        user_id: user.id,
+        redirect_uri: client.redirect_uri.clone(),
+        scopes: "email".parse().unwrap(),
+        code_challenge: "challenge".into(),
+        created_at: user.created_at,
+        expires_at: user.created_at + Duration::minutes(1),
+    }
+}
+
+fn refresh_token(user: &User, client: &OAuthClient, hash: &str) -> RefreshToken {
+    RefreshToken {
+        token_hash: hash.into(),
+        client_id: client.id,
+        user_id: user.id,
+        scopes: "api".parse().unwrap(),
+        session_id: SessionId(format!("{hash} session")),
+        created_at: user.created_at,
+        expires_at: user.created_at + Duration::days(1),
+    }
+}
+
+async fn finds_oauth_clients(db: Arc<dyn Db>) {
+    let first = OAuthClient {
+        secret_hash: None,
+        ..oauth_client("first")
+    };
+    let second = OAuthClient {
+        created_at: first.created_at + Duration::seconds(1),
+        ..oauth_client("second")
+    };
+    db.create_client(second.clone()).await.unwrap();
+    db.create_client(first.clone()).await.unwrap();
+
+    let found = db.client_by_id(first.id).await.unwrap().unwrap();
+    assert_eq!(found.name, "first");
+    assert_eq!(found.secret_hash, None);
+    assert_eq!(found.redirect_uri, first.redirect_uri);
+    assert_eq!(found.scopes, first.scopes);
+    assert_eq!(found.created_at, first.created_at);
+    assert!(db
+        .client_by_id(ClientId(Uuid::new_v4()))
+        .await
+        .unwrap()
+        .is_none());
+
+    let ids: Vec<_> = db
+        .clients()
+        .await
+        .unwrap()
+        .iter()
+        .map(|client| client.id)
+        .collect();
+    assert_eq!(ids, [first.id, second.id]);
+
+    assert_already_exists(db.create_client(first.clone()).await, "oauth_clients");
+    db.delete_client(first.id).await.unwrap();
+    assert_rows_modified(db.delete_client(first.id).await, 0);
+    assert_eq!(db.clients().await.unwrap().len(), 1);
+}
+
+async fn saves_oauth_consents(db: Arc<dyn Db>) {
+    let (user, other) = (default_user(), other_user());
+    let (client, another) = (oauth_client("client"), oauth_client("another"));
+    db.create_user(user.clone()).await.unwrap();
+    db.create_user(other.clone()).await.unwrap();
+    db.create_client(client.clone()).await.unwrap();
+    db.create_client(another.clone()).await.unwrap();
+
+    db.save_consent(consent(&user, &client)).await.unwrap();
+    db.save_consent(consent(&other, &client)).await.unwrap();
+    let widened = OAuthConsent {
+        scopes: "api email".parse().unwrap(),
+        granted_at: user.created_at + Duration::seconds(2),
+        ..consent(&user, &client)
+    };
+    db.save_consent(widened.clone()).await.unwrap();
+    let later = OAuthConsent {
+        granted_at: user.created_at + Duration::seconds(1),
+        ..consent(&user, &another)
+    };
+    db.save_consent(later).await.unwrap();
+
+    let found = db.consent(user.id, client.id).await.unwrap().unwrap();
+    assert_eq!(found.scopes, widened.scopes);
+    assert_eq!(found.granted_at, widened.granted_at);
+    let clients: Vec<_> = db
+        .consents_for_user(user.id)
+        .await
+        .unwrap()
+        .iter()
+        .map(|consent| consent.client_id)
+        .collect();
+    assert_eq!(clients, [another.id, client.id]);
+
+    db.delete_consent(user.id, another.id).await.unwrap();
+    assert_rows_modified(db.delete_consent(user.id, another.id).await, 0);
+    assert!(db.consent(user.id, another.id).await.unwrap().is_none());
+
+    assert_eq!(db.delete_consents_for_user(user.id).await.unwrap(), 1);
+    assert!(db.consents_for_user(user.id).await.unwrap().is_empty());
+    assert_eq!(db.consents_for_user(other.id).await.unwrap().len(), 1);
+}
+
+async fn oauth_consent_needs_a_user_and_client(db: Arc<dyn Db>) {
+    let (user, client) = (default_user(), oauth_client("client"));
+
+    db.create_client(client.clone()).await.unwrap();
+    assert!(db.save_consent(consent(&user, &client)).await.is_err());
+
+    db.create_user(user.clone()).await.unwrap();
+    let missing = oauth_client("missing");
+    assert!(db.save_consent(consent(&user, &missing)).await.is_err());
+    assert!(db
+        .create_authorization_code(authorization_code(&user, &missing, "code"))
+        .await
+        .is_err());
+    assert!(db
+        .create_refresh_token(refresh_token(&user, &missing, "token"))
+        .await
+        .is_err());
+}
+
+async fn deletes_authorization_code_once(db: Arc<dyn Db>) {
+    let (user, client) = (default_user(), oauth_client("client"));
+    db.create_user(user.clone()).await.unwrap();
+    db.create_client(client.clone()).await.unwrap();
+    let code = authorization_code(&user, &client, "code");
+    db.create_authorization_code(code.clone()).await.unwrap();
+
+    let found = db
+        .authorization_code_by_hash("code".into())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(found.client_id, client.id);
+    assert_eq!(found.user_id, user.id);
+    assert_eq!(found.redirect_uri, code.redirect_uri);
+    assert_eq!(found.scopes, code.scopes);
+    assert_eq!(found.code_challenge, "challenge");
+    assert_eq!(found.expires_at, code.expires_at);
+
+    assert_already_exists(
+        db.create_authorization_code(code).await,
+        "oauth_authorization_codes",
+    );
+    db.delete_authorization_code("code".into()).await.unwrap();
+    assert_rows_modified(db.delete_authorization_code("code".into()).await, 0);
+    assert!(db
+        .authorization_code_by_hash("code".into())
+        .await
+        .unwrap()
+        .is_none());
+}
+
+async fn finds_refresh_tokens(db: Arc<dyn Db>) {
+    let (user, other) = (default_user(), other_user());
+    let (client, another) = (oauth_client("client"), oauth_client("another"));
+    db.create_user(user.clone()).await.unwrap();
+    db.create_user(other.clone()).await.unwrap();
+    db.create_client(client.clone()).await.unwrap();
+    db.create_client(another.clone()).await.unwrap();
+
+    let later = RefreshToken {
+        created_at: user.created_at + Duration::seconds(1),
+        ..refresh_token(&user, &client, "a")
+    };
+    db.create_refresh_token(later).await.unwrap();
+    db.create_refresh_token(refresh_token(&user, &another, "b"))
+        .await
+        .unwrap();
+    db.create_refresh_token(refresh_token(&user, &client, "c"))
+        .await
+        .unwrap();
+    db.create_refresh_token(refresh_token(&other, &client, "d"))
+        .await
+        .unwrap();
+
+    let found = db.refresh_token_by_hash("b".into()).await.unwrap().unwrap();
+    assert_eq!(found.client_id, another.id);
+    assert_eq!(found.user_id, user.id);
+    assert_eq!(found.scopes.as_str(), "api");
+    assert_eq!(found.session_id.0, "b session");
+    let hashes: Vec<_> = db
+        .refresh_tokens_for_user(user.id)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|token| token.token_hash)
+        .collect();
+    assert_eq!(hashes, ["b", "c", "a"]);
+
+    db.delete_refresh_token("b".into()).await.unwrap();
+    assert_rows_modified(db.delete_refresh_token("b".into()).await, 0);
+    assert_eq!(
+        db.delete_refresh_tokens(user.id, client.id).await.unwrap(),
+        2
+    );
+    assert_eq!(
+        db.delete_refresh_tokens_for_user(other.id).await.unwrap(),
+        1
+    );
+    assert_eq!(db.delete_refresh_tokens_for_user(user.id).await.unwrap(), 0);
+}
+
+async fn deletes_expired_oauth_tokens(db: Arc<dyn Db>) {
+    let (user, client) = (default_user(), oauth_client("client"));
+    db.create_user(user.clone()).await.unwrap();
+    db.create_client(client.clone()).await.unwrap();
+    let code = authorization_code(&user, &client, "code");
+    let token = refresh_token(&user, &client, "token");
+    db.create_authorization_code(code.clone()).await.unwrap();
+    db.create_refresh_token(token.clone()).await.unwrap();
+
+    assert_eq!(
+        db.delete_expired_oauth_tokens(code.expires_at)
+            .await
+            .unwrap(),
+        0
+    );
+    let after_code = code.expires_at + Duration::seconds(1);
+    assert_eq!(db.delete_expired_oauth_tokens(after_code).await.unwrap(), 1);
+    assert!(db
+        .refresh_token_by_hash("token".into())
+        .await
+        .unwrap()
+        .is_some());
+    let after_token = token.expires_at + Duration::seconds(1);
+    assert_eq!(
+        db.delete_expired_oauth_tokens(after_token).await.unwrap(),
+        1
+    );
+}
+
+async fn deleting_client_removes_its_grants(db: Arc<dyn Db>) {
+    let (user, client) = (default_user(), oauth_client("client"));
+    db.create_user(user.clone()).await.unwrap();
+    db.create_client(client.clone()).await.unwrap();
+    db.save_consent(consent(&user, &client)).await.unwrap();
+    db.create_authorization_code(authorization_code(&user, &client, "code"))
+        .await
+        .unwrap();
+    db.create_refresh_token(refresh_token(&user, &client, "token"))
+        .await
+        .unwrap();
+
+    db.delete_client(client.id).await.unwrap();
+
+    assert!(db.consents_for_user(user.id).await.unwrap().is_empty());
+    assert!(db
+        .authorization_code_by_hash("code".into())
+        .await
+        .unwrap()
+        .is_none());
+    assert!(db
+        .refresh_tokens_for_user(user.id)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+async fn purging_user_removes_oauth_grants(db: Arc<dyn Db>) {
+    let (user, client) = (default_user(), oauth_client("client"));
+    db.create_user(user.clone()).await.unwrap();
+    db.create_client(client.clone()).await.unwrap();
+    db.save_consent(consent(&user, &client)).await.unwrap();
+    db.create_authorization_code(authorization_code(&user, &client, "code"))
+        .await
+        .unwrap();
+    db.create_refresh_token(refresh_token(&user, &client, "token"))
+        .await
+        .unwrap();
+    db.delete_user(user.id, user.created_at).await.unwrap();
+    db.purge_deleted_users(user.created_at + Duration::days(1))
+        .await
+        .unwrap();
+
+    assert!(db.consent(user.id, client.id).await.unwrap().is_none());
+    assert!(db
+        .authorization_code_by_hash("code".into())
+        .await
+        .unwrap()
+        .is_none());
+    assert!(db
+        .refresh_token_by_hash("token".into())
+        .await
+        .unwrap()
+        .is_none());
+    assert!(db.client_by_id(client.id).await.unwrap().is_some());
+}
+
+fn api_key(user: &User, name: &str, prefix: &str) -> ApiKey {
+    ApiKey {
+        id: Uuid::new_v4(),
+        user_id: user.id,
+        name: name.into(),
+        prefix: prefix.into(),
+        key_hash: format!("{prefix} hash"),
+        scopes: "api".parse().unwrap(),
+        created_at: user.created_at,
+        expires_at: user.created_at + Duration::days(30),
+        last_used_at: None,
+    }
+}
+
+async fn finds_api_keys(db: Arc<dyn Db>) {
+    let user = default_user();
+    db.create_user(user.clone()).await.unwrap();
+    let first = api_key(&user, "first", "aaaa");
+    let second = ApiKey {
+        scopes: "api profile".parse().unwrap(),
+        created_at: user.created_at + Duration::seconds(1),
+        ..api_key(&user, "second", "bbbb")
+    };
+    db.create_api_key(second.clone()).await.unwrap();
+    db.create_api_key(first.clone()).await.unwrap();
+
+    let found = db.api_key_by_prefix("bbbb".into()).await.unwrap().unwrap();
+    assert_eq!(found.id, second.id);
+    assert_eq!(found.user_id, user.id);
+    assert_eq!(found.key_hash, second.key_hash);
+    assert_eq!(found.scopes, second.scopes);
+    assert_eq!(found.expires_at, second.expires_at);
+    assert_eq!(found.last_used_at, None);
+    assert!(db.api_key_by_prefix("cccc".into()).await.unwrap().is_none());
+
+    let found = db.api_keys_for_user(user.id).await.unwrap();
+    let ids: Vec<_> = found.iter().map(|key| key.id).collect();
+    assert_eq!(ids, [first.id, second.id]);
+}
+
+async fn api_keys_are_unique(db: Arc<dyn Db>) {
+    let (user, other) = (default_user(), other_user());
+    db.create_user(user.clone()).await.unwrap();
+    db.create_user(other.clone()).await.unwrap();
+    db.create_api_key(api_key(&user, "ci", "aaaa"))
+        .await
+        .unwrap();
+
+    // someone else can use the same name, but nobody can reuse a prefix
+    db.create_api_key(api_key(&other, "ci", "bbbb"))
+        .await
+        .unwrap();
+    let result = db.create_api_key(api_key(&other, "deploy", "aaaa")).await;
+    assert_already_exists(result, "api_keys");
+
+    let result = db.create_api_key(api_key(&user, "ci", "cccc")).await;
+    assert_already_exists(result, "api_keys");
+}
+
+async fn api_key_needs_a_user(db: Arc<dyn Db>) {
+    assert!(db
+        .create_api_key(api_key(&default_user(), "ci", "aaaa"))
+        .await
+        .is_err());
+}
+
+async fn touches_api_key(db: Arc<dyn Db>) {
+    let user = default_user();
+    db.create_user(user.clone()).await.unwrap();
+    let key = api_key(&user, "ci", "aaaa");
+    db.create_api_key(key.clone()).await.unwrap();
+
+    let at = user.created_at + Duration::minutes(5);
+    db.touch_api_key(key.id, at).await.unwrap();
+
+    let found = db.api_key_by_prefix("aaaa".into()).await.unwrap().unwrap();
+    assert_eq!(found.last_used_at, Some(at));
+}
+
+async fn deletes_only_own_api_keys(db: Arc<dyn Db>) {
+    let (user, other) = (default_user(), other_user());
+    db.create_user(user.clone()).await.unwrap();
+    db.create_user(other.clone()).await.unwrap();
+    let (first, second) = (api_key(&user, "a", "aaaa"), api_key(&user, "b", "bbbb"));
+    let others = api_key(&other, "a", "cccc");
+    for key in [&first, &second, &others] {
+        db.create_api_key(key.clone()).await.unwrap();
+    }
+
+    assert_rows_modified(db.delete_api_key(user.id, others.id).await, 0);
+    db.delete_api_key(user.id, first.id).await.unwrap();
+    assert_rows_modified(db.delete_api_key(user.id, first.id).await, 0);
+
+    assert_eq!(db.delete_api_keys_for_user(user.id).await.unwrap(), 1);
+    assert!(db.api_keys_for_user(user.id).await.unwrap().is_empty());
+    assert_eq!(db.api_keys_for_user(other.id).await.unwrap().len(), 1);
+}
+
+async fn purging_user_removes_api_keys(db: Arc<dyn Db>) {
+    let user = default_user();
+    db.create_user(user.clone()).await.unwrap();
+    db.create_api_key(api_key(&user, "ci", "aaaa"))
+        .await
+        .unwrap();
+    db.delete_user(user.id, user.created_at).await.unwrap();
+    db.purge_deleted_users(user.created_at + Duration::days(1))
+        .await
+        .unwrap();
+
+    assert!(db.api_key_by_prefix("aaaa".into()).await.unwrap().is_none());
+}
+
+fn magic_link(email: &str, token: &str) -> MagicLink {
+    let created_at = default_user().created_at;
+    MagicLink {
+        token_hash: token.into(),
+        email: Email(email.into()),
+        created_at,
+        expires_at: created_at + Duration::minutes(15),
+    }
+}
+
+async fn finds_magic_links(db: Arc<dyn Db>) {
+    // the address needn't belong to anyone
+    let link = magic_link("someone@email.com", "token");
+    db.create_magic_link(link.clone()).await.unwrap();
+
+    let found = db
+        .magic_link_by_token("token".into())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(found.email, link.email);
+    assert_eq!(found.created_at, link.created_at);
+    assert_eq!(found.expires_at, link.expires_at);
+    assert!(db
+        .magic_link_by_token("other".into())
+        .await
+        .unwrap()
+        .is_none());
+
+    let found = db
+        .magic_link_for_email(link.email.clone())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(found.token_hash, link.token_hash);
+    assert!(db
+        .magic_link_for_email(Email("other@email.com".into()))
+        .await
+        .unwrap()
+        .is_none());
+}
+
+async fn one_magic_link_per_email(db: Arc<dyn Db>) {
+    db.create_magic_link(magic_link("a@email.com", "a"))
+        .await
+        .unwrap();
+
+    let result = db.create_magic_link(magic_link("a@email.com", "b")).await;
+    assert_already_exists(result, "magic_links");
+    let result = db.create_magic_link(magic_link("b@email.com", "a")).await;
+    assert_already_exists(result, "magic_links");
+
+    let email = Email("a@email.com".into());
+    assert_eq!(db.delete_magic_links_for_email(email).await.unwrap(), 1);
+    db.create_magic_link(magic_link("a@email.com", "b"))
+        .await
+        .unwrap();
+}
+
+async fn deletes_magic_link_once(db: Arc<dyn Db>) {
+    db.create_magic_link(magic_link("a@email.com", "token"))
+        .await
+        .unwrap();
+
+    db.delete_magic_link("token".into()).await.unwrap();
+    assert_rows_modified(db.delete_magic_link("token".into()).await, 0);
+}
+
+async fn deletes_expired_magic_links(db: Arc<dyn Db>) {
+    let link = magic_link("a@email.com", "a");
+    let later = MagicLink {
+        expires_at: link.expires_at + Duration::minutes(1),
+        ..magic_link("b@email.com", "b")
+    };
+    db.create_magic_link(link.clone()).await.unwrap();
+    db.create_magic_link(later).await.unwrap();
+
+    assert_eq!(
+        db.delete_expired_magic_links(link.expires_at)
+            .await
+            .unwrap(),
+        0
+    );
+    let after = link.expires_at + Duration::seconds(1);
+    assert_eq!(db.delete_expired_magic_links(after).await.unwrap(), 1);
+    assert!(db.magic_link_by_token("b".into()).await.unwrap().is_some());
+}
+
+fn organization(name: &str) -> Organization {
+    Organization {
+        id: OrgId(Uuid::new_v4()),
+        name: name.into(),
+        created_at: default_user().created_at,
+    }
+}
+
+fn membership(organization: &Organization, user: &User, role: OrgRole) -> Membership {
+    Membership {
+        organization_id: organization.id,
+        user_id: user.id,
+        role,
+        created_at: organization.created_at,
+    }
+}
+
+fn org_invitation(organization: &Organization, email: &str, token: &str) -> OrgInvitation {
+    OrgInvitation {
+        id: Uuid::new_v4(),
+        organization_id: organization.id,
+        email: Email(email.into()),
+        role: OrgRole::Member,
+        token_hash: token.into(),
+        created_at: organization.created_at,
+        expires_at: organization.created_at + Duration::days(7),
+    }
+}
+
+async fn finds_organizations_and_members(db: Arc<dyn Db>) {
+    let (user, other) = (default_user(), other_user());
+    db.create_user(user.clone()).await.unwrap();
+    db.create_user(other.clone()).await.unwrap();
+    let (first, second) = (organization("first"), organization("second"));
+    db.create_organization(first.clone()).await.unwrap();
+    db.create_organization(second.clone()).await.unwrap();
+
+    let joined_later = Membership {
+        created_at: first.created_at + Duration::seconds(1),
+        ..membership(&first, &user, OrgRole::Member)
+    };
+    db.create_membership(joined_later).await.unwrap();
+    db.create_membership(membership(&first, &other, OrgRole::Owner))
+        .await
+        .unwrap();
+    db.create_membership(membership(&second, &user, OrgRole::Admin))
+        .await
+        .unwrap();
+
+    let found = db.organization_by_id(first.id).await.unwrap().unwrap();
+    assert_eq!(found.name, "first");
+    assert_eq!(found.created_at, first.created_at);
+    let missing = OrgId(Uuid::new_v4());
+    assert!(db.organization_by_id(missing).await.unwrap().is_none());
+
+    let found = db.membership(second.id, user.id).await.unwrap().unwrap();
+    assert_eq!(found.role, OrgRole::Admin);
+    assert!(db.membership(second.id, other.id).await.unwrap().is_none());
+
+    let found = db.memberships_for_organization(first.id).await.unwrap();
+    let members: Vec<_> = found.iter().map(|m| (m.user_id, m.role)).collect();
+    assert_eq!(
+        members,
+        [(other.id, OrgRole::Owner), (user.id, OrgRole::Member)]
+    );
+
+    let found = db.memberships_for_user(user.id).await.unwrap();
+    let organizations: Vec<_> = found.iter().map(|m| m.organization_id).collect();
+    assert_eq!(organizations, [second.id, first.id]);
+}
+
+async fn one_membership_per_user(db: Arc<dyn Db>) {
+    let user = default_user();
+    db.create_user(user.clone()).await.unwrap();
+    let org = organization("org");
+    db.create_organization(org.clone()).await.unwrap();
+    db.create_membership(membership(&org, &user, OrgRole::Member))
+        .await
+        .unwrap();
+
+    let result = db
+        .create_membership(membership(&org, &user, OrgRole::Owner))
+        .await;
+    assert_already_exists(result, "org_memberships");
+}
+
+async fn membership_needs_a_user_and_organization(db: Arc<dyn Db>) {
+    let user = default_user();
+    let org = organization("org");
+    db.create_organization(org.clone()).await.unwrap();
+    assert!(db
+        .create_membership(membership(&org, &user, OrgRole::Member))
+        .await
+        .is_err());
+
+    db.create_user(user.clone()).await.unwrap();
+    let missing = organization("missing");
+    assert!(db
+        .create_membership(membership(&missing, &user, OrgRole::Member))
+        .await
+        .is_err());
+}
+
+async fn changes_and_removes_memberships(db: Arc<dyn Db>) {
+    let (user, other) = (default_user(), other_user());
+    db.create_user(user.clone()).await.unwrap();
+    db.create_user(other.clone()).await.unwrap();
+    let (first, second) = (organization("first"), organization("second"));
+    for org in [&first, &second] {
+        db.create_organization(org.clone()).await.unwrap();
+        db.create_membership(membership(org, &user, OrgRole::Member))
+            .await
+            .unwrap();
+    }
+
+    db.update_membership_role(first.id, user.id, OrgRole::Admin)
+        .await
+        .unwrap();
+    let found = db.membership(first.id, user.id).await.unwrap().unwrap();
+    assert_eq!(found.role, OrgRole::Admin);
+    let result = db
+        .update_membership_role(first.id, other.id, OrgRole::Admin)
+        .await;
+    assert_rows_modified(result, 0);
+
+    db.delete_membership(first.id, user.id).await.unwrap();
+    assert_rows_modified(db.delete_membership(first.id, user.id).await, 0);
+
+    assert_eq!(db.delete_memberships_for_user(user.id).await.unwrap(), 1);
+    assert!(db.memberships_for_user(user.id).await.unwrap().is_empty());
+    assert!(db.organization_by_id(second.id).await.unwrap().is_some());
+}
+
+async fn finds_org_invitations(db: Arc<dyn Db>) {
+    let (first, second) = (organization("first"), organization("second"));
+    db.create_organization(first.clone()).await.unwrap();
+    db.create_organization(second.clone()).await.unwrap();
+    // the address needn't belong to anyone yet
+    let invitation = OrgInvitation {
+        role: OrgRole::Admin,
+        created_at: first.created_at + Duration::seconds(1),
+        ..org_invitation(&first, "a@email.com", "a")
+    };
+    let earlier = org_invitation(&first, "b@email.com", "b");
+    db.create_org_invitation(invitation.clone()).await.unwrap();
+    db.create_org_invitation(earlier.clone()).await.unwrap();
+    db.create_org_invitation(org_invitation(&second, "a@email.com", "c"))
+        .await
+        .unwrap();
+
+    let found = db
+        .org_invitation_by_token("a".into())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(found.id, invitation.id);
+    assert_eq!(found.organization_id, first.id);
+    assert_eq!(found.email, invitation.email);
+    assert_eq!(found.role, OrgRole::Admin);
+    assert_eq!(found.expires_at, invitation.expires_at);
+    assert!(db
+        .org_invitation_by_token("d".into())
+        .await
+        .unwrap()
+        .is_none());
+
+    let found = db.org_invitations_for_organization(first.id).await.unwrap();
+    let ids: Vec<_> = found.iter().map(|invitation| invitation.id).collect();
+    assert_eq!(ids, [earlier.id, invitation.id]);
+
+    // only from the organization that sent it
+    let result = db.delete_org_invitation(second.id, invitation.id).await;
+    assert_rows_modified(result, 0);
+    db.delete_org_invitation(first.id, invitation.id)
+        .await
+        .unwrap();
+    let result = db.delete_org_invitation(first.id, invitation.id).await;
+    assert_rows_modified(result, 0);
+}
+
+async fn deletes_org_invitations_for_email(db: Arc<dyn Db>) {
+    let (first, second) = (organization("first"), organization("second"));
+    db.create_organization(first.clone()).await.unwrap();
+    db.create_organization(second.clone()).await.unwrap();
+    let earlier = org_invitation(&second, "a@email.com", "a");
+    let later = OrgInvitation {
+        created_at: earlier.created_at + Duration::seconds(1),
+        ..org_invitation(&first, "a@email.com", "b")
+    };
+    db.create_org_invitation(later.clone()).await.unwrap();
+    db.create_org_invitation(earlier.clone()).await.unwrap();
+    db.create_org_invitation(org_invitation(&first, "b@email.com", "c"))
+        .await
+        .unwrap();
+
+    let email = Email("a@email.com".into());
+    let found = db.org_invitations_for_email(email.clone()).await.unwrap();
+    let ids: Vec<_> = found.iter().map(|invitation| invitation.id).collect();
+    assert_eq!(ids, [earlier.id, later.id]);
+
+    assert_eq!(
+        db.delete_org_invitations_for_email(email.clone())
+            .await
+            .unwrap(),
+        2
+    );
+    assert!(db
+        .org_invitations_for_email(email)
+        .await
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        db.org_invitations_for_organization(first.id)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+}
+
+async fn one_org_invitation_per_email(db: Arc<dyn Db>) {
+    let org = organization("org");
+    db.create_organization(org.clone()).await.unwrap();
+    db.create_org_invitation(org_invitation(&org, "a@email.com", "a"))
+        .await
+        .unwrap();
+
+    let result = db
+        .create_org_invitation(org_invitation(&org, "a@email.com", "b"))
+        .await;
+    assert_already_exists(result, "org_invitations");
+    let result = db
+        .create_org_invitation(org_invitation(&org, "b@email.com", "a"))
+        .await;
+    assert_already_exists(result, "org_invitations");
+
+    let missing = organization("missing");
+    assert!(db
+        .create_org_invitation(org_invitation(&missing, "a@email.com", "c"))
+        .await
+        .is_err());
+}
+
+async fn deletes_expired_org_invitations(db: Arc<dyn Db>) {
+    let org = organization("org");
+    db.create_organization(org.clone()).await.unwrap();
+    let invitation = org_invitation(&org, "a@email.com", "a");
+    let later = OrgInvitation {
+        expires_at: invitation.expires_at + Duration::minutes(1),
+        ..org_invitation(&org, "b@email.com", "b")
+    };
+    db.create_org_invitation(invitation.clone()).await.unwrap();
+    db.create_org_invitation(later).await.unwrap();
+
+    let at = invitation.expires_at;
+    assert_eq!(db.delete_expired_org_invitations(at).await.unwrap(), 0);
+    let after = at + Duration::seconds(1);
+    assert_eq!(db.delete_expired_org_invitations(after).await.unwrap(), 1);
+    assert!(db
+        .org_invitation_by_token("b".into())
+        .await
+        .unwrap()
+        .is_some());
+}
+
+async fn deleting_organization_removes_members_and_invitations(db: Arc<dyn Db>) {
+    let user = default_user();
+    db.create_user(user.clone()).await.unwrap();
+    let (org, other) = (organization("org"), organization("other"));
+    for org in [&org, &other] {
+        db.create_organization(org.clone()).await.unwrap();
+        db.create_membership(membership(org, &user, OrgRole::Owner))
+            .await
+            .unwrap();
+    }
+    db.create_org_invitation(org_invitation(&org, "a@email.com", "a"))
+        .await
+        .unwrap();
+
+    db.delete_organization(org.id).await.unwrap();
+    assert_rows_modified(db.delete_organization(org.id).await, 0);
+
+    assert!(db.organization_by_id(org.id).await.unwrap().is_none());
+    assert!(db.membership(org.id, user.id).await.unwrap().is_none());
+    assert!(db
+        .org_invitation_by_token("a".into())
+        .await
+        .unwrap()
+        .is_none());
+    assert!(db.membership(other.id, user.id).await.unwrap().is_some());
+}
+
+async fn purging_user_removes_memberships(db: Arc<dyn Db>) {
+    let user = default_user();
+    db.create_user(user.clone()).await.unwrap();
+    let org = organization("org");
+    db.create_organization(org.clone()).await.unwrap();
+    db.create_membership(membership(&org, &user, OrgRole::Owner))
+        .await
+        .unwrap();
+    db.delete_user(user.id, user.created_at).await.unwrap();
+    db.purge_deleted_users(user.created_at + Duration::days(1))
+        .await
+        .unwrap();
+
+    assert!(db
+        .memberships_for_organization(org.id)
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(db.organization_by_id(org.id).await.unwrap().is_some());
+}
+
+fn password_reset(user: &User, token: &str) -> PasswordReset {
+    PasswordReset {
+        token_hash: token.into(),
+        user_id: user.id,
+        created_at: user.created_at,
+        expires_at: user.created_at + Duration::hours(1),
+    }
+}
+
+async fn finds_password_resets(db: Arc<dyn Db>) {
+    let user = default_user();
+    db.create_user(user.clone()).await.unwrap();
+    let reset = password_reset(&user, "token");
+    db.create_password_reset(reset.clone()).await.unwrap();
+
+    let found = db
+        .password_reset_by_token("token".into())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(found.user_id, user.id);
+    assert_eq!(found.created_at, reset.created_at);
+    assert_eq!(found.expires_at, reset.expires_at);
+    assert!(db
+        .password_reset_by_token("other".into())
+        .await
+        .unwrap()
+        .is_none());
+}
+
+async fn one_password_reset_per_user(db: Arc<dyn Db>) {
+    let (user, other) = (default_user(), other_user());
+    db.create_user(user.clone()).await.unwrap();
+    db.create_user(other.clone()).await.unwrap();
+    db.create_password_reset(password_reset(&user, "a"))
+        .await
+        .unwrap();
+
+    let result = db.create_password_reset(password_reset(&user, "b")).await;
+    assert_already_exists(result, "password_resets");
+    let result = db.create_password_reset(password_reset(&other, "a")).await;
+    assert_already_exists(result, "password_resets");
+
+    assert_eq!(
+        db.delete_password_resets_for_user(user.id).await.unwrap(),
+        1
+    );
+    db.create_password_reset(password_reset(&user, "b"))
+        .await
+        .unwrap();
+}
+
+async fn password_reset_needs_a_user(db: Arc<dyn Db>) {
+    assert!(db
+        .create_password_reset(password_reset(&default_user(), "a"))
+        .await
+        .is_err());
+}
+
+async fn deletes_password_reset_once(db: Arc<dyn Db>) {
+    let user = default_user();
+    db.create_user(user.clone()).await.unwrap();
+    db.create_password_reset(password_reset(&user, "token"))
+        .await
+        .unwrap();
+
+    db.delete_password_reset("token".into()).await.unwrap();
+    assert_rows_modified(db.delete_password_reset("token".into()).await, 0);
+    assert_eq!(
+        db.delete_password_resets_for_user(user.id).await.unwrap(),
+        0
+    );
+}
+
+async fn deletes_expired_password_resets(db: Arc<dyn Db>) {
+    let (user, other) = (default_user(), other_user());
+    db.create_user(user.clone()).await.unwrap();
+    db.create_user(other.clone()).await.unwrap();
+    let reset = password_reset(&user, "a");
+    let later = PasswordReset {
+        expires_at: reset.expires_at + Duration::minutes(1),
+        ..password_reset(&other, "b")
+    };
+    db.create_password_reset(reset.clone()).await.unwrap();
+    db.create_password_reset(later).await.unwrap();
+
+    assert_eq!(
+        db.delete_expired_password_resets(reset.expires_at)
+            .await
+            .unwrap(),
+        0
+    );
+    let after = reset.expires_at + Duration::seconds(1);
+    assert_eq!(db.delete_expired_password_resets(after).await.unwrap(), 1);
+    assert!(db
+        .password_reset_by_token("b".into())
+        .await
+        .unwrap()
+        .is_some());
+}
+
+async fn purging_user_removes_password_resets(db: Arc<dyn Db>) {
+    let user = default_user();
+    db.create_user(user.clone()).await.unwrap();
+    db.create_password_reset(password_reset(&user, "a"))
+        .await
+        .unwrap();
+    db.delete_user(user.id, user.created_at).await.unwrap();
+    db.purge_deleted_users(user.created_at + Duration::days(1))
+        .await
+        .unwrap();
+
+    assert!(db
+        .password_reset_by_token("a".into())
+        .await
+        .unwrap()
+        .is_none());
+}
@@ -0,0 +1,108 @@
+use diesel::{delete, insert_into, ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::RunQueryDsl;
+use futures::FutureExt;
+use uuid::Uuid;
+
+use crate::{
+    db::schema::email_changes,
+    model::{email_change::EmailChange, types::UserId},
+};
+
+use super::sql::{DbError, SqlDb};
+
+/// Access to pending email changes, which are removed once they're confirmed or cancelled
+#[axum::async_trait]
+pub trait EmailChangeDao {
+    /// Fails with `AlreadyExists` if the user already has a change pending
+    async fn create_email_change(&self, change: EmailChange) -> Result<(), DbError>;
+
+    async fn email_change_for_user(&self, user_id: UserId) -> Result<Option<EmailChange>, DbError>;
+
+    async fn email_change_by_confirm_token(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<EmailChange>, DbError>;
+
+    async fn email_change_by_cancel_token(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<EmailChange>, DbError>;
+
+    /// Fails with `RowsModified` if there's no such change, e.g. because it was already used
+    async fn delete_email_change(&self, id: Uuid) -> Result<(), DbError>;
+
+    /// Remove the user's pending change, if they have one, returning how many there were
+    async fn delete_email_changes_for_user(&self, user_id: UserId) -> Result<usize, DbError>;
+}
+
+#[axum::async_trait]
+impl EmailChangeDao for SqlDb {
+    async fn create_email_change(&self, change: EmailChange) -> Result<(), DbError> {
+        let query = insert_into(email_changes::table).values(change);
+        let rows_modified = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn email_change_for_user(&self, user_id: UserId) -> Result<Option<EmailChange>, DbError> {
+        let query = email_changes::table
+            .filter(email_changes::user_id.eq(user_id))
+            .limit(1);
+        let change = self
+            .read(query, |query, conn| {
+                async move { query.get_result(conn).await.optional() }.boxed()
+            })
+            .await?;
+
+        Ok(change)
+    }
+
+    async fn email_change_by_confirm_token(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<EmailChange>, DbError> {
+        let query = email_changes::table
+            .filter(email_changes::confirm_token_hash.eq(token_hash))
+            .limit(1);
+        let change = self
+            .read(query, |query, conn| {
+                async move { query.get_result(conn).await.optional() }.boxed()
+            })
+            .await?;
+
+        Ok(change)
+    }
+
+    async fn email_change_by_cancel_token(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<EmailChange>, DbError> {
+        let query = email_changes::table
+            .filter(email_changes::cancel_token_hash.eq(token_hash))
+            .limit(1);
+        let change = self
+            .read(query, |query, conn| {
+                async move { query.get_result(conn).await.optional() }.boxed()
+            })
+            .await?;
+
+        Ok(change)
+    }
+
+    async fn delete_email_change(&self, id: Uuid) -> Result<(), DbError> {
+        let query = delete(email_changes::table.filter(email_changes::id.eq(id)));
+        let rows_modified = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn delete_email_changes_for_user(&self, user_id: UserId) -> Result<usize, DbError> {
+        let query = delete(email_changes::table.filter(email_changes::user_id.eq(user_id)));
+        self.exec(query, |query, conn| query.execute(conn).boxed())
+            .await
+    }
+}
@@ -0,0 +1,90 @@
+use diesel::{delete, insert_into, ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::RunQueryDsl;
+use futures::FutureExt;
+
+use crate::{
+    db::schema::user_identities,
+    model::{identity::UserIdentity, types::UserId},
+};
+
+use super::sql::{DbError, SqlDb};
+
+/// Access to the accounts at identity providers that users have linked
+#[axum::async_trait]
+pub trait IdentityDao {
+    /// Fails with `AlreadyExists` if the account is linked already, or the user already has one at
+    /// that provider
+    async fn create_identity(&self, identity: UserIdentity) -> Result<(), DbError>;
+
+    async fn identity_by_subject(
+        &self,
+        provider: String,
+        subject: String,
+    ) -> Result<Option<UserIdentity>, DbError>;
+
+    /// Every identity a user has linked, oldest first
+    async fn identities_for_user(&self, user_id: UserId) -> Result<Vec<UserIdentity>, DbError>;
+
+    /// Unlink a user's identity at `provider`, failing with `RowsModified` if they don't have one
+    async fn delete_identity(&self, user_id: UserId, provider: String) -> Result<(), DbError>;
+
+    /// Unlink all of a user's identities, returning how many there were
+    async fn delete_identities_for_user(&self, user_id: UserId) -> Result<usize, DbError>;
+}
+
+#[axum::async_trait]
+impl IdentityDao for SqlDb {
+    async fn create_identity(&self, identity: UserIdentity) -> Result<(), DbError> {
+        let query = insert_into(user_identities::table).values(identity);
+        let rows_modified = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn identity_by_subject(
+        &self,
+        provider: String,
+        subject: String,
+    ) -> Result<Option<UserIdentity>, DbError> {
+        let query = user_identities::table
+            .filter(user_identities::provider.eq(provider))
+            .filter(user_identities::subject.eq(subject))
+            .limit(1);
+        let identity = self
+            .read(query, |query, conn| {
+                async move { query.get_result(conn).await.optional() }.boxed()
+            })
+            .await?;
+
+        Ok(identity)
+    }
+
+    async fn identities_for_user(&self, user_id: UserId) -> Result<Vec<UserIdentity>, DbError> {
+        let query = user_identities::table
+            .filter(user_identities::user_id.eq(user_id))
+            .order((user_identities::created_at, user_identities::id));
+        self.read(query, |query, conn| query.load(conn).boxed())
+            .await
+    }
+
+    async fn delete_identity(&self, user_id: UserId, provider: String) -> Result<(), DbError> {
+        let query = delete(
+            user_identities::table
+                .filter(user_identities::user_id.eq(user_id))
+                .filter(user_identities::provider.eq(provider)),
+        );
+        let rows_modified = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn delete_identities_for_user(&self, user_id: UserId) -> Result<usize, DbError> {
+        let query = delete(user_identities::table.filter(user_identities::user_id.eq(user_id)));
+        self.exec(query, |query, conn| query.execute(conn).boxed())
+            .await
+    }
+}
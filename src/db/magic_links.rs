@@ -0,0 +1,91 @@
+use chrono::{DateTime, Utc};
+use diesel::{delete, insert_into, ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::RunQueryDsl;
+use futures::FutureExt;
+
+use crate::{
+    db::schema::magic_links,
+    model::{magic_link::MagicLink, types::Email},
+};
+
+use super::sql::{DbError, SqlDb};
+
+/// Access to sign in links that haven't been used yet
+#[axum::async_trait]
+pub trait MagicLinkDao {
+    /// Fails with `AlreadyExists` if the address already has a link out
+    async fn create_magic_link(&self, link: MagicLink) -> Result<(), DbError>;
+
+    async fn magic_link_by_token(&self, token_hash: String) -> Result<Option<MagicLink>, DbError>;
+
+    /// The link out for an address, if there is one
+    async fn magic_link_for_email(&self, email: Email) -> Result<Option<MagicLink>, DbError>;
+
+    /// Fails with `RowsModified` if there's no such link, e.g. because it was already used
+    async fn delete_magic_link(&self, token_hash: String) -> Result<(), DbError>;
+
+    /// Remove the link sent to an address, if there is one, returning how many there were
+    async fn delete_magic_links_for_email(&self, email: Email) -> Result<usize, DbError>;
+
+    /// Remove links that expired before `at`, returning how many there were
+    async fn delete_expired_magic_links(&self, at: DateTime<Utc>) -> Result<usize, DbError>;
+}
+
+#[axum::async_trait]
+impl MagicLinkDao for SqlDb {
+    async fn create_magic_link(&self, link: MagicLink) -> Result<(), DbError> {
+        let query = insert_into(magic_links::table).values(link);
+        let rows_modified = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn magic_link_by_token(&self, token_hash: String) -> Result<Option<MagicLink>, DbError> {
+        let query = magic_links::table
+            .filter(magic_links::token_hash.eq(token_hash))
+            .limit(1);
+        let link = self
+            .read(query, |query, conn| {
+                async move { query.get_result(conn).await.optional() }.boxed()
+            })
+            .await?;
+
+        Ok(link)
+    }
+
+    async fn magic_link_for_email(&self, email: Email) -> Result<Option<MagicLink>, DbError> {
+        let query = magic_links::table
+            .filter(magic_links::email.eq(email))
+            .limit(1);
+        let link = self
+            .read(query, |query, conn| {
+                async move { query.get_result(conn).await.optional() }.boxed()
+            })
+            .await?;
+
+        Ok(link)
+    }
+
+    async fn delete_magic_link(&self, token_hash: String) -> Result<(), DbError> {
+        let query = delete(magic_links::table.filter(magic_links::token_hash.eq(token_hash)));
+        let rows_modified = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn delete_magic_links_for_email(&self, email: Email) -> Result<usize, DbError> {
+        let query = delete(magic_links::table.filter(magic_links::email.eq(email)));
+        self.exec(query, |query, conn| query.execute(conn).boxed())
+            .await
+    }
+
+    async fn delete_expired_magic_links(&self, at: DateTime<Utc>) -> Result<usize, DbError> {
+        let query = delete(magic_links::table.filter(magic_links::expires_at.lt(at)));
+        self.exec(query, |query, conn| query.execute(conn).boxed())
+            .await
+    }
+}
@@ -0,0 +1,2035 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+use microtype::{secrecy::ExposeSecret, SecretMicrotype};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::model::{
+    api_key::ApiKey,
+    audit::{AuditAction, AuditEvent},
+    email_change::EmailChange,
+    identity::UserIdentity,
+    magic_link::MagicLink,
+    oauth::{AuthorizationCode, OAuthClient, OAuthConsent, RefreshToken, Scopes},
+    organization::{Membership, OrgInvitation, OrgRole, Organization},
+    password_reset::PasswordReset,
+    session::Session,
+    types::{ClientId, Email, OrgId, PasswordHash, SessionId, UserId},
+    user::{ProfileChanges, User},
+};
+
+use super::{
+    api_keys::ApiKeyDao,
+    audit::{AuditDao, AuditFilter},
+    email_changes::EmailChangeDao,
+    identities::IdentityDao,
+    magic_links::MagicLinkDao,
+    oauth::OAuthDao,
+    organizations::OrganizationDao,
+    password_resets::PasswordResetDao,
+    sessions::SessionDao,
+    sql::DbError,
+    transaction::{ErasedBody, ErasedResult},
+    users::UserDao,
+    Db,
+};
+
+/// A database held entirely in memory, for tests, demos and frontend development
+///
+/// Constraints are checked the same way Postgres checks them, and the contents can be loaded from
+/// and saved to a JSON snapshot, so that they survive restarts
+#[derive(Debug, Clone, Default)]
+pub struct MemoryDb {
+    tables: Arc<Mutex<Tables>>,
+    snapshot: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Tables {
+    users: HashMap<Uuid, User>,
+    users_by_email: HashMap<String, Uuid>,
+    sessions: HashMap<String, Session>,
+    email_changes: HashMap<Uuid, EmailChange>,
+    identities: HashMap<Uuid, UserIdentity>,
+    magic_links: HashMap<String, MagicLink>,
+    oauth_clients: HashMap<Uuid, OAuthClient>,
+    oauth_consents: HashMap<(Uuid, Uuid), OAuthConsent>,
+    authorization_codes: HashMap<String, AuthorizationCode>,
+    refresh_tokens: HashMap<String, RefreshToken>,
+    api_keys: HashMap<Uuid, ApiKey>,
+    audit_events: Vec<AuditEvent>,
+    organizations: HashMap<Uuid, Organization>,
+    memberships: HashMap<(Uuid, Uuid), Membership>,
+    org_invitations: HashMap<Uuid, OrgInvitation>,
+    password_resets: HashMap<String, PasswordReset>,
+}
+
+impl Tables {
+    fn insert_user(&mut self, user: User) -> Result<(), DbError> {
+        // Postgres doesn't name the column for unique violations
+        if self.users.contains_key(&user.id.0) || self.users_by_email.contains_key(&user.email.0) {
+            return Err(DbError::AlreadyExists {
+                table: Some("users".into()),
+                col: None,
+            });
+        }
+
+        self.users_by_email.insert(user.email.0.clone(), user.id.0);
+        self.users.insert(user.id.0, user);
+
+        Ok(())
+    }
+
+    /// Deleted users stay in the tables until they're purged, but can't be found
+    fn active_user(&mut self, id: Uuid) -> Option<&mut User> {
+        self.users
+            .get_mut(&id)
+            .filter(|user| user.deleted_at.is_none())
+    }
+
+    /// Sessions, email changes, identities, OAuth grants, API keys, memberships and password resets
+    /// go along with their user, as with `ON DELETE CASCADE`
+    fn remove_user(&mut self, id: Uuid) {
+        if let Some(user) = self.users.remove(&id) {
+            self.users_by_email.remove(&user.email.0);
+            self.sessions.retain(|_, session| session.user_id.0 != id);
+            self.email_changes
+                .retain(|_, change| change.user_id.0 != id);
+            self.identities
+                .retain(|_, identity| identity.user_id.0 != id);
+            self.oauth_consents.retain(|(user_id, _), _| *user_id != id);
+            self.authorization_codes
+                .retain(|_, code| code.user_id.0 != id);
+            self.refresh_tokens.retain(|_, token| token.user_id.0 != id);
+            self.api_keys.retain(|_, key| key.user_id.0 != id);
+            self.memberships.retain(|(_, user_id), _| *user_id != id);
+            self.password_resets
+                .retain(|_, reset| reset.user_id.0 != id);
+        }
+    }
+
+    /// Consents, codes and refresh tokens go along with their client, as with `ON DELETE CASCADE`
+    fn remove_client(&mut self, id: Uuid) -> bool {
+        let removed = self.oauth_clients.remove(&id).is_some();
+        self.oauth_consents
+            .retain(|(_, client_id), _| *client_id != id);
+        self.authorization_codes
+            .retain(|_, code| code.client_id.0 != id);
+        self.refresh_tokens
+            .retain(|_, token| token.client_id.0 != id);
+        removed
+    }
+
+    /// Memberships and invitations go along with their organization, as with `ON DELETE CASCADE`
+    fn remove_organization(&mut self, id: Uuid) -> bool {
+        let removed = self.organizations.remove(&id).is_some();
+        self.memberships.retain(|(org_id, _), _| *org_id != id);
+        self.org_invitations
+            .retain(|_, invitation| invitation.organization_id.0 != id);
+        removed
+    }
+
+    fn insert_session(&mut self, session: Session) -> Result<(), DbError> {
+        if !self.users.contains_key(&session.user_id.0) {
+            return Err(foreign_key_violation("sessions_user_id_fkey"));
+        }
+
+        if self.sessions.contains_key(&session.id.0) {
+            return Err(DbError::AlreadyExists {
+                table: Some("sessions".into()),
+                col: None,
+            });
+        }
+
+        self.sessions.insert(session.id.0.clone(), session);
+        Ok(())
+    }
+
+    fn insert_email_change(&mut self, change: EmailChange) -> Result<(), DbError> {
+        if !self.users.contains_key(&change.user_id.0) {
+            return Err(foreign_key_violation("email_changes_user_id_fkey"));
+        }
+
+        let duplicate = self.email_changes.values().any(|existing| {
+            existing.id == change.id
+                || existing.user_id == change.user_id
+                || existing.confirm_token_hash == change.confirm_token_hash
+                || existing.cancel_token_hash == change.cancel_token_hash
+        });
+        if duplicate {
+            return Err(DbError::AlreadyExists {
+                table: Some("email_changes".into()),
+                col: None,
+            });
+        }
+
+        self.email_changes.insert(change.id, change);
+        Ok(())
+    }
+
+    fn insert_identity(&mut self, identity: UserIdentity) -> Result<(), DbError> {
+        if !self.users.contains_key(&identity.user_id.0) {
+            return Err(foreign_key_violation("user_identities_user_id_fkey"));
+        }
+
+        let duplicate = self.identities.values().any(|existing| {
+            existing.id == identity.id
+                || (existing.provider == identity.provider && existing.subject == identity.subject)
+                || (existing.provider == identity.provider && existing.user_id == identity.user_id)
+        });
+        if duplicate {
+            return Err(DbError::AlreadyExists {
+                table: Some("user_identities".into()),
+                col: None,
+            });
+        }
+
+        self.identities.insert(identity.id, identity);
+        Ok(())
+    }
+
+    fn insert_magic_link(&mut self, link: MagicLink) -> Result<(), DbError> {
+        let duplicate = self
+            .magic_links
+            .values()
+            .any(|existing| existing.token_hash == link.token_hash || existing.email == link.email);
+        if duplicate {
+            return Err(DbError::AlreadyExists {
+                table: Some("magic_links".into()),
+                col: None,
+            });
+        }
+
+        self.magic_links.insert(link.token_hash.clone(), link);
+        Ok(())
+    }
+
+    /// Remove the links matching `f`, returning how many there were
+    fn remove_magic_links(&mut self, f: impl Fn(&MagicLink) -> bool) -> usize {
+        let before = self.magic_links.len();
+        self.magic_links.retain(|_, link| !f(link));
+        before - self.magic_links.len()
+    }
+
+    fn insert_oauth_client(&mut self, client: OAuthClient) -> Result<(), DbError> {
+        if self.oauth_clients.contains_key(&client.id.0) {
+            return Err(DbError::AlreadyExists {
+                table: Some("oauth_clients".into()),
+                col: None,
+            });
+        }
+
+        self.oauth_clients.insert(client.id.0, client);
+        Ok(())
+    }
+
+    /// Replaces any consent the user already gave the client
+    fn upsert_oauth_consent(&mut self, consent: OAuthConsent) -> Result<(), DbError> {
+        if !self.users.contains_key(&consent.user_id.0) {
+            return Err(foreign_key_violation("oauth_consents_user_id_fkey"));
+        }
+        if !self.oauth_clients.contains_key(&consent.client_id.0) {
+            return Err(foreign_key_violation("oauth_consents_client_id_fkey"));
+        }
+
+        let key = (consent.user_id.0, consent.client_id.0);
+        self.oauth_consents.insert(key, consent);
+        Ok(())
+    }
+
+    fn insert_authorization_code(&mut self, code: AuthorizationCode) -> Result<(), DbError> {
+        if !self.oauth_clients.contains_key(&code.client_id.0) {
+            return Err(foreign_key_violation(
+                "oauth_authorization_codes_client_id_fkey",
+            ));
+        }
+        if !self.users.contains_key(&code.user_id.0) {
+            return Err(foreign_key_violation(
+                "oauth_authorization_codes_user_id_fkey",
+            ));
+        }
+
+        if self.authorization_codes.contains_key(&code.code_hash) {
+            return Err(DbError::AlreadyExists {
+                table: Some("oauth_authorization_codes".into()),
+                col: None,
+            });
+        }
+
+        self.authorization_codes
+            .insert(code.code_hash.clone(), code);
+        Ok(())
+    }
+
+    fn insert_refresh_token(&mut self, token: RefreshToken) -> Result<(), DbError> {
+        if !self.oauth_clients.contains_key(&token.client_id.0) {
+            return Err(foreign_key_violation("oauth_refresh_tokens_client_id_fkey"));
+        }
+        if !self.users.contains_key(&token.user_id.0) {
+            return Err(foreign_key_violation("oauth_refresh_tokens_user_id_fkey"));
+        }
+
+        if self.refresh_tokens.contains_key(&token.token_hash) {
+            return Err(DbError::AlreadyExists {
+                table: Some("oauth_refresh_tokens".into()),
+                col: None,
+            });
+        }
+
+        self.refresh_tokens.insert(token.token_hash.clone(), token);
+        Ok(())
+    }
+
+    /// Remove the refresh tokens matching `f`, returning how many there were
+    fn remove_refresh_tokens(&mut self, f: impl Fn(&RefreshToken) -> bool) -> usize {
+        let before = self.refresh_tokens.len();
+        self.refresh_tokens.retain(|_, token| !f(token));
+        before - self.refresh_tokens.len()
+    }
+
+    fn insert_api_key(&mut self, key: ApiKey) -> Result<(), DbError> {
+        if !self.users.contains_key(&key.user_id.0) {
+            return Err(foreign_key_violation("api_keys_user_id_fkey"));
+        }
+
+        let duplicate = self.api_keys.values().any(|existing| {
+            existing.id == key.id
+                || existing.prefix == key.prefix
+                || (existing.user_id == key.user_id && existing.name == key.name)
+        });
+        if duplicate {
+            return Err(DbError::AlreadyExists {
+                table: Some("api_keys".into()),
+                col: None,
+            });
+        }
+
+        self.api_keys.insert(key.id, key);
+        Ok(())
+    }
+
+    fn insert_audit_event(&mut self, event: AuditEvent) -> Result<(), DbError> {
+        if self
+            .audit_events
+            .iter()
+            .any(|existing| existing.id == event.id)
+        {
+            return Err(DbError::AlreadyExists {
+                table: Some("audit_events".into()),
+                col: None,
+            });
+        }
+
+        self.audit_events.push(event);
+        Ok(())
+    }
+
+    fn insert_organization(&mut self, organization: Organization) -> Result<(), DbError> {
+        if self.organizations.contains_key(&organization.id.0) {
+            return Err(DbError::AlreadyExists {
+                table: Some("organizations".into()),
+                col: None,
+            });
+        }
+
+        self.organizations.insert(organization.id.0, organization);
+        Ok(())
+    }
+
+    fn insert_membership(&mut self, membership: Membership) -> Result<(), DbError> {
+        if !self
+            .organizations
+            .contains_key(&membership.organization_id.0)
+        {
+            return Err(foreign_key_violation(
+                "org_memberships_organization_id_fkey",
+            ));
+        }
+        if !self.users.contains_key(&membership.user_id.0) {
+            return Err(foreign_key_violation("org_memberships_user_id_fkey"));
+        }
+
+        let key = (membership.organization_id.0, membership.user_id.0);
+        if self.memberships.contains_key(&key) {
+            return Err(DbError::AlreadyExists {
+                table: Some("org_memberships".into()),
+                col: None,
+            });
+        }
+
+        self.memberships.insert(key, membership);
+        Ok(())
+    }
+
+    fn insert_org_invitation(&mut self, invitation: OrgInvitation) -> Result<(), DbError> {
+        if !self
+            .organizations
+            .contains_key(&invitation.organization_id.0)
+        {
+            return Err(foreign_key_violation(
+                "org_invitations_organization_id_fkey",
+            ));
+        }
+
+        let duplicate = self.org_invitations.values().any(|existing| {
+            existing.id == invitation.id
+                || existing.token_hash == invitation.token_hash
+                || (existing.organization_id == invitation.organization_id
+                    && existing.email == invitation.email)
+        });
+        if duplicate {
+            return Err(DbError::AlreadyExists {
+                table: Some("org_invitations".into()),
+                col: None,
+            });
+        }
+
+        self.org_invitations.insert(invitation.id, invitation);
+        Ok(())
+    }
+
+    fn insert_password_reset(&mut self, reset: PasswordReset) -> Result<(), DbError> {
+        if !self.users.contains_key(&reset.user_id.0) {
+            return Err(foreign_key_violation("password_resets_user_id_fkey"));
+        }
+
+        let duplicate = self.password_resets.values().any(|existing| {
+            existing.token_hash == reset.token_hash || existing.user_id == reset.user_id
+        });
+        if duplicate {
+            return Err(DbError::AlreadyExists {
+                table: Some("password_resets".into()),
+                col: None,
+            });
+        }
+
+        self.password_resets.insert(reset.token_hash.clone(), reset);
+        Ok(())
+    }
+
+    /// Remove the resets matching `f`, returning how many there were
+    fn remove_password_resets(&mut self, f: impl Fn(&PasswordReset) -> bool) -> usize {
+        let before = self.password_resets.len();
+        self.password_resets.retain(|_, reset| !f(reset));
+        before - self.password_resets.len()
+    }
+
+    /// Remove the sessions matching `f`, returning how many there were
+    fn remove_sessions(&mut self, f: impl Fn(&Session) -> bool) -> usize {
+        let before = self.sessions.len();
+        self.sessions.retain(|_, session| !f(session));
+        before - self.sessions.len()
+    }
+}
+
+fn foreign_key_violation(constraint: &str) -> DbError {
+    let info = Box::new(String::from(constraint));
+    let e = diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::ForeignKeyViolation,
+        info,
+    );
+    e.into()
+}
+
+impl MemoryDb {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load the snapshot at `path` if there is one, and save back to it on shutdown
+    pub fn open(path: PathBuf) -> Result<Self, DbError> {
+        let tables = match path.exists() {
+            true => Snapshot::load(&path)?.into_tables()?,
+            false => Tables::default(),
+        };
+
+        Ok(Self {
+            tables: Arc::new(Mutex::new(tables)),
+            snapshot: Some(path),
+        })
+    }
+
+    /// Insert users directly, for setting up test data
+    #[cfg(test)]
+    pub fn seed(&self, users: impl IntoIterator<Item = User>) {
+        let mut tables = self.tables.lock().unwrap();
+        for user in users {
+            tables.insert_user(user).unwrap();
+        }
+    }
+}
+
+#[axum::async_trait]
+impl Db for MemoryDb {
+    async fn ping(&self) -> Result<(), DbError> {
+        Ok(())
+    }
+
+    async fn has_pending_migrations(&self) -> Result<bool, DbError> {
+        Ok(false)
+    }
+
+    /// Runs `body` directly against this database, putting back a snapshot taken beforehand if
+    /// it fails
+    ///
+    /// Unlike a real transaction, this isn't isolated from anything else using the database at
+    /// the same time
+    async fn run_transaction(&self, body: &mut ErasedBody<'_>) -> Result<ErasedResult, DbError> {
+        let snapshot = self.tables.lock().unwrap().clone();
+
+        let result = body(self).await;
+        if result.is_err() {
+            *self.tables.lock().unwrap() = snapshot;
+        }
+
+        Ok(result)
+    }
+
+    fn read_your_writes(&self) -> Arc<dyn Db> {
+        Arc::new(self.clone())
+    }
+
+    async fn shutdown(&self) -> Result<(), DbError> {
+        let Some(path) = &self.snapshot else {
+            return Ok(());
+        };
+
+        let snapshot = Snapshot::from_tables(&self.tables.lock().unwrap());
+        snapshot.save(path)
+    }
+
+    #[cfg(test)]
+    fn as_memory(&self) -> MemoryDb {
+        self.clone()
+    }
+}
+
+#[axum::async_trait]
+impl UserDao for MemoryDb {
+    async fn user_by_id(&self, user_id: UserId) -> Result<Option<User>, DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        Ok(tables.active_user(user_id.0).cloned())
+    }
+
+    async fn user_by_id_including_deleted(&self, user_id: UserId) -> Result<Option<User>, DbError> {
+        let tables = self.tables.lock().unwrap();
+        Ok(tables.users.get(&user_id.0).cloned())
+    }
+
+    async fn user_by_email(&self, email: Email) -> Result<Option<User>, DbError> {
+        let tables = self.tables.lock().unwrap();
+        let user = tables
+            .users_by_email
+            .get(&email.0)
+            .and_then(|id| tables.users.get(id))
+            .filter(|user| user.deleted_at.is_none())
+            .cloned();
+        Ok(user)
+    }
+
+    async fn create_user(&self, user: User) -> Result<(), DbError> {
+        self.tables.lock().unwrap().insert_user(user)
+    }
+
+    async fn update_profile(
+        &self,
+        user_id: UserId,
+        changes: ProfileChanges,
+    ) -> Result<(), DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        let Some(user) = tables.active_user(user_id.0) else {
+            return DbError::check_rows_modified(1, 0);
+        };
+
+        let ProfileChanges {
+            display_name,
+            locale,
+            timezone,
+            avatar_url,
+        } = changes;
+        let fields = [
+            (&mut user.display_name, display_name),
+            (&mut user.locale, locale),
+            (&mut user.timezone, timezone),
+            (&mut user.avatar_url, avatar_url),
+        ];
+        for (field, change) in fields {
+            if let Some(value) = change {
+                *field = value;
+            }
+        }
+
+        touch(user);
+        Ok(())
+    }
+
+    async fn update_email(&self, user_id: UserId, email: Email) -> Result<(), DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        let taken = tables
+            .users_by_email
+            .get(&email.0)
+            .is_some_and(|id| *id != user_id.0);
+        let Some(user) = tables.active_user(user_id.0) else {
+            return DbError::check_rows_modified(1, 0);
+        };
+        if taken {
+            return Err(DbError::AlreadyExists {
+                table: Some("users".into()),
+                col: None,
+            });
+        }
+
+        let old_email = std::mem::replace(&mut user.email, email.clone());
+        touch(user);
+        tables.users_by_email.remove(&old_email.0);
+        tables.users_by_email.insert(email.0, user_id.0);
+        Ok(())
+    }
+
+    async fn update_password(
+        &self,
+        user_id: UserId,
+        password_hash: PasswordHash,
+    ) -> Result<(), DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        let Some(user) = tables.active_user(user_id.0) else {
+            return DbError::check_rows_modified(1, 0);
+        };
+
+        user.password_hash = password_hash;
+        touch(user);
+        Ok(())
+    }
+
+    async fn delete_user(&self, user_id: UserId, at: DateTime<Utc>) -> Result<(), DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        match tables.active_user(user_id.0) {
+            Some(user) => {
+                user.deleted_at = Some(at);
+                touch(user);
+                Ok(())
+            }
+            None => DbError::check_rows_modified(1, 0),
+        }
+    }
+
+    async fn restore_user(
+        &self,
+        user_id: UserId,
+        deleted_since: DateTime<Utc>,
+    ) -> Result<(), DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        let user = tables
+            .users
+            .get_mut(&user_id.0)
+            .filter(|user| user.deleted_at.is_some_and(|at| at >= deleted_since));
+
+        match user {
+            Some(user) => {
+                user.deleted_at = None;
+                touch(user);
+                Ok(())
+            }
+            None => DbError::check_rows_modified(1, 0),
+        }
+    }
+
+    async fn erase_user(&self, user_id: UserId) -> Result<(), DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        let erased = tables.users.contains_key(&user_id.0);
+        tables.remove_user(user_id.0);
+
+        DbError::check_rows_modified(1, usize::from(erased))
+    }
+
+    async fn users_deleted_before(
+        &self,
+        deleted_before: DateTime<Utc>,
+    ) -> Result<Vec<UserId>, DbError> {
+        let tables = self.tables.lock().unwrap();
+        Ok(tables
+            .users
+            .values()
+            .filter(|user| user.deleted_at.is_some_and(|at| at < deleted_before))
+            .map(|user| user.id)
+            .collect())
+    }
+
+    async fn purge_deleted_users(&self, deleted_before: DateTime<Utc>) -> Result<usize, DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        let purged: Vec<_> = tables
+            .users
+            .values()
+            .filter(|user| user.deleted_at.is_some_and(|at| at < deleted_before))
+            .map(|user| user.id.0)
+            .collect();
+
+        for id in &purged {
+            tables.remove_user(*id);
+        }
+
+        Ok(purged.len())
+    }
+}
+
+/// Bump `updated_at` whenever a user changes, as the Postgres trigger would
+///
+/// Like the trigger, this uses the current time rather than anything from `Time`
+fn touch(user: &mut User) {
+    user.updated_at = Utc::now();
+}
+
+#[axum::async_trait]
+impl SessionDao for MemoryDb {
+    async fn session_by_id(&self, session_id: SessionId) -> Result<Option<Session>, DbError> {
+        let tables = self.tables.lock().unwrap();
+        Ok(tables.sessions.get(&session_id.0).cloned())
+    }
+
+    async fn sessions_for_user(&self, user_id: UserId) -> Result<Vec<Session>, DbError> {
+        let tables = self.tables.lock().unwrap();
+        let mut sessions: Vec<_> = tables
+            .sessions
+            .values()
+            .filter(|session| session.user_id == user_id)
+            .cloned()
+            .collect();
+        sessions.sort_by_key(|session| std::cmp::Reverse(session.last_seen_at));
+
+        Ok(sessions)
+    }
+
+    async fn create_session(&self, session: Session) -> Result<(), DbError> {
+        self.tables.lock().unwrap().insert_session(session)
+    }
+
+    async fn touch_session(&self, session_id: SessionId, at: DateTime<Utc>) -> Result<(), DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        if let Some(session) = tables.sessions.get_mut(&session_id.0) {
+            session.last_seen_at = at;
+        }
+        Ok(())
+    }
+
+    async fn delete_session(&self, user_id: UserId, session_id: SessionId) -> Result<(), DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        let removed = tables
+            .remove_sessions(|session| session.id == session_id && session.user_id == user_id);
+
+        DbError::check_rows_modified(1, removed)
+    }
+
+    async fn delete_sessions_for_user(&self, user_id: UserId) -> Result<usize, DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        Ok(tables.remove_sessions(|session| session.user_id == user_id))
+    }
+
+    async fn delete_sessions_created_before(
+        &self,
+        created_before: DateTime<Utc>,
+    ) -> Result<usize, DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        Ok(tables.remove_sessions(|session| session.created_at < created_before))
+    }
+}
+
+#[axum::async_trait]
+impl EmailChangeDao for MemoryDb {
+    async fn create_email_change(&self, change: EmailChange) -> Result<(), DbError> {
+        self.tables.lock().unwrap().insert_email_change(change)
+    }
+
+    async fn email_change_for_user(&self, user_id: UserId) -> Result<Option<EmailChange>, DbError> {
+        let tables = self.tables.lock().unwrap();
+        let change = tables
+            .email_changes
+            .values()
+            .find(|change| change.user_id == user_id);
+        Ok(change.cloned())
+    }
+
+    async fn email_change_by_confirm_token(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<EmailChange>, DbError> {
+        let tables = self.tables.lock().unwrap();
+        let change = tables
+            .email_changes
+            .values()
+            .find(|change| change.confirm_token_hash == token_hash);
+        Ok(change.cloned())
+    }
+
+    async fn email_change_by_cancel_token(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<EmailChange>, DbError> {
+        let tables = self.tables.lock().unwrap();
+        let change = tables
+            .email_changes
+            .values()
+            .find(|change| change.cancel_token_hash == token_hash);
+        Ok(change.cloned())
+    }
+
+    async fn delete_email_change(&self, id: Uuid) -> Result<(), DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        let removed = tables.email_changes.remove(&id).map_or(0, |_| 1);
+
+        DbError::check_rows_modified(1, removed)
+    }
+
+    async fn delete_email_changes_for_user(&self, user_id: UserId) -> Result<usize, DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        let before = tables.email_changes.len();
+        tables
+            .email_changes
+            .retain(|_, change| change.user_id != user_id);
+        Ok(before - tables.email_changes.len())
+    }
+}
+
+#[axum::async_trait]
+impl IdentityDao for MemoryDb {
+    async fn create_identity(&self, identity: UserIdentity) -> Result<(), DbError> {
+        self.tables.lock().unwrap().insert_identity(identity)
+    }
+
+    async fn identity_by_subject(
+        &self,
+        provider: String,
+        subject: String,
+    ) -> Result<Option<UserIdentity>, DbError> {
+        let tables = self.tables.lock().unwrap();
+        let identity = tables
+            .identities
+            .values()
+            .find(|identity| identity.provider == provider && identity.subject == subject);
+        Ok(identity.cloned())
+    }
+
+    async fn identities_for_user(&self, user_id: UserId) -> Result<Vec<UserIdentity>, DbError> {
+        let tables = self.tables.lock().unwrap();
+        let mut identities: Vec<_> = tables
+            .identities
+            .values()
+            .filter(|identity| identity.user_id == user_id)
+            .cloned()
+            .collect();
+        identities.sort_by_key(|identity| (identity.created_at, identity.id));
+
+        Ok(identities)
+    }
+
+    async fn delete_identity(&self, user_id: UserId, provider: String) -> Result<(), DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        let before = tables.identities.len();
+        tables
+            .identities
+            .retain(|_, identity| identity.user_id != user_id || identity.provider != provider);
+
+        DbError::check_rows_modified(1, before - tables.identities.len())
+    }
+
+    async fn delete_identities_for_user(&self, user_id: UserId) -> Result<usize, DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        let before = tables.identities.len();
+        tables
+            .identities
+            .retain(|_, identity| identity.user_id != user_id);
+        Ok(before - tables.identities.len())
+    }
+}
+
+#[axum::async_trait]
+impl MagicLinkDao for MemoryDb {
+    async fn create_magic_link(&self, link: MagicLink) -> Result<(), DbError> {
+        self.tables.lock().unwrap().insert_magic_link(link)
+    }
+
+    async fn magic_link_by_token(&self, token_hash: String) -> Result<Option<MagicLink>, DbError> {
+        let tables = self.tables.lock().unwrap();
+        Ok(tables.magic_links.get(&token_hash).cloned())
+    }
+
+    async fn magic_link_for_email(&self, email: Email) -> Result<Option<MagicLink>, DbError> {
+        let tables = self.tables.lock().unwrap();
+        Ok(tables
+            .magic_links
+            .values()
+            .find(|link| link.email == email)
+            .cloned())
+    }
+
+    async fn delete_magic_link(&self, token_hash: String) -> Result<(), DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        let removed = tables.remove_magic_links(|link| link.token_hash == token_hash);
+
+        DbError::check_rows_modified(1, removed)
+    }
+
+    async fn delete_magic_links_for_email(&self, email: Email) -> Result<usize, DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        Ok(tables.remove_magic_links(|link| link.email == email))
+    }
+
+    async fn delete_expired_magic_links(&self, at: DateTime<Utc>) -> Result<usize, DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        Ok(tables.remove_magic_links(|link| link.expires_at < at))
+    }
+}
+
+#[axum::async_trait]
+impl OAuthDao for MemoryDb {
+    async fn create_client(&self, client: OAuthClient) -> Result<(), DbError> {
+        self.tables.lock().unwrap().insert_oauth_client(client)
+    }
+
+    async fn client_by_id(&self, client_id: ClientId) -> Result<Option<OAuthClient>, DbError> {
+        let tables = self.tables.lock().unwrap();
+        Ok(tables.oauth_clients.get(&client_id.0).cloned())
+    }
+
+    async fn clients(&self) -> Result<Vec<OAuthClient>, DbError> {
+        let tables = self.tables.lock().unwrap();
+        let mut clients: Vec<_> = tables.oauth_clients.values().cloned().collect();
+        clients.sort_by_key(|client| (client.created_at, client.id.0));
+
+        Ok(clients)
+    }
+
+    async fn delete_client(&self, client_id: ClientId) -> Result<(), DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        let removed = tables.remove_client(client_id.0);
+
+        DbError::check_rows_modified(1, usize::from(removed))
+    }
+
+    async fn consent(
+        &self,
+        user_id: UserId,
+        client_id: ClientId,
+    ) -> Result<Option<OAuthConsent>, DbError> {
+        let tables = self.tables.lock().unwrap();
+        let consent = tables.oauth_consents.get(&(user_id.0, client_id.0));
+        Ok(consent.cloned())
+    }
+
+    async fn consents_for_user(&self, user_id: UserId) -> Result<Vec<OAuthConsent>, DbError> {
+        let tables = self.tables.lock().unwrap();
+        let mut consents: Vec<_> = tables
+            .oauth_consents
+            .values()
+            .filter(|consent| consent.user_id == user_id)
+            .cloned()
+            .collect();
+        consents.sort_by_key(|consent| (consent.granted_at, consent.client_id.0));
+
+        Ok(consents)
+    }
+
+    async fn save_consent(&self, consent: OAuthConsent) -> Result<(), DbError> {
+        self.tables.lock().unwrap().upsert_oauth_consent(consent)
+    }
+
+    async fn delete_consent(&self, user_id: UserId, client_id: ClientId) -> Result<(), DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        let removed = tables.oauth_consents.remove(&(user_id.0, client_id.0));
+
+        DbError::check_rows_modified(1, removed.map_or(0, |_| 1))
+    }
+
+    async fn delete_consents_for_user(&self, user_id: UserId) -> Result<usize, DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        let before = tables.oauth_consents.len();
+        tables
+            .oauth_consents
+            .retain(|_, consent| consent.user_id != user_id);
+        Ok(before - tables.oauth_consents.len())
+    }
+
+    async fn create_authorization_code(&self, code: AuthorizationCode) -> Result<(), DbError> {
+        self.tables.lock().unwrap().insert_authorization_code(code)
+    }
+
+    async fn authorization_code_by_hash(
+        &self,
+        code_hash: String,
+    ) -> Result<Option<AuthorizationCode>, DbError> {
+        let tables = self.tables.lock().unwrap();
+        Ok(tables.authorization_codes.get(&code_hash).cloned())
+    }
+
+    async fn delete_authorization_code(&self, code_hash: String) -> Result<(), DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        let removed = tables.authorization_codes.remove(&code_hash);
+
+        DbError::check_rows_modified(1, removed.map_or(0, |_| 1))
+    }
+
+    async fn create_refresh_token(&self, token: RefreshToken) -> Result<(), DbError> {
+        self.tables.lock().unwrap().insert_refresh_token(token)
+    }
+
+    async fn refresh_token_by_hash(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<RefreshToken>, DbError> {
+        let tables = self.tables.lock().unwrap();
+        Ok(tables.refresh_tokens.get(&token_hash).cloned())
+    }
+
+    async fn refresh_tokens_for_user(&self, user_id: UserId) -> Result<Vec<RefreshToken>, DbError> {
+        let tables = self.tables.lock().unwrap();
+        let mut tokens: Vec<_> = tables
+            .refresh_tokens
+            .values()
+            .filter(|token| token.user_id == user_id)
+            .cloned()
+            .collect();
+        tokens.sort_by(|a, b| (a.created_at, &a.token_hash).cmp(&(b.created_at, &b.token_hash)));
+
+        Ok(tokens)
+    }
+
+    async fn delete_refresh_token(&self, token_hash: String) -> Result<(), DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        let removed = tables.remove_refresh_tokens(|token| token.token_hash == token_hash);
+
+        DbError::check_rows_modified(1, removed)
+    }
+
+    async fn delete_refresh_tokens(
+        &self,
+        user_id: UserId,
+        client_id: ClientId,
+    ) -> Result<usize, DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        Ok(tables.remove_refresh_tokens(|token| {
+            token.user_id == user_id && token.client_id == client_id
+        }))
+    }
+
+    async fn delete_refresh_tokens_for_user(&self, user_id: UserId) -> Result<usize, DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        Ok(tables.remove_refresh_tokens(|token| token.user_id == user_id))
+    }
+
+    async fn delete_expired_oauth_tokens(&self, at: DateTime<Utc>) -> Result<usize, DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        let before = tables.authorization_codes.len();
+        tables
+            .authorization_codes
+            .retain(|_, code| code.expires_at >= at);
+        let codes = before - tables.authorization_codes.len();
+
+        Ok(codes + tables.remove_refresh_tokens(|token| token.expires_at < at))
+    }
+}
+
+#[axum::async_trait]
+impl ApiKeyDao for MemoryDb {
+    async fn create_api_key(&self, key: ApiKey) -> Result<(), DbError> {
+        self.tables.lock().unwrap().insert_api_key(key)
+    }
+
+    async fn api_key_by_prefix(&self, prefix: String) -> Result<Option<ApiKey>, DbError> {
+        let tables = self.tables.lock().unwrap();
+        let key = tables.api_keys.values().find(|key| key.prefix == prefix);
+        Ok(key.cloned())
+    }
+
+    async fn api_keys_for_user(&self, user_id: UserId) -> Result<Vec<ApiKey>, DbError> {
+        let tables = self.tables.lock().unwrap();
+        let mut keys: Vec<_> = tables
+            .api_keys
+            .values()
+            .filter(|key| key.user_id == user_id)
+            .cloned()
+            .collect();
+        keys.sort_by_key(|key| (key.created_at, key.id));
+
+        Ok(keys)
+    }
+
+    async fn touch_api_key(&self, id: Uuid, at: DateTime<Utc>) -> Result<(), DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        if let Some(key) = tables.api_keys.get_mut(&id) {
+            key.last_used_at = Some(at);
+        }
+        Ok(())
+    }
+
+    async fn delete_api_key(&self, user_id: UserId, id: Uuid) -> Result<(), DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        let before = tables.api_keys.len();
+        tables
+            .api_keys
+            .retain(|_, key| key.id != id || key.user_id != user_id);
+
+        DbError::check_rows_modified(1, before - tables.api_keys.len())
+    }
+
+    async fn delete_api_keys_for_user(&self, user_id: UserId) -> Result<usize, DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        let before = tables.api_keys.len();
+        tables.api_keys.retain(|_, key| key.user_id != user_id);
+        Ok(before - tables.api_keys.len())
+    }
+}
+
+#[axum::async_trait]
+impl AuditDao for MemoryDb {
+    async fn create_audit_event(&self, event: AuditEvent) -> Result<(), DbError> {
+        self.tables.lock().unwrap().insert_audit_event(event)
+    }
+
+    async fn audit_events(&self, filter: AuditFilter) -> Result<Vec<AuditEvent>, DbError> {
+        let tables = self.tables.lock().unwrap();
+        let mut events: Vec<_> = tables
+            .audit_events
+            .iter()
+            .filter(|event| filter.matches(event))
+            .cloned()
+            .collect();
+        events.sort_by_key(|event| std::cmp::Reverse((event.occurred_at, event.id)));
+        events.truncate(filter.limit.try_into().unwrap_or(0));
+
+        Ok(events)
+    }
+
+    async fn anonymize_audit_events(&self, user_id: UserId) -> Result<usize, DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        let mut anonymized = 0;
+
+        for event in &mut tables.audit_events {
+            if event.actor_id != Some(user_id) && event.subject_id != Some(user_id) {
+                continue;
+            }
+
+            for id in [&mut event.actor_id, &mut event.subject_id] {
+                if *id == Some(user_id) {
+                    *id = None;
+                }
+            }
+            event.ip = None;
+            event.user_agent = None;
+            anonymized += 1;
+        }
+
+        Ok(anonymized)
+    }
+}
+
+#[axum::async_trait]
+impl OrganizationDao for MemoryDb {
+    async fn create_organization(&self, organization: Organization) -> Result<(), DbError> {
+        self.tables
+            .lock()
+            .unwrap()
+            .insert_organization(organization)
+    }
+
+    async fn organization_by_id(&self, id: OrgId) -> Result<Option<Organization>, DbError> {
+        let tables = self.tables.lock().unwrap();
+        Ok(tables.organizations.get(&id.0).cloned())
+    }
+
+    async fn delete_organization(&self, id: OrgId) -> Result<(), DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        let removed = tables.remove_organization(id.0);
+
+        DbError::check_rows_modified(1, usize::from(removed))
+    }
+
+    async fn create_membership(&self, membership: Membership) -> Result<(), DbError> {
+        self.tables.lock().unwrap().insert_membership(membership)
+    }
+
+    async fn membership(
+        &self,
+        organization_id: OrgId,
+        user_id: UserId,
+    ) -> Result<Option<Membership>, DbError> {
+        let tables = self.tables.lock().unwrap();
+        let membership = tables.memberships.get(&(organization_id.0, user_id.0));
+        Ok(membership.cloned())
+    }
+
+    async fn memberships_for_organization(
+        &self,
+        organization_id: OrgId,
+    ) -> Result<Vec<Membership>, DbError> {
+        let tables = self.tables.lock().unwrap();
+        let mut memberships: Vec<_> = tables
+            .memberships
+            .values()
+            .filter(|membership| membership.organization_id == organization_id)
+            .cloned()
+            .collect();
+        memberships.sort_by_key(|membership| (membership.created_at, membership.user_id.0));
+
+        Ok(memberships)
+    }
+
+    async fn memberships_for_user(&self, user_id: UserId) -> Result<Vec<Membership>, DbError> {
+        let tables = self.tables.lock().unwrap();
+        let mut memberships: Vec<_> = tables
+            .memberships
+            .values()
+            .filter(|membership| membership.user_id == user_id)
+            .cloned()
+            .collect();
+        memberships.sort_by_key(|membership| (membership.created_at, membership.organization_id.0));
+
+        Ok(memberships)
+    }
+
+    async fn update_membership_role(
+        &self,
+        organization_id: OrgId,
+        user_id: UserId,
+        role: OrgRole,
+    ) -> Result<(), DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        let Some(membership) = tables.memberships.get_mut(&(organization_id.0, user_id.0)) else {
+            return DbError::check_rows_modified(1, 0);
+        };
+
+        membership.role = role;
+        Ok(())
+    }
+
+    async fn delete_membership(
+        &self,
+        organization_id: OrgId,
+        user_id: UserId,
+    ) -> Result<(), DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        let removed = tables
+            .memberships
+            .remove(&(organization_id.0, user_id.0))
+            .is_some();
+
+        DbError::check_rows_modified(1, usize::from(removed))
+    }
+
+    async fn delete_memberships_for_user(&self, user_id: UserId) -> Result<usize, DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        let before = tables.memberships.len();
+        tables
+            .memberships
+            .retain(|_, membership| membership.user_id != user_id);
+        Ok(before - tables.memberships.len())
+    }
+
+    async fn create_org_invitation(&self, invitation: OrgInvitation) -> Result<(), DbError> {
+        self.tables
+            .lock()
+            .unwrap()
+            .insert_org_invitation(invitation)
+    }
+
+    async fn org_invitation_by_token(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<OrgInvitation>, DbError> {
+        let tables = self.tables.lock().unwrap();
+        let invitation = tables
+            .org_invitations
+            .values()
+            .find(|invitation| invitation.token_hash == token_hash);
+        Ok(invitation.cloned())
+    }
+
+    async fn org_invitations_for_organization(
+        &self,
+        organization_id: OrgId,
+    ) -> Result<Vec<OrgInvitation>, DbError> {
+        let tables = self.tables.lock().unwrap();
+        let mut invitations: Vec<_> = tables
+            .org_invitations
+            .values()
+            .filter(|invitation| invitation.organization_id == organization_id)
+            .cloned()
+            .collect();
+        invitations.sort_by_key(|invitation| (invitation.created_at, invitation.id));
+
+        Ok(invitations)
+    }
+
+    async fn org_invitations_for_email(&self, email: Email) -> Result<Vec<OrgInvitation>, DbError> {
+        let tables = self.tables.lock().unwrap();
+        let mut invitations: Vec<_> = tables
+            .org_invitations
+            .values()
+            .filter(|invitation| invitation.email == email)
+            .cloned()
+            .collect();
+        invitations.sort_by_key(|invitation| (invitation.created_at, invitation.id));
+
+        Ok(invitations)
+    }
+
+    async fn delete_org_invitation(&self, organization_id: OrgId, id: Uuid) -> Result<(), DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        let before = tables.org_invitations.len();
+        tables.org_invitations.retain(|_, invitation| {
+            invitation.id != id || invitation.organization_id != organization_id
+        });
+
+        DbError::check_rows_modified(1, before - tables.org_invitations.len())
+    }
+
+    async fn delete_org_invitations_for_email(&self, email: Email) -> Result<usize, DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        let before = tables.org_invitations.len();
+        tables
+            .org_invitations
+            .retain(|_, invitation| invitation.email != email);
+        Ok(before - tables.org_invitations.len())
+    }
+
+    async fn delete_expired_org_invitations(&self, at: DateTime<Utc>) -> Result<usize, DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        let before = tables.org_invitations.len();
+        tables
+            .org_invitations
+            .retain(|_, invitation| invitation.expires_at >= at);
+        Ok(before - tables.org_invitations.len())
+    }
+}
+
+#[axum::async_trait]
+impl PasswordResetDao for MemoryDb {
+    async fn create_password_reset(&self, reset: PasswordReset) -> Result<(), DbError> {
+        self.tables.lock().unwrap().insert_password_reset(reset)
+    }
+
+    async fn password_reset_by_token(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<PasswordReset>, DbError> {
+        let tables = self.tables.lock().unwrap();
+        Ok(tables.password_resets.get(&token_hash).cloned())
+    }
+
+    async fn delete_password_reset(&self, token_hash: String) -> Result<(), DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        let removed = tables.remove_password_resets(|reset| reset.token_hash == token_hash);
+
+        DbError::check_rows_modified(1, removed)
+    }
+
+    async fn delete_password_resets_for_user(&self, user_id: UserId) -> Result<usize, DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        Ok(tables.remove_password_resets(|reset| reset.user_id == user_id))
+    }
+
+    async fn delete_expired_password_resets(&self, at: DateTime<Utc>) -> Result<usize, DbError> {
+        let mut tables = self.tables.lock().unwrap();
+        Ok(tables.remove_password_resets(|reset| reset.expires_at < at))
+    }
+}
+
+/// The JSON form of the whole database
+#[derive(Debug, Serialize, Deserialize)]
+struct Snapshot {
+    users: Vec<UserRecord>,
+    /// Missing from snapshots saved before sessions existed
+    #[serde(default)]
+    sessions: Vec<SessionRecord>,
+    /// Missing from snapshots saved before email changes existed
+    #[serde(default)]
+    email_changes: Vec<EmailChangeRecord>,
+    /// Missing from snapshots saved before identities existed
+    #[serde(default)]
+    identities: Vec<IdentityRecord>,
+    /// Missing from snapshots saved before magic links existed
+    #[serde(default)]
+    magic_links: Vec<MagicLinkRecord>,
+    /// This and the rest of the OAuth tables are missing from snapshots saved before OAuth existed
+    #[serde(default)]
+    oauth_clients: Vec<OAuthClientRecord>,
+    #[serde(default)]
+    oauth_consents: Vec<OAuthConsentRecord>,
+    #[serde(default)]
+    authorization_codes: Vec<AuthorizationCodeRecord>,
+    #[serde(default)]
+    refresh_tokens: Vec<RefreshTokenRecord>,
+    /// Missing from snapshots saved before API keys existed
+    #[serde(default)]
+    api_keys: Vec<ApiKeyRecord>,
+    /// Missing from snapshots saved before the audit log existed
+    #[serde(default)]
+    audit_events: Vec<AuditEventRecord>,
+    /// This and the rest of the organization tables are missing from snapshots saved before
+    /// organizations existed
+    #[serde(default)]
+    organizations: Vec<OrganizationRecord>,
+    #[serde(default)]
+    memberships: Vec<MembershipRecord>,
+    #[serde(default)]
+    org_invitations: Vec<OrgInvitationRecord>,
+    /// Missing from snapshots saved before password resets existed
+    #[serde(default)]
+    password_resets: Vec<PasswordResetRecord>,
+}
+
+/// A `User` in a snapshot, with its password hash exposed so that it can be saved
+#[derive(Debug, Serialize, Deserialize)]
+struct UserRecord {
+    id: Uuid,
+    email: String,
+    password_hash: String,
+    created_at: DateTime<Utc>,
+    /// Missing from snapshots saved before soft deletes existed
+    #[serde(default)]
+    deleted_at: Option<DateTime<Utc>>,
+    /// This and the rest of the profile are missing from snapshots saved before profiles existed
+    #[serde(default)]
+    display_name: Option<String>,
+    #[serde(default)]
+    locale: Option<String>,
+    #[serde(default)]
+    timezone: Option<String>,
+    #[serde(default)]
+    avatar_url: Option<String>,
+    /// Taken to be `created_at` if it's missing
+    #[serde(default)]
+    updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SessionRecord {
+    id: String,
+    user_id: Uuid,
+    created_at: DateTime<Utc>,
+    last_seen_at: DateTime<Utc>,
+    user_agent: Option<String>,
+    ip: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct EmailChangeRecord {
+    id: Uuid,
+    user_id: Uuid,
+    new_email: String,
+    confirm_token_hash: String,
+    cancel_token_hash: String,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct IdentityRecord {
+    id: Uuid,
+    user_id: Uuid,
+    provider: String,
+    subject: String,
+    email: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MagicLinkRecord {
+    token_hash: String,
+    email: String,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OAuthClientRecord {
+    id: Uuid,
+    name: String,
+    secret_hash: Option<String>,
+    redirect_uri: String,
+    scopes: Scopes,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OAuthConsentRecord {
+    user_id: Uuid,
+    client_id: Uuid,
+    scopes: Scopes,
+    granted_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AuthorizationCodeRecord {
+    code_hash: String,
+    client_id: Uuid,
+    user_id: Uuid,
+    redirect_uri: String,
+    scopes: Scopes,
+    code_challenge: String,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RefreshTokenRecord {
+    token_hash: String,
+    client_id: Uuid,
+    user_id: Uuid,
+    scopes: Scopes,
+    session_id: String,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ApiKeyRecord {
+    id: Uuid,
+    user_id: Uuid,
+    name: String,
+    prefix: String,
+    key_hash: String,
+    scopes: Scopes,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    last_used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AuditEventRecord {
+    id: Uuid,
+    occurred_at: DateTime<Utc>,
+    actor_id: Option<Uuid>,
+    subject_id: Option<Uuid>,
+    action: AuditAction,
+    ip: Option<String>,
+    user_agent: Option<String>,
+    request_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OrganizationRecord {
+    id: Uuid,
+    name: String,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MembershipRecord {
+    organization_id: Uuid,
+    user_id: Uuid,
+    role: OrgRole,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OrgInvitationRecord {
+    id: Uuid,
+    organization_id: Uuid,
+    email: String,
+    role: OrgRole,
+    token_hash: String,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PasswordResetRecord {
+    token_hash: String,
+    user_id: Uuid,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+}
+
+impl Snapshot {
+    fn load(path: &Path) -> Result<Self, DbError> {
+        let json = std::fs::read_to_string(path).map_err(|e| DbError::Snapshot(e.into()))?;
+        serde_json::from_str(&json).map_err(|e| DbError::Snapshot(e.into()))
+    }
+
+    /// Write to a temporary file first, so a crash part way through can't corrupt the snapshot
+    fn save(&self, path: &Path) -> Result<(), DbError> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| DbError::Snapshot(e.into()))?;
+        let tmp = path.with_extension("tmp");
+
+        std::fs::write(&tmp, json).map_err(|e| DbError::Snapshot(e.into()))?;
+        std::fs::rename(&tmp, path).map_err(|e| DbError::Snapshot(e.into()))
+    }
+
+    fn from_tables(tables: &Tables) -> Self {
+        let mut users: Vec<_> = tables
+            .users
+            .values()
+            .map(|user| UserRecord {
+                id: user.id.0,
+                email: user.email.0.clone(),
+                password_hash: user.password_hash.expose_secret().clone(),
+                created_at: user.created_at,
+                deleted_at: user.deleted_at,
+                display_name: user.display_name.clone(),
+                locale: user.locale.clone(),
+                timezone: user.timezone.clone(),
+                avatar_url: user.avatar_url.clone(),
+                updated_at: Some(user.updated_at),
+            })
+            .collect();
+        users.sort_by_key(|user| (user.created_at, user.id));
+
+        let mut sessions: Vec<_> = tables
+            .sessions
+            .values()
+            .map(|session| SessionRecord {
+                id: session.id.0.clone(),
+                user_id: session.user_id.0,
+                created_at: session.created_at,
+                last_seen_at: session.last_seen_at,
+                user_agent: session.user_agent.clone(),
+                ip: session.ip.clone(),
+            })
+            .collect();
+        sessions.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+
+        let mut email_changes: Vec<_> = tables
+            .email_changes
+            .values()
+            .map(|change| EmailChangeRecord {
+                id: change.id,
+                user_id: change.user_id.0,
+                new_email: change.new_email.0.clone(),
+                confirm_token_hash: change.confirm_token_hash.clone(),
+                cancel_token_hash: change.cancel_token_hash.clone(),
+                created_at: change.created_at,
+                expires_at: change.expires_at,
+            })
+            .collect();
+        email_changes.sort_by_key(|change| (change.created_at, change.id));
+
+        let mut identities: Vec<_> = tables
+            .identities
+            .values()
+            .map(|identity| IdentityRecord {
+                id: identity.id,
+                user_id: identity.user_id.0,
+                provider: identity.provider.clone(),
+                subject: identity.subject.clone(),
+                email: identity.email.as_ref().map(|email| email.0.clone()),
+                created_at: identity.created_at,
+            })
+            .collect();
+        identities.sort_by_key(|identity| (identity.created_at, identity.id));
+
+        let mut magic_links: Vec<_> = tables
+            .magic_links
+            .values()
+            .map(|link| MagicLinkRecord {
+                token_hash: link.token_hash.clone(),
+                email: link.email.0.clone(),
+                created_at: link.created_at,
+                expires_at: link.expires_at,
+            })
+            .collect();
+        magic_links
+            .sort_by(|a, b| (a.created_at, &a.token_hash).cmp(&(b.created_at, &b.token_hash)));
+
+        let mut oauth_clients: Vec<_> = tables
+            .oauth_clients
+            .values()
+            .map(|client| OAuthClientRecord {
+                id: client.id.0,
+                name: client.name.clone(),
+                secret_hash: client.secret_hash.clone(),
+                redirect_uri: client.redirect_uri.clone(),
+                scopes: client.scopes.clone(),
+                created_at: client.created_at,
+            })
+            .collect();
+        oauth_clients.sort_by_key(|client| (client.created_at, client.id));
+
+        let mut oauth_consents: Vec<_> = tables
+            .oauth_consents
+            .values()
+            .map(|consent| OAuthConsentRecord {
+                user_id: consent.user_id.0,
+                client_id: consent.client_id.0,
+                scopes: consent.scopes.clone(),
+                granted_at: consent.granted_at,
+            })
+            .collect();
+        oauth_consents
+            .sort_by_key(|consent| (consent.granted_at, consent.user_id, consent.client_id));
+
+        let mut authorization_codes: Vec<_> = tables
+            .authorization_codes
+            .values()
+            .map(|code| AuthorizationCodeRecord {
+                code_hash: code.code_hash.clone(),
+                client_id: code.client_id.0,
+                user_id: code.user_id.0,
+                redirect_uri: code.redirect_uri.clone(),
+                scopes: code.scopes.clone(),
+                code_challenge: code.code_challenge.clone(),
+                created_at: code.created_at,
+                expires_at: code.expires_at,
+            })
+            .collect();
+        authorization_codes
+            .sort_by(|a, b| (a.created_at, &a.code_hash).cmp(&(b.created_at, &b.code_hash)));
+
+        let mut refresh_tokens: Vec<_> = tables
+            .refresh_tokens
+            .values()
+            .map(|token| RefreshTokenRecord {
+                token_hash: token.token_hash.clone(),
+                client_id: token.client_id.0,
+                user_id: token.user_id.0,
+                scopes: token.scopes.clone(),
+                session_id: token.session_id.0.clone(),
+                created_at: token.created_at,
+                expires_at: token.expires_at,
+            })
+            .collect();
+        refresh_tokens
+            .sort_by(|a, b| (a.created_at, &a.token_hash).cmp(&(b.created_at, &b.token_hash)));
+
+        let mut api_keys: Vec<_> = tables
+            .api_keys
+            .values()
+            .map(|key| ApiKeyRecord {
+                id: key.id,
+                user_id: key.user_id.0,
+                name: key.name.clone(),
+                prefix: key.prefix.clone(),
+                key_hash: key.key_hash.clone(),
+                scopes: key.scopes.clone(),
+                created_at: key.created_at,
+                expires_at: key.expires_at,
+                last_used_at: key.last_used_at,
+            })
+            .collect();
+        api_keys.sort_by_key(|key| (key.created_at, key.id));
+
+        let audit_events = tables
+            .audit_events
+            .iter()
+            .map(|event| AuditEventRecord {
+                id: event.id,
+                occurred_at: event.occurred_at,
+                actor_id: event.actor_id.map(|id| id.0),
+                subject_id: event.subject_id.map(|id| id.0),
+                action: event.action,
+                ip: event.ip.clone(),
+                user_agent: event.user_agent.clone(),
+                request_id: event.request_id.clone(),
+            })
+            .collect();
+
+        let mut organizations: Vec<_> = tables
+            .organizations
+            .values()
+            .map(|organization| OrganizationRecord {
+                id: organization.id.0,
+                name: organization.name.clone(),
+                created_at: organization.created_at,
+            })
+            .collect();
+        organizations.sort_by_key(|organization| (organization.created_at, organization.id));
+
+        let mut memberships: Vec<_> = tables
+            .memberships
+            .values()
+            .map(|membership| MembershipRecord {
+                organization_id: membership.organization_id.0,
+                user_id: membership.user_id.0,
+                role: membership.role,
+                created_at: membership.created_at,
+            })
+            .collect();
+        memberships.sort_by_key(|membership| {
+            (
+                membership.created_at,
+                membership.organization_id,
+                membership.user_id,
+            )
+        });
+
+        let mut org_invitations: Vec<_> = tables
+            .org_invitations
+            .values()
+            .map(|invitation| OrgInvitationRecord {
+                id: invitation.id,
+                organization_id: invitation.organization_id.0,
+                email: invitation.email.0.clone(),
+                role: invitation.role,
+                token_hash: invitation.token_hash.clone(),
+                created_at: invitation.created_at,
+                expires_at: invitation.expires_at,
+            })
+            .collect();
+        org_invitations.sort_by_key(|invitation| (invitation.created_at, invitation.id));
+
+        let mut password_resets: Vec<_> = tables
+            .password_resets
+            .values()
+            .map(|reset| PasswordResetRecord {
+                token_hash: reset.token_hash.clone(),
+                user_id: reset.user_id.0,
+                created_at: reset.created_at,
+                expires_at: reset.expires_at,
+            })
+            .collect();
+        password_resets
+            .sort_by(|a, b| (a.created_at, &a.token_hash).cmp(&(b.created_at, &b.token_hash)));
+
+        Self {
+            users,
+            sessions,
+            email_changes,
+            identities,
+            magic_links,
+            oauth_clients,
+            oauth_consents,
+            authorization_codes,
+            refresh_tokens,
+            api_keys,
+            audit_events,
+            organizations,
+            memberships,
+            org_invitations,
+            password_resets,
+        }
+    }
+
+    /// Rebuild the tables, checking constraints as if each row had been inserted again
+    fn into_tables(self) -> Result<Tables, DbError> {
+        let mut tables = Tables::default();
+
+        for record in self.users {
+            tables.insert_user(User {
+                id: UserId(record.id),
+                email: Email(record.email),
+                password_hash: PasswordHash::new(record.password_hash),
+                created_at: record.created_at,
+                deleted_at: record.deleted_at,
+                display_name: record.display_name,
+                locale: record.locale,
+                timezone: record.timezone,
+                avatar_url: record.avatar_url,
+                updated_at: record.updated_at.unwrap_or(record.created_at),
+            })?;
+        }
+
+        for record in self.sessions {
+            tables.insert_session(Session {
+                id: SessionId(record.id),
+                user_id: UserId(record.user_id),
+                created_at: record.created_at,
+                last_seen_at: record.last_seen_at,
+                user_agent: record.user_agent,
+                ip: record.ip,
+            })?;
+        }
+
+        for record in self.email_changes {
+            tables.insert_email_change(EmailChange {
+                id: record.id,
+                user_id: UserId(record.user_id),
+                new_email: Email(record.new_email),
+                confirm_token_hash: record.confirm_token_hash,
+                cancel_token_hash: record.cancel_token_hash,
+                created_at: record.created_at,
+                expires_at: record.expires_at,
+            })?;
+        }
+
+        for record in self.identities {
+            tables.insert_identity(UserIdentity {
+                id: record.id,
+                user_id: UserId(record.user_id),
+                provider: record.provider,
+                subject: record.subject,
+                email: record.email.map(Email),
+                created_at: record.created_at,
+            })?;
+        }
+
+        for record in self.magic_links {
+            tables.insert_magic_link(MagicLink {
+                token_hash: record.token_hash,
+                email: Email(record.email),
+                created_at: record.created_at,
+                expires_at: record.expires_at,
+            })?;
+        }
+
+        // clients first, since everything else refers to them
+        for record in self.oauth_clients {
+            tables.insert_oauth_client(OAuthClient {
+                id: ClientId(record.id),
+                name: record.name,
+                secret_hash: record.secret_hash,
+                redirect_uri: record.redirect_uri,
+                scopes: record.scopes,
+                created_at: record.created_at,
+            })?;
+        }
+
+        for record in self.oauth_consents {
+            tables.upsert_oauth_consent(OAuthConsent {
+                user_id: UserId(record.user_id),
+                client_id: ClientId(record.client_id),
+                scopes: record.scopes,
+                granted_at: record.granted_at,
+            })?;
+        }
+
+        for record in self.authorization_codes {
+            tables.insert_authorization_code(AuthorizationCode {
+                code_hash: record.code_hash,
+                client_id: ClientId(record.client_id),
+                user_id: UserId(record.user_id),
+                redirect_uri: record.redirect_uri,
+                scopes: record.scopes,
+                code_challenge: record.code_challenge,
+                created_at: record.created_at,
+                expires_at: record.expires_at,
+            })?;
+        }
+
+        for record in self.refresh_tokens {
+            tables.insert_refresh_token(RefreshToken {
+                token_hash: record.token_hash,
+                client_id: ClientId(record.client_id),
+                user_id: UserId(record.user_id),
+                scopes: record.scopes,
+                session_id: SessionId(record.session_id),
+                created_at: record.created_at,
+                expires_at: record.expires_at,
+            })?;
+        }
+
+        for record in self.api_keys {
+            tables.insert_api_key(ApiKey {
+                id: record.id,
+                user_id: UserId(record.user_id),
+                name: record.name,
+                prefix: record.prefix,
+                key_hash: record.key_hash,
+                scopes: record.scopes,
+                created_at: record.created_at,
+                expires_at: record.expires_at,
+                last_used_at: record.last_used_at,
+            })?;
+        }
+
+        for record in self.audit_events {
+            tables.insert_audit_event(AuditEvent {
+                id: record.id,
+                occurred_at: record.occurred_at,
+                actor_id: record.actor_id.map(UserId),
+                subject_id: record.subject_id.map(UserId),
+                action: record.action,
+                ip: record.ip,
+                user_agent: record.user_agent,
+                request_id: record.request_id,
+            })?;
+        }
+
+        // organizations before their members and invitations, which refer to them
+        for record in self.organizations {
+            tables.insert_organization(Organization {
+                id: OrgId(record.id),
+                name: record.name,
+                created_at: record.created_at,
+            })?;
+        }
+
+        for record in self.memberships {
+            tables.insert_membership(Membership {
+                organization_id: OrgId(record.organization_id),
+                user_id: UserId(record.user_id),
+                role: record.role,
+                created_at: record.created_at,
+            })?;
+        }
+
+        for record in self.org_invitations {
+            tables.insert_org_invitation(OrgInvitation {
+                id: record.id,
+                organization_id: OrgId(record.organization_id),
+                email: Email(record.email),
+                role: record.role,
+                token_hash: record.token_hash,
+                created_at: record.created_at,
+                expires_at: record.expires_at,
+            })?;
+        }
+
+        for record in self.password_resets {
+            tables.insert_password_reset(PasswordReset {
+                token_hash: record.token_hash,
+                user_id: UserId(record.user_id),
+                created_at: record.created_at,
+                expires_at: record.expires_at,
+            })?;
+        }
+
+        Ok(tables)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::user::mock::default_user;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn snapshot_round_trip() {
+        let path = std::env::temp_dir().join(format!("{}.json", Uuid::new_v4()));
+        let user = default_user();
+
+        let db = MemoryDb::open(path.clone()).unwrap();
+        db.create_user(user.clone()).await.unwrap();
+        db.shutdown().await.unwrap();
+
+        let db = MemoryDb::open(path.clone()).unwrap();
+        let loaded = db.user_by_email(user.email.clone()).await.unwrap().unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(loaded.id, user.id);
+        assert_eq!(loaded.created_at, user.created_at);
+        assert_eq!(
+            loaded.password_hash.expose_secret(),
+            user.password_hash.expose_secret()
+        );
+    }
+
+    #[tokio::test]
+    async fn email_index_follows_purges() {
+        let db = MemoryDb::new();
+        let first = default_user();
+        let second = User {
+            id: UserId(Uuid::new_v4()),
+            ..default_user()
+        };
+        let deleted_at = first.created_at;
+
+        db.create_user(first.clone()).await.unwrap();
+        db.delete_user(first.id, deleted_at).await.unwrap();
+        assert!(db
+            .user_by_email(first.email.clone())
+            .await
+            .unwrap()
+            .is_none());
+
+        db.purge_deleted_users(deleted_at + chrono::Duration::seconds(1))
+            .await
+            .unwrap();
+        db.create_user(second.clone()).await.unwrap();
+        let found = db.user_by_email(first.email).await.unwrap();
+        assert_eq!(found.unwrap().id, second.id);
+    }
+}
@@ -0,0 +1,11 @@
+-- one row per issued token, keyed by its `jti`
+CREATE TABLE sessions (
+  id TEXT PRIMARY KEY,
+  user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  created_at TIMESTAMPTZ NOT NULL,
+  last_seen_at TIMESTAMPTZ NOT NULL,
+  user_agent TEXT,
+  ip TEXT
+);
+
+CREATE INDEX sessions_user_id_idx ON sessions (user_id);
@@ -0,0 +1,15 @@
+-- append-only, and without foreign keys, so that events outlive the users they mention
+CREATE TABLE audit_events (
+  id UUID PRIMARY KEY,
+  occurred_at TIMESTAMPTZ NOT NULL,
+  actor_id UUID,
+  subject_id UUID,
+  action TEXT NOT NULL,
+  ip TEXT,
+  user_agent TEXT,
+  request_id TEXT
+);
+
+CREATE INDEX audit_events_occurred_at_idx ON audit_events (occurred_at);
+CREATE INDEX audit_events_actor_id_idx ON audit_events (actor_id, occurred_at);
+CREATE INDEX audit_events_subject_id_idx ON audit_events (subject_id, occurred_at);
@@ -0,0 +1,8 @@
+DROP TRIGGER set_updated_at ON users;
+
+ALTER TABLE users
+  DROP COLUMN display_name,
+  DROP COLUMN locale,
+  DROP COLUMN timezone,
+  DROP COLUMN avatar_url,
+  DROP COLUMN updated_at;
@@ -0,0 +1,11 @@
+ALTER TABLE users
+  ADD COLUMN display_name TEXT,
+  ADD COLUMN locale TEXT,
+  ADD COLUMN timezone TEXT,
+  ADD COLUMN avatar_url TEXT,
+  ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+
+-- as far as we know, nobody has changed since they signed up
+UPDATE users SET updated_at = created_at;
+
+SELECT diesel_manage_updated_at('users');
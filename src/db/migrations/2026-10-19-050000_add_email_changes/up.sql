@@ -0,0 +1,10 @@
+-- at most one pending change per user, found by the hash of either of its tokens
+CREATE TABLE email_changes (
+  id UUID PRIMARY KEY,
+  user_id UUID NOT NULL UNIQUE REFERENCES users (id) ON DELETE CASCADE,
+  new_email TEXT NOT NULL,
+  confirm_token_hash TEXT NOT NULL UNIQUE,
+  cancel_token_hash TEXT NOT NULL UNIQUE,
+  created_at TIMESTAMPTZ NOT NULL,
+  expires_at TIMESTAMPTZ NOT NULL
+);
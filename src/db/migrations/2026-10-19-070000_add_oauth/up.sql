@@ -0,0 +1,41 @@
+-- apps registered to act on behalf of users, which only confidential clients have a secret for
+CREATE TABLE oauth_clients (
+  id UUID PRIMARY KEY,
+  name TEXT NOT NULL,
+  secret_hash TEXT,
+  redirect_uri TEXT NOT NULL,
+  scopes TEXT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL
+);
+
+-- what each user has let each client do
+CREATE TABLE oauth_consents (
+  user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  client_id UUID NOT NULL REFERENCES oauth_clients (id) ON DELETE CASCADE,
+  scopes TEXT NOT NULL,
+  granted_at TIMESTAMPTZ NOT NULL,
+  PRIMARY KEY (user_id, client_id)
+);
+
+-- codes waiting to be exchanged for tokens, found by their hash
+CREATE TABLE oauth_authorization_codes (
+  code_hash TEXT PRIMARY KEY,
+  client_id UUID NOT NULL REFERENCES oauth_clients (id) ON DELETE CASCADE,
+  user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  redirect_uri TEXT NOT NULL,
+  scopes TEXT NOT NULL,
+  code_challenge TEXT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL,
+  expires_at TIMESTAMPTZ NOT NULL
+);
+
+-- found by their hash, along with the session of the access token last issued with them
+CREATE TABLE oauth_refresh_tokens (
+  token_hash TEXT PRIMARY KEY,
+  client_id UUID NOT NULL REFERENCES oauth_clients (id) ON DELETE CASCADE,
+  user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  scopes TEXT NOT NULL,
+  session_id TEXT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL,
+  expires_at TIMESTAMPTZ NOT NULL
+);
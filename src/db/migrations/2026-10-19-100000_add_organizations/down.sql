@@ -0,0 +1,3 @@
+DROP TABLE org_invitations;
+DROP TABLE org_memberships;
+DROP TABLE organizations;
@@ -0,0 +1,7 @@
+-- at most one unused reset link per user
+CREATE TABLE password_resets (
+  token_hash TEXT PRIMARY KEY,
+  user_id UUID NOT NULL UNIQUE REFERENCES users (id) ON DELETE CASCADE,
+  created_at TIMESTAMPTZ NOT NULL,
+  expires_at TIMESTAMPTZ NOT NULL
+);
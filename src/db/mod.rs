@@ -1,25 +1,163 @@
-#[cfg(test)]
-use crate::db::mock::MockDb;
-use std::fmt::Debug;
+use std::{fmt::Debug, sync::Arc, time::Duration};
+
+use diesel::{migration::MigrationSource, pg::Pg, sql_query, QueryDsl};
+use diesel_async::RunQueryDsl;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations};
+use futures::FutureExt;
 
-use self::{sql::SqlDb, users::UserDao};
+use self::{
+    api_keys::ApiKeyDao,
+    audit::AuditDao,
+    email_changes::EmailChangeDao,
+    identities::IdentityDao,
+    magic_links::MagicLinkDao,
+    oauth::OAuthDao,
+    organizations::OrganizationDao,
+    password_resets::PasswordResetDao,
+    sessions::SessionDao,
+    sql::{DbError, SqlDb},
+    transaction::{ErasedBody, ErasedResult},
+    users::UserDao,
+};
 
+pub mod api_keys;
+pub mod audit;
+pub mod email_changes;
+pub mod identities;
+pub mod magic_links;
+pub mod oauth;
+pub mod organizations;
+pub mod password_resets;
 pub mod schema;
+pub mod sessions;
 pub mod sql;
+pub mod sqlite;
+pub mod transaction;
 pub mod users;
 
+#[cfg(any(test, feature = "in-memory-db"))]
+pub mod memory;
+
 #[cfg(test)]
-pub mod mock;
+mod conformance;
+
+/// How many times a transaction is tried before giving up on serialization failures
+const MAX_TRANSACTION_ATTEMPTS: u32 = 3;
+
+/// Delay before retrying a transaction, multiplied by the number of attempts so far
+const RETRY_BACKOFF: Duration = Duration::from_millis(10);
+
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("src/db/migrations");
+
+// bookkeeping table maintained by diesel's migration harness
+diesel::table! {
+    __diesel_schema_migrations (version) {
+        version -> VarChar,
+        run_on -> Timestamp,
+    }
+}
+
+#[axum::async_trait]
+pub trait Db:
+    UserDao
+    + SessionDao
+    + EmailChangeDao
+    + IdentityDao
+    + MagicLinkDao
+    + OAuthDao
+    + ApiKeyDao
+    + AuditDao
+    + OrganizationDao
+    + PasswordResetDao
+    + Send
+    + Sync
+    + Debug
+{
+    /// Check that the database is reachable and can answer a trivial query
+    async fn ping(&self) -> Result<(), DbError>;
+
+    /// Check whether any embedded migrations have not yet been applied
+    async fn has_pending_migrations(&self) -> Result<bool, DbError>;
+
+    /// Run `body` inside a transaction, see `transaction` for a typed version of this
+    ///
+    /// The outer `Result` holds errors starting or committing the transaction, and the inner one
+    /// holds whatever `body` returned
+    async fn run_transaction(&self, body: &mut ErasedBody<'_>) -> Result<ErasedResult, DbError>;
+
+    /// A handle whose reads see every write made before it was taken
+    ///
+    /// Reads are otherwise allowed to go to a replica, which may lag behind the primary, so use
+    /// this for reading back something written moments ago, e.g. earlier in the same request
+    fn read_your_writes(&self) -> Arc<dyn Db>;
+
+    /// Called once the server has stopped, to persist anything that needs persisting
+    async fn shutdown(&self) -> Result<(), DbError> {
+        Ok(())
+    }
 
-pub trait Db: UserDao + Send + Sync + Debug {
     #[cfg(test)]
-    fn as_mock(&self) -> MockDb;
+    fn as_memory(&self) -> memory::MemoryDb;
 }
 
+#[axum::async_trait]
 impl Db for SqlDb {
-    #[cfg(test)]
-    fn as_mock(&self) -> MockDb {
+    async fn ping(&self) -> Result<(), DbError> {
+        self.exec(sql_query("SELECT 1"), |query, conn| {
+            query.execute(conn).boxed()
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn has_pending_migrations(&self) -> Result<bool, DbError> {
+        // the migration harness only works with synchronous connections, so compare the embedded
+        // migrations against the bookkeeping table directly
+        let query = __diesel_schema_migrations::table.select(__diesel_schema_migrations::version);
+        let applied: Vec<String> = self
+            .exec(query, |query, conn| query.load(conn).boxed())
+            .await?;
+
+        let embedded =
+            MigrationSource::<Pg>::migrations(&MIGRATIONS).map_err(DbError::Migration)?;
+        let pending = embedded
+            .iter()
+            .any(|migration| !applied.contains(&migration.name().version().to_string()));
+
+        Ok(pending)
+    }
+
+    async fn run_transaction(&self, body: &mut ErasedBody<'_>) -> Result<ErasedResult, DbError> {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let tx = self.begin().await?;
 
+            let result = match body(&tx).await {
+                Ok(value) => tx.commit().await.map(|()| Ok(value)),
+                Err(e) => tx.rollback().await.map(|()| Err(e)),
+            };
+
+            let retry = match &result {
+                Err(DbError::SerializationFailure) => true,
+                Ok(Err(_)) => tx.conflicted(),
+                _ => false,
+            };
+            if !retry || attempt == MAX_TRANSACTION_ATTEMPTS {
+                return result;
+            }
+
+            debug!(attempt, "retrying transaction after serialization failure");
+            tokio::time::sleep(RETRY_BACKOFF * attempt).await;
+        }
+    }
+
+    fn read_your_writes(&self) -> Arc<dyn Db> {
+        Arc::new(self.primary_only())
+    }
+
+    #[cfg(test)]
+    fn as_memory(&self) -> memory::MemoryDb {
         unimplemented!()
     }
 }
@@ -0,0 +1,308 @@
+use chrono::{DateTime, Utc};
+use diesel::{
+    delete, insert_into, upsert::excluded, ExpressionMethods, OptionalExtension, QueryDsl,
+};
+use diesel_async::RunQueryDsl;
+use futures::FutureExt;
+
+use crate::{
+    db::schema::{oauth_authorization_codes, oauth_clients, oauth_consents, oauth_refresh_tokens},
+    model::{
+        oauth::{AuthorizationCode, OAuthClient, OAuthConsent, RefreshToken},
+        types::{ClientId, UserId},
+    },
+};
+
+use super::sql::{DbError, SqlDb};
+
+/// Access to OAuth clients, and to what users have let them do
+///
+/// Removing a client removes its consents, codes and refresh tokens along with it
+#[axum::async_trait]
+pub trait OAuthDao {
+    async fn create_client(&self, client: OAuthClient) -> Result<(), DbError>;
+
+    async fn client_by_id(&self, client_id: ClientId) -> Result<Option<OAuthClient>, DbError>;
+
+    /// Every registered client, oldest first
+    async fn clients(&self) -> Result<Vec<OAuthClient>, DbError>;
+
+    /// Fails with `RowsModified` if there's no such client
+    async fn delete_client(&self, client_id: ClientId) -> Result<(), DbError>;
+
+    async fn consent(
+        &self,
+        user_id: UserId,
+        client_id: ClientId,
+    ) -> Result<Option<OAuthConsent>, DbError>;
+
+    /// Every client a user has consented to, oldest grant first
+    async fn consents_for_user(&self, user_id: UserId) -> Result<Vec<OAuthConsent>, DbError>;
+
+    /// Create the consent, or replace the one the user already gave the client
+    async fn save_consent(&self, consent: OAuthConsent) -> Result<(), DbError>;
+
+    /// Fails with `RowsModified` if the user hasn't consented to the client
+    async fn delete_consent(&self, user_id: UserId, client_id: ClientId) -> Result<(), DbError>;
+
+    /// Remove every consent a user has given, returning how many there were
+    async fn delete_consents_for_user(&self, user_id: UserId) -> Result<usize, DbError>;
+
+    async fn create_authorization_code(&self, code: AuthorizationCode) -> Result<(), DbError>;
+
+    async fn authorization_code_by_hash(
+        &self,
+        code_hash: String,
+    ) -> Result<Option<AuthorizationCode>, DbError>;
+
+    /// Fails with `RowsModified` if there's no such code, e.g. because it was already used
+    async fn delete_authorization_code(&self, code_hash: String) -> Result<(), DbError>;
+
+    async fn create_refresh_token(&self, token: RefreshToken) -> Result<(), DbError>;
+
+    async fn refresh_token_by_hash(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<RefreshToken>, DbError>;
+
+    async fn refresh_tokens_for_user(&self, user_id: UserId) -> Result<Vec<RefreshToken>, DbError>;
+
+    /// Fails with `RowsModified` if there's no such token, e.g. because it was already used
+    async fn delete_refresh_token(&self, token_hash: String) -> Result<(), DbError>;
+
+    /// Remove the refresh tokens a user has given a client, returning how many there were
+    async fn delete_refresh_tokens(
+        &self,
+        user_id: UserId,
+        client_id: ClientId,
+    ) -> Result<usize, DbError>;
+
+    /// Remove every refresh token a user has given out, returning how many there were
+    async fn delete_refresh_tokens_for_user(&self, user_id: UserId) -> Result<usize, DbError>;
+
+    /// Remove the codes and refresh tokens that expired before `at`, returning how many there were
+    async fn delete_expired_oauth_tokens(&self, at: DateTime<Utc>) -> Result<usize, DbError>;
+}
+
+#[axum::async_trait]
+impl OAuthDao for SqlDb {
+    async fn create_client(&self, client: OAuthClient) -> Result<(), DbError> {
+        let query = insert_into(oauth_clients::table).values(client);
+        let rows_modified = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn client_by_id(&self, client_id: ClientId) -> Result<Option<OAuthClient>, DbError> {
+        let query = oauth_clients::table
+            .filter(oauth_clients::id.eq(client_id))
+            .limit(1);
+        let client = self
+            .read(query, |query, conn| {
+                async move { query.get_result(conn).await.optional() }.boxed()
+            })
+            .await?;
+
+        Ok(client)
+    }
+
+    async fn clients(&self) -> Result<Vec<OAuthClient>, DbError> {
+        let query = oauth_clients::table.order((oauth_clients::created_at, oauth_clients::id));
+        self.read(query, |query, conn| query.load(conn).boxed())
+            .await
+    }
+
+    async fn delete_client(&self, client_id: ClientId) -> Result<(), DbError> {
+        let query = delete(oauth_clients::table.filter(oauth_clients::id.eq(client_id)));
+        let rows_modified = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn consent(
+        &self,
+        user_id: UserId,
+        client_id: ClientId,
+    ) -> Result<Option<OAuthConsent>, DbError> {
+        let query = oauth_consents::table
+            .filter(oauth_consents::user_id.eq(user_id))
+            .filter(oauth_consents::client_id.eq(client_id))
+            .limit(1);
+        let consent = self
+            .read(query, |query, conn| {
+                async move { query.get_result(conn).await.optional() }.boxed()
+            })
+            .await?;
+
+        Ok(consent)
+    }
+
+    async fn consents_for_user(&self, user_id: UserId) -> Result<Vec<OAuthConsent>, DbError> {
+        let query = oauth_consents::table
+            .filter(oauth_consents::user_id.eq(user_id))
+            .order((oauth_consents::granted_at, oauth_consents::client_id));
+        self.read(query, |query, conn| query.load(conn).boxed())
+            .await
+    }
+
+    async fn save_consent(&self, consent: OAuthConsent) -> Result<(), DbError> {
+        let query = insert_into(oauth_consents::table)
+            .values(consent)
+            .on_conflict((oauth_consents::user_id, oauth_consents::client_id))
+            .do_update()
+            .set((
+                oauth_consents::scopes.eq(excluded(oauth_consents::scopes)),
+                oauth_consents::granted_at.eq(excluded(oauth_consents::granted_at)),
+            ));
+        let rows_modified = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn delete_consent(&self, user_id: UserId, client_id: ClientId) -> Result<(), DbError> {
+        let query = delete(
+            oauth_consents::table
+                .filter(oauth_consents::user_id.eq(user_id))
+                .filter(oauth_consents::client_id.eq(client_id)),
+        );
+        let rows_modified = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn delete_consents_for_user(&self, user_id: UserId) -> Result<usize, DbError> {
+        let query = delete(oauth_consents::table.filter(oauth_consents::user_id.eq(user_id)));
+        self.exec(query, |query, conn| query.execute(conn).boxed())
+            .await
+    }
+
+    async fn create_authorization_code(&self, code: AuthorizationCode) -> Result<(), DbError> {
+        let query = insert_into(oauth_authorization_codes::table).values(code);
+        let rows_modified = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn authorization_code_by_hash(
+        &self,
+        code_hash: String,
+    ) -> Result<Option<AuthorizationCode>, DbError> {
+        let query = oauth_authorization_codes::table
+            .filter(oauth_authorization_codes::code_hash.eq(code_hash))
+            .limit(1);
+        let code = self
+            .read(query, |query, conn| {
+                async move { query.get_result(conn).await.optional() }.boxed()
+            })
+            .await?;
+
+        Ok(code)
+    }
+
+    async fn delete_authorization_code(&self, code_hash: String) -> Result<(), DbError> {
+        let query = delete(
+            oauth_authorization_codes::table
+                .filter(oauth_authorization_codes::code_hash.eq(code_hash)),
+        );
+        let rows_modified = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn create_refresh_token(&self, token: RefreshToken) -> Result<(), DbError> {
+        let query = insert_into(oauth_refresh_tokens::table).values(token);
+        let rows_modified = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn refresh_token_by_hash(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<RefreshToken>, DbError> {
+        let query = oauth_refresh_tokens::table
+            .filter(oauth_refresh_tokens::token_hash.eq(token_hash))
+            .limit(1);
+        let token = self
+            .read(query, |query, conn| {
+                async move { query.get_result(conn).await.optional() }.boxed()
+            })
+            .await?;
+
+        Ok(token)
+    }
+
+    async fn refresh_tokens_for_user(&self, user_id: UserId) -> Result<Vec<RefreshToken>, DbError> {
+        let query = oauth_refresh_tokens::table
+            .filter(oauth_refresh_tokens::user_id.eq(user_id))
+            .order((
+                oauth_refresh_tokens::created_at,
+                oauth_refresh_tokens::token_hash,
+            ));
+        self.read(query, |query, conn| query.load(conn).boxed())
+            .await
+    }
+
+    async fn delete_refresh_token(&self, token_hash: String) -> Result<(), DbError> {
+        let query = delete(
+            oauth_refresh_tokens::table.filter(oauth_refresh_tokens::token_hash.eq(token_hash)),
+        );
+        let rows_modified = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn delete_refresh_tokens(
+        &self,
+        user_id: UserId,
+        client_id: ClientId,
+    ) -> Result<usize, DbError> {
+        let query = delete(
+            oauth_refresh_tokens::table
+                .filter(oauth_refresh_tokens::user_id.eq(user_id))
+                .filter(oauth_refresh_tokens::client_id.eq(client_id)),
+        );
+        self.exec(query, |query, conn| query.execute(conn).boxed())
+            .await
+    }
+
+    async fn delete_refresh_tokens_for_user(&self, user_id: UserId) -> Result<usize, DbError> {
+        let query =
+            delete(oauth_refresh_tokens::table.filter(oauth_refresh_tokens::user_id.eq(user_id)));
+        self.exec(query, |query, conn| query.execute(conn).boxed())
+            .await
+    }
+
+    async fn delete_expired_oauth_tokens(&self, at: DateTime<Utc>) -> Result<usize, DbError> {
+        let codes = delete(
+            oauth_authorization_codes::table.filter(oauth_authorization_codes::expires_at.lt(at)),
+        );
+        let codes = self
+            .exec(codes, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        let refresh_tokens =
+            delete(oauth_refresh_tokens::table.filter(oauth_refresh_tokens::expires_at.lt(at)));
+        let refresh_tokens = self
+            .exec(refresh_tokens, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        Ok(codes + refresh_tokens)
+    }
+}
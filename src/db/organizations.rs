@@ -0,0 +1,285 @@
+use chrono::{DateTime, Utc};
+use diesel::{delete, insert_into, update, ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::RunQueryDsl;
+use futures::FutureExt;
+use uuid::Uuid;
+
+use crate::{
+    db::schema::{org_invitations, org_memberships, organizations},
+    model::{
+        organization::{Membership, OrgInvitation, OrgRole, Organization},
+        types::{Email, OrgId, UserId},
+    },
+};
+
+use super::sql::{DbError, SqlDb};
+
+/// Access to organizations, their members, and the invitations they've sent out
+///
+/// Removing an organization removes its memberships and invitations along with it
+#[axum::async_trait]
+pub trait OrganizationDao {
+    async fn create_organization(&self, organization: Organization) -> Result<(), DbError>;
+
+    async fn organization_by_id(&self, id: OrgId) -> Result<Option<Organization>, DbError>;
+
+    /// Fails with `RowsModified` if there's no such organization
+    async fn delete_organization(&self, id: OrgId) -> Result<(), DbError>;
+
+    /// Fails with `AlreadyExists` if the user is already a member
+    async fn create_membership(&self, membership: Membership) -> Result<(), DbError>;
+
+    async fn membership(
+        &self,
+        organization_id: OrgId,
+        user_id: UserId,
+    ) -> Result<Option<Membership>, DbError>;
+
+    /// Everyone in an organization, longest standing first
+    async fn memberships_for_organization(
+        &self,
+        organization_id: OrgId,
+    ) -> Result<Vec<Membership>, DbError>;
+
+    /// Every organization a user is in, oldest membership first
+    async fn memberships_for_user(&self, user_id: UserId) -> Result<Vec<Membership>, DbError>;
+
+    /// Fails with `RowsModified` if the user isn't a member
+    async fn update_membership_role(
+        &self,
+        organization_id: OrgId,
+        user_id: UserId,
+        role: OrgRole,
+    ) -> Result<(), DbError>;
+
+    /// Fails with `RowsModified` if the user isn't a member
+    async fn delete_membership(
+        &self,
+        organization_id: OrgId,
+        user_id: UserId,
+    ) -> Result<(), DbError>;
+
+    /// Remove a user from every organization they're in, returning how many there were
+    async fn delete_memberships_for_user(&self, user_id: UserId) -> Result<usize, DbError>;
+
+    /// Fails with `AlreadyExists` if the organization already has an invitation out to the address
+    async fn create_org_invitation(&self, invitation: OrgInvitation) -> Result<(), DbError>;
+
+    async fn org_invitation_by_token(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<OrgInvitation>, DbError>;
+
+    /// The invitations an organization has out, oldest first
+    async fn org_invitations_for_organization(
+        &self,
+        organization_id: OrgId,
+    ) -> Result<Vec<OrgInvitation>, DbError>;
+
+    /// The invitations out to an address from any organization, oldest first
+    async fn org_invitations_for_email(&self, email: Email) -> Result<Vec<OrgInvitation>, DbError>;
+
+    /// Fails with `RowsModified` if the organization has no such invitation, e.g. because it was
+    /// already accepted
+    async fn delete_org_invitation(&self, organization_id: OrgId, id: Uuid) -> Result<(), DbError>;
+
+    /// Remove every invitation out to an address, returning how many there were
+    async fn delete_org_invitations_for_email(&self, email: Email) -> Result<usize, DbError>;
+
+    /// Remove invitations that expired before `at`, returning how many there were
+    async fn delete_expired_org_invitations(&self, at: DateTime<Utc>) -> Result<usize, DbError>;
+}
+
+#[axum::async_trait]
+impl OrganizationDao for SqlDb {
+    async fn create_organization(&self, organization: Organization) -> Result<(), DbError> {
+        let query = insert_into(organizations::table).values(organization);
+        let rows_modified = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn organization_by_id(&self, id: OrgId) -> Result<Option<Organization>, DbError> {
+        let query = organizations::table
+            .filter(organizations::id.eq(id))
+            .limit(1);
+        let organization = self
+            .read(query, |query, conn| {
+                async move { query.get_result(conn).await.optional() }.boxed()
+            })
+            .await?;
+
+        Ok(organization)
+    }
+
+    async fn delete_organization(&self, id: OrgId) -> Result<(), DbError> {
+        let query = delete(organizations::table.filter(organizations::id.eq(id)));
+        let rows_modified = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn create_membership(&self, membership: Membership) -> Result<(), DbError> {
+        let query = insert_into(org_memberships::table).values(membership);
+        let rows_modified = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn membership(
+        &self,
+        organization_id: OrgId,
+        user_id: UserId,
+    ) -> Result<Option<Membership>, DbError> {
+        let query = org_memberships::table
+            .filter(org_memberships::organization_id.eq(organization_id))
+            .filter(org_memberships::user_id.eq(user_id))
+            .limit(1);
+        let membership = self
+            .read(query, |query, conn| {
+                async move { query.get_result(conn).await.optional() }.boxed()
+            })
+            .await?;
+
+        Ok(membership)
+    }
+
+    async fn memberships_for_organization(
+        &self,
+        organization_id: OrgId,
+    ) -> Result<Vec<Membership>, DbError> {
+        let query = org_memberships::table
+            .filter(org_memberships::organization_id.eq(organization_id))
+            .order((org_memberships::created_at, org_memberships::user_id));
+        self.read(query, |query, conn| query.load(conn).boxed())
+            .await
+    }
+
+    async fn memberships_for_user(&self, user_id: UserId) -> Result<Vec<Membership>, DbError> {
+        let query = org_memberships::table
+            .filter(org_memberships::user_id.eq(user_id))
+            .order((
+                org_memberships::created_at,
+                org_memberships::organization_id,
+            ));
+        self.read(query, |query, conn| query.load(conn).boxed())
+            .await
+    }
+
+    async fn update_membership_role(
+        &self,
+        organization_id: OrgId,
+        user_id: UserId,
+        role: OrgRole,
+    ) -> Result<(), DbError> {
+        let query = update(
+            org_memberships::table
+                .filter(org_memberships::organization_id.eq(organization_id))
+                .filter(org_memberships::user_id.eq(user_id)),
+        )
+        .set(org_memberships::role.eq(role));
+        let rows_modified = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn delete_membership(
+        &self,
+        organization_id: OrgId,
+        user_id: UserId,
+    ) -> Result<(), DbError> {
+        let query = delete(
+            org_memberships::table
+                .filter(org_memberships::organization_id.eq(organization_id))
+                .filter(org_memberships::user_id.eq(user_id)),
+        );
+        let rows_modified = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn delete_memberships_for_user(&self, user_id: UserId) -> Result<usize, DbError> {
+        let query = delete(org_memberships::table.filter(org_memberships::user_id.eq(user_id)));
+        self.exec(query, |query, conn| query.execute(conn).boxed())
+            .await
+    }
+
+    async fn create_org_invitation(&self, invitation: OrgInvitation) -> Result<(), DbError> {
+        let query = insert_into(org_invitations::table).values(invitation);
+        let rows_modified = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn org_invitation_by_token(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<OrgInvitation>, DbError> {
+        let query = org_invitations::table
+            .filter(org_invitations::token_hash.eq(token_hash))
+            .limit(1);
+        let invitation = self
+            .read(query, |query, conn| {
+                async move { query.get_result(conn).await.optional() }.boxed()
+            })
+            .await?;
+
+        Ok(invitation)
+    }
+
+    async fn org_invitations_for_organization(
+        &self,
+        organization_id: OrgId,
+    ) -> Result<Vec<OrgInvitation>, DbError> {
+        let query = org_invitations::table
+            .filter(org_invitations::organization_id.eq(organization_id))
+            .order((org_invitations::created_at, org_invitations::id));
+        self.read(query, |query, conn| query.load(conn).boxed())
+            .await
+    }
+
+    async fn org_invitations_for_email(&self, email: Email) -> Result<Vec<OrgInvitation>, DbError> {
+        let query = org_invitations::table
+            .filter(org_invitations::email.eq(email))
+            .order((org_invitations::created_at, org_invitations::id));
+        self.read(query, |query, conn| query.load(conn).boxed())
+            .await
+    }
+
+    async fn delete_org_invitation(&self, organization_id: OrgId, id: Uuid) -> Result<(), DbError> {
+        let query = delete(
+            org_invitations::table
+                .filter(org_invitations::organization_id.eq(organization_id))
+                .filter(org_invitations::id.eq(id)),
+        );
+        let rows_modified = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn delete_org_invitations_for_email(&self, email: Email) -> Result<usize, DbError> {
+        let query = delete(org_invitations::table.filter(org_invitations::email.eq(email)));
+        self.exec(query, |query, conn| query.execute(conn).boxed())
+            .await
+    }
+
+    async fn delete_expired_org_invitations(&self, at: DateTime<Utc>) -> Result<usize, DbError> {
+        let query = delete(org_invitations::table.filter(org_invitations::expires_at.lt(at)));
+        self.exec(query, |query, conn| query.execute(conn).boxed())
+            .await
+    }
+}
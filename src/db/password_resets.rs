@@ -0,0 +1,82 @@
+use chrono::{DateTime, Utc};
+use diesel::{delete, insert_into, ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::RunQueryDsl;
+use futures::FutureExt;
+
+use crate::{
+    db::schema::password_resets,
+    model::{password_reset::PasswordReset, types::UserId},
+};
+
+use super::sql::{DbError, SqlDb};
+
+/// Access to password reset links that haven't been used yet
+#[axum::async_trait]
+pub trait PasswordResetDao {
+    /// Fails with `AlreadyExists` if the user already has a link out
+    async fn create_password_reset(&self, reset: PasswordReset) -> Result<(), DbError>;
+
+    async fn password_reset_by_token(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<PasswordReset>, DbError>;
+
+    /// Fails with `RowsModified` if there's no such link, e.g. because it was already used
+    async fn delete_password_reset(&self, token_hash: String) -> Result<(), DbError>;
+
+    /// Remove the link sent to a user, if there is one, returning how many there were
+    async fn delete_password_resets_for_user(&self, user_id: UserId) -> Result<usize, DbError>;
+
+    /// Remove links that expired before `at`, returning how many there were
+    async fn delete_expired_password_resets(&self, at: DateTime<Utc>) -> Result<usize, DbError>;
+}
+
+#[axum::async_trait]
+impl PasswordResetDao for SqlDb {
+    async fn create_password_reset(&self, reset: PasswordReset) -> Result<(), DbError> {
+        let query = insert_into(password_resets::table).values(reset);
+        let rows_modified = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn password_reset_by_token(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<PasswordReset>, DbError> {
+        let query = password_resets::table
+            .filter(password_resets::token_hash.eq(token_hash))
+            .limit(1);
+        let reset = self
+            .read(query, |query, conn| {
+                async move { query.get_result(conn).await.optional() }.boxed()
+            })
+            .await?;
+
+        Ok(reset)
+    }
+
+    async fn delete_password_reset(&self, token_hash: String) -> Result<(), DbError> {
+        let query =
+            delete(password_resets::table.filter(password_resets::token_hash.eq(token_hash)));
+        let rows_modified = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn delete_password_resets_for_user(&self, user_id: UserId) -> Result<usize, DbError> {
+        let query = delete(password_resets::table.filter(password_resets::user_id.eq(user_id)));
+        self.exec(query, |query, conn| query.execute(conn).boxed())
+            .await
+    }
+
+    async fn delete_expired_password_resets(&self, at: DateTime<Utc>) -> Result<usize, DbError> {
+        let query = delete(password_resets::table.filter(password_resets::expires_at.lt(at)));
+        self.exec(query, |query, conn| query.execute(conn).boxed())
+            .await
+    }
+}
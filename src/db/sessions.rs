@@ -0,0 +1,107 @@
+use chrono::{DateTime, Utc};
+use diesel::{delete, insert_into, update, ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::RunQueryDsl;
+use futures::FutureExt;
+
+use crate::{
+    db::schema::sessions,
+    model::{
+        session::Session,
+        types::{SessionId, UserId},
+    },
+};
+
+use super::sql::{DbError, SqlDb};
+
+/// Access to sessions, which are removed outright when they're revoked
+#[axum::async_trait]
+pub trait SessionDao {
+    async fn session_by_id(&self, session_id: SessionId) -> Result<Option<Session>, DbError>;
+
+    /// Every session belonging to a user, most recently seen first
+    async fn sessions_for_user(&self, user_id: UserId) -> Result<Vec<Session>, DbError>;
+
+    async fn create_session(&self, session: Session) -> Result<(), DbError>;
+
+    /// Record that a session was used at `at`
+    async fn touch_session(&self, session_id: SessionId, at: DateTime<Utc>) -> Result<(), DbError>;
+
+    /// Remove one of a user's sessions, failing with `RowsModified` if they have no such session
+    async fn delete_session(&self, user_id: UserId, session_id: SessionId) -> Result<(), DbError>;
+
+    /// Remove all of a user's sessions, returning how many there were
+    async fn delete_sessions_for_user(&self, user_id: UserId) -> Result<usize, DbError>;
+
+    /// Remove sessions created before `created_before`, returning how many there were
+    async fn delete_sessions_created_before(
+        &self,
+        created_before: DateTime<Utc>,
+    ) -> Result<usize, DbError>;
+}
+
+#[axum::async_trait]
+impl SessionDao for SqlDb {
+    async fn session_by_id(&self, session_id: SessionId) -> Result<Option<Session>, DbError> {
+        let query = sessions::table.filter(sessions::id.eq(session_id)).limit(1);
+        let session = self
+            .read(query, |query, conn| {
+                async move { query.get_result(conn).await.optional() }.boxed()
+            })
+            .await?;
+
+        Ok(session)
+    }
+
+    async fn sessions_for_user(&self, user_id: UserId) -> Result<Vec<Session>, DbError> {
+        let query = sessions::table
+            .filter(sessions::user_id.eq(user_id))
+            .order(sessions::last_seen_at.desc());
+        self.read(query, |query, conn| query.load(conn).boxed())
+            .await
+    }
+
+    async fn create_session(&self, session: Session) -> Result<(), DbError> {
+        let query = insert_into(sessions::table).values(session);
+        let rows_modified = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn touch_session(&self, session_id: SessionId, at: DateTime<Utc>) -> Result<(), DbError> {
+        let query = update(sessions::table.filter(sessions::id.eq(session_id)))
+            .set(sessions::last_seen_at.eq(at));
+        self.exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_session(&self, user_id: UserId, session_id: SessionId) -> Result<(), DbError> {
+        let query = delete(
+            sessions::table
+                .filter(sessions::id.eq(session_id))
+                .filter(sessions::user_id.eq(user_id)),
+        );
+        let rows_modified = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn delete_sessions_for_user(&self, user_id: UserId) -> Result<usize, DbError> {
+        let query = delete(sessions::table.filter(sessions::user_id.eq(user_id)));
+        self.exec(query, |query, conn| query.execute(conn).boxed())
+            .await
+    }
+
+    async fn delete_sessions_created_before(
+        &self,
+        created_before: DateTime<Utc>,
+    ) -> Result<usize, DbError> {
+        let query = delete(sessions::table.filter(sessions::created_at.lt(created_before)));
+        self.exec(query, |query, conn| query.execute(conn).boxed())
+            .await
+    }
+}
@@ -1,81 +1,424 @@
+use std::{
+    cell::Cell,
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
 use color_eyre::Result;
+use deadpool::{
+    managed::{Hook, HookError, TimeoutType},
+    Runtime,
+};
 use diesel::{
+    backend::Backend,
     pg::Pg,
-    query_builder::QueryFragment,
-    query_dsl::LoadQuery,
-    r2d2::{ConnectionManager, Pool, PooledConnection},
-    PgConnection, QueryResult,
+    query_builder::{QueryBuilder, QueryFragment},
+    ConnectionError,
+};
+use diesel_async::{
+    pooled_connection::{
+        deadpool::{Object, Pool, PoolError},
+        AsyncDieselConnectionManager,
+    },
+    AnsiTransactionManager, AsyncPgConnection, TransactionManager,
+};
+use futures::{
+    future::{join_all, BoxFuture},
+    FutureExt,
+};
+use prometheus::{
+    core::{Collector, Desc},
+    proto::MetricFamily,
+    IntGaugeVec, Opts,
 };
-use serde::Deserialize;
 use thiserror::Error;
+use tokio::sync::Mutex;
+use tracing::Instrument;
 
-#[derive(Debug, Clone, Deserialize)]
-pub struct DbConfig {
-    pub host: String,
-    pub name: String,
-    pub username: String,
-    pub password: Option<String>,
-}
+use crate::state::metrics::Metrics;
 
-#[derive(Debug)]
+pub use config::DbConfig;
+
+use replicas::{is_unavailable, Replica, Replicas, REPLICA_TIMEOUT};
+
+mod config;
+mod replicas;
+
+/// How often idle and expired connections are closed, and the pool topped back up
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(30);
+
+type PgPool = Pool<AsyncPgConnection>;
+type PgConn = Object<AsyncPgConnection>;
+
+/// A Postgres database, with reads spread across any healthy replicas
+#[derive(Clone)]
 pub struct SqlDb {
-    pool: Pool<ConnectionManager<PgConnection>>,
+    pool: PgPool,
+    replicas: Arc<Replicas>,
+    metrics: Arc<Metrics>,
+    /// Set on handles given out by `Db::transaction`, which run every query on one connection
+    tx: Option<Arc<TxConn>>,
+    /// Set on handles given out by `Db::read_your_writes`, which never read from a replica
+    primary_only: bool,
+}
+
+struct TxConn {
+    conn: Mutex<PgConn>,
+    /// Whether a query failed because of a concurrent transaction, so it's worth retrying
+    conflicted: AtomicBool,
+}
+
+impl Debug for SqlDb {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SqlDb")
+            .field("pool", &self.pool.status())
+            .field("in_transaction", &self.tx.is_some())
+            .field("primary_only", &self.primary_only)
+            .finish_non_exhaustive()
+    }
 }
 
 impl SqlDb {
-    pub fn connect(
-        DbConfig {
-            host,
-            name,
-            username,
-            password,
-        }: DbConfig,
-    ) -> Result<Self> {
-        let separator = match password {
-            Some(_) => ":",
-            None => "",
-        };
-        let url = format!(
-            "postgres://{}{}{}@{}/{}",
-            username,
-            separator,
-            password.unwrap_or_default(),
-            host,
-            name
-        );
+    pub fn connect(config: DbConfig, metrics: Arc<Metrics>) -> Result<Self> {
+        let pool = build_pool(&config, "primary", &metrics)?;
 
-        let manager = ConnectionManager::new(&url);
-        let pool = Pool::builder().build(manager)?;
+        let replicas = config
+            .replicas
+            .iter()
+            .enumerate()
+            .map(|(i, target)| {
+                let name = format!("replica_{i}");
+                let pool = build_pool(&config.replica(target.clone()), &name, &metrics)?;
+                Ok((name, pool))
+            })
+            .collect::<Result<_>>()?;
+        let replicas = Arc::new(Replicas::new(replicas));
+
+        if !replicas.is_empty() {
+            let replicas = replicas.clone();
+            tokio::spawn(async move { replicas.monitor().await });
+        }
+
+        Ok(Self {
+            pool,
+            replicas,
+            metrics,
+            tx: None,
+            primary_only: false,
+        })
+    }
+
+    /// Run a query on a pooled connection, inside a span recording its SQL
+    ///
+    /// `f` receives the query back along with the connection, and decides how to run it
+    pub(super) async fn exec<Q, T, E, F>(&self, query: Q, f: F) -> Result<T, DbError>
+    where
+        Q: DbQuery + 'static,
+        E: Into<DbError>,
+        F: for<'c> FnOnce(Q, &'c mut AsyncPgConnection) -> BoxFuture<'c, Result<T, E>>,
+    {
+        let span = query_span(&query, "primary");
+
+        async move {
+            let Some(tx) = &self.tx else {
+                let mut conn = self.conn().await?;
+                return f(query, &mut conn).await.map_err(Into::into);
+            };
+
+            let mut conn = tx.conn.lock().await;
+            let result = f(query, &mut conn).await.map_err(Into::into);
+            if let Err(DbError::SerializationFailure) = result {
+                tx.conflicted.store(true, Ordering::Relaxed);
+            }
+            result
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Like `exec`, but for queries that only read, which go to a replica if there's a healthy one
+    ///
+    /// Inside a transaction, or on a `read_your_writes` handle, this is the same as `exec`. If the
+    /// replica turns out to be unreachable, it's taken out of rotation and the query is run on
+    /// the primary instead
+    pub(super) async fn read<Q, T, E, F>(&self, query: Q, f: F) -> Result<T, DbError>
+    where
+        Q: DbQuery + Clone + 'static,
+        E: Into<DbError>,
+        F: for<'c> Fn(Q, &'c mut AsyncPgConnection) -> BoxFuture<'c, Result<T, E>>,
+    {
+        if self.tx.is_none() && !self.primary_only {
+            if let Some(replica) = self.replicas.pick() {
+                match self.exec_on_replica(replica, query.clone(), &f).await {
+                    Err(e) if is_unavailable(&e) => replica.mark_down(&e),
+                    result => return result,
+                }
+            }
+        }
 
-        Ok(Self { pool })
+        self.exec(query, f).await
     }
 
-    pub async fn exec<T, F>(&self, f: F) -> Result<T, DbError>
+    async fn exec_on_replica<Q, T, E, F>(
+        &self,
+        replica: &Replica,
+        query: Q,
+        f: &F,
+    ) -> Result<T, DbError>
     where
-        T: Send + 'static,
-        F: Send
-            + 'static
-            + FnOnce(&mut PooledConnection<ConnectionManager<PgConnection>>) -> QueryResult<T>,
+        Q: DbQuery + 'static,
+        E: Into<DbError>,
+        F: for<'c> Fn(Q, &'c mut AsyncPgConnection) -> BoxFuture<'c, Result<T, E>>,
     {
-        let mut conn = self.pool.get()?;
-        let result = tokio::task::spawn_blocking(move || f(&mut conn)).await??;
+        let span = query_span(&query, &replica.name);
+
+        async move {
+            let start = Instant::now();
+            let conn = tokio::time::timeout(REPLICA_TIMEOUT, replica.pool.get()).await;
+            self.metrics.record_pool_wait(start.elapsed());
+
+            let mut conn = conn.map_err(|_| DbError::PoolTimeout)??;
+            f(query, &mut conn).await.map_err(Into::into)
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// A handle that sends everything to the primary, even reads
+    pub(super) fn primary_only(&self) -> Self {
+        Self {
+            primary_only: true,
+            ..self.clone()
+        }
+    }
+
+    /// Start a serializable transaction, returning a handle that runs queries inside it
+    pub(super) async fn begin(&self) -> Result<Self, DbError> {
+        let mut conn = self.conn().await?;
+        AnsiTransactionManager::begin_transaction_sql(
+            &mut *conn,
+            "BEGIN TRANSACTION ISOLATION LEVEL SERIALIZABLE",
+        )
+        .await?;
+
+        let tx = TxConn {
+            conn: Mutex::new(conn),
+            conflicted: AtomicBool::new(false),
+        };
+
+        Ok(Self {
+            tx: Some(Arc::new(tx)),
+            ..self.clone()
+        })
+    }
+
+    pub(super) async fn commit(&self) -> Result<(), DbError> {
+        let tx = self.tx.as_ref().expect("not in a transaction");
+        let mut conn = tx.conn.lock().await;
+        AnsiTransactionManager::commit_transaction(&mut **conn).await?;
+        Ok(())
+    }
 
-        Ok(result)
+    pub(super) async fn rollback(&self) -> Result<(), DbError> {
+        let tx = self.tx.as_ref().expect("not in a transaction");
+        let mut conn = tx.conn.lock().await;
+        AnsiTransactionManager::rollback_transaction(&mut **conn).await?;
+        Ok(())
     }
+
+    /// Whether a query in this transaction failed because of a concurrent transaction
+    pub(super) fn conflicted(&self) -> bool {
+        self.tx
+            .as_ref()
+            .is_some_and(|tx| tx.conflicted.load(Ordering::Relaxed))
+    }
+
+    /// Run raw SQL, which may contain several statements, for setting up test databases
+    #[cfg(test)]
+    pub(super) async fn batch_execute(&self, sql: &str) -> Result<(), DbError> {
+        use diesel_async::SimpleAsyncConnection;
+
+        self.conn().await?.batch_execute(sql).await?;
+        Ok(())
+    }
+
+    async fn conn(&self) -> Result<PgConn, DbError> {
+        let start = Instant::now();
+        let conn = self.pool.get().await;
+        self.metrics.record_pool_wait(start.elapsed());
+
+        Ok(conn?)
+    }
+}
+
+/// A connection pool for one database server, kept topped up in the background
+fn build_pool(config: &DbConfig, name: &str, metrics: &Metrics) -> Result<PgPool> {
+    let url = config.url()?;
+    let tls = config.tls_connector()?;
+
+    let manager = AsyncDieselConnectionManager::new_with_setup(url, move |url| {
+        let tls = tls.clone();
+        let url = url.to_string();
+        async move {
+            let (client, connection) = tokio_postgres::connect(&url, tls)
+                .await
+                .map_err(|e| ConnectionError::BadConnection(e.to_string()))?;
+            tokio::spawn(async move {
+                if let Err(e) = connection.await {
+                    error!("database connection error: {e}");
+                }
+            });
+            AsyncPgConnection::try_from(client).await
+        }
+        .boxed()
+    });
+
+    let max_lifetime = config.max_lifetime();
+    let pool = Pool::builder(manager)
+        .max_size(config.max_pool_size)
+        .wait_timeout(Some(config.connection_timeout()))
+        .create_timeout(Some(config.connection_timeout()))
+        .runtime(Runtime::Tokio1)
+        .pre_recycle(Hook::sync_fn(move |_, metrics| match max_lifetime {
+            Some(max_lifetime) if metrics.age() > max_lifetime => Err(HookError::Continue(None)),
+            _ => Ok(()),
+        }))
+        // a transaction that was dropped part way through leaves its connection inside it
+        .pre_recycle(Hook::sync_fn(|conn, _| {
+            match AnsiTransactionManager::transaction_manager_status_mut(conn).transaction_depth() {
+                Ok(None) => Ok(()),
+                _ => Err(HookError::Continue(None)),
+            }
+        }))
+        .build()?;
+
+    metrics.register(Box::new(PoolCollector::new(pool.clone(), name)?))?;
+    tokio::spawn(maintain(pool.clone(), config.clone()));
+
+    Ok(pool)
 }
 
-pub(super) trait DbQuery<'a, T>
+fn query_span<Q: DbQuery>(query: &Q, pool: &str) -> tracing::Span {
+    info_span!(
+        "db.query",
+        otel.kind = "client",
+        db.system = "postgresql",
+        db.pool = pool,
+        db.statement = %query.sql_string(),
+    )
+}
+
+/// Config for the database named by `DATABASE_URL`, for tests that need a real database
+///
+/// Those tests are skipped when it isn't set
+#[cfg(test)]
+pub fn test_config() -> Option<DbConfig> {
+    let url = std::env::var("DATABASE_URL").ok()?;
+    Some(DbConfig::from_target(config::DbTarget::Url { url }))
+}
+
+/// Periodically close connections that have been idle or open for too long, while keeping at
+/// least `min_pool_size` connections open
+async fn maintain(pool: PgPool, config: DbConfig) {
+    let idle_timeout = config.idle_timeout();
+    let max_lifetime = config.max_lifetime();
+    let mut interval = tokio::time::interval(MAINTENANCE_INTERVAL);
+
+    while !pool.is_closed() {
+        // the first tick completes immediately, which warms the pool up on startup
+        interval.tick().await;
+
+        let kept = Cell::new(0);
+        pool.retain(|_, metrics| {
+            let expired = max_lifetime.is_some_and(|max| metrics.age() > max);
+            let idle = idle_timeout.is_some_and(|max| metrics.last_used() > max);
+            let keep = !expired && (!idle || kept.get() < config.min_pool_size);
+            if keep {
+                kept.set(kept.get() + 1);
+            }
+            keep
+        });
+
+        // deadpool only opens connections on demand, so demand enough of them at once to reach
+        // the minimum, counting the idle ones we'd be handed first
+        let status = pool.status();
+        let missing = config.min_pool_size.saturating_sub(status.size);
+        if missing > 0 {
+            let idle = status.available.max(0) as usize;
+            let conns = join_all((0..idle + missing).map(|_| pool.get())).await;
+            if let Some(Err(e)) = conns.into_iter().find(Result::is_err) {
+                warn!("failed to open minimum database connections: {e}");
+            }
+        }
+    }
+}
+
+/// Samples the connection pool's state whenever metrics are scraped
+struct PoolCollector {
+    pool: PgPool,
+    connections: IntGaugeVec,
+}
+
+impl PoolCollector {
+    fn new(pool: PgPool, name: &str) -> Result<Self> {
+        let connections = IntGaugeVec::new(
+            Opts::new("db_pool_connections", "Database connections by state")
+                .const_label("pool", name),
+            &["state"],
+        )?;
+
+        Ok(Self { pool, connections })
+    }
+}
+
+impl Collector for PoolCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.connections.desc()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let status = self.pool.status();
+        // `available` goes negative when there are tasks waiting for a connection
+        let idle = status.available.max(0);
+        let waiting = (-status.available).max(0);
+        let in_use = status.size as isize - idle;
+
+        for (state, value) in [("idle", idle), ("in_use", in_use), ("waiting", waiting)] {
+            self.connections
+                .with_label_values(&[state])
+                .set(value as i64);
+        }
+
+        self.connections.collect()
+    }
+}
+
+pub(super) trait DbQuery<DB = Pg>: QueryFragment<DB> + Send
 where
-    Self: LoadQuery<'a, PgConnection, T> + QueryFragment<Pg> + Send + 'a,
+    DB: Backend + Default,
+    DB::QueryBuilder: Default,
 {
+    /// The SQL text of this query, with placeholders in place of bind parameters
+    ///
+    /// Bind values are left out so that this is safe to log
     fn sql_string(&self) -> String {
-        let debug = diesel::debug_query(self);
-        format!("{debug}")
+        let mut builder = DB::QueryBuilder::default();
+        match self.to_sql(&mut builder, &DB::default()) {
+            Ok(()) => builder.finish(),
+            Err(e) => format!("<unprintable query: {e}>"),
+        }
     }
 }
 
-impl<'a, T, S> DbQuery<'a, T> for S where
-    S: LoadQuery<'a, PgConnection, T> + QueryFragment<Pg> + Send + 'a
+impl<S, DB> DbQuery<DB> for S
+where
+    S: QueryFragment<DB> + Send,
+    DB: Backend + Default,
+    DB::QueryBuilder: Default,
 {
 }
 
@@ -84,34 +427,175 @@ pub enum DbError {
     #[error("db error: {0}")]
     Db(diesel::result::Error), // no #[from] here, since we want to capture some diesel errors
     #[error("pool error: {0}")]
-    Pool(#[from] diesel::r2d2::PoolError),
-    #[error("error joining thread: {0}")]
-    Join(#[from] tokio::task::JoinError),
+    Pool(PoolError), // no #[from] here either, timeouts get their own variant
+    #[error("timed out waiting for a database connection")]
+    PoolTimeout,
     #[error("entity already exists")]
     AlreadyExists {
         table: Option<String>,
         col: Option<String>,
     },
+    #[error("migration error: {0}")]
+    Migration(Box<dyn std::error::Error + Send + Sync>),
+    #[error("snapshot error: {0}")]
+    Snapshot(Box<dyn std::error::Error + Send + Sync>),
+    #[error("transaction aborted by a concurrent transaction")]
+    SerializationFailure,
     #[error("rows modified, expected: {expected}, actual: {actual}")]
     RowsModified { expected: usize, actual: usize },
 }
 
+impl DbError {
+    /// `Ok` if `actual` rows were modified as `expected`, and `RowsModified` otherwise
+    pub fn check_rows_modified(expected: usize, actual: usize) -> Result<(), Self> {
+        match actual == expected {
+            true => Ok(()),
+            false => Err(Self::RowsModified { expected, actual }),
+        }
+    }
+}
+
+impl From<PoolError> for DbError {
+    fn from(e: PoolError) -> Self {
+        match e {
+            PoolError::Timeout(TimeoutType::Wait) => Self::PoolTimeout,
+            e => Self::Pool(e),
+        }
+    }
+}
+
 mod diesel_impl {
     use diesel::result::{DatabaseErrorKind, Error};
 
     use super::DbError;
 
+    /// SQLite only names the table and column in its message, e.g.
+    /// `UNIQUE constraint failed: users.email`
+    fn sqlite_unique_violation(message: &str) -> DbError {
+        let target = message
+            .strip_prefix("UNIQUE constraint failed: ")
+            .and_then(|columns| columns.split(", ").next())
+            .and_then(|column| column.split_once('.'));
+
+        DbError::AlreadyExists {
+            table: target.map(|(table, _)| table.into()),
+            col: target.map(|(_, col)| col.into()),
+        }
+    }
+
     impl From<Error> for DbError {
         fn from(e: Error) -> Self {
             match e {
                 Error::DatabaseError(DatabaseErrorKind::UniqueViolation, info) => {
-                    Self::AlreadyExists {
-                        table: info.table_name().map(From::from),
-                        col: info.column_name().map(From::from),
+                    match info.table_name() {
+                        Some(table) => Self::AlreadyExists {
+                            table: Some(table.into()),
+                            col: info.column_name().map(From::from),
+                        },
+                        None => sqlite_unique_violation(info.message()),
                     }
                 }
+                Error::DatabaseError(DatabaseErrorKind::SerializationFailure, _) => {
+                    Self::SerializationFailure
+                }
                 e => Self::Db(e),
             }
         }
     }
 }
+
+/// Load tests against a real database, which only run if `DATABASE_URL` is set
+#[cfg(test)]
+mod load_tests {
+    use diesel::sql_query;
+    use diesel_async::RunQueryDsl;
+
+    use super::*;
+
+    fn db(max_pool_size: usize, connection_timeout_seconds: u64) -> Option<Arc<SqlDb>> {
+        let config = DbConfig {
+            max_pool_size,
+            connection_timeout_seconds,
+            ..test_config()?
+        };
+        let metrics = Arc::new(Metrics::new().unwrap());
+        let db = SqlDb::connect(config, metrics).unwrap();
+        Some(Arc::new(db))
+    }
+
+    async fn sleep(db: &SqlDb, seconds: f64) -> Result<(), DbError> {
+        let query = sql_query(format!("SELECT pg_sleep({seconds})"));
+        db.exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn saturated_pool_times_out() {
+        let Some(db) = db(2, 1) else {
+            return;
+        };
+
+        let holders: Vec<_> = (0..2)
+            .map(|_| {
+                let db = db.clone();
+                tokio::spawn(async move { sleep(&db, 2.0).await })
+            })
+            .collect();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let result = sleep(&db, 0.0).await;
+        assert!(matches!(result, Err(DbError::PoolTimeout)));
+
+        for holder in holders {
+            holder.await.unwrap().unwrap();
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn throughput_under_saturation() {
+        const QUERIES: u32 = 200;
+        const POOL_SIZE: usize = 4;
+        const QUERY_TIME: Duration = Duration::from_millis(10);
+
+        let Some(db) = db(POOL_SIZE, 30) else {
+            return;
+        };
+
+        // a query costs more than its sleep, so time a few one after another to compare against,
+        // which also opens the connections ahead of time
+        let start = Instant::now();
+        for _ in 0..10 {
+            sleep(&db, QUERY_TIME.as_secs_f64()).await.unwrap();
+        }
+        let serial = start.elapsed() / 10 * QUERIES;
+
+        // measures how late a timer fires while the pool is saturated; waiting for a connection
+        // must not tie up runtime threads, so this should stay small
+        let ticker = tokio::spawn(async {
+            let mut max_lag = Duration::ZERO;
+            for _ in 0..20 {
+                let start = Instant::now();
+                tokio::time::sleep(QUERY_TIME).await;
+                max_lag = max_lag.max(start.elapsed() - QUERY_TIME);
+            }
+            max_lag
+        });
+
+        let start = Instant::now();
+        let queries = (0..QUERIES).map(|_| sleep(&db, QUERY_TIME.as_secs_f64()));
+        let results = join_all(queries).await;
+        let elapsed = start.elapsed();
+        let max_lag = ticker.await.unwrap();
+
+        assert!(results.iter().all(Result::is_ok));
+        assert!(
+            elapsed < serial / 2,
+            "{QUERIES} queries over {POOL_SIZE} connections took {elapsed:?}, serially {serial:?}"
+        );
+        assert!(
+            max_lag < Duration::from_millis(50),
+            "timer fired {max_lag:?} late while the pool was saturated"
+        );
+    }
+}
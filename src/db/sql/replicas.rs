@@ -0,0 +1,210 @@
+use std::{
+    fmt::Display,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use diesel::sql_query;
+use futures::future::join_all;
+use tokio::time::timeout;
+
+use super::{DbError, PgPool};
+
+/// How often every replica is checked, whether it's currently up or down
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long to wait on a replica, for a health check or a connection, before giving up on it
+pub(super) const REPLICA_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Read replicas, which reads are spread across for as long as they pass health checks
+pub(super) struct Replicas {
+    replicas: Vec<Replica>,
+    /// Where to start looking for a healthy replica, so that reads take turns
+    next: AtomicUsize,
+}
+
+pub(super) struct Replica {
+    pub name: String,
+    pub pool: PgPool,
+    /// Starts out false, so nothing is sent to a replica until it has passed a check
+    healthy: AtomicBool,
+}
+
+impl Replicas {
+    pub fn new(pools: Vec<(String, PgPool)>) -> Self {
+        let replicas = pools
+            .into_iter()
+            .map(|(name, pool)| Replica {
+                name,
+                pool,
+                healthy: AtomicBool::new(false),
+            })
+            .collect();
+
+        Self {
+            replicas,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.replicas.is_empty()
+    }
+
+    /// The next healthy replica in turn, if there is one
+    pub fn pick(&self) -> Option<&Replica> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let len = self.replicas.len();
+
+        (0..len)
+            .map(|offset| &self.replicas[(start + offset) % len])
+            .find(|replica| replica.is_healthy())
+    }
+
+    /// Check every replica, taking them in or out of rotation accordingly
+    pub async fn check(&self) {
+        join_all(self.replicas.iter().map(Replica::check)).await;
+    }
+
+    /// Keep checking the replicas until their pools are closed
+    pub async fn monitor(&self) {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+
+        while !self.replicas.iter().all(|replica| replica.pool.is_closed()) {
+            interval.tick().await;
+            self.check().await;
+        }
+    }
+}
+
+impl Replica {
+    fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    async fn check(&self) {
+        // brought in here, since its `load` would otherwise shadow `AtomicBool::load`
+        use diesel_async::RunQueryDsl;
+
+        let ping = async {
+            let mut conn = self.pool.get().await?;
+            sql_query("SELECT 1").execute(&mut conn).await?;
+            Ok::<_, DbError>(())
+        };
+
+        match timeout(REPLICA_TIMEOUT, ping).await {
+            Ok(Ok(())) => self.mark_up(),
+            Ok(Err(e)) => self.mark_down(&e),
+            Err(_) => self.mark_down(&"health check timed out"),
+        }
+    }
+
+    fn mark_up(&self) {
+        if !self.healthy.swap(true, Ordering::Relaxed) {
+            info!(replica = %self.name, "read replica is up");
+        }
+    }
+
+    /// Take this replica out of rotation until it passes a health check again
+    pub fn mark_down(&self, reason: &dyn Display) {
+        if self.healthy.swap(false, Ordering::Relaxed) {
+            warn!(replica = %self.name, "read replica is down, failing over to the primary: {reason}");
+        }
+    }
+}
+
+/// Whether an error means the database couldn't be reached, rather than that the query failed
+pub(super) fn is_unavailable(e: &DbError) -> bool {
+    use diesel::result::{DatabaseErrorKind, Error};
+
+    matches!(
+        e,
+        DbError::Pool(_)
+            | DbError::PoolTimeout
+            | DbError::Db(Error::DatabaseError(DatabaseErrorKind::ClosedConnection, _))
+    )
+}
+
+/// Only run if `DATABASE_URL` is set, using that database as both the primary and its replica
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use diesel::{sql_types::Text, QueryableByName};
+    use diesel_async::RunQueryDsl;
+    use futures::FutureExt;
+    use url::Url;
+
+    use crate::{
+        db::sql::{config::DbTarget, test_config, DbConfig, SqlDb},
+        state::metrics::Metrics,
+    };
+
+    use super::*;
+
+    #[derive(QueryableByName)]
+    struct Setting {
+        #[diesel(sql_type = Text)]
+        value: String,
+    }
+
+    /// Which pool served a read, told apart by the `application_name` each one connects with
+    async fn read_from(db: &SqlDb) -> String {
+        let query = sql_query("SELECT current_setting('application_name') AS value");
+        let setting = db
+            .read(query, |query, conn| {
+                async move { query.get_result::<Setting>(conn).await }.boxed()
+            })
+            .await
+            .unwrap();
+
+        setting.value
+    }
+
+    fn db(replica: DbTarget) -> SqlDb {
+        let config = DbConfig {
+            replicas: vec![replica],
+            application_name: Some("primary".into()),
+            ..test_config().unwrap()
+        };
+        SqlDb::connect(config, Arc::new(Metrics::new().unwrap())).unwrap()
+    }
+
+    #[tokio::test]
+    async fn reads_go_to_healthy_replicas() {
+        let Ok(url) = std::env::var("DATABASE_URL") else {
+            return;
+        };
+        let mut url = Url::parse(&url).unwrap();
+        url.query_pairs_mut()
+            .append_pair("application_name", "replica");
+        let db = db(DbTarget::Url { url: url.into() });
+
+        // not until the replica has passed a check
+        assert_eq!(read_from(&db).await, "primary");
+
+        db.replicas.check().await;
+        assert_eq!(read_from(&db).await, "replica");
+        assert_eq!(read_from(&db.primary_only()).await, "primary");
+    }
+
+    #[tokio::test]
+    async fn fails_over_to_the_primary() {
+        if test_config().is_none() {
+            return;
+        }
+        let db = db(DbTarget::Url {
+            url: "postgres://postgres@127.0.0.1:1/unreachable".into(),
+        });
+        let replica = &db.replicas.replicas[0];
+
+        // as if it had gone down since it was last checked
+        replica.mark_up();
+        assert_eq!(read_from(&db).await, "primary");
+        assert!(!replica.is_healthy());
+
+        replica.mark_up();
+        db.replicas.check().await;
+        assert!(!replica.is_healthy());
+    }
+}
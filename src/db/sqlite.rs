@@ -0,0 +1,247 @@
+use std::{
+    fmt::Debug,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use color_eyre::{eyre::eyre, Result};
+use diesel::{
+    connection::{AnsiTransactionManager, SimpleConnection, TransactionManager},
+    sql_query,
+    sqlite::Sqlite,
+    Connection, QueryResult, RunQueryDsl, SqliteConnection,
+};
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+use tracing::Instrument;
+
+use super::{
+    sql::{DbError, DbQuery},
+    transaction::{ErasedBody, ErasedResult},
+    Db,
+};
+
+mod api_keys;
+mod audit;
+mod email_changes;
+mod identities;
+mod magic_links;
+mod oauth;
+mod organizations;
+mod password_resets;
+mod schema;
+mod sessions;
+mod users;
+
+/// Kept in lockstep with the Postgres migrations, see `migrations_match_postgres`
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("src/db/sqlite/migrations");
+
+/// Settings applied to the connection when it's opened
+///
+/// SQLite leaves foreign keys unenforced unless asked, and would otherwise fail immediately if
+/// another process holds the write lock
+const PRAGMAS: &str =
+    "PRAGMA foreign_keys = ON; PRAGMA journal_mode = WAL; PRAGMA busy_timeout = 5000;";
+
+/// A database stored in a single SQLite file, for local development and small deployments
+///
+/// SQLite only allows one writer at a time anyway, so everything shares one connection. Queries
+/// run on the blocking thread pool, and take turns through `gate`, which a transaction holds for
+/// as long as it's open
+#[derive(Clone)]
+pub struct SqliteDb {
+    conn: Arc<Mutex<SqliteConnection>>,
+    gate: Arc<AsyncMutex<()>>,
+    /// Set on handles given out by `Db::transaction`
+    tx: Option<Arc<SqliteTx>>,
+}
+
+impl Debug for SqliteDb {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SqliteDb")
+            .field("in_transaction", &self.tx.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl SqliteDb {
+    /// Open (or create) the database at `path`, and bring it up to date with the migrations
+    pub fn connect(path: &Path) -> Result<Self> {
+        let path = path
+            .to_str()
+            .ok_or_else(|| eyre!("sqlite path must be valid unicode"))?;
+
+        let mut conn = SqliteConnection::establish(path)?;
+        conn.batch_execute(PRAGMAS)?;
+        conn.run_pending_migrations(MIGRATIONS)
+            .map_err(|e| eyre!("failed to run sqlite migrations: {e}"))?;
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+            gate: Arc::new(AsyncMutex::new(())),
+            tx: None,
+        })
+    }
+
+    /// Run a query on the connection, inside a span recording its SQL
+    ///
+    /// `f` receives the query back along with the connection, and decides how to run it
+    pub(super) async fn exec<Q, T, F>(&self, query: Q, f: F) -> Result<T, DbError>
+    where
+        Q: DbQuery<Sqlite> + 'static,
+        T: Send + 'static,
+        F: FnOnce(Q, &mut SqliteConnection) -> QueryResult<T> + Send + 'static,
+    {
+        let span = info_span!(
+            "db.query",
+            otel.kind = "client",
+            db.system = "sqlite",
+            db.statement = %query.sql_string(),
+        );
+
+        self.with_conn(move |conn| f(query, conn).map_err(Into::into))
+            .instrument(span)
+            .await
+    }
+
+    /// Run `f` with the connection on the blocking thread pool, once it's our turn
+    async fn with_conn<T, F>(&self, f: F) -> Result<T, DbError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut SqliteConnection) -> Result<T, DbError> + Send + 'static,
+    {
+        // inside a transaction, the handle already holds the gate
+        let _turn = match &self.tx {
+            Some(_) => None,
+            None => Some(self.gate.lock().await),
+        };
+
+        let conn = self.conn.clone();
+        let task = tokio::task::spawn_blocking(move || f(&mut conn.lock().unwrap()));
+
+        match task.await {
+            Ok(result) => result,
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+
+    async fn begin(&self) -> Result<Self, DbError> {
+        let turn = self.gate.clone().lock_owned().await;
+
+        let tx = Self {
+            conn: self.conn.clone(),
+            gate: self.gate.clone(),
+            tx: Some(Arc::new(SqliteTx {
+                conn: self.conn.clone(),
+                _turn: turn,
+            })),
+        };
+
+        // take the write lock up front, so the transaction can't fail part way through because
+        // another process wrote in the meantime
+        tx.with_conn(|conn| {
+            AnsiTransactionManager::begin_transaction_sql(conn, "BEGIN IMMEDIATE")?;
+            Ok(())
+        })
+        .await?;
+
+        Ok(tx)
+    }
+
+    async fn commit(&self) -> Result<(), DbError> {
+        self.with_conn(|conn| Ok(AnsiTransactionManager::commit_transaction(conn)?))
+            .await
+    }
+
+    async fn rollback(&self) -> Result<(), DbError> {
+        self.with_conn(|conn| Ok(AnsiTransactionManager::rollback_transaction(conn)?))
+            .await
+    }
+}
+
+/// Marks a transaction as open, and rolls it back if it's dropped before finishing
+struct SqliteTx {
+    conn: Arc<Mutex<SqliteConnection>>,
+    _turn: OwnedMutexGuard<()>,
+}
+
+impl Drop for SqliteTx {
+    fn drop(&mut self) {
+        let mut conn = self.conn.lock().unwrap_or_else(|e| e.into_inner());
+        if let Ok(Some(_)) =
+            AnsiTransactionManager::transaction_manager_status_mut(&mut *conn).transaction_depth()
+        {
+            if let Err(e) = AnsiTransactionManager::rollback_transaction(&mut *conn) {
+                error!("failed to roll back abandoned sqlite transaction: {e}");
+            }
+        }
+    }
+}
+
+#[axum::async_trait]
+impl Db for SqliteDb {
+    async fn ping(&self) -> Result<(), DbError> {
+        self.exec(sql_query("SELECT 1"), |query, conn| query.execute(conn))
+            .await?;
+        Ok(())
+    }
+
+    async fn has_pending_migrations(&self) -> Result<bool, DbError> {
+        self.with_conn(|conn| {
+            conn.has_pending_migration(MIGRATIONS)
+                .map_err(DbError::Migration)
+        })
+        .await
+    }
+
+    /// SQLite transactions never conflict, since they take turns, so they're never retried
+    async fn run_transaction(&self, body: &mut ErasedBody<'_>) -> Result<ErasedResult, DbError> {
+        let tx = self.begin().await?;
+
+        match body(&tx).await {
+            Ok(value) => tx.commit().await.map(|()| Ok(value)),
+            Err(e) => tx.rollback().await.map(|()| Err(e)),
+        }
+    }
+
+    /// There are no replicas, so every read already sees every write
+    fn read_your_writes(&self) -> Arc<dyn Db> {
+        Arc::new(self.clone())
+    }
+
+    #[cfg(test)]
+    fn as_memory(&self) -> super::memory::MemoryDb {
+        unimplemented!()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::{migration::MigrationSource, pg::Pg};
+
+    use super::*;
+
+    #[test]
+    fn migrations_match_postgres() {
+        fn versions<DB: diesel::backend::Backend>(migrations: &EmbeddedMigrations) -> Vec<String> {
+            MigrationSource::<DB>::migrations(migrations)
+                .unwrap()
+                .iter()
+                .map(|migration| migration.name().version().to_string())
+                .collect()
+        }
+
+        assert_eq!(
+            versions::<Sqlite>(&MIGRATIONS),
+            versions::<Pg>(&crate::db::MIGRATIONS),
+        );
+    }
+
+    #[tokio::test]
+    async fn connects_and_migrates() {
+        let db = SqliteDb::connect(Path::new(":memory:")).unwrap();
+
+        db.ping().await.unwrap();
+        assert!(!db.has_pending_migrations().await.unwrap());
+    }
+}
@@ -0,0 +1,118 @@
+use chrono::{DateTime, Utc};
+use diesel::{
+    delete, insert_into, update, ExpressionMethods, Insertable, OptionalExtension, QueryDsl,
+    Queryable, RunQueryDsl,
+};
+use uuid::Uuid;
+
+use crate::{
+    db::{api_keys::ApiKeyDao, sql::DbError},
+    model::{api_key::ApiKey, oauth::Scopes, types::UserId},
+};
+
+use super::{schema::api_keys, SqliteDb};
+
+/// An `ApiKey` as stored in SQLite, which has no UUID type
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = api_keys)]
+struct ApiKeyRow {
+    id: String,
+    user_id: String,
+    name: String,
+    prefix: String,
+    key_hash: String,
+    scopes: Scopes,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    last_used_at: Option<DateTime<Utc>>,
+}
+
+impl From<ApiKey> for ApiKeyRow {
+    fn from(key: ApiKey) -> Self {
+        Self {
+            id: key.id.to_string(),
+            user_id: key.user_id.0.to_string(),
+            name: key.name,
+            prefix: key.prefix,
+            key_hash: key.key_hash,
+            scopes: key.scopes,
+            created_at: key.created_at,
+            expires_at: key.expires_at,
+            last_used_at: key.last_used_at,
+        }
+    }
+}
+
+impl TryFrom<ApiKeyRow> for ApiKey {
+    type Error = DbError;
+
+    fn try_from(row: ApiKeyRow) -> Result<Self, Self::Error> {
+        let parse = |id: &str| {
+            Uuid::parse_str(id)
+                .map_err(|e| DbError::Db(diesel::result::Error::DeserializationError(e.into())))
+        };
+
+        Ok(Self {
+            id: parse(&row.id)?,
+            user_id: UserId(parse(&row.user_id)?),
+            name: row.name,
+            prefix: row.prefix,
+            key_hash: row.key_hash,
+            scopes: row.scopes,
+            created_at: row.created_at,
+            expires_at: row.expires_at,
+            last_used_at: row.last_used_at,
+        })
+    }
+}
+
+#[axum::async_trait]
+impl ApiKeyDao for SqliteDb {
+    async fn create_api_key(&self, key: ApiKey) -> Result<(), DbError> {
+        let query = insert_into(api_keys::table).values(ApiKeyRow::from(key));
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn api_key_by_prefix(&self, prefix: String) -> Result<Option<ApiKey>, DbError> {
+        let query = api_keys::table.filter(api_keys::prefix.eq(prefix)).limit(1);
+        let row: Option<ApiKeyRow> = self
+            .exec(query, |query, conn| query.get_result(conn).optional())
+            .await?;
+
+        row.map(ApiKey::try_from).transpose()
+    }
+
+    async fn api_keys_for_user(&self, user_id: UserId) -> Result<Vec<ApiKey>, DbError> {
+        let query = api_keys::table
+            .filter(api_keys::user_id.eq(user_id.0.to_string()))
+            .order((api_keys::created_at, api_keys::id));
+        let rows: Vec<ApiKeyRow> = self.exec(query, |query, conn| query.load(conn)).await?;
+
+        rows.into_iter().map(ApiKey::try_from).collect()
+    }
+
+    async fn touch_api_key(&self, id: Uuid, at: DateTime<Utc>) -> Result<(), DbError> {
+        let query = update(api_keys::table.filter(api_keys::id.eq(id.to_string())))
+            .set(api_keys::last_used_at.eq(at));
+        self.exec(query, |query, conn| query.execute(conn)).await?;
+        Ok(())
+    }
+
+    async fn delete_api_key(&self, user_id: UserId, id: Uuid) -> Result<(), DbError> {
+        let query = delete(
+            api_keys::table
+                .filter(api_keys::id.eq(id.to_string()))
+                .filter(api_keys::user_id.eq(user_id.0.to_string())),
+        );
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn delete_api_keys_for_user(&self, user_id: UserId) -> Result<usize, DbError> {
+        let query = delete(api_keys::table.filter(api_keys::user_id.eq(user_id.0.to_string())));
+        self.exec(query, |query, conn| query.execute(conn)).await
+    }
+}
@@ -0,0 +1,139 @@
+use chrono::{DateTime, Utc};
+use diesel::{
+    insert_into, update, BoolExpressionMethods, ExpressionMethods, Insertable, QueryDsl, Queryable,
+    RunQueryDsl,
+};
+use uuid::Uuid;
+
+use crate::{
+    db::{
+        audit::{AuditDao, AuditFilter},
+        sql::DbError,
+    },
+    model::{
+        audit::{AuditAction, AuditEvent},
+        types::UserId,
+    },
+};
+
+use super::{schema::audit_events, SqliteDb};
+
+/// An `AuditEvent` as stored in SQLite, which has no UUID type
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = audit_events)]
+struct AuditEventRow {
+    id: String,
+    occurred_at: DateTime<Utc>,
+    actor_id: Option<String>,
+    subject_id: Option<String>,
+    action: AuditAction,
+    ip: Option<String>,
+    user_agent: Option<String>,
+    request_id: Option<String>,
+}
+
+impl From<AuditEvent> for AuditEventRow {
+    fn from(event: AuditEvent) -> Self {
+        Self {
+            id: event.id.to_string(),
+            occurred_at: event.occurred_at,
+            actor_id: event.actor_id.map(|id| id.0.to_string()),
+            subject_id: event.subject_id.map(|id| id.0.to_string()),
+            action: event.action,
+            ip: event.ip,
+            user_agent: event.user_agent,
+            request_id: event.request_id,
+        }
+    }
+}
+
+impl TryFrom<AuditEventRow> for AuditEvent {
+    type Error = DbError;
+
+    fn try_from(row: AuditEventRow) -> Result<Self, Self::Error> {
+        let parse = |id: &str| {
+            Uuid::parse_str(id)
+                .map_err(|e| DbError::Db(diesel::result::Error::DeserializationError(e.into())))
+        };
+        let user_id = |id: Option<String>| {
+            id.as_deref()
+                .map(parse)
+                .transpose()
+                .map(|id| id.map(UserId))
+        };
+
+        Ok(Self {
+            id: parse(&row.id)?,
+            occurred_at: row.occurred_at,
+            actor_id: user_id(row.actor_id)?,
+            subject_id: user_id(row.subject_id)?,
+            action: row.action,
+            ip: row.ip,
+            user_agent: row.user_agent,
+            request_id: row.request_id,
+        })
+    }
+}
+
+#[axum::async_trait]
+impl AuditDao for SqliteDb {
+    async fn create_audit_event(&self, event: AuditEvent) -> Result<(), DbError> {
+        let query = insert_into(audit_events::table).values(AuditEventRow::from(event));
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn audit_events(&self, filter: AuditFilter) -> Result<Vec<AuditEvent>, DbError> {
+        let mut query = audit_events::table
+            .order((audit_events::occurred_at.desc(), audit_events::id.desc()))
+            .limit(filter.limit)
+            .into_boxed();
+
+        if let Some(user_id) = filter.user_id {
+            let user_id = user_id.0.to_string();
+            query = query.filter(
+                audit_events::actor_id
+                    .eq(user_id.clone())
+                    .or(audit_events::subject_id.eq(user_id)),
+            );
+        }
+        if let Some(action) = filter.action {
+            query = query.filter(audit_events::action.eq(action));
+        }
+        if let Some(since) = filter.since {
+            query = query.filter(audit_events::occurred_at.ge(since));
+        }
+        if let Some(until) = filter.until {
+            query = query.filter(audit_events::occurred_at.lt(until));
+        }
+
+        let rows: Vec<AuditEventRow> = self.exec(query, |query, conn| query.load(conn)).await?;
+        rows.into_iter().map(AuditEvent::try_from).collect()
+    }
+
+    async fn anonymize_audit_events(&self, user_id: UserId) -> Result<usize, DbError> {
+        let user_id = user_id.0.to_string();
+        let query = update(
+            audit_events::table.filter(
+                audit_events::actor_id
+                    .eq(user_id.clone())
+                    .or(audit_events::subject_id.eq(user_id.clone())),
+            ),
+        )
+        .set((
+            audit_events::ip.eq(None::<String>),
+            audit_events::user_agent.eq(None::<String>),
+        ));
+        let anonymized = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        let query = update(audit_events::table.filter(audit_events::actor_id.eq(user_id.clone())))
+            .set(audit_events::actor_id.eq(None::<String>));
+        self.exec(query, |query, conn| query.execute(conn)).await?;
+        let query = update(audit_events::table.filter(audit_events::subject_id.eq(user_id)))
+            .set(audit_events::subject_id.eq(None::<String>));
+        self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        Ok(anonymized)
+    }
+}
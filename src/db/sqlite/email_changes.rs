@@ -0,0 +1,126 @@
+use chrono::{DateTime, Utc};
+use diesel::{
+    delete, insert_into, ExpressionMethods, Insertable, OptionalExtension, QueryDsl, Queryable,
+    RunQueryDsl,
+};
+use uuid::Uuid;
+
+use crate::{
+    db::{email_changes::EmailChangeDao, sql::DbError},
+    model::{
+        email_change::EmailChange,
+        types::{Email, UserId},
+    },
+};
+
+use super::{schema::email_changes, SqliteDb};
+
+/// An `EmailChange` as stored in SQLite, which has no UUID type
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = email_changes)]
+struct EmailChangeRow {
+    id: String,
+    user_id: String,
+    new_email: Email,
+    confirm_token_hash: String,
+    cancel_token_hash: String,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+}
+
+impl From<EmailChange> for EmailChangeRow {
+    fn from(change: EmailChange) -> Self {
+        Self {
+            id: change.id.to_string(),
+            user_id: change.user_id.0.to_string(),
+            new_email: change.new_email,
+            confirm_token_hash: change.confirm_token_hash,
+            cancel_token_hash: change.cancel_token_hash,
+            created_at: change.created_at,
+            expires_at: change.expires_at,
+        }
+    }
+}
+
+impl TryFrom<EmailChangeRow> for EmailChange {
+    type Error = DbError;
+
+    fn try_from(row: EmailChangeRow) -> Result<Self, Self::Error> {
+        let parse = |id: &str| {
+            Uuid::parse_str(id)
+                .map_err(|e| DbError::Db(diesel::result::Error::DeserializationError(e.into())))
+        };
+
+        Ok(Self {
+            id: parse(&row.id)?,
+            user_id: UserId(parse(&row.user_id)?),
+            new_email: row.new_email,
+            confirm_token_hash: row.confirm_token_hash,
+            cancel_token_hash: row.cancel_token_hash,
+            created_at: row.created_at,
+            expires_at: row.expires_at,
+        })
+    }
+}
+
+#[axum::async_trait]
+impl EmailChangeDao for SqliteDb {
+    async fn create_email_change(&self, change: EmailChange) -> Result<(), DbError> {
+        let query = insert_into(email_changes::table).values(EmailChangeRow::from(change));
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn email_change_for_user(&self, user_id: UserId) -> Result<Option<EmailChange>, DbError> {
+        let query = email_changes::table
+            .filter(email_changes::user_id.eq(user_id.0.to_string()))
+            .limit(1);
+        let row: Option<EmailChangeRow> = self
+            .exec(query, |query, conn| query.get_result(conn).optional())
+            .await?;
+
+        row.map(EmailChange::try_from).transpose()
+    }
+
+    async fn email_change_by_confirm_token(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<EmailChange>, DbError> {
+        let query = email_changes::table
+            .filter(email_changes::confirm_token_hash.eq(token_hash))
+            .limit(1);
+        let row: Option<EmailChangeRow> = self
+            .exec(query, |query, conn| query.get_result(conn).optional())
+            .await?;
+
+        row.map(EmailChange::try_from).transpose()
+    }
+
+    async fn email_change_by_cancel_token(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<EmailChange>, DbError> {
+        let query = email_changes::table
+            .filter(email_changes::cancel_token_hash.eq(token_hash))
+            .limit(1);
+        let row: Option<EmailChangeRow> = self
+            .exec(query, |query, conn| query.get_result(conn).optional())
+            .await?;
+
+        row.map(EmailChange::try_from).transpose()
+    }
+
+    async fn delete_email_change(&self, id: Uuid) -> Result<(), DbError> {
+        let query = delete(email_changes::table.filter(email_changes::id.eq(id.to_string())));
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn delete_email_changes_for_user(&self, user_id: UserId) -> Result<usize, DbError> {
+        let query =
+            delete(email_changes::table.filter(email_changes::user_id.eq(user_id.0.to_string())));
+        self.exec(query, |query, conn| query.execute(conn)).await
+    }
+}
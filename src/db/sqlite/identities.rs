@@ -0,0 +1,114 @@
+use chrono::{DateTime, Utc};
+use diesel::{
+    delete, insert_into, ExpressionMethods, Insertable, OptionalExtension, QueryDsl, Queryable,
+    RunQueryDsl,
+};
+use uuid::Uuid;
+
+use crate::{
+    db::{identities::IdentityDao, sql::DbError},
+    model::{
+        identity::UserIdentity,
+        types::{Email, UserId},
+    },
+};
+
+use super::{schema::user_identities, SqliteDb};
+
+/// A `UserIdentity` as stored in SQLite, which has no UUID type
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = user_identities)]
+struct UserIdentityRow {
+    id: String,
+    user_id: String,
+    provider: String,
+    subject: String,
+    email: Option<Email>,
+    created_at: DateTime<Utc>,
+}
+
+impl From<UserIdentity> for UserIdentityRow {
+    fn from(identity: UserIdentity) -> Self {
+        Self {
+            id: identity.id.to_string(),
+            user_id: identity.user_id.0.to_string(),
+            provider: identity.provider,
+            subject: identity.subject,
+            email: identity.email,
+            created_at: identity.created_at,
+        }
+    }
+}
+
+impl TryFrom<UserIdentityRow> for UserIdentity {
+    type Error = DbError;
+
+    fn try_from(row: UserIdentityRow) -> Result<Self, Self::Error> {
+        let parse = |id: &str| {
+            Uuid::parse_str(id)
+                .map_err(|e| DbError::Db(diesel::result::Error::DeserializationError(e.into())))
+        };
+
+        Ok(Self {
+            id: parse(&row.id)?,
+            user_id: UserId(parse(&row.user_id)?),
+            provider: row.provider,
+            subject: row.subject,
+            email: row.email,
+            created_at: row.created_at,
+        })
+    }
+}
+
+#[axum::async_trait]
+impl IdentityDao for SqliteDb {
+    async fn create_identity(&self, identity: UserIdentity) -> Result<(), DbError> {
+        let query = insert_into(user_identities::table).values(UserIdentityRow::from(identity));
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn identity_by_subject(
+        &self,
+        provider: String,
+        subject: String,
+    ) -> Result<Option<UserIdentity>, DbError> {
+        let query = user_identities::table
+            .filter(user_identities::provider.eq(provider))
+            .filter(user_identities::subject.eq(subject))
+            .limit(1);
+        let row: Option<UserIdentityRow> = self
+            .exec(query, |query, conn| query.get_result(conn).optional())
+            .await?;
+
+        row.map(UserIdentity::try_from).transpose()
+    }
+
+    async fn identities_for_user(&self, user_id: UserId) -> Result<Vec<UserIdentity>, DbError> {
+        let query = user_identities::table
+            .filter(user_identities::user_id.eq(user_id.0.to_string()))
+            .order((user_identities::created_at, user_identities::id));
+        let rows: Vec<UserIdentityRow> = self.exec(query, |query, conn| query.load(conn)).await?;
+
+        rows.into_iter().map(UserIdentity::try_from).collect()
+    }
+
+    async fn delete_identity(&self, user_id: UserId, provider: String) -> Result<(), DbError> {
+        let query = delete(
+            user_identities::table
+                .filter(user_identities::user_id.eq(user_id.0.to_string()))
+                .filter(user_identities::provider.eq(provider)),
+        );
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn delete_identities_for_user(&self, user_id: UserId) -> Result<usize, DbError> {
+        let query = delete(
+            user_identities::table.filter(user_identities::user_id.eq(user_id.0.to_string())),
+        );
+        self.exec(query, |query, conn| query.execute(conn)).await
+    }
+}
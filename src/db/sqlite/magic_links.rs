@@ -0,0 +1,93 @@
+use chrono::{DateTime, Utc};
+use diesel::{
+    delete, insert_into, ExpressionMethods, Insertable, OptionalExtension, QueryDsl, Queryable,
+    RunQueryDsl,
+};
+
+use crate::{
+    db::{magic_links::MagicLinkDao, sql::DbError},
+    model::{magic_link::MagicLink, types::Email},
+};
+
+use super::{schema::magic_links, SqliteDb};
+
+/// A `MagicLink` as stored in SQLite
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = magic_links)]
+struct MagicLinkRow {
+    token_hash: String,
+    email: Email,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+}
+
+impl From<MagicLink> for MagicLinkRow {
+    fn from(link: MagicLink) -> Self {
+        Self {
+            token_hash: link.token_hash,
+            email: link.email,
+            created_at: link.created_at,
+            expires_at: link.expires_at,
+        }
+    }
+}
+
+impl From<MagicLinkRow> for MagicLink {
+    fn from(row: MagicLinkRow) -> Self {
+        Self {
+            token_hash: row.token_hash,
+            email: row.email,
+            created_at: row.created_at,
+            expires_at: row.expires_at,
+        }
+    }
+}
+
+#[axum::async_trait]
+impl MagicLinkDao for SqliteDb {
+    async fn create_magic_link(&self, link: MagicLink) -> Result<(), DbError> {
+        let query = insert_into(magic_links::table).values(MagicLinkRow::from(link));
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn magic_link_by_token(&self, token_hash: String) -> Result<Option<MagicLink>, DbError> {
+        let query = magic_links::table
+            .filter(magic_links::token_hash.eq(token_hash))
+            .limit(1);
+        let row: Option<MagicLinkRow> = self
+            .exec(query, |query, conn| query.get_result(conn).optional())
+            .await?;
+
+        Ok(row.map(MagicLink::from))
+    }
+
+    async fn magic_link_for_email(&self, email: Email) -> Result<Option<MagicLink>, DbError> {
+        let query = magic_links::table
+            .filter(magic_links::email.eq(email))
+            .limit(1);
+        let row: Option<MagicLinkRow> = self
+            .exec(query, |query, conn| query.get_result(conn).optional())
+            .await?;
+
+        Ok(row.map(MagicLink::from))
+    }
+
+    async fn delete_magic_link(&self, token_hash: String) -> Result<(), DbError> {
+        let query = delete(magic_links::table.filter(magic_links::token_hash.eq(token_hash)));
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn delete_magic_links_for_email(&self, email: Email) -> Result<usize, DbError> {
+        let query = delete(magic_links::table.filter(magic_links::email.eq(email)));
+        self.exec(query, |query, conn| query.execute(conn)).await
+    }
+
+    async fn delete_expired_magic_links(&self, at: DateTime<Utc>) -> Result<usize, DbError> {
+        let query = delete(magic_links::table.filter(magic_links::expires_at.lt(at)));
+        self.exec(query, |query, conn| query.execute(conn)).await
+    }
+}
@@ -0,0 +1,3 @@
+-- Counterpart to the Postgres setup migration, which defines helpers for maintaining
+-- `updated_at` columns. SQLite has no stored functions, so tables that need one get their own
+-- trigger instead.
@@ -0,0 +1,4 @@
+ALTER TABLE users ADD COLUMN deleted_at TEXT;
+
+-- only deleted users are ever looked up by this, when purging them
+CREATE INDEX users_deleted_at_idx ON users (deleted_at) WHERE deleted_at IS NOT NULL;
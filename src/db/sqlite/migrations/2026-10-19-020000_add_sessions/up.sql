@@ -0,0 +1,11 @@
+-- one row per issued token, keyed by its `jti`
+CREATE TABLE sessions (
+  id TEXT PRIMARY KEY NOT NULL,
+  user_id TEXT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  created_at TEXT NOT NULL,
+  last_seen_at TEXT NOT NULL,
+  user_agent TEXT,
+  ip TEXT
+);
+
+CREATE INDEX sessions_user_id_idx ON sessions (user_id);
@@ -0,0 +1,15 @@
+-- append-only, and without foreign keys, so that events outlive the users they mention
+CREATE TABLE audit_events (
+  id TEXT PRIMARY KEY NOT NULL,
+  occurred_at TEXT NOT NULL,
+  actor_id TEXT,
+  subject_id TEXT,
+  action TEXT NOT NULL,
+  ip TEXT,
+  user_agent TEXT,
+  request_id TEXT
+);
+
+CREATE INDEX audit_events_occurred_at_idx ON audit_events (occurred_at);
+CREATE INDEX audit_events_actor_id_idx ON audit_events (actor_id, occurred_at);
+CREATE INDEX audit_events_subject_id_idx ON audit_events (subject_id, occurred_at);
@@ -0,0 +1,7 @@
+DROP TRIGGER users_set_updated_at;
+
+ALTER TABLE users DROP COLUMN display_name;
+ALTER TABLE users DROP COLUMN locale;
+ALTER TABLE users DROP COLUMN timezone;
+ALTER TABLE users DROP COLUMN avatar_url;
+ALTER TABLE users DROP COLUMN updated_at;
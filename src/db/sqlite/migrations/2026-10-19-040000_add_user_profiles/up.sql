@@ -0,0 +1,16 @@
+ALTER TABLE users ADD COLUMN display_name TEXT;
+ALTER TABLE users ADD COLUMN locale TEXT;
+ALTER TABLE users ADD COLUMN timezone TEXT;
+ALTER TABLE users ADD COLUMN avatar_url TEXT;
+-- SQLite won't add a column defaulting to the current time, so existing rows are filled in below
+ALTER TABLE users ADD COLUMN updated_at TEXT NOT NULL DEFAULT '1970-01-01 00:00:00+00:00';
+
+-- as far as we know, nobody has changed since they signed up
+UPDATE users SET updated_at = created_at;
+
+-- stands in for `diesel_manage_updated_at`, bumping `updated_at` unless the update set it
+CREATE TRIGGER users_set_updated_at AFTER UPDATE ON users
+FOR EACH ROW WHEN NEW.updated_at IS OLD.updated_at
+BEGIN
+  UPDATE users SET updated_at = strftime('%Y-%m-%d %H:%M:%f+00:00', 'now') WHERE id = NEW.id;
+END;
@@ -0,0 +1,10 @@
+-- at most one pending change per user, found by the hash of either of its tokens
+CREATE TABLE email_changes (
+  id TEXT PRIMARY KEY NOT NULL,
+  user_id TEXT NOT NULL UNIQUE REFERENCES users (id) ON DELETE CASCADE,
+  new_email TEXT NOT NULL,
+  confirm_token_hash TEXT NOT NULL UNIQUE,
+  cancel_token_hash TEXT NOT NULL UNIQUE,
+  created_at TEXT NOT NULL,
+  expires_at TEXT NOT NULL
+);
@@ -0,0 +1,12 @@
+-- accounts at external identity providers, each linked to at most one user, and at most one per
+-- provider for each user
+CREATE TABLE user_identities (
+  id TEXT PRIMARY KEY NOT NULL,
+  user_id TEXT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  provider TEXT NOT NULL,
+  subject TEXT NOT NULL,
+  email TEXT,
+  created_at TEXT NOT NULL,
+  UNIQUE (provider, subject),
+  UNIQUE (user_id, provider)
+);
@@ -0,0 +1,7 @@
+-- at most one unused sign in link per address, which needn't belong to anyone yet
+CREATE TABLE magic_links (
+  token_hash TEXT PRIMARY KEY NOT NULL,
+  email TEXT NOT NULL UNIQUE,
+  created_at TEXT NOT NULL,
+  expires_at TEXT NOT NULL
+);
@@ -0,0 +1,27 @@
+-- groups of users who share an account, each with at least one owner
+CREATE TABLE organizations (
+  id TEXT PRIMARY KEY NOT NULL,
+  name TEXT NOT NULL,
+  created_at TEXT NOT NULL
+);
+
+-- who belongs to each organization, and what they're allowed to do in it
+CREATE TABLE org_memberships (
+  organization_id TEXT NOT NULL REFERENCES organizations (id) ON DELETE CASCADE,
+  user_id TEXT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  role TEXT NOT NULL,
+  created_at TEXT NOT NULL,
+  PRIMARY KEY (organization_id, user_id)
+);
+
+-- invitations emailed to addresses that needn't belong to anyone yet, found by their token's hash
+CREATE TABLE org_invitations (
+  id TEXT PRIMARY KEY NOT NULL,
+  organization_id TEXT NOT NULL REFERENCES organizations (id) ON DELETE CASCADE,
+  email TEXT NOT NULL,
+  role TEXT NOT NULL,
+  token_hash TEXT NOT NULL UNIQUE,
+  created_at TEXT NOT NULL,
+  expires_at TEXT NOT NULL,
+  UNIQUE (organization_id, email)
+);
@@ -0,0 +1,7 @@
+-- at most one unused reset link per user
+CREATE TABLE password_resets (
+  token_hash TEXT PRIMARY KEY NOT NULL,
+  user_id TEXT NOT NULL UNIQUE REFERENCES users (id) ON DELETE CASCADE,
+  created_at TEXT NOT NULL,
+  expires_at TEXT NOT NULL
+);
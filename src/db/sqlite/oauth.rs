@@ -0,0 +1,386 @@
+use chrono::{DateTime, Utc};
+use diesel::{
+    delete, insert_into, upsert::excluded, ExpressionMethods, Insertable, OptionalExtension,
+    QueryDsl, Queryable, RunQueryDsl,
+};
+use uuid::Uuid;
+
+use crate::{
+    db::{oauth::OAuthDao, sql::DbError},
+    model::{
+        oauth::{AuthorizationCode, OAuthClient, OAuthConsent, RefreshToken, Scopes},
+        types::{ClientId, SessionId, UserId},
+    },
+};
+
+use super::{
+    schema::{oauth_authorization_codes, oauth_clients, oauth_consents, oauth_refresh_tokens},
+    SqliteDb,
+};
+
+fn parse(id: &str) -> Result<Uuid, DbError> {
+    Uuid::parse_str(id)
+        .map_err(|e| DbError::Db(diesel::result::Error::DeserializationError(e.into())))
+}
+
+/// An `OAuthClient` as stored in SQLite, which has no UUID type
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = oauth_clients)]
+struct OAuthClientRow {
+    id: String,
+    name: String,
+    secret_hash: Option<String>,
+    redirect_uri: String,
+    scopes: Scopes,
+    created_at: DateTime<Utc>,
+}
+
+impl From<OAuthClient> for OAuthClientRow {
+    fn from(client: OAuthClient) -> Self {
+        Self {
+            id: client.id.0.to_string(),
+            name: client.name,
+            secret_hash: client.secret_hash,
+            redirect_uri: client.redirect_uri,
+            scopes: client.scopes,
+            created_at: client.created_at,
+        }
+    }
+}
+
+impl TryFrom<OAuthClientRow> for OAuthClient {
+    type Error = DbError;
+
+    fn try_from(row: OAuthClientRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: ClientId(parse(&row.id)?),
+            name: row.name,
+            secret_hash: row.secret_hash,
+            redirect_uri: row.redirect_uri,
+            scopes: row.scopes,
+            created_at: row.created_at,
+        })
+    }
+}
+
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = oauth_consents)]
+struct OAuthConsentRow {
+    user_id: String,
+    client_id: String,
+    scopes: Scopes,
+    granted_at: DateTime<Utc>,
+}
+
+impl From<OAuthConsent> for OAuthConsentRow {
+    fn from(consent: OAuthConsent) -> Self {
+        Self {
+            user_id: consent.user_id.0.to_string(),
+            client_id: consent.client_id.0.to_string(),
+            scopes: consent.scopes,
+            granted_at: consent.granted_at,
+        }
+    }
+}
+
+impl TryFrom<OAuthConsentRow> for OAuthConsent {
+    type Error = DbError;
+
+    fn try_from(row: OAuthConsentRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            user_id: UserId(parse(&row.user_id)?),
+            client_id: ClientId(parse(&row.client_id)?),
+            scopes: row.scopes,
+            granted_at: row.granted_at,
+        })
+    }
+}
+
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = oauth_authorization_codes)]
+struct AuthorizationCodeRow {
+    code_hash: String,
+    client_id: String,
+    user_id: String,
+    redirect_uri: String,
+    scopes: Scopes,
+    code_challenge: String,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+}
+
+impl From<AuthorizationCode> for AuthorizationCodeRow {
+    fn from(code: AuthorizationCode) -> Self {
+        Self {
+            code_hash: code.code_hash,
+            client_id: code.client_id.0.to_string(),
+            user_id: code.user_id.0.to_string(),
+            redirect_uri: code.redirect_uri,
+            scopes: code.scopes,
+            code_challenge: code.code_challenge,
+            created_at: code.created_at,
+            expires_at: code.expires_at,
+        }
+    }
+}
+
+impl TryFrom<AuthorizationCodeRow> for AuthorizationCode {
+    type Error = DbError;
+
+    fn try_from(row: AuthorizationCodeRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            code_hash: row.code_hash,
+            client_id: ClientId(parse(&row.client_id)?),
+            user_id: UserId(parse(&row.user_id)?),
+            redirect_uri: row.redirect_uri,
+            scopes: row.scopes,
+            code_challenge: row.code_challenge,
+            created_at: row.created_at,
+            expires_at: row.expires_at,
+        })
+    }
+}
+
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = oauth_refresh_tokens)]
+struct RefreshTokenRow {
+    token_hash: String,
+    client_id: String,
+    user_id: String,
+    scopes: Scopes,
+    session_id: SessionId,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+}
+
+impl From<RefreshToken> for RefreshTokenRow {
+    fn from(token: RefreshToken) -> Self {
+        Self {
+            token_hash: token.token_hash,
+            client_id: token.client_id.0.to_string(),
+            user_id: token.user_id.0.to_string(),
+            scopes: token.scopes,
+            session_id: token.session_id,
+            created_at: token.created_at,
+            expires_at: token.expires_at,
+        }
+    }
+}
+
+impl TryFrom<RefreshTokenRow> for RefreshToken {
+    type Error = DbError;
+
+    fn try_from(row: RefreshTokenRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            token_hash: row.token_hash,
+            client_id: ClientId(parse(&row.client_id)?),
+            user_id: UserId(parse(&row.user_id)?),
+            scopes: row.scopes,
+            session_id: row.session_id,
+            created_at: row.created_at,
+            expires_at: row.expires_at,
+        })
+    }
+}
+
+#[axum::async_trait]
+impl OAuthDao for SqliteDb {
+    async fn create_client(&self, client: OAuthClient) -> Result<(), DbError> {
+        let query = insert_into(oauth_clients::table).values(OAuthClientRow::from(client));
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn client_by_id(&self, client_id: ClientId) -> Result<Option<OAuthClient>, DbError> {
+        let query = oauth_clients::table
+            .filter(oauth_clients::id.eq(client_id.0.to_string()))
+            .limit(1);
+        let row: Option<OAuthClientRow> = self
+            .exec(query, |query, conn| query.get_result(conn).optional())
+            .await?;
+
+        row.map(OAuthClient::try_from).transpose()
+    }
+
+    async fn clients(&self) -> Result<Vec<OAuthClient>, DbError> {
+        let query = oauth_clients::table.order((oauth_clients::created_at, oauth_clients::id));
+        let rows: Vec<OAuthClientRow> = self.exec(query, |query, conn| query.load(conn)).await?;
+
+        rows.into_iter().map(OAuthClient::try_from).collect()
+    }
+
+    async fn delete_client(&self, client_id: ClientId) -> Result<(), DbError> {
+        let query =
+            delete(oauth_clients::table.filter(oauth_clients::id.eq(client_id.0.to_string())));
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn consent(
+        &self,
+        user_id: UserId,
+        client_id: ClientId,
+    ) -> Result<Option<OAuthConsent>, DbError> {
+        let query = oauth_consents::table
+            .filter(oauth_consents::user_id.eq(user_id.0.to_string()))
+            .filter(oauth_consents::client_id.eq(client_id.0.to_string()))
+            .limit(1);
+        let row: Option<OAuthConsentRow> = self
+            .exec(query, |query, conn| query.get_result(conn).optional())
+            .await?;
+
+        row.map(OAuthConsent::try_from).transpose()
+    }
+
+    async fn consents_for_user(&self, user_id: UserId) -> Result<Vec<OAuthConsent>, DbError> {
+        let query = oauth_consents::table
+            .filter(oauth_consents::user_id.eq(user_id.0.to_string()))
+            .order((oauth_consents::granted_at, oauth_consents::client_id));
+        let rows: Vec<OAuthConsentRow> = self.exec(query, |query, conn| query.load(conn)).await?;
+
+        rows.into_iter().map(OAuthConsent::try_from).collect()
+    }
+
+    async fn save_consent(&self, consent: OAuthConsent) -> Result<(), DbError> {
+        let query = insert_into(oauth_consents::table)
+            .values(OAuthConsentRow::from(consent))
+            .on_conflict((oauth_consents::user_id, oauth_consents::client_id))
+            .do_update()
+            .set((
+                oauth_consents::scopes.eq(excluded(oauth_consents::scopes)),
+                oauth_consents::granted_at.eq(excluded(oauth_consents::granted_at)),
+            ));
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn delete_consent(&self, user_id: UserId, client_id: ClientId) -> Result<(), DbError> {
+        let query = delete(
+            oauth_consents::table
+                .filter(oauth_consents::user_id.eq(user_id.0.to_string()))
+                .filter(oauth_consents::client_id.eq(client_id.0.to_string())),
+        );
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn delete_consents_for_user(&self, user_id: UserId) -> Result<usize, DbError> {
+        let query =
+            delete(oauth_consents::table.filter(oauth_consents::user_id.eq(user_id.0.to_string())));
+        self.exec(query, |query, conn| query.execute(conn)).await
+    }
+
+    async fn create_authorization_code(&self, code: AuthorizationCode) -> Result<(), DbError> {
+        let query =
+            insert_into(oauth_authorization_codes::table).values(AuthorizationCodeRow::from(code));
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn authorization_code_by_hash(
+        &self,
+        code_hash: String,
+    ) -> Result<Option<AuthorizationCode>, DbError> {
+        let query = oauth_authorization_codes::table
+            .filter(oauth_authorization_codes::code_hash.eq(code_hash))
+            .limit(1);
+        let row: Option<AuthorizationCodeRow> = self
+            .exec(query, |query, conn| query.get_result(conn).optional())
+            .await?;
+
+        row.map(AuthorizationCode::try_from).transpose()
+    }
+
+    async fn delete_authorization_code(&self, code_hash: String) -> Result<(), DbError> {
+        let query = delete(
+            oauth_authorization_codes::table
+                .filter(oauth_authorization_codes::code_hash.eq(code_hash)),
+        );
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn create_refresh_token(&self, token: RefreshToken) -> Result<(), DbError> {
+        let query = insert_into(oauth_refresh_tokens::table).values(RefreshTokenRow::from(token));
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn refresh_token_by_hash(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<RefreshToken>, DbError> {
+        let query = oauth_refresh_tokens::table
+            .filter(oauth_refresh_tokens::token_hash.eq(token_hash))
+            .limit(1);
+        let row: Option<RefreshTokenRow> = self
+            .exec(query, |query, conn| query.get_result(conn).optional())
+            .await?;
+
+        row.map(RefreshToken::try_from).transpose()
+    }
+
+    async fn refresh_tokens_for_user(&self, user_id: UserId) -> Result<Vec<RefreshToken>, DbError> {
+        let query = oauth_refresh_tokens::table
+            .filter(oauth_refresh_tokens::user_id.eq(user_id.0.to_string()))
+            .order((
+                oauth_refresh_tokens::created_at,
+                oauth_refresh_tokens::token_hash,
+            ));
+        let rows: Vec<RefreshTokenRow> = self.exec(query, |query, conn| query.load(conn)).await?;
+
+        rows.into_iter().map(RefreshToken::try_from).collect()
+    }
+
+    async fn delete_refresh_token(&self, token_hash: String) -> Result<(), DbError> {
+        let query = delete(
+            oauth_refresh_tokens::table.filter(oauth_refresh_tokens::token_hash.eq(token_hash)),
+        );
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn delete_refresh_tokens(
+        &self,
+        user_id: UserId,
+        client_id: ClientId,
+    ) -> Result<usize, DbError> {
+        let query = delete(
+            oauth_refresh_tokens::table
+                .filter(oauth_refresh_tokens::user_id.eq(user_id.0.to_string()))
+                .filter(oauth_refresh_tokens::client_id.eq(client_id.0.to_string())),
+        );
+        self.exec(query, |query, conn| query.execute(conn)).await
+    }
+
+    async fn delete_refresh_tokens_for_user(&self, user_id: UserId) -> Result<usize, DbError> {
+        let query = delete(
+            oauth_refresh_tokens::table
+                .filter(oauth_refresh_tokens::user_id.eq(user_id.0.to_string())),
+        );
+        self.exec(query, |query, conn| query.execute(conn)).await
+    }
+
+    async fn delete_expired_oauth_tokens(&self, at: DateTime<Utc>) -> Result<usize, DbError> {
+        let codes = delete(
+            oauth_authorization_codes::table.filter(oauth_authorization_codes::expires_at.lt(at)),
+        );
+        let codes = self.exec(codes, |query, conn| query.execute(conn)).await?;
+
+        let refresh_tokens =
+            delete(oauth_refresh_tokens::table.filter(oauth_refresh_tokens::expires_at.lt(at)));
+        let refresh_tokens = self
+            .exec(refresh_tokens, |query, conn| query.execute(conn))
+            .await?;
+
+        Ok(codes + refresh_tokens)
+    }
+}
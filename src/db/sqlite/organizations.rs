@@ -0,0 +1,309 @@
+use chrono::{DateTime, Utc};
+use diesel::{
+    delete, insert_into, update, ExpressionMethods, Insertable, OptionalExtension, QueryDsl,
+    Queryable, RunQueryDsl,
+};
+use uuid::Uuid;
+
+use crate::{
+    db::{organizations::OrganizationDao, sql::DbError},
+    model::{
+        organization::{Membership, OrgInvitation, OrgRole, Organization},
+        types::{Email, OrgId, UserId},
+    },
+};
+
+use super::{
+    schema::{org_invitations, org_memberships, organizations},
+    SqliteDb,
+};
+
+/// An `Organization` as stored in SQLite, which has no UUID type
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = organizations)]
+struct OrganizationRow {
+    id: String,
+    name: String,
+    created_at: DateTime<Utc>,
+}
+
+/// A `Membership` as stored in SQLite
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = org_memberships)]
+struct MembershipRow {
+    organization_id: String,
+    user_id: String,
+    role: OrgRole,
+    created_at: DateTime<Utc>,
+}
+
+/// An `OrgInvitation` as stored in SQLite
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = org_invitations)]
+struct OrgInvitationRow {
+    id: String,
+    organization_id: String,
+    email: Email,
+    role: OrgRole,
+    token_hash: String,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+}
+
+fn parse(id: &str) -> Result<Uuid, DbError> {
+    Uuid::parse_str(id)
+        .map_err(|e| DbError::Db(diesel::result::Error::DeserializationError(e.into())))
+}
+
+impl From<Organization> for OrganizationRow {
+    fn from(organization: Organization) -> Self {
+        Self {
+            id: organization.id.0.to_string(),
+            name: organization.name,
+            created_at: organization.created_at,
+        }
+    }
+}
+
+impl TryFrom<OrganizationRow> for Organization {
+    type Error = DbError;
+
+    fn try_from(row: OrganizationRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: OrgId(parse(&row.id)?),
+            name: row.name,
+            created_at: row.created_at,
+        })
+    }
+}
+
+impl From<Membership> for MembershipRow {
+    fn from(membership: Membership) -> Self {
+        Self {
+            organization_id: membership.organization_id.0.to_string(),
+            user_id: membership.user_id.0.to_string(),
+            role: membership.role,
+            created_at: membership.created_at,
+        }
+    }
+}
+
+impl TryFrom<MembershipRow> for Membership {
+    type Error = DbError;
+
+    fn try_from(row: MembershipRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            organization_id: OrgId(parse(&row.organization_id)?),
+            user_id: UserId(parse(&row.user_id)?),
+            role: row.role,
+            created_at: row.created_at,
+        })
+    }
+}
+
+impl From<OrgInvitation> for OrgInvitationRow {
+    fn from(invitation: OrgInvitation) -> Self {
+        Self {
+            id: invitation.id.to_string(),
+            organization_id: invitation.organization_id.0.to_string(),
+            email: invitation.email,
+            role: invitation.role,
+            token_hash: invitation.token_hash,
+            created_at: invitation.created_at,
+            expires_at: invitation.expires_at,
+        }
+    }
+}
+
+impl TryFrom<OrgInvitationRow> for OrgInvitation {
+    type Error = DbError;
+
+    fn try_from(row: OrgInvitationRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: parse(&row.id)?,
+            organization_id: OrgId(parse(&row.organization_id)?),
+            email: row.email,
+            role: row.role,
+            token_hash: row.token_hash,
+            created_at: row.created_at,
+            expires_at: row.expires_at,
+        })
+    }
+}
+
+#[axum::async_trait]
+impl OrganizationDao for SqliteDb {
+    async fn create_organization(&self, organization: Organization) -> Result<(), DbError> {
+        let query = insert_into(organizations::table).values(OrganizationRow::from(organization));
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn organization_by_id(&self, id: OrgId) -> Result<Option<Organization>, DbError> {
+        let query = organizations::table
+            .filter(organizations::id.eq(id.0.to_string()))
+            .limit(1);
+        let row: Option<OrganizationRow> = self
+            .exec(query, |query, conn| query.get_result(conn).optional())
+            .await?;
+
+        row.map(Organization::try_from).transpose()
+    }
+
+    async fn delete_organization(&self, id: OrgId) -> Result<(), DbError> {
+        let query = delete(organizations::table.filter(organizations::id.eq(id.0.to_string())));
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn create_membership(&self, membership: Membership) -> Result<(), DbError> {
+        let query = insert_into(org_memberships::table).values(MembershipRow::from(membership));
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn membership(
+        &self,
+        organization_id: OrgId,
+        user_id: UserId,
+    ) -> Result<Option<Membership>, DbError> {
+        let query = org_memberships::table
+            .filter(org_memberships::organization_id.eq(organization_id.0.to_string()))
+            .filter(org_memberships::user_id.eq(user_id.0.to_string()))
+            .limit(1);
+        let row: Option<MembershipRow> = self
+            .exec(query, |query, conn| query.get_result(conn).optional())
+            .await?;
+
+        row.map(Membership::try_from).transpose()
+    }
+
+    async fn memberships_for_organization(
+        &self,
+        organization_id: OrgId,
+    ) -> Result<Vec<Membership>, DbError> {
+        let query = org_memberships::table
+            .filter(org_memberships::organization_id.eq(organization_id.0.to_string()))
+            .order((org_memberships::created_at, org_memberships::user_id));
+        let rows: Vec<MembershipRow> = self.exec(query, |query, conn| query.load(conn)).await?;
+
+        rows.into_iter().map(Membership::try_from).collect()
+    }
+
+    async fn memberships_for_user(&self, user_id: UserId) -> Result<Vec<Membership>, DbError> {
+        let query = org_memberships::table
+            .filter(org_memberships::user_id.eq(user_id.0.to_string()))
+            .order((
+                org_memberships::created_at,
+                org_memberships::organization_id,
+            ));
+        let rows: Vec<MembershipRow> = self.exec(query, |query, conn| query.load(conn)).await?;
+
+        rows.into_iter().map(Membership::try_from).collect()
+    }
+
+    async fn update_membership_role(
+        &self,
+        organization_id: OrgId,
+        user_id: UserId,
+        role: OrgRole,
+    ) -> Result<(), DbError> {
+        let query = update(
+            org_memberships::table
+                .filter(org_memberships::organization_id.eq(organization_id.0.to_string()))
+                .filter(org_memberships::user_id.eq(user_id.0.to_string())),
+        )
+        .set(org_memberships::role.eq(role));
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn delete_membership(
+        &self,
+        organization_id: OrgId,
+        user_id: UserId,
+    ) -> Result<(), DbError> {
+        let query = delete(
+            org_memberships::table
+                .filter(org_memberships::organization_id.eq(organization_id.0.to_string()))
+                .filter(org_memberships::user_id.eq(user_id.0.to_string())),
+        );
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn delete_memberships_for_user(&self, user_id: UserId) -> Result<usize, DbError> {
+        let query = delete(
+            org_memberships::table.filter(org_memberships::user_id.eq(user_id.0.to_string())),
+        );
+        self.exec(query, |query, conn| query.execute(conn)).await
+    }
+
+    async fn create_org_invitation(&self, invitation: OrgInvitation) -> Result<(), DbError> {
+        let query = insert_into(org_invitations::table).values(OrgInvitationRow::from(invitation));
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn org_invitation_by_token(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<OrgInvitation>, DbError> {
+        let query = org_invitations::table
+            .filter(org_invitations::token_hash.eq(token_hash))
+            .limit(1);
+        let row: Option<OrgInvitationRow> = self
+            .exec(query, |query, conn| query.get_result(conn).optional())
+            .await?;
+
+        row.map(OrgInvitation::try_from).transpose()
+    }
+
+    async fn org_invitations_for_organization(
+        &self,
+        organization_id: OrgId,
+    ) -> Result<Vec<OrgInvitation>, DbError> {
+        let query = org_invitations::table
+            .filter(org_invitations::organization_id.eq(organization_id.0.to_string()))
+            .order((org_invitations::created_at, org_invitations::id));
+        let rows: Vec<OrgInvitationRow> = self.exec(query, |query, conn| query.load(conn)).await?;
+
+        rows.into_iter().map(OrgInvitation::try_from).collect()
+    }
+
+    async fn org_invitations_for_email(&self, email: Email) -> Result<Vec<OrgInvitation>, DbError> {
+        let query = org_invitations::table
+            .filter(org_invitations::email.eq(email))
+            .order((org_invitations::created_at, org_invitations::id));
+        let rows: Vec<OrgInvitationRow> = self.exec(query, |query, conn| query.load(conn)).await?;
+
+        rows.into_iter().map(OrgInvitation::try_from).collect()
+    }
+
+    async fn delete_org_invitation(&self, organization_id: OrgId, id: Uuid) -> Result<(), DbError> {
+        let query = delete(
+            org_invitations::table
+                .filter(org_invitations::organization_id.eq(organization_id.0.to_string()))
+                .filter(org_invitations::id.eq(id.to_string())),
+        );
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn delete_org_invitations_for_email(&self, email: Email) -> Result<usize, DbError> {
+        let query = delete(org_invitations::table.filter(org_invitations::email.eq(email)));
+        self.exec(query, |query, conn| query.execute(conn)).await
+    }
+
+    async fn delete_expired_org_invitations(&self, at: DateTime<Utc>) -> Result<usize, DbError> {
+        let query = delete(org_invitations::table.filter(org_invitations::expires_at.lt(at)));
+        self.exec(query, |query, conn| query.execute(conn)).await
+    }
+}
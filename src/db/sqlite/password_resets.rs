@@ -0,0 +1,94 @@
+use chrono::{DateTime, Utc};
+use diesel::{
+    delete, insert_into, ExpressionMethods, Insertable, OptionalExtension, QueryDsl, Queryable,
+    RunQueryDsl,
+};
+use uuid::Uuid;
+
+use crate::{
+    db::{password_resets::PasswordResetDao, sql::DbError},
+    model::{password_reset::PasswordReset, types::UserId},
+};
+
+use super::{schema::password_resets, SqliteDb};
+
+/// A `PasswordReset` as stored in SQLite, which has no UUID type
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = password_resets)]
+struct PasswordResetRow {
+    token_hash: String,
+    user_id: String,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+}
+
+impl From<PasswordReset> for PasswordResetRow {
+    fn from(reset: PasswordReset) -> Self {
+        Self {
+            token_hash: reset.token_hash,
+            user_id: reset.user_id.0.to_string(),
+            created_at: reset.created_at,
+            expires_at: reset.expires_at,
+        }
+    }
+}
+
+impl TryFrom<PasswordResetRow> for PasswordReset {
+    type Error = DbError;
+
+    fn try_from(row: PasswordResetRow) -> Result<Self, Self::Error> {
+        let user_id = Uuid::parse_str(&row.user_id)
+            .map_err(|e| DbError::Db(diesel::result::Error::DeserializationError(e.into())))?;
+
+        Ok(Self {
+            token_hash: row.token_hash,
+            user_id: UserId(user_id),
+            created_at: row.created_at,
+            expires_at: row.expires_at,
+        })
+    }
+}
+
+#[axum::async_trait]
+impl PasswordResetDao for SqliteDb {
+    async fn create_password_reset(&self, reset: PasswordReset) -> Result<(), DbError> {
+        let query = insert_into(password_resets::table).values(PasswordResetRow::from(reset));
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn password_reset_by_token(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<PasswordReset>, DbError> {
+        let query = password_resets::table
+            .filter(password_resets::token_hash.eq(token_hash))
+            .limit(1);
+        let row: Option<PasswordResetRow> = self
+            .exec(query, |query, conn| query.get_result(conn).optional())
+            .await?;
+
+        row.map(PasswordReset::try_from).transpose()
+    }
+
+    async fn delete_password_reset(&self, token_hash: String) -> Result<(), DbError> {
+        let query =
+            delete(password_resets::table.filter(password_resets::token_hash.eq(token_hash)));
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn delete_password_resets_for_user(&self, user_id: UserId) -> Result<usize, DbError> {
+        let query = delete(
+            password_resets::table.filter(password_resets::user_id.eq(user_id.0.to_string())),
+        );
+        self.exec(query, |query, conn| query.execute(conn)).await
+    }
+
+    async fn delete_expired_password_resets(&self, at: DateTime<Utc>) -> Result<usize, DbError> {
+        let query = delete(password_resets::table.filter(password_resets::expires_at.lt(at)));
+        self.exec(query, |query, conn| query.execute(conn)).await
+    }
+}
@@ -0,0 +1,118 @@
+use chrono::{DateTime, Utc};
+use diesel::{
+    delete, insert_into, update, ExpressionMethods, Insertable, OptionalExtension, QueryDsl,
+    Queryable, RunQueryDsl,
+};
+use uuid::Uuid;
+
+use crate::{
+    db::{sessions::SessionDao, sql::DbError},
+    model::{
+        session::Session,
+        types::{SessionId, UserId},
+    },
+};
+
+use super::{schema::sessions, SqliteDb};
+
+/// A `Session` as stored in SQLite, which has no UUID type
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = sessions)]
+struct SessionRow {
+    id: SessionId,
+    user_id: String,
+    created_at: DateTime<Utc>,
+    last_seen_at: DateTime<Utc>,
+    user_agent: Option<String>,
+    ip: Option<String>,
+}
+
+impl From<Session> for SessionRow {
+    fn from(session: Session) -> Self {
+        Self {
+            id: session.id,
+            user_id: session.user_id.0.to_string(),
+            created_at: session.created_at,
+            last_seen_at: session.last_seen_at,
+            user_agent: session.user_agent,
+            ip: session.ip,
+        }
+    }
+}
+
+impl TryFrom<SessionRow> for Session {
+    type Error = DbError;
+
+    fn try_from(row: SessionRow) -> Result<Self, Self::Error> {
+        let user_id = Uuid::parse_str(&row.user_id)
+            .map_err(|e| DbError::Db(diesel::result::Error::DeserializationError(e.into())))?;
+
+        Ok(Self {
+            id: row.id,
+            user_id: UserId(user_id),
+            created_at: row.created_at,
+            last_seen_at: row.last_seen_at,
+            user_agent: row.user_agent,
+            ip: row.ip,
+        })
+    }
+}
+
+#[axum::async_trait]
+impl SessionDao for SqliteDb {
+    async fn session_by_id(&self, session_id: SessionId) -> Result<Option<Session>, DbError> {
+        let query = sessions::table.filter(sessions::id.eq(session_id)).limit(1);
+        let row: Option<SessionRow> = self
+            .exec(query, |query, conn| query.get_result(conn).optional())
+            .await?;
+
+        row.map(Session::try_from).transpose()
+    }
+
+    async fn sessions_for_user(&self, user_id: UserId) -> Result<Vec<Session>, DbError> {
+        let query = sessions::table
+            .filter(sessions::user_id.eq(user_id.0.to_string()))
+            .order(sessions::last_seen_at.desc());
+        let rows: Vec<SessionRow> = self.exec(query, |query, conn| query.load(conn)).await?;
+
+        rows.into_iter().map(Session::try_from).collect()
+    }
+
+    async fn create_session(&self, session: Session) -> Result<(), DbError> {
+        let query = insert_into(sessions::table).values(SessionRow::from(session));
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn touch_session(&self, session_id: SessionId, at: DateTime<Utc>) -> Result<(), DbError> {
+        let query = update(sessions::table.filter(sessions::id.eq(session_id)))
+            .set(sessions::last_seen_at.eq(at));
+        self.exec(query, |query, conn| query.execute(conn)).await?;
+        Ok(())
+    }
+
+    async fn delete_session(&self, user_id: UserId, session_id: SessionId) -> Result<(), DbError> {
+        let query = delete(
+            sessions::table
+                .filter(sessions::id.eq(session_id))
+                .filter(sessions::user_id.eq(user_id.0.to_string())),
+        );
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn delete_sessions_for_user(&self, user_id: UserId) -> Result<usize, DbError> {
+        let query = delete(sessions::table.filter(sessions::user_id.eq(user_id.0.to_string())));
+        self.exec(query, |query, conn| query.execute(conn)).await
+    }
+
+    async fn delete_sessions_created_before(
+        &self,
+        created_before: DateTime<Utc>,
+    ) -> Result<usize, DbError> {
+        let query = delete(sessions::table.filter(sessions::created_at.lt(created_before)));
+        self.exec(query, |query, conn| query.execute(conn)).await
+    }
+}
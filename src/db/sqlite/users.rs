@@ -0,0 +1,236 @@
+use chrono::{DateTime, Utc};
+use diesel::{
+    delete, insert_into, update, AsChangeset, ExpressionMethods, Insertable, OptionalExtension,
+    QueryDsl, Queryable, RunQueryDsl,
+};
+use uuid::Uuid;
+
+use crate::{
+    db::{sql::DbError, users::UserDao},
+    model::{
+        types::{Email, PasswordHash, UserId},
+        user::{ProfileChanges, User},
+    },
+};
+
+use super::{schema::users, SqliteDb};
+
+fn parse(id: &str) -> Result<Uuid, DbError> {
+    Uuid::parse_str(id)
+        .map_err(|e| DbError::Db(diesel::result::Error::DeserializationError(e.into())))
+}
+
+/// A `User` as stored in SQLite, which has no UUID type
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = users)]
+struct UserRow {
+    id: String,
+    email: Email,
+    password_hash: PasswordHash,
+    created_at: DateTime<Utc>,
+    deleted_at: Option<DateTime<Utc>>,
+    display_name: Option<String>,
+    locale: Option<String>,
+    timezone: Option<String>,
+    avatar_url: Option<String>,
+    updated_at: DateTime<Utc>,
+}
+
+/// `ProfileChanges` for the SQLite table, which is a different table as far as diesel is
+/// concerned
+#[derive(AsChangeset)]
+#[diesel(table_name = users)]
+struct ProfileChangesRow {
+    display_name: Option<Option<String>>,
+    locale: Option<Option<String>>,
+    timezone: Option<Option<String>>,
+    avatar_url: Option<Option<String>>,
+}
+
+impl From<ProfileChanges> for ProfileChangesRow {
+    fn from(changes: ProfileChanges) -> Self {
+        Self {
+            display_name: changes.display_name,
+            locale: changes.locale,
+            timezone: changes.timezone,
+            avatar_url: changes.avatar_url,
+        }
+    }
+}
+
+impl From<User> for UserRow {
+    fn from(user: User) -> Self {
+        Self {
+            id: user.id.0.to_string(),
+            email: user.email,
+            password_hash: user.password_hash,
+            created_at: user.created_at,
+            deleted_at: user.deleted_at,
+            display_name: user.display_name,
+            locale: user.locale,
+            timezone: user.timezone,
+            avatar_url: user.avatar_url,
+            updated_at: user.updated_at,
+        }
+    }
+}
+
+impl TryFrom<UserRow> for User {
+    type Error = DbError;
+
+    fn try_from(row: UserRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: UserId(parse(&row.id)?),
+            email: row.email,
+            password_hash: row.password_hash,
+            created_at: row.created_at,
+            deleted_at: row.deleted_at,
+            display_name: row.display_name,
+            locale: row.locale,
+            timezone: row.timezone,
+            avatar_url: row.avatar_url,
+            updated_at: row.updated_at,
+        })
+    }
+}
+
+#[axum::async_trait]
+impl UserDao for SqliteDb {
+    async fn user_by_id(&self, user_id: UserId) -> Result<Option<User>, DbError> {
+        let query = users::table
+            .filter(users::id.eq(user_id.0.to_string()))
+            .filter(users::deleted_at.is_null())
+            .limit(1);
+        let row: Option<UserRow> = self
+            .exec(query, |query, conn| query.get_result(conn).optional())
+            .await?;
+
+        row.map(User::try_from).transpose()
+    }
+
+    async fn user_by_id_including_deleted(&self, user_id: UserId) -> Result<Option<User>, DbError> {
+        let query = users::table
+            .filter(users::id.eq(user_id.0.to_string()))
+            .limit(1);
+        let row: Option<UserRow> = self
+            .exec(query, |query, conn| query.get_result(conn).optional())
+            .await?;
+
+        row.map(User::try_from).transpose()
+    }
+
+    async fn user_by_email(&self, email: Email) -> Result<Option<User>, DbError> {
+        let query = users::table
+            .filter(users::email.eq(email))
+            .filter(users::deleted_at.is_null())
+            .limit(1);
+        let row: Option<UserRow> = self
+            .exec(query, |query, conn| query.get_result(conn).optional())
+            .await?;
+
+        row.map(User::try_from).transpose()
+    }
+
+    async fn create_user(&self, user: User) -> Result<(), DbError> {
+        let query = insert_into(users::table).values(UserRow::from(user));
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn update_profile(
+        &self,
+        user_id: UserId,
+        changes: ProfileChanges,
+    ) -> Result<(), DbError> {
+        let query = update(
+            users::table
+                .filter(users::id.eq(user_id.0.to_string()))
+                .filter(users::deleted_at.is_null()),
+        )
+        .set(ProfileChangesRow::from(changes));
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn update_email(&self, user_id: UserId, email: Email) -> Result<(), DbError> {
+        let query = update(
+            users::table
+                .filter(users::id.eq(user_id.0.to_string()))
+                .filter(users::deleted_at.is_null()),
+        )
+        .set(users::email.eq(email));
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn update_password(
+        &self,
+        user_id: UserId,
+        password_hash: PasswordHash,
+    ) -> Result<(), DbError> {
+        let query = update(
+            users::table
+                .filter(users::id.eq(user_id.0.to_string()))
+                .filter(users::deleted_at.is_null()),
+        )
+        .set(users::password_hash.eq(password_hash));
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn delete_user(&self, user_id: UserId, at: DateTime<Utc>) -> Result<(), DbError> {
+        let query = update(
+            users::table
+                .filter(users::id.eq(user_id.0.to_string()))
+                .filter(users::deleted_at.is_null()),
+        )
+        .set(users::deleted_at.eq(at));
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn restore_user(
+        &self,
+        user_id: UserId,
+        deleted_since: DateTime<Utc>,
+    ) -> Result<(), DbError> {
+        let query = update(
+            users::table
+                .filter(users::id.eq(user_id.0.to_string()))
+                .filter(users::deleted_at.ge(deleted_since)),
+        )
+        .set(users::deleted_at.eq(None::<DateTime<Utc>>));
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn users_deleted_before(
+        &self,
+        deleted_before: DateTime<Utc>,
+    ) -> Result<Vec<UserId>, DbError> {
+        let query = users::table
+            .filter(users::deleted_at.lt(deleted_before))
+            .select(users::id);
+        let ids: Vec<String> = self.exec(query, |query, conn| query.load(conn)).await?;
+
+        ids.iter().map(|id| parse(id).map(UserId)).collect()
+    }
+
+    async fn purge_deleted_users(&self, deleted_before: DateTime<Utc>) -> Result<usize, DbError> {
+        let query = delete(users::table.filter(users::deleted_at.lt(deleted_before)));
+        self.exec(query, |query, conn| query.execute(conn)).await
+    }
+
+    async fn erase_user(&self, user_id: UserId) -> Result<(), DbError> {
+        let query = delete(users::table.filter(users::id.eq(user_id.0.to_string())));
+        let rows_modified = self.exec(query, |query, conn| query.execute(conn)).await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+}
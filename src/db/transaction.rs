@@ -0,0 +1,185 @@
+use std::any::Any;
+
+use futures::{future::BoxFuture, FutureExt};
+
+use super::{
+    api_keys::ApiKeyDao, audit::AuditDao, email_changes::EmailChangeDao, identities::IdentityDao,
+    magic_links::MagicLinkDao, oauth::OAuthDao, organizations::OrganizationDao,
+    password_resets::PasswordResetDao, sessions::SessionDao, sql::DbError, users::UserDao, Db,
+};
+
+/// A handle to an open transaction, offering the same operations as `Db`
+pub trait Transaction:
+    UserDao
+    + SessionDao
+    + EmailChangeDao
+    + IdentityDao
+    + MagicLinkDao
+    + OAuthDao
+    + ApiKeyDao
+    + AuditDao
+    + OrganizationDao
+    + PasswordResetDao
+    + Send
+    + Sync
+{
+}
+
+impl<T> Transaction for T where
+    T: UserDao
+        + SessionDao
+        + EmailChangeDao
+        + IdentityDao
+        + MagicLinkDao
+        + OAuthDao
+        + ApiKeyDao
+        + AuditDao
+        + OrganizationDao
+        + PasswordResetDao
+        + Send
+        + Sync
+{
+}
+
+type Erased = Box<dyn Any + Send>;
+
+/// The result of a transaction body, with its types erased so that `Db` stays object safe
+pub type ErasedResult = Result<Erased, Erased>;
+
+/// A transaction body, with its types erased so that `Db` stays object safe
+///
+/// It may be called more than once, if the transaction has to be retried
+pub type ErasedBody<'a> =
+    dyn for<'t> FnMut(&'t dyn Transaction) -> BoxFuture<'t, ErasedResult> + Send + 'a;
+
+impl dyn Db {
+    /// Run `f` inside a transaction, committing if it returns `Ok` and rolling back otherwise
+    ///
+    /// `f` is run again from the start if the transaction loses a race with a concurrent one, so
+    /// it shouldn't have side effects outside the database
+    pub async fn transaction<T, E, F>(&self, mut f: F) -> Result<T, E>
+    where
+        T: Send + 'static,
+        E: From<DbError> + Send + 'static,
+        F: for<'t> FnMut(&'t dyn Transaction) -> BoxFuture<'t, Result<T, E>> + Send,
+    {
+        let body: &mut ErasedBody = &mut |tx| {
+            f(tx)
+                .map(|result| match result {
+                    Ok(value) => Ok(Box::new(value) as Erased),
+                    Err(e) => Err(Box::new(e) as Erased),
+                })
+                .boxed()
+        };
+
+        match self.run_transaction(body).await? {
+            Ok(value) => Ok(*value.downcast().expect("transaction returned wrong type")),
+            Err(e) => Err(*e.downcast().expect("transaction returned wrong type")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::Path,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use microtype::Microtype;
+    use tokio::sync::Barrier;
+    use uuid::Uuid;
+
+    use crate::{
+        db::{conformance::TestSchema, sqlite::SqliteDb},
+        model::{
+            types::{Email, UserId},
+            user::{mock::default_user, User},
+        },
+    };
+
+    use super::*;
+
+    fn user(email: &str) -> User {
+        User {
+            id: UserId::new(Uuid::new_v4()),
+            email: Email(email.into()),
+            ..default_user()
+        }
+    }
+
+    fn sqlite_db() -> Arc<dyn Db> {
+        Arc::new(SqliteDb::connect(Path::new(":memory:")).unwrap())
+    }
+
+    #[tokio::test]
+    async fn sqlite_rolls_back_when_dropped() {
+        let db = sqlite_db();
+        let user = user("dropped@rollback.test");
+
+        // abandon the transaction part way through, as if the request had been cancelled
+        let transaction = db.transaction(|tx| {
+            let user = user.clone();
+            async move {
+                tx.create_user(user).await?;
+                std::future::pending::<Result<(), DbError>>().await
+            }
+            .boxed()
+        });
+        let _ = tokio::time::timeout(Duration::from_millis(50), transaction).await;
+
+        assert!(db.user_by_id(user.id).await.unwrap().is_none());
+    }
+
+    /// Only runs if `DATABASE_URL` is set
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn sql_retries_serialization_failures() {
+        if let Some(schema) = TestSchema::create().await {
+            schema.run(retries_serialization_failures).await;
+        }
+    }
+
+    async fn retries_serialization_failures(db: Arc<dyn Db>) {
+        // each transaction checks for the other's user before inserting its own, and the barrier
+        // makes both check before either inserts, so one of them has to be retried
+        let a = user("a@retry.test");
+        let b = user("b@retry.test");
+        let barrier = Arc::new(Barrier::new(2));
+        let attempts = Arc::new(AtomicUsize::new(0));
+
+        let insert = |mine: User, theirs: User| {
+            let db = db.clone();
+            let barrier = barrier.clone();
+            let attempts = attempts.clone();
+            async move {
+                db.transaction(|tx| {
+                    let (mine, theirs) = (mine.clone(), theirs.clone());
+                    let barrier = barrier.clone();
+                    let first = attempts.fetch_add(1, Ordering::SeqCst) < 2;
+                    async move {
+                        tx.user_by_email(theirs.email).await?;
+                        if first {
+                            barrier.wait().await;
+                        }
+                        tx.create_user(mine).await
+                    }
+                    .boxed()
+                })
+                .await
+            }
+        };
+
+        let (result_a, result_b) = tokio::join!(
+            tokio::spawn(insert(a.clone(), b.clone())),
+            tokio::spawn(insert(b.clone(), a.clone())),
+        );
+
+        result_a.unwrap().unwrap();
+        result_b.unwrap().unwrap();
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+}
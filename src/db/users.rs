@@ -1,35 +1,94 @@
-use diesel::{delete, insert_into, ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
+use chrono::{DateTime, Utc};
+use diesel::{delete, insert_into, update, ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::RunQueryDsl;
+use futures::FutureExt;
 
 use crate::{
     db::schema::users,
     model::{
-        types::{Email, UserId},
-        user::User,
+        types::{Email, PasswordHash, UserId},
+        user::{ProfileChanges, User},
     },
 };
 
 use super::sql::{DbError, SqlDb};
 
+/// Access to users, where deleted users are left in place until they're purged
+///
+/// Deleted users can't be found by id or email, but still hold on to their email address
 #[axum::async_trait]
 pub trait UserDao {
     async fn user_by_id(&self, user_id: UserId) -> Result<Option<User>, DbError>;
 
+    /// Like `user_by_id`, but also finds users who've been deleted and not yet purged
+    async fn user_by_id_including_deleted(&self, user_id: UserId) -> Result<Option<User>, DbError>;
+
     async fn user_by_email(&self, email: Email) -> Result<Option<User>, DbError>;
 
     async fn create_user(&self, user: User) -> Result<(), DbError>;
 
-    async fn delete_user(&self, user_id: UserId) -> Result<(), DbError>;
+    /// Apply `changes`, which mustn't be empty, failing with `RowsModified` if there's no such user
+    async fn update_profile(&self, user_id: UserId, changes: ProfileChanges)
+        -> Result<(), DbError>;
+
+    /// Fails with `RowsModified` if there's no such user, or `AlreadyExists` if the address is taken
+    async fn update_email(&self, user_id: UserId, email: Email) -> Result<(), DbError>;
+
+    /// Fails with `RowsModified` if there's no such user
+    async fn update_password(
+        &self,
+        user_id: UserId,
+        password_hash: PasswordHash,
+    ) -> Result<(), DbError>;
+
+    /// Mark a user as deleted at `at`, failing with `RowsModified` if there's no such user
+    async fn delete_user(&self, user_id: UserId, at: DateTime<Utc>) -> Result<(), DbError>;
+
+    /// Undo `delete_user`, as long as the user was deleted no earlier than `deleted_since`
+    ///
+    /// Fails with `RowsModified` if there's no such deleted user
+    async fn restore_user(
+        &self,
+        user_id: UserId,
+        deleted_since: DateTime<Utc>,
+    ) -> Result<(), DbError>;
+
+    /// The users deleted before `deleted_before`, who are due to be purged
+    async fn users_deleted_before(
+        &self,
+        deleted_before: DateTime<Utc>,
+    ) -> Result<Vec<UserId>, DbError>;
+
+    /// Permanently remove users deleted before `deleted_before`, returning how many there were
+    async fn purge_deleted_users(&self, deleted_before: DateTime<Utc>) -> Result<usize, DbError>;
+
+    /// Permanently remove a user straight away, whether or not they've been deleted
+    ///
+    /// Fails with `RowsModified` if there's no such user
+    async fn erase_user(&self, user_id: UserId) -> Result<(), DbError>;
 }
 
 #[axum::async_trait]
 impl UserDao for SqlDb {
     async fn user_by_id(&self, user_id: UserId) -> Result<Option<User>, DbError> {
+        let query = users::table
+            .filter(users::id.eq(user_id.0))
+            .filter(users::deleted_at.is_null())
+            .limit(1);
         let user = self
-            .exec(move |conn| {
-                users::table
-                    .filter(users::id.eq(user_id.0))
-                    .first(conn)
-                    .optional()
+            .read(query, |query, conn| {
+                async move { query.get_result(conn).await.optional() }.boxed()
+            })
+            .await?;
+
+        Ok(user)
+    }
+
+    async fn user_by_id_including_deleted(&self, user_id: UserId) -> Result<Option<User>, DbError> {
+        let query = users::table.filter(users::id.eq(user_id.0)).limit(1);
+        let user = self
+            .read(query, |query, conn| {
+                async move { query.get_result(conn).await.optional() }.boxed()
             })
             .await?;
 
@@ -37,34 +96,132 @@ impl UserDao for SqlDb {
     }
 
     async fn user_by_email(&self, email: Email) -> Result<Option<User>, DbError> {
+        let query = users::table
+            .filter(users::email.eq(email))
+            .filter(users::deleted_at.is_null())
+            .limit(1);
         let user = self
-            .exec(move |conn| {
-                users::table
-                    .filter(users::email.eq(email))
-                    .first(conn)
-                    .optional()
+            .read(query, |query, conn| {
+                async move { query.get_result(conn).await.optional() }.boxed()
             })
             .await?;
         Ok(user)
     }
 
     async fn create_user(&self, user: User) -> Result<(), DbError> {
+        let query = insert_into(users::table).values(user);
+        let rows_modified = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn update_profile(
+        &self,
+        user_id: UserId,
+        changes: ProfileChanges,
+    ) -> Result<(), DbError> {
+        let query = update(
+            users::table
+                .filter(users::id.eq(user_id))
+                .filter(users::deleted_at.is_null()),
+        )
+        .set(changes);
+        let rows_modified = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn update_email(&self, user_id: UserId, email: Email) -> Result<(), DbError> {
+        let query = update(
+            users::table
+                .filter(users::id.eq(user_id))
+                .filter(users::deleted_at.is_null()),
+        )
+        .set(users::email.eq(email));
+        let rows_modified = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn update_password(
+        &self,
+        user_id: UserId,
+        password_hash: PasswordHash,
+    ) -> Result<(), DbError> {
+        let query = update(
+            users::table
+                .filter(users::id.eq(user_id))
+                .filter(users::deleted_at.is_null()),
+        )
+        .set(users::password_hash.eq(password_hash));
         let rows_modified = self
-            .exec(move |conn| insert_into(users::table).values(user).execute(conn))
+            .exec(query, |query, conn| query.execute(conn).boxed())
             .await?;
 
-        match rows_modified {
-            1 => Ok(()),
-            n => Err(DbError::RowsModified {
-                expected: 1,
-                actual: n,
-            }),
-        }
+        DbError::check_rows_modified(1, rows_modified)
     }
 
-    async fn delete_user(&self, user_id: UserId) -> Result<(), DbError> {
-        self.exec(move |conn| delete(users::table.filter(users::id.eq(user_id))).execute(conn))
+    async fn delete_user(&self, user_id: UserId, at: DateTime<Utc>) -> Result<(), DbError> {
+        let query = update(
+            users::table
+                .filter(users::id.eq(user_id))
+                .filter(users::deleted_at.is_null()),
+        )
+        .set(users::deleted_at.eq(at));
+        let rows_modified = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
             .await?;
-        Ok(())
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn restore_user(
+        &self,
+        user_id: UserId,
+        deleted_since: DateTime<Utc>,
+    ) -> Result<(), DbError> {
+        let query = update(
+            users::table
+                .filter(users::id.eq(user_id))
+                .filter(users::deleted_at.ge(deleted_since)),
+        )
+        .set(users::deleted_at.eq(None::<DateTime<Utc>>));
+        let rows_modified = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        DbError::check_rows_modified(1, rows_modified)
+    }
+
+    async fn users_deleted_before(
+        &self,
+        deleted_before: DateTime<Utc>,
+    ) -> Result<Vec<UserId>, DbError> {
+        let query = users::table
+            .filter(users::deleted_at.lt(deleted_before))
+            .select(users::id);
+        self.read(query, |query, conn| query.load(conn).boxed())
+            .await
+    }
+
+    async fn purge_deleted_users(&self, deleted_before: DateTime<Utc>) -> Result<usize, DbError> {
+        let query = delete(users::table.filter(users::deleted_at.lt(deleted_before)));
+        self.exec(query, |query, conn| query.execute(conn).boxed())
+            .await
+    }
+
+    async fn erase_user(&self, user_id: UserId) -> Result<(), DbError> {
+        let query = delete(users::table.filter(users::id.eq(user_id)));
+        let rows_modified = self
+            .exec(query, |query, conn| query.execute(conn).boxed())
+            .await?;
+
+        DbError::check_rows_modified(1, rows_modified)
     }
 }
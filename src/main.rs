@@ -7,6 +7,7 @@ mod db;
 mod model;
 mod routing;
 mod state;
+mod telemetry;
 
 #[cfg(test)]
 mod testing;
@@ -16,36 +17,78 @@ use std::net::SocketAddr;
 use axum::{Router, Server};
 use color_eyre::Result;
 use state::{make_services, Dependencies, Services};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
 
 #[macro_use]
 extern crate tracing;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt().init();
     color_eyre::install()?;
 
+    let deps = Dependencies::load()?;
+    let telemetry = telemetry::init(&deps.config)?;
+
     info!("hello");
     let addr = addr().unwrap_or_else(|_| ([127, 0, 0, 1], 8000).into());
 
-    let deps = Dependencies::load()?;
+    let purge_interval = deps.config.accounts.purge_interval();
     let services = make_services(deps)?;
+    tokio::spawn(services.clone().run_purges(purge_interval));
 
     Server::bind(&addr)
-        .serve(make_app(services).into_make_service())
+        .serve(make_app(services.clone()).into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown_signal())
         .await
         .unwrap();
 
+    services.db.shutdown().await?;
+
     info!("goodbye");
+    telemetry.shutdown();
     Ok(())
 }
 
 fn make_app(state: Services) -> Router<()> {
     let router = Router::new();
     let router = routing::attach_routes(router);
+    let router = routing::attach_middleware(router, state.clone());
     router.with_state(state)
 }
 
+/// Resolves on Ctrl-C or SIGTERM, after which in-flight requests are allowed to finish
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("failed to listen for ctrl-c: {e}");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                error!("failed to listen for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        () = ctrl_c => {},
+        () = terminate => {},
+    }
+
+    info!("shutting down");
+}
+
 fn addr() -> Result<SocketAddr> {
     let s = std::env::var("BIND_ADDR")?;
     Ok(s.parse()?)
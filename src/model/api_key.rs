@@ -0,0 +1,27 @@
+use chrono::{DateTime, Utc};
+use diesel::{Insertable, Queryable, Selectable};
+use uuid::Uuid;
+
+use crate::db::schema::api_keys;
+
+use super::{oauth::Scopes, types::UserId};
+
+/// A long-lived credential a user makes for scripts to use in place of their password
+#[derive(Debug, Clone, Selectable, Queryable, Insertable)]
+#[diesel(table_name = api_keys)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub user_id: UserId,
+    /// What the user called it, which is unique among their keys
+    pub name: String,
+    /// The start of the key, kept in the clear so that the key can be looked up by it, and so
+    /// that its owner can tell which key is which
+    pub prefix: String,
+    pub key_hash: String,
+    /// What the key can be used for
+    pub scopes: Scopes,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    /// Only updated every so often, so it can be a little behind
+    pub last_used_at: Option<DateTime<Utc>>,
+}
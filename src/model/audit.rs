@@ -0,0 +1,207 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use diesel::{
+    backend::{Backend, RawValue},
+    deserialize::{self, FromSql},
+    serialize::{self, Output, ToSql},
+    sql_types::Text,
+    AsExpression, FromSqlRow, Insertable, Queryable, Selectable,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::db::schema::audit_events;
+
+use super::types::UserId;
+
+/// Something security-relevant that happened, as recorded in the audit log
+#[derive(Debug, Clone, Serialize, Selectable, Queryable, Insertable)]
+pub struct AuditEvent {
+    pub id: Uuid,
+    pub occurred_at: DateTime<Utc>,
+    /// Who did it, if anyone was signed in
+    pub actor_id: Option<UserId>,
+    /// Whose account it was done to, if anyone's
+    pub subject_id: Option<UserId>,
+    pub action: AuditAction,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub request_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, AsExpression, FromSqlRow)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Login,
+    LoginFailed,
+    UserCreated,
+    UserDeleted,
+    UserRestored,
+    /// Recorded once per purge, with no actor or subject
+    UsersPurged,
+    SessionRevoked,
+    SessionsRevoked,
+    /// Recorded once per purge, with no actor or subject
+    SessionsPurged,
+    PasswordChanged,
+    PasswordResetRequested,
+    PasswordReset,
+    EmailChangeRequested,
+    EmailChanged,
+    EmailChangeCancelled,
+    DataExported,
+    /// Recorded with no subject, since the user it was for no longer exists
+    UserErased,
+    IdentityLinked,
+    IdentityUnlinked,
+    /// Recorded with no subject, since clients aren't anyone's account
+    #[serde(rename = "oauth_client_registered")]
+    OAuthClientRegistered,
+    /// Recorded with no subject, since clients aren't anyone's account
+    #[serde(rename = "oauth_client_deleted")]
+    OAuthClientDeleted,
+    #[serde(rename = "oauth_consent_granted")]
+    OAuthConsentGranted,
+    #[serde(rename = "oauth_consent_revoked")]
+    OAuthConsentRevoked,
+    ApiKeyCreated,
+    ApiKeyRevoked,
+    /// Recorded with no subject if the address isn't anyone's yet
+    MagicLinkSent,
+    /// Recorded with no subject, since organizations aren't anyone's account
+    OrganizationCreated,
+    /// Recorded with no subject, since organizations aren't anyone's account
+    OrganizationDeleted,
+    /// Recorded with no subject if the address isn't anyone's yet
+    OrgMemberInvited,
+    OrgMemberJoined,
+    OrgMemberRoleChanged,
+    OrgMemberRemoved,
+    /// Recorded with no subject, since the address may not be anyone's
+    OrgInvitationRevoked,
+}
+
+impl AuditAction {
+    const ALL: [Self; 33] = [
+        Self::Login,
+        Self::LoginFailed,
+        Self::UserCreated,
+        Self::UserDeleted,
+        Self::UserRestored,
+        Self::UsersPurged,
+        Self::SessionRevoked,
+        Self::SessionsRevoked,
+        Self::SessionsPurged,
+        Self::PasswordChanged,
+        Self::PasswordResetRequested,
+        Self::PasswordReset,
+        Self::EmailChangeRequested,
+        Self::EmailChanged,
+        Self::EmailChangeCancelled,
+        Self::DataExported,
+        Self::UserErased,
+        Self::IdentityLinked,
+        Self::IdentityUnlinked,
+        Self::OAuthClientRegistered,
+        Self::OAuthClientDeleted,
+        Self::OAuthConsentGranted,
+        Self::OAuthConsentRevoked,
+        Self::ApiKeyCreated,
+        Self::ApiKeyRevoked,
+        Self::MagicLinkSent,
+        Self::OrganizationCreated,
+        Self::OrganizationDeleted,
+        Self::OrgMemberInvited,
+        Self::OrgMemberJoined,
+        Self::OrgMemberRoleChanged,
+        Self::OrgMemberRemoved,
+        Self::OrgInvitationRevoked,
+    ];
+
+    /// The name this action is stored and serialized as
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Login => "login",
+            Self::LoginFailed => "login_failed",
+            Self::UserCreated => "user_created",
+            Self::UserDeleted => "user_deleted",
+            Self::UserRestored => "user_restored",
+            Self::UsersPurged => "users_purged",
+            Self::SessionRevoked => "session_revoked",
+            Self::SessionsRevoked => "sessions_revoked",
+            Self::SessionsPurged => "sessions_purged",
+            Self::PasswordChanged => "password_changed",
+            Self::PasswordResetRequested => "password_reset_requested",
+            Self::PasswordReset => "password_reset",
+            Self::EmailChangeRequested => "email_change_requested",
+            Self::EmailChanged => "email_changed",
+            Self::EmailChangeCancelled => "email_change_cancelled",
+            Self::DataExported => "data_exported",
+            Self::UserErased => "user_erased",
+            Self::IdentityLinked => "identity_linked",
+            Self::IdentityUnlinked => "identity_unlinked",
+            Self::OAuthClientRegistered => "oauth_client_registered",
+            Self::OAuthClientDeleted => "oauth_client_deleted",
+            Self::OAuthConsentGranted => "oauth_consent_granted",
+            Self::OAuthConsentRevoked => "oauth_consent_revoked",
+            Self::ApiKeyCreated => "api_key_created",
+            Self::ApiKeyRevoked => "api_key_revoked",
+            Self::MagicLinkSent => "magic_link_sent",
+            Self::OrganizationCreated => "organization_created",
+            Self::OrganizationDeleted => "organization_deleted",
+            Self::OrgMemberInvited => "org_member_invited",
+            Self::OrgMemberJoined => "org_member_joined",
+            Self::OrgMemberRoleChanged => "org_member_role_changed",
+            Self::OrgMemberRemoved => "org_member_removed",
+            Self::OrgInvitationRevoked => "org_invitation_revoked",
+        }
+    }
+}
+
+impl FromStr for AuditAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|action| action.as_str() == s)
+            .ok_or_else(|| format!("unknown audit action: {s}"))
+    }
+}
+
+impl<DB> ToSql<Text, DB> for AuditAction
+where
+    DB: Backend,
+    str: ToSql<Text, DB>,
+{
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, DB>) -> serialize::Result {
+        self.as_str().to_sql(out)
+    }
+}
+
+impl<DB> FromSql<Text, DB> for AuditAction
+where
+    DB: Backend,
+    String: FromSql<Text, DB>,
+{
+    fn from_sql(bytes: RawValue<'_, DB>) -> deserialize::Result<Self> {
+        Ok(String::from_sql(bytes)?.parse()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, to_value};
+
+    use super::*;
+
+    #[test]
+    fn stored_and_serialized_names_agree() {
+        for action in AuditAction::ALL {
+            assert_eq!(to_value(action).unwrap(), json!(action.as_str()));
+            assert_eq!(action.as_str().parse::<AuditAction>().unwrap(), action);
+        }
+    }
+}
@@ -0,0 +1,24 @@
+use chrono::{DateTime, Utc};
+use diesel::{Insertable, Queryable, Selectable};
+use uuid::Uuid;
+
+use crate::db::schema::email_changes;
+
+use super::types::{Email, UserId};
+
+/// A change of email address, waiting to be confirmed from the new address
+///
+/// The old address is sent a link that cancels it instead
+#[derive(Debug, Clone, Selectable, Queryable, Insertable)]
+pub struct EmailChange {
+    pub id: Uuid,
+    pub user_id: UserId,
+    pub new_email: Email,
+    /// Hash of the token sent to the new address
+    pub confirm_token_hash: String,
+    /// Hash of the token sent to the old address
+    pub cancel_token_hash: String,
+    pub created_at: DateTime<Utc>,
+    /// After which the change can no longer be confirmed, though it can still be cancelled
+    pub expires_at: DateTime<Utc>,
+}
@@ -0,0 +1,24 @@
+use chrono::{DateTime, Utc};
+use diesel::{Insertable, Queryable, Selectable};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::db::schema::user_identities;
+
+use super::types::{Email, UserId};
+
+/// An account at an external identity provider, which its user can sign in with instead of a
+/// password
+#[derive(Debug, Clone, Serialize, Selectable, Queryable, Insertable)]
+#[diesel(table_name = user_identities)]
+pub struct UserIdentity {
+    pub id: Uuid,
+    pub user_id: UserId,
+    /// The name the provider is configured under
+    pub provider: String,
+    /// The provider's `sub` for the account, which never changes
+    pub subject: String,
+    /// The address the provider had for the account when it was linked
+    pub email: Option<Email>,
+    pub created_at: DateTime<Utc>,
+}
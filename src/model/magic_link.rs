@@ -0,0 +1,18 @@
+use chrono::{DateTime, Utc};
+use diesel::{Insertable, Queryable, Selectable};
+
+use crate::db::schema::magic_links;
+
+use super::types::Email;
+
+/// A link emailed to an address that signs in whoever follows it, once
+///
+/// It's tied to the address rather than a user, since following it can also sign up
+#[derive(Debug, Clone, Selectable, Queryable, Insertable)]
+pub struct MagicLink {
+    /// Hash of the token in the link
+    pub token_hash: String,
+    pub email: Email,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
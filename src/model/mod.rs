@@ -1,2 +1,11 @@
+pub mod api_key;
+pub mod audit;
+pub mod email_change;
+pub mod identity;
+pub mod magic_link;
+pub mod oauth;
+pub mod organization;
+pub mod password_reset;
+pub mod session;
 pub mod types;
 pub mod user;
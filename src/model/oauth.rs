@@ -0,0 +1,238 @@
+use std::{collections::BTreeSet, fmt, str::FromStr};
+
+use chrono::{DateTime, Utc};
+use diesel::{
+    backend::{Backend, RawValue},
+    deserialize::{self, FromSql},
+    serialize::{self, Output, ToSql},
+    sql_types::Text,
+    AsExpression, FromSqlRow, Insertable, Queryable, Selectable,
+};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::db::schema::{
+    oauth_authorization_codes, oauth_clients, oauth_consents, oauth_refresh_tokens,
+};
+
+use super::types::{ClientId, SessionId, UserId};
+
+/// Something an OAuth client can be allowed to do on a user's behalf
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    /// Anything the user could do themselves, as with a token from `/auth/login`
+    Api,
+    /// Read the user's profile from `/oauth/userinfo`
+    Profile,
+    /// Read the user's email address from `/oauth/userinfo`
+    Email,
+}
+
+impl Scope {
+    const ALL: [Self; 3] = [Self::Api, Self::Profile, Self::Email];
+
+    /// The name this scope is asked for by
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Api => "api",
+            Self::Profile => "profile",
+            Self::Email => "email",
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|scope| scope.as_str() == s)
+            .ok_or_else(|| format!("unknown scope: {s}"))
+    }
+}
+
+/// A set of scopes, written the way OAuth writes them, as names separated by spaces
+///
+/// The names are kept in a fixed order without duplicates, so equal sets are written the same way
+#[derive(Debug, Clone, Default, PartialEq, Eq, AsExpression, FromSqlRow)]
+#[diesel(sql_type = Text)]
+pub struct Scopes(String);
+
+impl Scopes {
+    pub fn iter(&self) -> impl Iterator<Item = Scope> + '_ {
+        // only ever built from valid scopes
+        self.0.split_whitespace().filter_map(|s| s.parse().ok())
+    }
+
+    pub fn contains(&self, scope: Scope) -> bool {
+        self.iter().any(|s| s == scope)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn is_subset(&self, other: &Scopes) -> bool {
+        self.iter().all(|scope| other.contains(scope))
+    }
+
+    pub fn union(&self, other: &Scopes) -> Scopes {
+        self.iter().chain(other.iter()).collect()
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromIterator<Scope> for Scopes {
+    fn from_iter<T: IntoIterator<Item = Scope>>(iter: T) -> Self {
+        let scopes: BTreeSet<_> = iter.into_iter().collect();
+        let names: Vec<_> = scopes.into_iter().map(Scope::as_str).collect();
+        Self(names.join(" "))
+    }
+}
+
+impl FromStr for Scopes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split_whitespace().map(str::parse).collect()
+    }
+}
+
+impl fmt::Display for Scopes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Serialize for Scopes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Scopes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+impl<DB> ToSql<Text, DB> for Scopes
+where
+    DB: Backend,
+    str: ToSql<Text, DB>,
+{
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, DB>) -> serialize::Result {
+        self.0.as_str().to_sql(out)
+    }
+}
+
+impl<DB> FromSql<Text, DB> for Scopes
+where
+    DB: Backend,
+    String: FromSql<Text, DB>,
+{
+    fn from_sql(bytes: RawValue<'_, DB>) -> deserialize::Result<Self> {
+        Ok(String::from_sql(bytes)?.parse()?)
+    }
+}
+
+/// An app that users can let act on their behalf
+#[derive(Debug, Clone, Selectable, Queryable, Insertable)]
+#[diesel(table_name = oauth_clients)]
+pub struct OAuthClient {
+    pub id: ClientId,
+    pub name: String,
+    /// Hash of the secret a confidential client authenticates with, or `None` for a public
+    /// client, such as a mobile app, which has nowhere to keep one
+    pub secret_hash: Option<String>,
+    /// Where users are sent back to with a code, which requests have to match exactly
+    pub redirect_uri: String,
+    /// The most the client can ask for
+    pub scopes: Scopes,
+    pub created_at: DateTime<Utc>,
+}
+
+/// What a user has let a client do, which grows as the client asks for more
+#[derive(Debug, Clone, Serialize, Selectable, Queryable, Insertable)]
+#[diesel(table_name = oauth_consents)]
+pub struct OAuthConsent {
+    pub user_id: UserId,
+    pub client_id: ClientId,
+    pub scopes: Scopes,
+    /// When the scopes were last added to
+    pub granted_at: DateTime<Utc>,
+}
+
+/// The code a user is sent back to a client with, for the client to exchange for tokens
+#[derive(Debug, Clone, Selectable, Queryable, Insertable)]
+#[diesel(table_name = oauth_authorization_codes)]
+pub struct AuthorizationCode {
+    pub code_hash: String,
+    pub client_id: ClientId,
+    pub user_id: UserId,
+    /// Has to be given again when the code is exchanged
+    pub redirect_uri: String,
+    pub scopes: Scopes,
+    /// The S256 PKCE challenge, which the client proves it made when exchanging the code
+    pub code_challenge: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Lets a client get new access tokens for a user, without the user having to sign in again
+///
+/// Each is only good for one refresh, which issues a new one in its place
+#[derive(Debug, Clone, Selectable, Queryable, Insertable)]
+#[diesel(table_name = oauth_refresh_tokens)]
+pub struct RefreshToken {
+    pub token_hash: String,
+    pub client_id: ClientId,
+    pub user_id: UserId,
+    pub scopes: Scopes,
+    /// The session of the access token issued along with this, which is ended by refreshing
+    pub session_id: SessionId,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{from_value, json, to_value};
+
+    use super::*;
+
+    #[test]
+    fn stored_and_serialized_names_agree() {
+        for scope in Scope::ALL {
+            assert_eq!(to_value(scope).unwrap(), json!(scope.as_str()));
+            assert_eq!(scope.as_str().parse::<Scope>().unwrap(), scope);
+        }
+    }
+
+    #[test]
+    fn scopes_are_written_the_same_way() {
+        let scopes: Scopes = "email  api email".parse().unwrap();
+
+        assert_eq!(scopes.as_str(), "api email");
+        assert_eq!(to_value(&scopes).unwrap(), json!("api email"));
+        assert_eq!(from_value::<Scopes>(json!("email api")).unwrap(), scopes);
+        assert!("api nonsense".parse::<Scopes>().is_err());
+    }
+
+    #[test]
+    fn scope_sets() {
+        let api: Scopes = "api".parse().unwrap();
+        let both: Scopes = "email api".parse().unwrap();
+
+        assert!(api.is_subset(&both));
+        assert!(!both.is_subset(&api));
+        assert!(Scopes::default().is_subset(&api));
+        assert_eq!(api.union(&"email".parse().unwrap()), both);
+    }
+}
@@ -0,0 +1,141 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use diesel::{
+    backend::{Backend, RawValue},
+    deserialize::{self, FromSql},
+    serialize::{self, Output, ToSql},
+    sql_types::Text,
+    AsExpression, FromSqlRow, Insertable, Queryable, Selectable,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::db::schema::{org_invitations, org_memberships, organizations};
+
+use super::types::{Email, OrgId, UserId};
+
+/// A group of users who share an account
+#[derive(Debug, Clone, Serialize, Selectable, Queryable, Insertable)]
+#[diesel(table_name = organizations)]
+pub struct Organization {
+    pub id: OrgId,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// What a member is allowed to do in an organization, each role allowing everything the ones
+/// before it do
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+    AsExpression,
+    FromSqlRow,
+)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum OrgRole {
+    Member,
+    /// Can invite and remove members, and change their roles
+    Admin,
+    /// Can also manage other owners, and delete the organization
+    Owner,
+}
+
+impl OrgRole {
+    const ALL: [Self; 3] = [Self::Member, Self::Admin, Self::Owner];
+
+    /// The name this role is stored and serialized as
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Member => "member",
+            Self::Admin => "admin",
+            Self::Owner => "owner",
+        }
+    }
+}
+
+impl FromStr for OrgRole {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|role| role.as_str() == s)
+            .ok_or_else(|| format!("unknown organization role: {s}"))
+    }
+}
+
+impl<DB> ToSql<Text, DB> for OrgRole
+where
+    DB: Backend,
+    str: ToSql<Text, DB>,
+{
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, DB>) -> serialize::Result {
+        self.as_str().to_sql(out)
+    }
+}
+
+impl<DB> FromSql<Text, DB> for OrgRole
+where
+    DB: Backend,
+    String: FromSql<Text, DB>,
+{
+    fn from_sql(bytes: RawValue<'_, DB>) -> deserialize::Result<Self> {
+        Ok(String::from_sql(bytes)?.parse()?)
+    }
+}
+
+/// A user's place in an organization
+#[derive(Debug, Clone, Serialize, Selectable, Queryable, Insertable)]
+#[diesel(table_name = org_memberships)]
+pub struct Membership {
+    pub organization_id: OrgId,
+    pub user_id: UserId,
+    pub role: OrgRole,
+    pub created_at: DateTime<Utc>,
+}
+
+/// An emailed invitation to join an organization, which whoever has the address can accept once
+#[derive(Debug, Clone, Selectable, Queryable, Insertable)]
+#[diesel(table_name = org_invitations)]
+pub struct OrgInvitation {
+    pub id: Uuid,
+    pub organization_id: OrgId,
+    /// Only one invitation per address is out for each organization at a time
+    pub email: Email,
+    /// What the invitee becomes once they accept
+    pub role: OrgRole,
+    /// Hash of the token in the link
+    pub token_hash: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, to_value};
+
+    use super::*;
+
+    #[test]
+    fn stored_and_serialized_names_agree() {
+        for role in OrgRole::ALL {
+            assert_eq!(to_value(role).unwrap(), json!(role.as_str()));
+            assert_eq!(role.as_str().parse::<OrgRole>().unwrap(), role);
+        }
+    }
+
+    #[test]
+    fn roles_are_ordered_by_what_they_allow() {
+        assert!(OrgRole::Member < OrgRole::Admin);
+        assert!(OrgRole::Admin < OrgRole::Owner);
+    }
+}
@@ -0,0 +1,17 @@
+use chrono::{DateTime, Utc};
+use diesel::{Insertable, Queryable, Selectable};
+
+use crate::db::schema::password_resets;
+
+use super::types::UserId;
+
+/// A link emailed to a user who forgot their password, which lets whoever follows it choose a new
+/// one, once
+#[derive(Debug, Clone, Selectable, Queryable, Insertable)]
+pub struct PasswordReset {
+    /// Hash of the token in the link
+    pub token_hash: String,
+    pub user_id: UserId,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
@@ -0,0 +1,19 @@
+use chrono::{DateTime, Utc};
+use diesel::{Insertable, Queryable, Selectable};
+use serde::Serialize;
+
+use crate::db::schema::sessions;
+
+use super::types::{SessionId, UserId};
+
+/// A token issued to a user, and where it's being used from
+#[derive(Debug, Clone, Serialize, Selectable, Queryable, Insertable)]
+pub struct Session {
+    pub id: SessionId,
+    pub user_id: UserId,
+    pub created_at: DateTime<Utc>,
+    /// Updated at most once every `SESSION_TOUCH_INTERVAL_SECONDS`, to save a write on every request
+    pub last_seen_at: DateTime<Utc>,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
@@ -6,20 +6,33 @@ microtype! {
     #[derive(Debug, Clone, Copy, PartialEq, AsExpression, FromSqlRow)]
     #[diesel(sql_type = diesel::sql_types::Uuid)]
     pub Uuid {
-        UserId
+        UserId,
+        /// Identifies an app registered to act on behalf of users through OAuth
+        ClientId,
+        /// Identifies an organization, which several users can share
+        OrgId,
     }
 
     #[derive(Debug, Clone, PartialEq, AsExpression, FromSqlRow)]
     #[diesel(sql_type = diesel::sql_types::Text)]
     #[string]
     pub String {
-        Email
+        Email,
+        /// The `jti` of the token a session was issued with
+        SessionId,
     }
 
     #[secret]
     #[string]
     pub String {
         Password,
+        /// A one-time secret sent out in a link, of which only a hash is stored
+        Token,
+        /// Proves that requests come from an OAuth client, whether that's us at an identity provider
+        /// or an app registered with us
+        ClientSecret,
+        /// A key a user made for scripts to sign in with, of which only a hash is stored
+        ApiKeySecret,
     }
 
     #[secret]
@@ -29,8 +42,21 @@ microtype! {
     pub String {
         PasswordHash,
     }
+}
 
-
+impl Email {
+    /// A form of this address that's safe to record in logs and spans
+    ///
+    /// Only the first character of the local part is kept, e.g. `d***@email.com`
+    pub fn redacted(&self) -> String {
+        match self.0.split_once('@') {
+            Some((local, domain)) => {
+                let first: String = local.chars().take(1).collect();
+                format!("{first}***@{domain}")
+            }
+            None => "***".into(),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -52,9 +78,26 @@ pub mod mock {
             .collect()
     });
     pub static DEFAULT_USER_ID: Lazy<UserId> = Lazy::new(|| DEFAULT_USER_IDS[0]);
+    /// Listed in `Config::admins` by the test config
+    pub static ADMIN_USER_ID: Lazy<UserId> = Lazy::new(|| DEFAULT_USER_IDS[9]);
+    pub static ADMIN_EMAIL: Lazy<Email> = Lazy::new(|| Email(String::from("admin@email.com")));
     pub static DEFAULT_EMAIL: Lazy<Email> = Lazy::new(|| Email(String::from("default@email.com")));
     pub static DEFAULT_PASSWORD: Lazy<Password> =
         Lazy::new(|| Password::new("bad password".into()));
     pub static DEFAULT_PASSWORD_HASH: Lazy<PasswordHash> =
-        Lazy::new(|| BcryptHasher.hash(&*DEFAULT_PASSWORD).unwrap());
+        Lazy::new(|| BcryptHasher.hash(&DEFAULT_PASSWORD).unwrap());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn email_redaction() {
+        let email = Email("someone@example.com".into());
+        assert_eq!(email.redacted(), "s***@example.com");
+
+        let email = Email("not an email".into());
+        assert_eq!(email.redacted(), "***");
+    }
 }
@@ -1,11 +1,10 @@
 use chrono::{DateTime, Utc};
-use diesel::{Insertable, Queryable, Selectable};
+use diesel::{AsChangeset, Insertable, Queryable, Selectable};
+use url::Url;
 
 use crate::db::schema::users;
 
-use super::{
-    types::{Email, PasswordHash, UserId},
-};
+use super::types::{Email, PasswordHash, UserId};
 
 #[derive(Debug, Clone, Selectable, Queryable, Insertable)]
 pub struct User {
@@ -13,12 +12,130 @@ pub struct User {
     pub email: Email,
     pub password_hash: PasswordHash,
     pub created_at: DateTime<Utc>,
+    /// When the account was deleted, if it's waiting to be purged
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub display_name: Option<String>,
+    /// A BCP 47 language tag, e.g. `en-GB`
+    pub locale: Option<String>,
+    /// An IANA time zone name, e.g. `Europe/London`
+    pub timezone: Option<String>,
+    pub avatar_url: Option<String>,
+    /// Kept up to date by the database whenever the row changes, so it's never set by us
+    pub updated_at: DateTime<Utc>,
+}
+
+impl User {
+    /// A user with nothing in their profile yet
+    pub fn new(
+        id: UserId,
+        email: Email,
+        password_hash: PasswordHash,
+        created_at: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            id,
+            email,
+            password_hash,
+            created_at,
+            deleted_at: None,
+            display_name: None,
+            locale: None,
+            timezone: None,
+            avatar_url: None,
+            updated_at: created_at,
+        }
+    }
+}
+
+/// Changes to the parts of a user's profile they can edit themselves
+///
+/// `None` leaves a field as it is, and `Some(None)` clears it
+#[derive(Debug, Clone, Default, PartialEq, AsChangeset)]
+#[diesel(table_name = users)]
+pub struct ProfileChanges {
+    pub display_name: Option<Option<String>>,
+    pub locale: Option<Option<String>>,
+    pub timezone: Option<Option<String>>,
+    pub avatar_url: Option<Option<String>>,
+}
+
+const MAX_DISPLAY_NAME_LEN: usize = 100;
+const MAX_LOCALE_LEN: usize = 35;
+const MAX_TIMEZONE_LEN: usize = 64;
+const MAX_AVATAR_URL_LEN: usize = 2048;
+
+impl ProfileChanges {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Check every value being set, returning the name of the first field that's invalid
+    pub fn validate(&self) -> Result<(), &'static str> {
+        check("display_name", &self.display_name, is_display_name)?;
+        check("locale", &self.locale, is_locale)?;
+        check("timezone", &self.timezone, is_timezone)?;
+        check("avatar_url", &self.avatar_url, is_avatar_url)
+    }
+}
+
+/// Fails with `name` if `change` sets a value that isn't valid
+fn check(
+    name: &'static str,
+    change: &Option<Option<String>>,
+    is_valid: fn(&str) -> bool,
+) -> Result<(), &'static str> {
+    match change {
+        Some(Some(value)) if !is_valid(value) => Err(name),
+        _ => Ok(()),
+    }
+}
+
+/// Not blank, and with no surrounding whitespace or control characters
+fn is_display_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.chars().count() <= MAX_DISPLAY_NAME_LEN
+        && name.trim() == name
+        && !name.chars().any(char::is_control)
+}
+
+/// Shaped like a BCP 47 tag, i.e. a 2 or 3 letter language followed by any subtags
+fn is_locale(locale: &str) -> bool {
+    let mut subtags = locale.split('-');
+    let language = subtags.next().unwrap_or_default();
+
+    locale.len() <= MAX_LOCALE_LEN
+        && (2..=3).contains(&language.len())
+        && language.bytes().all(|b| b.is_ascii_alphabetic())
+        && subtags.all(|subtag| {
+            (1..=8).contains(&subtag.len()) && subtag.bytes().all(|b| b.is_ascii_alphanumeric())
+        })
+}
+
+/// Shaped like an IANA time zone name, e.g. `UTC` or `America/Argentina/Buenos_Aires`
+///
+/// There's no time zone database to check against, so a well-formed name that doesn't exist
+/// gets through
+fn is_timezone(timezone: &str) -> bool {
+    timezone.len() <= MAX_TIMEZONE_LEN
+        && timezone.split('/').all(|part| {
+            part.starts_with(|c: char| c.is_ascii_alphabetic())
+                && part
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"_+-".contains(&b))
+        })
+}
+
+fn is_avatar_url(url: &str) -> bool {
+    url.len() <= MAX_AVATAR_URL_LEN
+        && Url::parse(url).is_ok_and(|url| url.scheme() == "https" && url.has_host())
 }
 
 #[cfg(test)]
 pub mod mock {
     use crate::{
-        model::types::mock::{DEFAULT_EMAIL, DEFAULT_PASSWORD_HASH, DEFAULT_USER_ID},
+        model::types::mock::{
+            ADMIN_EMAIL, ADMIN_USER_ID, DEFAULT_EMAIL, DEFAULT_PASSWORD_HASH, DEFAULT_USER_ID,
+        },
         state::time::mock::DEFAULT_DATE_TIME,
     };
 
@@ -30,6 +147,101 @@ pub mod mock {
             email: DEFAULT_EMAIL.clone(),
             password_hash: DEFAULT_PASSWORD_HASH.clone(),
             created_at: *DEFAULT_DATE_TIME,
+            deleted_at: None,
+            display_name: None,
+            locale: None,
+            timezone: None,
+            avatar_url: None,
+            updated_at: *DEFAULT_DATE_TIME,
+        }
+    }
+
+    /// A user listed in `Config::admins` by the test config, with the default password
+    pub fn admin_user() -> User {
+        User {
+            id: *ADMIN_USER_ID,
+            email: ADMIN_EMAIL.clone(),
+            ..default_user()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn changes(field: &str, value: &str) -> ProfileChanges {
+        let value = Some(Some(value.to_string()));
+        match field {
+            "display_name" => ProfileChanges {
+                display_name: value,
+                ..ProfileChanges::default()
+            },
+            "locale" => ProfileChanges {
+                locale: value,
+                ..ProfileChanges::default()
+            },
+            "timezone" => ProfileChanges {
+                timezone: value,
+                ..ProfileChanges::default()
+            },
+            "avatar_url" => ProfileChanges {
+                avatar_url: value,
+                ..ProfileChanges::default()
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn accepts_valid_profiles() {
+        for (field, value) in [
+            ("display_name", "Ada Lovelace"),
+            ("locale", "en"),
+            ("locale", "zh-Hant-TW"),
+            ("timezone", "UTC"),
+            ("timezone", "America/Argentina/Buenos_Aires"),
+            ("timezone", "Etc/GMT+5"),
+            ("avatar_url", "https://example.com/ada.png"),
+        ] {
+            assert_eq!(changes(field, value).validate(), Ok(()), "{field}: {value}");
         }
     }
+
+    #[test]
+    fn rejects_invalid_profiles() {
+        let long_name = "a".repeat(MAX_DISPLAY_NAME_LEN + 1);
+        for (field, value) in [
+            ("display_name", ""),
+            ("display_name", " Ada"),
+            ("display_name", "Ada\nLovelace"),
+            ("display_name", &long_name),
+            ("locale", "e"),
+            ("locale", "en_GB"),
+            ("locale", "en-"),
+            ("timezone", "Europe/"),
+            ("timezone", "../etc/passwd"),
+            ("avatar_url", "http://example.com/ada.png"),
+            ("avatar_url", "javascript:alert(1)"),
+            ("avatar_url", "ada.png"),
+        ] {
+            assert_eq!(
+                changes(field, value).validate(),
+                Err(field),
+                "{field}: {value}"
+            );
+        }
+    }
+
+    #[test]
+    fn clearing_is_always_valid() {
+        let changes = ProfileChanges {
+            display_name: Some(None),
+            avatar_url: Some(None),
+            ..ProfileChanges::default()
+        };
+
+        assert!(!changes.is_empty());
+        assert_eq!(changes.validate(), Ok(()));
+    }
 }
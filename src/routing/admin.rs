@@ -0,0 +1,239 @@
+use axum::{
+    extract::{FromRequestParts, Path, Query, State},
+    http::request::Parts,
+    Json,
+};
+
+use self::requests::{
+    AuditEventsQuery, AuditEventsResponse, ClientResponse, ClientsResponse, RegisterClientRequest,
+    RegisteredClientResponse,
+};
+use super::{
+    client::ClientInfo,
+    errors::{ApiError, ApiResponse},
+};
+use crate::{
+    db::audit::AuditFilter,
+    model::types::{ClientId, UserId},
+    state::{
+        audit::DEFAULT_AUDIT_LIMIT,
+        jwt::claims::{Claims, Validated},
+        privacy::ErasureReport,
+        Services,
+    },
+};
+
+pub mod requests;
+
+/// The claims of a user listed in `Config::admins`
+pub struct Admin(pub Claims<Validated>);
+
+#[axum::async_trait]
+impl FromRequestParts<Services> for Admin {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        services: &Services,
+    ) -> Result<Self, Self::Rejection> {
+        let claims = Claims::<Validated>::from_request_parts(parts, services).await?;
+
+        match services.auth.is_admin(&claims) {
+            true => Ok(Self(claims)),
+            false => Err(ApiError::Auth),
+        }
+    }
+}
+
+#[instrument(skip_all, fields(user_id = %user_id.0, admin_id = %admin.subject.0))]
+pub(super) async fn restore_user(
+    State(services): State<Services>,
+    Admin(admin): Admin,
+    client: ClientInfo,
+    Path(user_id): Path<UserId>,
+) -> ApiResponse<()> {
+    services.auth.restore_user(&admin, user_id, &client).await?;
+    Ok(Json(()))
+}
+
+#[instrument(skip_all, fields(user_id = %user_id.0, admin_id = %admin.subject.0))]
+pub(super) async fn erase_user(
+    State(services): State<Services>,
+    Admin(admin): Admin,
+    client: ClientInfo,
+    Path(user_id): Path<UserId>,
+) -> ApiResponse<ErasureReport> {
+    let report = services
+        .privacy
+        .erase_user(&admin, user_id, &client)
+        .await?;
+    Ok(report.into())
+}
+
+#[instrument(skip_all, fields(admin_id = %admin.subject.0))]
+pub(super) async fn audit_events(
+    State(services): State<Services>,
+    Admin(admin): Admin,
+    Query(query): Query<AuditEventsQuery>,
+) -> ApiResponse<AuditEventsResponse> {
+    let filter = AuditFilter {
+        user_id: query.user_id,
+        action: query.action,
+        since: query.since,
+        until: query.until,
+        limit: query.limit.unwrap_or(DEFAULT_AUDIT_LIMIT),
+    };
+
+    let events = services.audit.events(filter).await?;
+    Ok(AuditEventsResponse { events }.into())
+}
+
+#[instrument(skip_all, fields(admin_id = %admin.subject.0))]
+pub(super) async fn register_client(
+    State(services): State<Services>,
+    Admin(admin): Admin,
+    client: ClientInfo,
+    Json(request): Json<RegisterClientRequest>,
+) -> ApiResponse<RegisteredClientResponse> {
+    let RegisterClientRequest {
+        name,
+        redirect_uri,
+        scopes,
+        public,
+    } = request;
+    let registered = services
+        .oauth
+        .register_client(&admin, name, redirect_uri, scopes, public, &client)
+        .await?;
+    Ok(RegisteredClientResponse::from(registered).into())
+}
+
+#[instrument(skip_all, fields(admin_id = %admin.subject.0))]
+pub(super) async fn clients(
+    State(services): State<Services>,
+    Admin(admin): Admin,
+) -> ApiResponse<ClientsResponse> {
+    let clients = services.oauth.clients().await?;
+    let clients = clients.into_iter().map(ClientResponse::from).collect();
+    Ok(ClientsResponse { clients }.into())
+}
+
+#[instrument(skip_all, fields(client_id = %client_id.0, admin_id = %admin.subject.0))]
+pub(super) async fn delete_client(
+    State(services): State<Services>,
+    Admin(admin): Admin,
+    client: ClientInfo,
+    Path(client_id): Path<ClientId>,
+) -> ApiResponse<()> {
+    services
+        .oauth
+        .delete_client(&admin, client_id, &client)
+        .await?;
+    Ok(Json(()))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{header::AUTHORIZATION, StatusCode};
+    use serde_json::{json, Value};
+
+    use crate::{
+        model::types::mock::{ADMIN_EMAIL, DEFAULT_EMAIL, DEFAULT_USER_ID},
+        routing::request_id::X_REQUEST_ID,
+        state::time::mock::DEFAULT_DATE_TIME,
+        testing::{bearer, login, test_client_with, test_data::TEST_DATA},
+    };
+
+    #[tokio::test]
+    async fn admin_can_restore_deleted_user() {
+        let (client, services) = test_client_with(TEST_DATA.clone());
+        let admin = bearer(&services, &ADMIN_EMAIL).await;
+        services
+            .db
+            .delete_user(*DEFAULT_USER_ID, *DEFAULT_DATE_TIME)
+            .await
+            .unwrap();
+
+        let uri = format!("/admin/users/{}/restore", DEFAULT_USER_ID.0);
+        let restore = || client.post(&uri).header(AUTHORIZATION, &admin).send();
+
+        assert_eq!(restore().await.status(), StatusCode::OK);
+        login(&services, &DEFAULT_EMAIL).await;
+
+        // nothing left to restore
+        assert_eq!(restore().await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn only_admins_can_restore() {
+        let (client, services) = test_client_with(TEST_DATA.clone());
+        let user = bearer(&services, &DEFAULT_EMAIL).await;
+
+        let uri = format!("/admin/users/{}/restore", DEFAULT_USER_ID.0);
+        let response = client.post(&uri).header(AUTHORIZATION, &user).send().await;
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            client.post(&uri).send().await.status(),
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[tokio::test]
+    async fn admin_can_erase_users() {
+        let (client, services) = test_client_with(TEST_DATA.clone());
+        let admin = bearer(&services, &ADMIN_EMAIL).await;
+        let user = bearer(&services, &DEFAULT_EMAIL).await;
+
+        let uri = format!("/admin/users/{}/erase", DEFAULT_USER_ID.0);
+        let erase = |bearer: &str| client.post(&uri).header(AUTHORIZATION, bearer).send();
+
+        assert_eq!(erase(&user).await.status(), StatusCode::FORBIDDEN);
+
+        let resp = erase(&admin).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let report: Value = resp.json().await;
+        assert_eq!(report["user_id"], json!(DEFAULT_USER_ID.0));
+        assert_eq!(report["users"], 1);
+        assert_eq!(report["sessions"], 1);
+        assert_eq!(report["email_changes"], 0);
+        assert_eq!(report["audit_events_anonymized"], 1);
+
+        assert_eq!(erase(&admin).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn admin_can_query_audit_events() {
+        let (client, services) = test_client_with(TEST_DATA.clone());
+        let admin = bearer(&services, &ADMIN_EMAIL).await;
+        let body = json!({
+            "email": DEFAULT_EMAIL.clone(),
+            "password": "wrong password",
+        });
+        client
+            .post("/auth/login")
+            .header(X_REQUEST_ID, "failed-login")
+            .json(&body)
+            .send()
+            .await;
+
+        let uri = format!(
+            "/admin/audit-events?user_id={}&action=login_failed&since=2020-01-01T00:00:00Z",
+            DEFAULT_USER_ID.0
+        );
+        let query = |bearer: &str| client.get(&uri).header(AUTHORIZATION, bearer).send();
+
+        let resp = query(&admin).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp: Value = resp.json().await;
+        let events = resp["events"].as_array().unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["action"], "login_failed");
+        assert_eq!(events[0]["actor_id"], Value::Null);
+        assert_eq!(events[0]["subject_id"], json!(DEFAULT_USER_ID.0));
+        assert_eq!(events[0]["request_id"], "failed-login");
+
+        let user = bearer(&services, &DEFAULT_EMAIL).await;
+        assert_eq!(query(&user).await.status(), StatusCode::FORBIDDEN);
+    }
+}
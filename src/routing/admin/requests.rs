@@ -0,0 +1,115 @@
+use chrono::{DateTime, Utc};
+use microtype::secrecy::ExposeSecret;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+    model::{
+        audit::{AuditAction, AuditEvent},
+        oauth::{OAuthClient, Scopes},
+        types::{ClientId, UserId},
+    },
+    state::oauth::RegisteredClient,
+};
+
+/// Every filter is optional, and they all have to match
+#[derive(Debug, Clone, Deserialize)]
+pub struct AuditEventsQuery {
+    /// Events where this user is either the actor or the subject
+    pub user_id: Option<UserId>,
+    pub action: Option<AuditAction>,
+    /// Inclusive
+    pub since: Option<DateTime<Utc>>,
+    /// Exclusive
+    pub until: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEventsResponse {
+    /// Most recent first
+    pub events: Vec<AuditEvent>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RegisterClientRequest {
+    pub name: String,
+    /// Where users are sent back to, which authorization requests have to give exactly
+    pub redirect_uri: Url,
+    /// The most the client can ask users for
+    pub scopes: Scopes,
+    /// Whether the client has nowhere to keep a secret, e.g. a mobile app, in which case it isn't
+    /// given one
+    #[serde(default)]
+    pub public: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ClientResponse {
+    pub id: ClientId,
+    pub name: String,
+    pub redirect_uri: String,
+    pub scopes: Scopes,
+    pub public: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<OAuthClient> for ClientResponse {
+    fn from(client: OAuthClient) -> Self {
+        Self {
+            id: client.id,
+            name: client.name,
+            redirect_uri: client.redirect_uri,
+            scopes: client.scopes,
+            public: client.secret_hash.is_none(),
+            created_at: client.created_at,
+        }
+    }
+}
+
+/// The only time a client's secret is shown, since only a hash of it is kept
+#[derive(Debug, Clone, Serialize)]
+pub struct RegisteredClientResponse {
+    #[serde(flatten)]
+    pub client: ClientResponse,
+    pub client_secret: Option<String>,
+}
+
+impl From<RegisteredClient> for RegisteredClientResponse {
+    fn from(registered: RegisteredClient) -> Self {
+        Self {
+            client: registered.client.into(),
+            client_secret: registered
+                .client_secret
+                .map(|secret| secret.expose_secret().clone()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ClientsResponse {
+    /// Oldest first
+    pub clients: Vec<ClientResponse>,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{from_value, json};
+
+    use crate::model::types::mock::DEFAULT_USER_ID;
+
+    use super::*;
+
+    #[test]
+    fn audit_events_query_test() {
+        let query = json!({
+            "user_id": DEFAULT_USER_ID.0,
+            "action": "login_failed",
+            "since": "2020-01-01T00:00:00Z",
+        });
+
+        let query = from_value::<AuditEventsQuery>(query).unwrap();
+        assert_eq!(query.action, Some(AuditAction::LoginFailed));
+        assert_eq!(query.until, None);
+    }
+}
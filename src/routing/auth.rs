@@ -1,49 +1,235 @@
-use self::requests::{CreateUserRequest, CreateUserResponse, LoginRequest, LoginResponse};
-use super::errors::ApiResponse;
-use crate::state::{
-    jwt::claims::{Claims, Validated},
-    Services,
+use self::requests::{
+    AuthorizationUrlResponse, ConsumeMagicLinkRequest, CreateUserRequest, CreateUserResponse,
+    IdentityCallbackRequest, IdentityProvidersResponse, LoginRequest, LoginResponse,
+    MagicLinkRequest, PasswordResetRequest, ResetPasswordRequest, RevokeSessionsResponse,
+    SessionResponse, SessionsResponse,
+};
+use super::{
+    client::ClientInfo,
+    cookies::{
+        clear_cookies, clear_identity_cookie, identity_binding, identity_cookie, SessionQuery,
+    },
+    errors::{ApiError, ApiResponse},
+};
+use crate::{
+    model::types::SessionId,
+    state::{
+        jwt::claims::{Claims, Validated},
+        Services,
+    },
+};
+use axum::{
+    extract::{Path, Query, State},
+    headers::Cookie,
+    response::{IntoResponse, Response},
+    Json, TypedHeader,
 };
-use axum::{extract::State, Json};
 
 pub mod requests;
 
-#[instrument]
+#[instrument(skip_all, fields(email = %email.redacted()))]
 pub(super) async fn create_user(
     State(services): State<Services>,
+    client: ClientInfo,
+    Query(SessionQuery { session }): Query<SessionQuery>,
     Json(CreateUserRequest { email, password }): Json<CreateUserRequest>,
-) -> ApiResponse<CreateUserResponse> {
-    let jwt = services.auth.create_user(email, password).await?;
-    Ok(CreateUserResponse { jwt }.into())
+) -> Result<Response, ApiError> {
+    let jwt = services.auth.create_user(email, password, &client).await?;
+    session.respond(&services, jwt, |jwt| CreateUserResponse { jwt })
 }
 
-#[instrument]
+#[instrument(skip_all, fields(email = %email.redacted()))]
 pub(super) async fn login(
     State(services): State<Services>,
+    client: ClientInfo,
+    Query(SessionQuery { session }): Query<SessionQuery>,
     Json(LoginRequest { email, password }): Json<LoginRequest>,
-) -> ApiResponse<LoginResponse> {
-    let jwt = services.auth.login(email, password).await?;
-    Ok(LoginResponse { jwt }.into())
+) -> Result<Response, ApiError> {
+    let jwt = services.auth.login(email, password, &client).await?;
+    session.respond(&services, jwt, |jwt| LoginResponse { jwt })
+}
+
+/// Ends the session the request was made with, and clears it from a browser's cookies
+#[instrument(skip_all, fields(user_id = %claims.subject.0))]
+pub(super) async fn logout(
+    State(services): State<Services>,
+    claims: Claims<Validated>,
+    client: ClientInfo,
+) -> Result<Response, ApiError> {
+    let session_id = SessionId(claims.jwt_id.0.clone());
+    services
+        .auth
+        .revoke_session(&claims, session_id, &client)
+        .await?;
+    Ok((clear_cookies(), Json(())).into_response())
 }
 
-#[instrument]
+/// Succeeds whether or not the address has an account
+#[instrument(skip_all, fields(email = %email.redacted()))]
+pub(super) async fn send_magic_link(
+    State(services): State<Services>,
+    client: ClientInfo,
+    Json(MagicLinkRequest { email }): Json<MagicLinkRequest>,
+) -> ApiResponse<()> {
+    services.magic_link.send(email, &client).await?;
+    Ok(Json(()))
+}
+
+#[instrument(skip_all)]
+pub(super) async fn consume_magic_link(
+    State(services): State<Services>,
+    client: ClientInfo,
+    Query(SessionQuery { session }): Query<SessionQuery>,
+    Json(ConsumeMagicLinkRequest { token }): Json<ConsumeMagicLinkRequest>,
+) -> Result<Response, ApiError> {
+    let jwt = services.magic_link.consume(token, &client).await?;
+    session.respond(&services, jwt, |jwt| LoginResponse { jwt })
+}
+
+/// Succeeds whether or not the address has an account
+#[instrument(skip_all, fields(email = %email.redacted()))]
+pub(super) async fn send_password_reset(
+    State(services): State<Services>,
+    client: ClientInfo,
+    Json(PasswordResetRequest { email }): Json<PasswordResetRequest>,
+) -> ApiResponse<()> {
+    services.password_reset.send(email, &client).await?;
+    Ok(Json(()))
+}
+
+/// Signs the account out everywhere, so that it has to sign in again with the new password
+#[instrument(skip_all)]
+pub(super) async fn reset_password(
+    State(services): State<Services>,
+    client: ClientInfo,
+    Json(ResetPasswordRequest { token, password }): Json<ResetPasswordRequest>,
+) -> ApiResponse<()> {
+    services
+        .password_reset
+        .reset(token, password, &client)
+        .await?;
+    Ok(Json(()))
+}
+
+pub(super) async fn identity_providers(
+    State(services): State<Services>,
+) -> Json<IdentityProvidersResponse> {
+    let providers = services.identity.providers();
+    Json(IdentityProvidersResponse { providers })
+}
+
+#[instrument(skip_all, fields(provider = %provider))]
+pub(super) async fn start_identity_login(
+    State(services): State<Services>,
+    Path(provider): Path<String>,
+) -> Result<Response, ApiError> {
+    let started = services.identity.start(&provider, None).await?;
+    let body = Json(AuthorizationUrlResponse {
+        authorization_url: started.authorization_url,
+    });
+    Ok((identity_cookie(&started.binding), body).into_response())
+}
+
+#[instrument(skip_all, fields(provider = %provider))]
+pub(super) async fn identity_login(
+    State(services): State<Services>,
+    client: ClientInfo,
+    cookies: Option<TypedHeader<Cookie>>,
+    Path(provider): Path<String>,
+    Query(SessionQuery { session }): Query<SessionQuery>,
+    Json(IdentityCallbackRequest { code, state }): Json<IdentityCallbackRequest>,
+) -> Result<Response, ApiError> {
+    let binding = identity_binding(cookies);
+    let jwt = services
+        .identity
+        .login(&provider, &code, &state, binding.as_ref(), &client)
+        .await?;
+    let response = session.respond(&services, jwt, |jwt| LoginResponse { jwt })?;
+    Ok((clear_identity_cookie(), response).into_response())
+}
+
+#[instrument(skip_all, fields(user_id = %claims.subject.0))]
 pub(super) async fn delete_user(
     State(services): State<Services>,
     claims: Claims<Validated>,
+    client: ClientInfo,
 ) -> ApiResponse<()> {
-    services.auth.delete_user(&claims).await?;
+    services.auth.delete_user(&claims, &client).await?;
     Ok(Json(()))
 }
 
+#[instrument(skip_all, fields(user_id = %claims.subject.0))]
+pub(super) async fn sessions(
+    State(services): State<Services>,
+    claims: Claims<Validated>,
+) -> ApiResponse<SessionsResponse> {
+    let sessions = services.auth.sessions(&claims).await?;
+
+    let sessions = sessions
+        .into_iter()
+        .map(|session| SessionResponse {
+            current: session.id.0 == claims.jwt_id.0,
+            id: session.id,
+            created_at: session.created_at,
+            last_seen_at: session.last_seen_at,
+            user_agent: session.user_agent,
+            ip: session.ip,
+        })
+        .collect();
+    Ok(SessionsResponse { sessions }.into())
+}
+
+#[instrument(skip_all, fields(user_id = %claims.subject.0, session_id = %session_id.0))]
+pub(super) async fn revoke_session(
+    State(services): State<Services>,
+    claims: Claims<Validated>,
+    client: ClientInfo,
+    Path(session_id): Path<SessionId>,
+) -> ApiResponse<()> {
+    services
+        .auth
+        .revoke_session(&claims, session_id, &client)
+        .await?;
+    Ok(Json(()))
+}
+
+#[instrument(skip_all, fields(user_id = %claims.subject.0))]
+pub(super) async fn revoke_all_sessions(
+    State(services): State<Services>,
+    claims: Claims<Validated>,
+    client: ClientInfo,
+) -> ApiResponse<RevokeSessionsResponse> {
+    let revoked = services.auth.revoke_all_sessions(&claims, &client).await?;
+    Ok(RevokeSessionsResponse { revoked }.into())
+}
+
 #[cfg(test)]
 mod tests {
-    use axum::http::StatusCode;
-    use microtype::secrecy::ExposeSecret;
+    use axum::{
+        body::Body,
+        http::{
+            header::{AUTHORIZATION, CONTENT_TYPE, COOKIE, SET_COOKIE},
+            Request, StatusCode,
+        },
+    };
+    use microtype::{secrecy::ExposeSecret, SecretMicrotype};
     use serde_json::{json, Value};
+    use tower::ServiceExt;
+    use tracing::{dispatcher, instrument::WithSubscriber, Dispatch};
+    use tracing_subscriber::prelude::*;
 
     use crate::{
+        make_app,
         model::types::mock::{DEFAULT_EMAIL, DEFAULT_PASSWORD, DEFAULT_USER_ID},
-        testing::{default_test_client, test_client_with, test_data::TEST_DATA},
+        routing::cookies::CSRF_HEADER,
+        state::{
+            identity::stub::{StubProvider, StubUser},
+            jwt::Jwt,
+        },
+        testing::{
+            default_test_client, spans::SpanCapture, test_client_from, test_client_with,
+            test_data::TEST_DATA, test_services,
+        },
     };
 
     #[tokio::test]
@@ -81,4 +267,322 @@ mod tests {
 
         assert!(matches!(resp, Value::Object(obj) if obj.contains_key("jwt")));
     }
+
+    #[tokio::test]
+    async fn browsers_can_keep_sessions_in_cookies() {
+        let (client, _) = test_client_with(TEST_DATA.clone());
+        let body = json!({
+            "email": DEFAULT_EMAIL.clone(),
+            "password": DEFAULT_PASSWORD.expose_secret().clone(),
+        });
+        let resp = client
+            .post("/auth/login?session=cookie")
+            .json(&body)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let cookies: Vec<_> = resp
+            .headers()
+            .get_all(SET_COOKIE)
+            .iter()
+            .map(|cookie| cookie.to_str().unwrap().to_string())
+            .collect();
+        let resp: Value = resp.json().await;
+        assert!(resp.get("jwt").is_none());
+        let csrf_token = resp["csrf_token"].as_str().unwrap().to_string();
+
+        let session = cookies
+            .iter()
+            .find(|cookie| cookie.starts_with("session="))
+            .unwrap();
+        assert!(session.contains("HttpOnly"));
+        assert!(session.contains("Secure"));
+        assert!(session.contains("SameSite=Strict"));
+        let session = session.split(';').next().unwrap().to_string();
+
+        let resp = client.get("/me").header(COOKIE, &session).send().await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = client
+            .get("/auth/sessions")
+            .header(COOKIE, &session)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = client
+            .post("/auth/logout")
+            .header(COOKIE, &session)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let resp = client
+            .post("/auth/logout")
+            .header(COOKIE, &session)
+            .header(CSRF_HEADER, "not-a-token")
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let resp = client
+            .post("/auth/logout")
+            .header(COOKIE, &session)
+            .header(CSRF_HEADER, &csrf_token)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let cleared = resp.headers().get_all(SET_COOKIE).iter().count();
+        assert_eq!(cleared, 2);
+
+        let resp = client
+            .get("/auth/sessions")
+            .header(COOKIE, &session)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn magic_link_test() {
+        let (client, services) = test_client_with(TEST_DATA.clone());
+        let body = json!({ "email": DEFAULT_EMAIL.clone() });
+        let resp = client.post("/auth/magic-link").json(&body).send().await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let token = services.mailer.as_mock().token_sent_to(&DEFAULT_EMAIL);
+        let body = json!({ "token": token.expose_secret() });
+        let resp = client
+            .post("/auth/magic-link/consume")
+            .json(&body)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp: Value = resp.json().await;
+        assert!(matches!(resp, Value::Object(obj) if obj.contains_key("jwt")));
+
+        let resp = client
+            .post("/auth/magic-link/consume")
+            .json(&body)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn password_reset_test() {
+        let (client, services) = test_client_with(TEST_DATA.clone());
+        let body = json!({ "email": DEFAULT_EMAIL.clone() });
+        let resp = client.post("/auth/password-reset").json(&body).send().await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let token = services.mailer.as_mock().token_sent_to(&DEFAULT_EMAIL);
+        let body = json!({ "token": token.expose_secret(), "password": "short" });
+        let resp = client
+            .post("/auth/password-reset/confirm")
+            .json(&body)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let resp: Value = resp.json().await;
+        assert_eq!(resp["reason"], "too_short");
+
+        let password = "staple horse battery";
+        let body = json!({ "token": token.expose_secret(), "password": password });
+        let resp = client
+            .post("/auth/password-reset/confirm")
+            .json(&body)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let body = json!({ "email": DEFAULT_EMAIL.clone(), "password": password });
+        let resp = client.post("/auth/login").json(&body).send().await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn sessions_can_be_listed_and_revoked() {
+        let (client, _) = test_client_with(TEST_DATA.clone());
+        let body = json!({
+            "email": DEFAULT_EMAIL.clone(),
+            "password": DEFAULT_PASSWORD.expose_secret().clone(),
+        });
+        let mut jwts = vec![];
+        for user_agent in ["laptop", "phone"] {
+            let resp = client
+                .post("/auth/login")
+                .header("user-agent", user_agent)
+                .json(&body)
+                .send()
+                .await;
+            let resp: Value = resp.json().await;
+            jwts.push(format!("Bearer {}", resp["jwt"].as_str().unwrap()));
+        }
+        let (laptop, phone) = (&jwts[0], &jwts[1]);
+
+        let resp = client
+            .get("/auth/sessions")
+            .header(AUTHORIZATION, laptop)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp: Value = resp.json().await;
+        let sessions = resp["sessions"].as_array().unwrap();
+        assert_eq!(sessions.len(), 2);
+        let phone_session = sessions
+            .iter()
+            .find(|session| session["user_agent"] == "phone")
+            .unwrap();
+        assert_eq!(phone_session["current"], false);
+
+        let uri = format!("/auth/sessions/{}", phone_session["id"].as_str().unwrap());
+        let resp = client
+            .delete(&uri)
+            .header(AUTHORIZATION, laptop)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = client
+            .get("/auth/sessions")
+            .header(AUTHORIZATION, phone)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let resp = client
+            .delete("/auth/sessions")
+            .header(AUTHORIZATION, laptop)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp: Value = resp.json().await;
+        assert_eq!(resp, json!({"revoked": 1}));
+        let resp = client
+            .get("/auth/sessions")
+            .header(AUTHORIZATION, laptop)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn spans_contain_no_secrets() {
+        let capture = SpanCapture::default();
+        let dispatch = Dispatch::new(tracing_subscriber::registry().with(capture.clone()));
+
+        let services = test_services();
+        let app = make_app(services.clone());
+        let body = json!({
+            "email": DEFAULT_EMAIL.clone(),
+            "password": DEFAULT_PASSWORD.expose_secret().clone(),
+        });
+        let request = |uri| {
+            Request::post(uri)
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+
+        let mut jwts = vec![];
+        for uri in ["/auth/create-user", "/auth/login"] {
+            let resp = app
+                .clone()
+                .oneshot(request(uri))
+                .with_subscriber(dispatch.clone())
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+
+            let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            let body: Value = serde_json::from_slice(&body).unwrap();
+            jwts.push(Jwt::new(body["jwt"].as_str().unwrap().to_string()));
+        }
+
+        for jwt in &jwts {
+            dispatcher::with_default(&dispatch, || services.auth.validate_jwt(jwt).unwrap());
+        }
+
+        let user = services
+            .auth
+            .user_with_id(*DEFAULT_USER_ID)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let output = capture.output();
+        assert!(output.contains(&DEFAULT_USER_ID.0.to_string()));
+        assert!(output.contains(&DEFAULT_EMAIL.redacted()));
+
+        assert!(!output.contains(DEFAULT_EMAIL.as_str()));
+        assert!(!output.contains(DEFAULT_PASSWORD.expose_secret()));
+        assert!(!output.contains(user.password_hash.expose_secret()));
+        for jwt in &jwts {
+            assert!(!output.contains(jwt.expose_secret()));
+        }
+        assert!(!output.contains("KeyPair"));
+        assert!(!output.contains("Services"));
+    }
+
+    #[tokio::test]
+    async fn can_sign_in_with_identity_provider() {
+        let stub = StubProvider::start().await;
+        let (client, services) = test_client_from(stub.deps(), TEST_DATA.clone());
+
+        let resp = client.get("/auth/oidc").send().await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp: Value = resp.json().await;
+        assert_eq!(resp, json!({ "providers": ["stub"] }));
+
+        let resp = client.post("/auth/oidc/stub/start").send().await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let cookie = resp.headers()[SET_COOKIE].to_str().unwrap();
+        assert!(cookie.starts_with("identity_binding="));
+        assert!(cookie.contains("HttpOnly"));
+        let (binding, _) = cookie.split_once(';').unwrap();
+        let binding = binding.to_string();
+        let resp: Value = resp.json().await;
+        let url = resp["authorization_url"].as_str().unwrap().parse().unwrap();
+        let code = stub.authorize(&url, StubUser::new("someone", "someone@email.com"));
+        let (_, state) = url.query_pairs().find(|(key, _)| key == "state").unwrap();
+
+        // from a browser that didn't start the sign in
+        let body = json!({ "code": code, "state": state });
+        let resp = client
+            .post("/auth/oidc/stub/callback")
+            .json(&body)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let resp = client
+            .post("/auth/oidc/stub/callback")
+            .header(COOKIE, &binding)
+            .json(&body)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let cleared = resp.headers()[SET_COOKIE].to_str().unwrap();
+        assert!(cleared.starts_with("identity_binding=;"));
+        let resp: Value = resp.json().await;
+        let jwt = Jwt::new(resp["jwt"].as_str().unwrap().into());
+        let claims = services.auth.authenticate(&jwt).await.unwrap();
+        let user = services
+            .auth
+            .user_with_id(claims.subject)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.email.0, "someone@email.com");
+
+        // the code has been used
+        let resp = client
+            .post("/auth/oidc/stub/callback")
+            .header(COOKIE, &binding)
+            .json(&body)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let resp = client.post("/auth/oidc/nowhere/start").send().await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
 }
@@ -1,7 +1,9 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use url::Url;
 
 use crate::{
-    model::types::{Email, Password},
+    model::types::{Email, Password, SessionId, Token},
     state::jwt::Jwt,
 };
 
@@ -26,9 +28,74 @@ pub struct LoginRequest {
 pub struct LoginResponse {
     pub jwt: Jwt,
 }
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MagicLinkRequest {
+    pub email: Email,
+}
+
+/// The token from a sign in link
+#[derive(Debug, Clone, Deserialize)]
+pub struct ConsumeMagicLinkRequest {
+    pub token: Token,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PasswordResetRequest {
+    pub email: Email,
+}
+
+/// The token from a reset link, and the password chosen to replace the forgotten one
+#[derive(Debug, Clone, Deserialize)]
+pub struct ResetPasswordRequest {
+    pub token: Token,
+    pub password: Password,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IdentityProvidersResponse {
+    pub providers: Vec<String>,
+}
+
+/// Where to send the user to sign in at an identity provider
+#[derive(Debug, Clone, Serialize)]
+pub struct AuthorizationUrlResponse {
+    pub authorization_url: Url,
+}
+
+/// What an identity provider sent the user back with
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdentityCallbackRequest {
+    pub code: String,
+    pub state: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionsResponse {
+    pub sessions: Vec<SessionResponse>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionResponse {
+    pub id: SessionId,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    /// Whether this is the session the request was made with
+    pub current: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RevokeSessionsResponse {
+    pub revoked: usize,
+}
 #[cfg(test)]
 mod tests {
-    use crate::model::types::mock::{DEFAULT_EMAIL, DEFAULT_PASSWORD};
+    use crate::{
+        model::types::mock::{DEFAULT_EMAIL, DEFAULT_PASSWORD},
+        state::time::mock::DEFAULT_DATE_TIME,
+    };
 
     use super::*;
     use microtype::{secrecy::ExposeSecret, SecretMicrotype};
@@ -71,4 +138,30 @@ mod tests {
 
         assert_eq!(to_value(response).unwrap(), json!({"jwt": "foo"}));
     }
+
+    #[test]
+    fn sessions_response_test() {
+        let response = SessionsResponse {
+            sessions: vec![SessionResponse {
+                id: SessionId("foo".into()),
+                created_at: *DEFAULT_DATE_TIME,
+                last_seen_at: *DEFAULT_DATE_TIME,
+                user_agent: Some("curl".into()),
+                ip: None,
+                current: true,
+            }],
+        };
+
+        assert_eq!(
+            to_value(response).unwrap(),
+            json!({"sessions": [{
+                "id": "foo",
+                "created_at": "2020-01-01T00:00:00Z",
+                "last_seen_at": "2020-01-01T00:00:00Z",
+                "user_agent": "curl",
+                "ip": null,
+                "current": true,
+            }]})
+        );
+    }
 }
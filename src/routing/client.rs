@@ -0,0 +1,54 @@
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+};
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header::USER_AGENT, request::Parts},
+};
+
+use super::request_id::RequestId;
+
+/// Longest user agent we keep, anything longer is cut short
+const MAX_USER_AGENT_LEN: usize = 512;
+
+/// Where a request came from, as far as we can tell
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    /// The peer's address, which is a proxy's if there's one in front of us
+    pub ip: Option<IpAddr>,
+    /// Only missing outside of the `request_id` middleware, e.g. in background jobs
+    pub request_id: Option<String>,
+}
+
+#[axum::async_trait]
+impl<S> FromRequestParts<S> for ClientInfo
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        let user_agent = parts
+            .headers
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(|user_agent| user_agent.chars().take(MAX_USER_AGENT_LEN).collect());
+
+        // only present when served with `into_make_service_with_connect_info`
+        let ip = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+
+        let request_id = parts.extensions.get::<RequestId>().map(|id| id.to_string());
+
+        Ok(Self {
+            user_agent,
+            ip,
+            request_id,
+        })
+    }
+}
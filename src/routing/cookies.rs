@@ -0,0 +1,127 @@
+use axum::{
+    headers::Cookie,
+    http::{header::SET_COOKIE, HeaderName},
+    response::{AppendHeaders, IntoResponse, Response},
+    Json, TypedHeader,
+};
+use microtype::{secrecy::ExposeSecret, SecretMicrotype};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    model::types::Token,
+    state::{identity::STATE_TTL_MINUTES, jwt::Jwt, Services},
+};
+
+use super::errors::ApiError;
+
+/// Holds the session's JWT, out of reach of scripts
+pub const SESSION_COOKIE: &str = "session";
+/// Holds the session's CSRF token, which scripts read to send back in `CSRF_HEADER`
+pub const CSRF_COOKIE: &str = "csrf_token";
+/// Has to be sent with any request that relies on the session cookie and could change something
+pub const CSRF_HEADER: &str = "x-csrf-token";
+/// Ties a sign in at an identity provider to the browser that started it
+pub const IDENTITY_COOKIE: &str = "identity_binding";
+
+/// How a client wants to be handed a new session
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionMode {
+    /// A JWT in the response body, to send back in the `Authorization` header
+    #[default]
+    Bearer,
+    /// Cookies, for browsers, so the JWT never has to be seen by scripts
+    Cookie,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SessionQuery {
+    #[serde(default)]
+    pub session: SessionMode,
+}
+
+/// What a browser is told when its session is kept in cookies
+#[derive(Debug, Clone, Serialize)]
+pub struct CookieSessionResponse {
+    pub csrf_token: Jwt,
+}
+
+impl SessionMode {
+    /// Hand `jwt` to the client the way it asked for, with `body` making the bearer response
+    pub fn respond<T: Serialize>(
+        self,
+        services: &Services,
+        jwt: Jwt,
+        body: impl FnOnce(Jwt) -> T,
+    ) -> Result<Response, ApiError> {
+        if self == SessionMode::Bearer {
+            return Ok(Json(body(jwt)).into_response());
+        }
+
+        let csrf_token = services.auth.csrf_token(&jwt)?;
+        let max_age = services.auth.session_ttl().num_seconds();
+        let cookies = [
+            (
+                SET_COOKIE,
+                cookie(SESSION_COOKIE, jwt.expose_secret(), max_age, true),
+            ),
+            (
+                SET_COOKIE,
+                cookie(CSRF_COOKIE, csrf_token.expose_secret(), max_age, false),
+            ),
+        ];
+        let body = Json(CookieSessionResponse { csrf_token });
+        Ok((AppendHeaders(cookies), body).into_response())
+    }
+}
+
+/// Headers that make a browser forget its session cookies
+pub fn clear_cookies() -> AppendHeaders<[(HeaderName, String); 2]> {
+    AppendHeaders([
+        (SET_COOKIE, cookie(SESSION_COOKIE, "", 0, true)),
+        (SET_COOKIE, cookie(CSRF_COOKIE, "", 0, false)),
+    ])
+}
+
+/// Headers that give a browser what ties the sign in it's starting at a provider to it
+pub fn identity_cookie(binding: &Token) -> AppendHeaders<[(HeaderName, String); 1]> {
+    let max_age = STATE_TTL_MINUTES * 60;
+    AppendHeaders([(
+        SET_COOKIE,
+        cookie(IDENTITY_COOKIE, binding.expose_secret(), max_age, true),
+    )])
+}
+
+/// Headers that make a browser forget the sign in it started at a provider, once it's finished
+pub fn clear_identity_cookie() -> AppendHeaders<[(HeaderName, String); 1]> {
+    AppendHeaders([(SET_COOKIE, cookie(IDENTITY_COOKIE, "", 0, true))])
+}
+
+/// What the browser was given when it started a sign in at a provider, if it sent it back
+pub fn identity_binding(cookies: Option<TypedHeader<Cookie>>) -> Option<Token> {
+    let binding = cookies?.get(IDENTITY_COOKIE)?.to_string();
+    Some(Token::new(binding))
+}
+
+/// Every cookie is only ever sent over HTTPS, and never along with requests from other sites
+fn cookie(name: &str, value: &str, max_age: i64, http_only: bool) -> String {
+    let http_only = if http_only { "; HttpOnly" } else { "" };
+    format!("{name}={value}; Path=/; Max-Age={max_age}; Secure; SameSite=Strict{http_only}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cookies_are_locked_down() {
+        assert_eq!(
+            cookie(SESSION_COOKIE, "jwt", 60, true),
+            "session=jwt; Path=/; Max-Age=60; Secure; SameSite=Strict; HttpOnly"
+        );
+        assert_eq!(
+            cookie(CSRF_COOKIE, "token", 60, false),
+            "csrf_token=token; Path=/; Max-Age=60; Secure; SameSite=Strict"
+        );
+    }
+}
@@ -1,9 +1,13 @@
-use axum::{http::StatusCode, response::IntoResponse, Json};
+use axum::{
+    http::{header::WWW_AUTHENTICATE, StatusCode},
+    response::IntoResponse,
+    Json,
+};
 use color_eyre::Report;
 use serde::Serialize;
 use thiserror::Error;
 
-use crate::db::sql::DbError;
+use crate::{db::sql::DbError, state::password::PasswordError};
 
 pub type ApiResponse<T> = Result<Json<T>, ApiError>;
 
@@ -11,6 +15,17 @@ pub type ApiResponse<T> = Result<Json<T>, ApiError>;
 pub enum ApiError {
     #[error("auth")]
     Auth,
+    #[error("not found")]
+    NotFound,
+    /// A request that was well-formed, but had an unacceptable value in `field`
+    #[error("invalid {field}")]
+    Invalid { field: &'static str },
+    /// A password that was chosen, but isn't allowed by the policy
+    #[error("invalid password")]
+    Password(#[from] PasswordError),
+    /// Too many attempts have been made recently, so this one wasn't
+    #[error("limited")]
+    Limited,
     #[error("unknown")]
     Unknown(#[from] Report),
     #[error("db")]
@@ -20,22 +35,44 @@ pub enum ApiError {
 #[derive(Serialize)]
 struct ErrorResponse {
     key: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    field: Option<&'static str>,
+    /// Why the value in `field` was unacceptable, where there's more than one reason it could be
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<&'static str>,
 }
 
 impl ApiError {
     fn response(&self) -> ErrorResponse {
         let key = match self {
             ApiError::Auth => "auth",
+            ApiError::NotFound => "not_found",
+            ApiError::Invalid { .. } | ApiError::Password(_) => "invalid",
+            ApiError::Limited => "limited",
             ApiError::Db(DbError::AlreadyExists { .. }) => "already_exists",
+            ApiError::Db(DbError::PoolTimeout) => "unavailable",
             ApiError::Db(_) | ApiError::Unknown(_) => "unknown",
         };
-        ErrorResponse { key }
+        let field = match self {
+            ApiError::Invalid { field } => Some(*field),
+            ApiError::Password(_) => Some("password"),
+            _ => None,
+        };
+        let reason = match self {
+            ApiError::Password(e) => Some(e.key()),
+            _ => None,
+        };
+        ErrorResponse { key, field, reason }
     }
 
     fn code(&self) -> StatusCode {
         match self {
             ApiError::Auth => StatusCode::FORBIDDEN,
+            ApiError::NotFound => StatusCode::NOT_FOUND,
+            ApiError::Invalid { .. } | ApiError::Password(_) => StatusCode::BAD_REQUEST,
+            ApiError::Limited => StatusCode::TOO_MANY_REQUESTS,
             ApiError::Db(DbError::AlreadyExists { .. }) => StatusCode::BAD_REQUEST,
+            ApiError::Db(DbError::PoolTimeout) => StatusCode::SERVICE_UNAVAILABLE,
             ApiError::Db(_) | ApiError::Unknown(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -53,13 +90,74 @@ impl IntoResponse for ApiError {
     }
 }
 
+/// An error from `/oauth/token`, in the shape RFC 6749 has clients expect rather than our own
+#[derive(Debug, Error)]
+pub enum OAuthError {
+    /// A parameter the grant needs is missing, or the request is otherwise malformed
+    #[error("invalid_request")]
+    InvalidRequest,
+    /// The client doesn't exist, or didn't prove it's who it says it is
+    #[error("invalid_client")]
+    InvalidClient,
+    /// The code or refresh token is unknown, expired, used, or for another client
+    #[error("invalid_grant")]
+    InvalidGrant,
+    /// The client isn't allowed to use the grant it asked for
+    #[error("unauthorized_client")]
+    UnauthorizedClient,
+    #[error("unsupported_grant_type")]
+    UnsupportedGrantType,
+    /// More was asked for than the client, or the grant, allows
+    #[error("invalid_scope")]
+    InvalidScope,
+    #[error("server_error")]
+    Server(ApiError),
+}
+
+impl From<DbError> for OAuthError {
+    fn from(e: DbError) -> Self {
+        Self::Server(e.into())
+    }
+}
+
+#[derive(Serialize)]
+struct OAuthErrorResponse {
+    error: String,
+}
+
+impl IntoResponse for OAuthError {
+    fn into_response(self) -> axum::response::Response {
+        if let OAuthError::Server(e) = &self {
+            error!("failed to issue oauth tokens: {e:?}");
+        }
+        let code = match self {
+            OAuthError::InvalidClient => StatusCode::UNAUTHORIZED,
+            OAuthError::Server(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        };
+
+        let error = self.to_string();
+        let mut response = Json(OAuthErrorResponse { error }).into_response();
+        *response.status_mut() = code;
+        if code == StatusCode::UNAUTHORIZED {
+            let challenge = "Basic realm=\"oauth\"".parse().unwrap();
+            response.headers_mut().insert(WWW_AUTHENTICATE, challenge);
+        }
+
+        response
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use axum::{http::StatusCode, routing::get, Router};
     use axum_test_helper::TestClient;
     use serde_json::{json, Value};
 
-    use crate::routing::errors::{ApiError, ApiResponse};
+    use crate::{
+        routing::errors::{ApiError, ApiResponse},
+        state::password::PasswordError,
+    };
 
     #[tokio::test]
     async fn error_response_conforms() {
@@ -80,4 +178,26 @@ mod tests {
             })
         );
     }
+
+    #[tokio::test]
+    async fn password_errors_give_the_reason() {
+        async fn handler() -> ApiResponse<()> {
+            Err(PasswordError::Breached.into())
+        }
+
+        let router = Router::new().route("/", get(handler));
+        let client = TestClient::new(router);
+
+        let response = client.get("/").send().await;
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            response.json::<Value>().await,
+            json!({
+                "key": "invalid",
+                "field": "password",
+                "reason": "breached"
+            })
+        );
+    }
 }
@@ -1,10 +1,27 @@
+use axum::{extract::State, http::StatusCode, Json};
+
+use crate::state::{health::ReadinessReport, Services};
+
 use super::errors::ApiResponse;
 
+/// Liveness probe: succeeds as long as the process is able to serve requests
 #[instrument]
-pub(super) async fn health() -> ApiResponse<&'static str> {
+pub(super) async fn live() -> ApiResponse<&'static str> {
     Ok("OK".into())
 }
 
+/// Readiness probe: succeeds only if every dependency needed to serve traffic is available
+#[instrument(skip(services))]
+pub(super) async fn ready(State(services): State<Services>) -> (StatusCode, Json<ReadinessReport>) {
+    let report = services.health.readiness().await;
+    let code = match report.ready {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+
+    (code, Json(report))
+}
+
 #[cfg(test)]
 mod tests {
     use axum::http::StatusCode;
@@ -20,4 +37,32 @@ mod tests {
         assert_eq!(resp.status(), StatusCode::OK);
         assert_eq!(resp.json::<Value>().await, json!("OK"));
     }
+
+    #[tokio::test]
+    async fn live_test() {
+        let (client, _) = default_test_client();
+        let resp = client.get("/health/live").send().await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.json::<Value>().await, json!("OK"));
+    }
+
+    #[tokio::test]
+    async fn ready_test() {
+        let (client, _) = default_test_client();
+        let resp = client.get("/health/ready").send().await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.json::<Value>().await,
+            json!({
+                "ready": true,
+                "components": {
+                    "db": {"status": "ok"},
+                    "migrations": {"status": "ok"},
+                    "signing_keys": {"status": "ok"},
+                },
+            })
+        );
+    }
 }
@@ -0,0 +1,527 @@
+use axum::{
+    extract::{Path, State},
+    headers::Cookie,
+    http::header::CONTENT_DISPOSITION,
+    response::{IntoResponse, Response},
+    Json, TypedHeader,
+};
+use uuid::Uuid;
+
+use self::requests::{
+    ApiKeyResponse, ApiKeysResponse, ChangeEmailRequest, ChangePasswordRequest,
+    CreateApiKeyRequest, CreatedApiKeyResponse, EmailChangeResponse, EmailChangeTokenRequest,
+    ExportResponse, IdentitiesResponse, IdentityResponse, OAuthConsentsResponse, ProfileResponse,
+    UnlinkIdentityRequest, UpdateProfileRequest,
+};
+use super::{
+    auth::requests::{AuthorizationUrlResponse, IdentityCallbackRequest},
+    client::ClientInfo,
+    cookies::{clear_identity_cookie, identity_binding, identity_cookie},
+    errors::{ApiError, ApiResponse},
+};
+use crate::{
+    model::types::ClientId,
+    state::{
+        jwt::claims::{Claims, Validated},
+        principal::Principal,
+        Services,
+    },
+};
+
+pub mod requests;
+
+/// Also open to API keys, like anything else scripts might want
+#[instrument(skip_all, fields(user_id = %principal.user_id.0))]
+pub(super) async fn profile(
+    State(services): State<Services>,
+    principal: Principal,
+) -> ApiResponse<ProfileResponse> {
+    let user = services.profile.profile(&principal).await?;
+    Ok(ProfileResponse::from(user).into())
+}
+
+#[instrument(skip_all, fields(user_id = %principal.user_id.0))]
+pub(super) async fn update_profile(
+    State(services): State<Services>,
+    principal: Principal,
+    Json(request): Json<UpdateProfileRequest>,
+) -> ApiResponse<ProfileResponse> {
+    let user = services
+        .profile
+        .update_profile(&principal, request.into())
+        .await?;
+    Ok(ProfileResponse::from(user).into())
+}
+
+/// Served as a download, so that it can be saved straight from a browser
+#[instrument(skip_all, fields(user_id = %claims.subject.0))]
+pub(super) async fn export(
+    State(services): State<Services>,
+    claims: Claims<Validated>,
+    client: ClientInfo,
+) -> Result<Response, ApiError> {
+    let export = services.privacy.export(&claims, &client).await?;
+    let disposition = format!("attachment; filename=\"export-{}.json\"", claims.subject.0);
+
+    Ok((
+        [(CONTENT_DISPOSITION, disposition)],
+        Json(ExportResponse::from(export)),
+    )
+        .into_response())
+}
+
+#[instrument(skip_all, fields(user_id = %claims.subject.0))]
+pub(super) async fn change_password(
+    State(services): State<Services>,
+    claims: Claims<Validated>,
+    client: ClientInfo,
+    Json(ChangePasswordRequest {
+        password,
+        new_password,
+    }): Json<ChangePasswordRequest>,
+) -> ApiResponse<()> {
+    services
+        .auth
+        .change_password(&claims, password, new_password, &client)
+        .await?;
+    Ok(Json(()))
+}
+
+#[instrument(skip_all, fields(user_id = %claims.subject.0))]
+pub(super) async fn change_email(
+    State(services): State<Services>,
+    claims: Claims<Validated>,
+    client: ClientInfo,
+    Json(ChangeEmailRequest { email, password }): Json<ChangeEmailRequest>,
+) -> ApiResponse<EmailChangeResponse> {
+    let change = services
+        .email_change
+        .start(&claims, email, password, &client)
+        .await?;
+    Ok(EmailChangeResponse::from(change).into())
+}
+
+/// Unauthenticated, since the link may well be opened somewhere the user isn't signed in
+#[instrument(skip_all)]
+pub(super) async fn confirm_email_change(
+    State(services): State<Services>,
+    client: ClientInfo,
+    Json(EmailChangeTokenRequest { token }): Json<EmailChangeTokenRequest>,
+) -> ApiResponse<()> {
+    services.email_change.confirm(token, &client).await?;
+    Ok(Json(()))
+}
+
+#[instrument(skip_all)]
+pub(super) async fn cancel_email_change(
+    State(services): State<Services>,
+    client: ClientInfo,
+    Json(EmailChangeTokenRequest { token }): Json<EmailChangeTokenRequest>,
+) -> ApiResponse<()> {
+    services.email_change.cancel(token, &client).await?;
+    Ok(Json(()))
+}
+
+#[instrument(skip_all, fields(user_id = %claims.subject.0))]
+pub(super) async fn identities(
+    State(services): State<Services>,
+    claims: Claims<Validated>,
+) -> ApiResponse<IdentitiesResponse> {
+    let identities = services.identity.identities(&claims).await?;
+    let identities = identities.into_iter().map(Into::into).collect();
+    Ok(IdentitiesResponse { identities }.into())
+}
+
+#[instrument(skip_all, fields(user_id = %claims.subject.0, provider = %provider))]
+pub(super) async fn start_link_identity(
+    State(services): State<Services>,
+    claims: Claims<Validated>,
+    Path(provider): Path<String>,
+) -> Result<Response, ApiError> {
+    let started = services.identity.start(&provider, Some(&claims)).await?;
+    let body = Json(AuthorizationUrlResponse {
+        authorization_url: started.authorization_url,
+    });
+    Ok((identity_cookie(&started.binding), body).into_response())
+}
+
+#[instrument(skip_all, fields(user_id = %claims.subject.0, provider = %provider))]
+pub(super) async fn link_identity(
+    State(services): State<Services>,
+    claims: Claims<Validated>,
+    client: ClientInfo,
+    cookies: Option<TypedHeader<Cookie>>,
+    Path(provider): Path<String>,
+    Json(IdentityCallbackRequest { code, state }): Json<IdentityCallbackRequest>,
+) -> Result<Response, ApiError> {
+    let binding = identity_binding(cookies);
+    let identity = services
+        .identity
+        .link(&claims, &provider, &code, &state, binding.as_ref(), &client)
+        .await?;
+    let body = Json(IdentityResponse::from(identity));
+    Ok((clear_identity_cookie(), body).into_response())
+}
+
+#[instrument(skip_all, fields(user_id = %claims.subject.0, provider = %provider))]
+pub(super) async fn unlink_identity(
+    State(services): State<Services>,
+    claims: Claims<Validated>,
+    client: ClientInfo,
+    Path(provider): Path<String>,
+    Json(UnlinkIdentityRequest { password }): Json<UnlinkIdentityRequest>,
+) -> ApiResponse<()> {
+    services
+        .identity
+        .unlink(&claims, &provider, password, &client)
+        .await?;
+    Ok(Json(()))
+}
+
+#[instrument(skip_all, fields(user_id = %claims.subject.0))]
+pub(super) async fn oauth_consents(
+    State(services): State<Services>,
+    claims: Claims<Validated>,
+) -> ApiResponse<OAuthConsentsResponse> {
+    let consents = services.oauth.consents(&claims).await?;
+    Ok(OAuthConsentsResponse { consents }.into())
+}
+
+#[instrument(skip_all, fields(user_id = %claims.subject.0, client_id = %client_id.0))]
+pub(super) async fn revoke_oauth_consent(
+    State(services): State<Services>,
+    claims: Claims<Validated>,
+    client: ClientInfo,
+    Path(client_id): Path<ClientId>,
+) -> ApiResponse<()> {
+    services
+        .oauth
+        .revoke_consent(&claims, client_id, &client)
+        .await?;
+    Ok(Json(()))
+}
+
+#[instrument(skip_all, fields(user_id = %claims.subject.0))]
+pub(super) async fn api_keys(
+    State(services): State<Services>,
+    claims: Claims<Validated>,
+) -> ApiResponse<ApiKeysResponse> {
+    let keys = services.api_keys.keys(&claims).await?;
+    let api_keys = keys.into_iter().map(ApiKeyResponse::from).collect();
+    Ok(ApiKeysResponse { api_keys }.into())
+}
+
+#[instrument(skip_all, fields(user_id = %claims.subject.0))]
+pub(super) async fn create_api_key(
+    State(services): State<Services>,
+    claims: Claims<Validated>,
+    client: ClientInfo,
+    Json(request): Json<CreateApiKeyRequest>,
+) -> ApiResponse<CreatedApiKeyResponse> {
+    let created = services
+        .api_keys
+        .create(
+            &claims,
+            request.name,
+            request.scopes,
+            request.expires_at,
+            &client,
+        )
+        .await?;
+    Ok(CreatedApiKeyResponse::from(created).into())
+}
+
+#[instrument(skip_all, fields(user_id = %claims.subject.0, key_id = %key_id))]
+pub(super) async fn revoke_api_key(
+    State(services): State<Services>,
+    claims: Claims<Validated>,
+    client: ClientInfo,
+    Path(key_id): Path<Uuid>,
+) -> ApiResponse<()> {
+    services.api_keys.revoke(&claims, key_id, &client).await?;
+    Ok(Json(()))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{
+        header::{AUTHORIZATION, CONTENT_DISPOSITION, COOKIE, SET_COOKIE},
+        StatusCode,
+    };
+    use microtype::secrecy::ExposeSecret;
+    use serde_json::{json, Value};
+
+    use crate::{
+        model::types::{
+            mock::{DEFAULT_EMAIL, DEFAULT_PASSWORD, DEFAULT_USER_ID},
+            Email,
+        },
+        state::identity::stub::{StubProvider, StubUser},
+        testing::{bearer, test_client_from, test_client_with, test_data::TEST_DATA},
+    };
+
+    #[tokio::test]
+    async fn can_view_and_update_profile() {
+        let (client, services) = test_client_with(TEST_DATA.clone());
+        let bearer = bearer(&services, &DEFAULT_EMAIL).await;
+
+        let resp = client
+            .get("/me")
+            .header(AUTHORIZATION, &bearer)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let profile: Value = resp.json().await;
+        assert_eq!(profile["id"], json!(DEFAULT_USER_ID.0));
+        assert_eq!(profile["display_name"], Value::Null);
+
+        let body = json!({ "display_name": "Ada", "timezone": "Europe/London" });
+        let resp = client
+            .patch("/me")
+            .header(AUTHORIZATION, &bearer)
+            .json(&body)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let updated: Value = resp.json().await;
+        assert_eq!(updated["display_name"], "Ada");
+        assert_eq!(updated["timezone"], "Europe/London");
+        assert_ne!(updated["updated_at"], profile["updated_at"]);
+
+        let body = json!({ "display_name": null });
+        let resp = client
+            .patch("/me")
+            .header(AUTHORIZATION, &bearer)
+            .json(&body)
+            .send()
+            .await;
+        let cleared: Value = resp.json().await;
+        assert_eq!(cleared["display_name"], Value::Null);
+        assert_eq!(cleared["timezone"], "Europe/London");
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_profile() {
+        let (client, services) = test_client_with(TEST_DATA.clone());
+        let bearer = bearer(&services, &DEFAULT_EMAIL).await;
+
+        let body = json!({ "locale": "en-GB", "avatar_url": "javascript:alert(1)" });
+        let resp = client
+            .patch("/me")
+            .header(AUTHORIZATION, &bearer)
+            .json(&body)
+            .send()
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let resp: Value = resp.json().await;
+        assert_eq!(resp, json!({ "key": "invalid", "field": "avatar_url" }));
+        let user = services.db.user_by_id(*DEFAULT_USER_ID).await.unwrap();
+        assert_eq!(user.unwrap().locale, None);
+    }
+
+    #[tokio::test]
+    async fn can_change_email() {
+        let (client, services) = test_client_with(TEST_DATA.clone());
+        let bearer = bearer(&services, &DEFAULT_EMAIL).await;
+        let new_email = Email("new@email.com".into());
+
+        let body = json!({
+            "email": new_email,
+            "password": DEFAULT_PASSWORD.expose_secret(),
+        });
+        let resp = client
+            .post("/me/email")
+            .header(AUTHORIZATION, &bearer)
+            .json(&body)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp: Value = resp.json().await;
+        assert_eq!(resp["new_email"], "new@email.com");
+
+        let token = services.mailer.as_mock().token_sent_to(&new_email);
+        let body = json!({ "token": token.expose_secret() });
+        let resp = client.post("/me/email/confirm").json(&body).send().await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = client
+            .get("/me")
+            .header(AUTHORIZATION, &bearer)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let resp = client.post("/me/email/cancel").json(&body).send().await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn export_is_a_download() {
+        let (client, services) = test_client_with(TEST_DATA.clone());
+        let bearer = bearer(&services, &DEFAULT_EMAIL).await;
+
+        let resp = client
+            .get("/me/export")
+            .header(AUTHORIZATION, &bearer)
+            .send()
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let disposition = resp.headers()[CONTENT_DISPOSITION].to_str().unwrap();
+        assert_eq!(
+            disposition,
+            format!("attachment; filename=\"export-{}.json\"", DEFAULT_USER_ID.0)
+        );
+        let export: Value = resp.json().await;
+        assert_eq!(export["profile"]["email"], "default@email.com");
+        assert_eq!(export["sessions"].as_array().unwrap().len(), 1);
+        assert_eq!(export["pending_email_change"], Value::Null);
+        assert_eq!(export["audit_events"][0]["action"], "login");
+
+        assert_eq!(
+            client.get("/me/export").send().await.status(),
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[tokio::test]
+    async fn profile_needs_auth() {
+        let (client, _) = test_client_with(TEST_DATA.clone());
+
+        assert_eq!(
+            client.get("/me").send().await.status(),
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[tokio::test]
+    async fn can_link_and_unlink_identities() {
+        let stub = StubProvider::start().await;
+        let (client, services) = test_client_from(stub.deps(), TEST_DATA.clone());
+        let bearer = bearer(&services, &DEFAULT_EMAIL).await;
+
+        let resp = client
+            .post("/me/identities/stub/start")
+            .header(AUTHORIZATION, &bearer)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let cookie = resp.headers()[SET_COOKIE].to_str().unwrap();
+        let (binding, _) = cookie.split_once(';').unwrap();
+        let binding = binding.to_string();
+        let resp: Value = resp.json().await;
+        let url = resp["authorization_url"].as_str().unwrap().parse().unwrap();
+        let code = stub.authorize(&url, StubUser::new("someone", "someone@email.com"));
+        let (_, state) = url.query_pairs().find(|(key, _)| key == "state").unwrap();
+
+        let body = json!({ "code": code, "state": state });
+        let resp = client
+            .post("/me/identities/stub/callback")
+            .header(AUTHORIZATION, &bearer)
+            .header(COOKIE, &binding)
+            .json(&body)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let identity: Value = resp.json().await;
+        assert_eq!(identity["provider"], "stub");
+        assert_eq!(identity["email"], "someone@email.com");
+
+        let resp = client
+            .get("/me/identities")
+            .header(AUTHORIZATION, &bearer)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp: Value = resp.json().await;
+        assert_eq!(resp, json!({ "identities": [identity] }));
+
+        let body = json!({ "password": "wrong password" });
+        let resp = client
+            .delete("/me/identities/stub")
+            .header(AUTHORIZATION, &bearer)
+            .json(&body)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let body = json!({ "password": DEFAULT_PASSWORD.expose_secret() });
+        let resp = client
+            .delete("/me/identities/stub")
+            .header(AUTHORIZATION, &bearer)
+            .json(&body)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = client
+            .delete("/me/identities/stub")
+            .header(AUTHORIZATION, &bearer)
+            .json(&body)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn api_keys_work_where_scripts_need_them() {
+        let (client, services) = test_client_with(TEST_DATA.clone());
+        let bearer = bearer(&services, &DEFAULT_EMAIL).await;
+
+        let body = json!({
+            "name": "ci",
+            "scopes": "profile",
+            "expires_at": "2020-02-01T00:00:00Z",
+        });
+        let resp = client
+            .post("/me/api-keys")
+            .header(AUTHORIZATION, &bearer)
+            .json(&body)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let created: Value = resp.json().await;
+        assert_eq!(created["name"], "ci");
+        let key = format!("Bearer {}", created["key"].as_str().unwrap());
+
+        let resp = client.get("/me").header(AUTHORIZATION, &key).send().await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.json::<Value>().await["id"], json!(DEFAULT_USER_ID.0));
+
+        // only what the key was made for, and nothing that needs a real sign in
+        let body = json!({ "display_name": "Ada" });
+        let resp = client
+            .patch("/me")
+            .header(AUTHORIZATION, &key)
+            .json(&body)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let resp = client
+            .get("/me/api-keys")
+            .header(AUTHORIZATION, &key)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let resp = client
+            .get("/me/api-keys")
+            .header(AUTHORIZATION, &bearer)
+            .send()
+            .await;
+        let keys: Value = resp.json().await;
+        assert_eq!(keys["api_keys"][0]["prefix"], created["prefix"]);
+        assert!(keys["api_keys"][0].get("key").is_none());
+        assert_ne!(keys["api_keys"][0]["last_used_at"], Value::Null);
+
+        let uri = format!("/me/api-keys/{}", created["id"].as_str().unwrap());
+        let resp = client
+            .delete(&uri)
+            .header(AUTHORIZATION, &bearer)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = client.get("/me").header(AUTHORIZATION, &key).send().await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,310 @@
+use chrono::{DateTime, Utc};
+use microtype::secrecy::ExposeSecret;
+use serde::{Deserialize, Deserializer, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    model::{
+        api_key::ApiKey,
+        audit::AuditEvent,
+        email_change::EmailChange,
+        identity::UserIdentity,
+        magic_link::MagicLink,
+        oauth::{OAuthConsent, Scopes},
+        organization::Membership,
+        session::Session,
+        types::{Email, Password, Token, UserId},
+        user::{ProfileChanges, User},
+    },
+    routing::orgs::requests::InvitationResponse,
+    state::{api_key::CreatedApiKey, oauth::ConsentedClient, privacy::UserExport},
+};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProfileResponse {
+    pub id: UserId,
+    pub email: Email,
+    pub display_name: Option<String>,
+    pub locale: Option<String>,
+    pub timezone: Option<String>,
+    pub avatar_url: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<User> for ProfileResponse {
+    fn from(user: User) -> Self {
+        Self {
+            id: user.id,
+            email: user.email,
+            display_name: user.display_name,
+            locale: user.locale,
+            timezone: user.timezone,
+            avatar_url: user.avatar_url,
+            created_at: user.created_at,
+            updated_at: user.updated_at,
+        }
+    }
+}
+
+/// Fields that are left out stay as they are, and fields that are `null` are cleared
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpdateProfileRequest {
+    #[serde(default, deserialize_with = "present")]
+    pub display_name: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub locale: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub timezone: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub avatar_url: Option<Option<String>>,
+}
+
+/// Marks a field as present, even if it's `null`, which a plain `Option<Option<_>>` can't tell
+/// apart from it being left out
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::deserialize(deserializer).map(Some)
+}
+
+impl From<UpdateProfileRequest> for ProfileChanges {
+    fn from(request: UpdateProfileRequest) -> Self {
+        Self {
+            display_name: request.display_name,
+            locale: request.locale,
+            timezone: request.timezone,
+            avatar_url: request.avatar_url,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChangePasswordRequest {
+    pub password: Password,
+    pub new_password: Password,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChangeEmailRequest {
+    pub email: Email,
+    pub password: Password,
+}
+
+/// A change that's waiting for the new address to be confirmed
+#[derive(Debug, Clone, Serialize)]
+pub struct EmailChangeResponse {
+    pub new_email: Email,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl From<EmailChange> for EmailChangeResponse {
+    fn from(change: EmailChange) -> Self {
+        Self {
+            new_email: change.new_email,
+            expires_at: change.expires_at,
+        }
+    }
+}
+
+/// The token from a link sent out about an email change
+#[derive(Debug, Clone, Deserialize)]
+pub struct EmailChangeTokenRequest {
+    pub token: Token,
+}
+
+/// An account at an identity provider that the user can sign in with
+#[derive(Debug, Clone, Serialize)]
+pub struct IdentityResponse {
+    pub provider: String,
+    pub email: Option<Email>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<UserIdentity> for IdentityResponse {
+    fn from(identity: UserIdentity) -> Self {
+        Self {
+            provider: identity.provider,
+            email: identity.email,
+            created_at: identity.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IdentitiesResponse {
+    pub identities: Vec<IdentityResponse>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UnlinkIdentityRequest {
+    pub password: Password,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OAuthConsentsResponse {
+    /// Oldest grant first
+    pub consents: Vec<ConsentedClient>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    pub scopes: Scopes,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiKeyResponse {
+    pub id: Uuid,
+    pub name: String,
+    /// Enough of the key to tell which one it is
+    pub prefix: String,
+    pub scopes: Scopes,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+impl From<ApiKey> for ApiKeyResponse {
+    fn from(key: ApiKey) -> Self {
+        Self {
+            id: key.id,
+            name: key.name,
+            prefix: key.prefix,
+            scopes: key.scopes,
+            created_at: key.created_at,
+            expires_at: key.expires_at,
+            last_used_at: key.last_used_at,
+        }
+    }
+}
+
+/// A sign in link that's out for the user's address, without the token it holds
+#[derive(Debug, Clone, Serialize)]
+pub struct MagicLinkResponse {
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl From<MagicLink> for MagicLinkResponse {
+    fn from(link: MagicLink) -> Self {
+        Self {
+            created_at: link.created_at,
+            expires_at: link.expires_at,
+        }
+    }
+}
+
+/// The only time a key is shown, since only a hash of it is kept
+#[derive(Debug, Clone, Serialize)]
+pub struct CreatedApiKeyResponse {
+    #[serde(flatten)]
+    pub api_key: ApiKeyResponse,
+    pub key: String,
+}
+
+impl From<CreatedApiKey> for CreatedApiKeyResponse {
+    fn from(created: CreatedApiKey) -> Self {
+        Self {
+            api_key: created.key.into(),
+            key: created.secret.expose_secret().clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiKeysResponse {
+    /// Oldest first, including expired keys
+    pub api_keys: Vec<ApiKeyResponse>,
+}
+
+/// A copy of everything stored about the signed in user
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportResponse {
+    pub exported_at: DateTime<Utc>,
+    pub profile: ProfileResponse,
+    pub sessions: Vec<Session>,
+    pub pending_email_change: Option<EmailChangeResponse>,
+    pub identities: Vec<UserIdentity>,
+    pub oauth_consents: Vec<OAuthConsent>,
+    pub api_keys: Vec<ApiKeyResponse>,
+    pub magic_link: Option<MagicLinkResponse>,
+    pub org_memberships: Vec<Membership>,
+    pub org_invitations: Vec<InvitationResponse>,
+    pub audit_events: Vec<AuditEvent>,
+}
+
+impl From<UserExport> for ExportResponse {
+    fn from(export: UserExport) -> Self {
+        Self {
+            exported_at: export.exported_at,
+            profile: export.user.into(),
+            sessions: export.sessions,
+            pending_email_change: export.email_change.map(Into::into),
+            identities: export.identities,
+            oauth_consents: export.oauth_consents,
+            api_keys: export.api_keys.into_iter().map(Into::into).collect(),
+            magic_link: export.magic_link.map(Into::into),
+            org_memberships: export.org_memberships,
+            org_invitations: export.org_invitations.into_iter().map(Into::into).collect(),
+            audit_events: export.audit_events,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{from_value, json, to_value};
+
+    use crate::model::user::mock::default_user;
+
+    use super::*;
+
+    #[test]
+    fn update_profile_request_test() {
+        let request = json!({
+            "display_name": "Ada",
+            "avatar_url": null,
+        });
+
+        let changes = ProfileChanges::from(from_value::<UpdateProfileRequest>(request).unwrap());
+        assert_eq!(
+            changes,
+            ProfileChanges {
+                display_name: Some(Some("Ada".into())),
+                avatar_url: Some(None),
+                ..ProfileChanges::default()
+            }
+        );
+    }
+
+    #[test]
+    fn update_profile_request_rejects_other_fields() {
+        let request = json!({ "email": "other@email.com" });
+
+        assert!(from_value::<UpdateProfileRequest>(request).is_err());
+    }
+
+    #[test]
+    fn profile_response_test() {
+        let response = ProfileResponse::from(default_user());
+
+        assert_eq!(
+            to_value(response).unwrap(),
+            json!({
+                "id": default_user().id.0,
+                "email": "default@email.com",
+                "display_name": null,
+                "locale": null,
+                "timezone": null,
+                "avatar_url": null,
+                "created_at": "2020-01-01T00:00:00Z",
+                "updated_at": "2020-01-01T00:00:00Z",
+            })
+        );
+    }
+}
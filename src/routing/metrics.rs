@@ -0,0 +1,81 @@
+use std::time::Instant;
+
+use axum::{
+    extract::{MatchedPath, State},
+    http::{header, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::state::Services;
+
+use super::errors::ApiError;
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Label used for requests that didn't match any route, to keep label cardinality bounded
+const UNMATCHED: &str = "unmatched";
+
+#[instrument(skip(services))]
+pub(super) async fn metrics(State(services): State<Services>) -> Result<Response, ApiError> {
+    let body = services.metrics.render()?;
+    Ok(([(header::CONTENT_TYPE, CONTENT_TYPE)], body).into_response())
+}
+
+/// Middleware recording the count and latency of every request, by route and status
+pub async fn track<B>(
+    State(services): State<Services>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let start = Instant::now();
+    let method = request.method().clone();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| UNMATCHED.to_string());
+
+    let response = next.run(request).await;
+
+    services.metrics.record_request(
+        method.as_str(),
+        &route,
+        response.status().as_u16(),
+        start.elapsed(),
+    );
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+
+    use crate::testing::default_test_client;
+
+    #[tokio::test]
+    async fn metrics_test() {
+        let (client, _) = default_test_client();
+        client.get("/health/live").send().await;
+
+        let resp = client.get("/metrics").send().await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let text = resp.text().await;
+        assert!(text
+            .contains(r#"http_requests_total{method="GET",route="/health/live",status="200"} 1"#));
+    }
+
+    #[tokio::test]
+    async fn unmatched_routes_share_a_label() {
+        let (client, _) = default_test_client();
+        client.get("/no/such/route").send().await;
+
+        let text = client.get("/metrics").send().await.text().await;
+        assert!(
+            text.contains(r#"http_requests_total{method="GET",route="unmatched",status="404"} 1"#)
+        );
+        assert!(!text.contains("/no/such/route"));
+    }
+}
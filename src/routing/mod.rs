@@ -1,13 +1,22 @@
 use axum::{
-    routing::{get, post},
+    middleware,
+    routing::{delete, get, patch, post},
     Router,
 };
 
 use crate::state::Services;
 
+mod admin;
 mod auth;
 mod health;
+mod me;
+mod metrics;
+mod oauth;
+mod orgs;
+mod request_id;
 
+pub mod client;
+pub mod cookies;
 pub mod errors;
 
 pub fn attach_routes(router: Router<Services>) -> Router<Services> {
@@ -15,9 +24,101 @@ pub fn attach_routes(router: Router<Services>) -> Router<Services> {
         .clone()
         .route("/create-user", post(auth::create_user))
         .route("/login", post(auth::login))
-        .route("/delete-user", post(auth::delete_user));
+        .route("/logout", post(auth::logout))
+        .route("/magic-link", post(auth::send_magic_link))
+        .route("/magic-link/consume", post(auth::consume_magic_link))
+        .route("/password-reset", post(auth::send_password_reset))
+        .route("/password-reset/confirm", post(auth::reset_password))
+        .route("/delete-user", post(auth::delete_user))
+        .route(
+            "/sessions",
+            get(auth::sessions).delete(auth::revoke_all_sessions),
+        )
+        .route("/sessions/:session_id", delete(auth::revoke_session))
+        .route("/oidc", get(auth::identity_providers))
+        .route("/oidc/:provider/start", post(auth::start_identity_login))
+        .route("/oidc/:provider/callback", post(auth::identity_login));
+
+    let admin = router
+        .clone()
+        .route("/users/:user_id/restore", post(admin::restore_user))
+        .route("/users/:user_id/erase", post(admin::erase_user))
+        .route("/audit-events", get(admin::audit_events))
+        .route(
+            "/oauth-clients",
+            get(admin::clients).post(admin::register_client),
+        )
+        .route("/oauth-clients/:client_id", delete(admin::delete_client));
+
+    let me = router
+        .clone()
+        .route("/", get(me::profile).patch(me::update_profile))
+        .route("/export", get(me::export))
+        .route("/password", post(me::change_password))
+        .route("/email", post(me::change_email))
+        .route("/email/confirm", post(me::confirm_email_change))
+        .route("/email/cancel", post(me::cancel_email_change))
+        .route("/identities", get(me::identities))
+        .route("/identities/:provider", delete(me::unlink_identity))
+        .route("/identities/:provider/start", post(me::start_link_identity))
+        .route("/identities/:provider/callback", post(me::link_identity))
+        .route("/oauth-consents", get(me::oauth_consents))
+        .route(
+            "/oauth-consents/:client_id",
+            delete(me::revoke_oauth_consent),
+        )
+        .route("/api-keys", get(me::api_keys).post(me::create_api_key))
+        .route("/api-keys/:key_id", delete(me::revoke_api_key));
+
+    let oauth = router
+        .clone()
+        .route(
+            "/authorize",
+            get(oauth::authorization).post(oauth::authorize),
+        )
+        .route("/token", post(oauth::token))
+        .route("/userinfo", get(oauth::userinfo))
+        .route("/client", get(oauth::client));
+
+    let orgs = router
+        .clone()
+        .route("/", get(orgs::organizations).post(orgs::create))
+        .route("/current", get(orgs::current))
+        .route("/switch", post(orgs::switch))
+        .route("/invitations/accept", post(orgs::accept_invitation))
+        .route("/:org_id", get(orgs::organization).delete(orgs::delete))
+        .route("/:org_id/members", get(orgs::members))
+        .route(
+            "/:org_id/members/:user_id",
+            patch(orgs::change_role).delete(orgs::remove_member),
+        )
+        .route(
+            "/:org_id/invitations",
+            get(orgs::invitations).post(orgs::invite),
+        )
+        .route(
+            "/:org_id/invitations/:invitation_id",
+            delete(orgs::revoke_invitation),
+        );
+
+    let health = router
+        .clone()
+        .route("/", get(health::live))
+        .route("/live", get(health::live))
+        .route("/ready", get(health::ready));
 
     router
-        .route("/health", get(health::health))
+        .route("/metrics", get(metrics::metrics))
+        .nest("/health", health)
         .nest("/auth", auth)
+        .nest("/admin", admin)
+        .nest("/me", me)
+        .nest("/oauth", oauth)
+        .nest("/orgs", orgs)
+}
+
+pub fn attach_middleware(router: Router<Services>, services: Services) -> Router<Services> {
+    router
+        .layer(middleware::from_fn_with_state(services, metrics::track))
+        .layer(middleware::from_fn(request_id::request_id))
 }
@@ -0,0 +1,363 @@
+use axum::{
+    extract::{rejection::FormRejection, Query, State},
+    headers::{
+        authorization::{Basic, Bearer},
+        Authorization,
+    },
+    http::header::{CACHE_CONTROL, PRAGMA},
+    response::{IntoResponse, Response},
+    Form, Json, TypedHeader,
+};
+use microtype::SecretMicrotype;
+
+use self::requests::{
+    AuthorizationResponse, AuthorizeRequest, AuthorizeResponse, ClientResponse, TokenRequest,
+    TokenResponse,
+};
+use super::{
+    client::ClientInfo,
+    errors::{ApiError, ApiResponse, OAuthError},
+};
+use crate::{
+    model::types::{ClientId, ClientSecret},
+    state::{
+        jwt::{
+            claims::{Claims, Validated},
+            Jwt,
+        },
+        oauth::{ActingClient, ClientCredentials, UserInfo},
+        Services,
+    },
+};
+
+pub mod requests;
+
+/// What the frontend shows a user a client is asking for, before they agree to it
+#[instrument(skip_all, fields(user_id = %claims.subject.0))]
+pub(super) async fn authorization(
+    State(services): State<Services>,
+    claims: Claims<Validated>,
+    Query(request): Query<AuthorizeRequest>,
+) -> ApiResponse<AuthorizationResponse> {
+    let prompt = services
+        .oauth
+        .authorization(&claims, &request.try_into()?)
+        .await?;
+    Ok(AuthorizationResponse::from(prompt).into())
+}
+
+/// Posted once the user agrees, with the same parameters the client sent them with
+#[instrument(skip_all, fields(user_id = %claims.subject.0))]
+pub(super) async fn authorize(
+    State(services): State<Services>,
+    claims: Claims<Validated>,
+    client: ClientInfo,
+    Json(request): Json<AuthorizeRequest>,
+) -> ApiResponse<AuthorizeResponse> {
+    let redirect_uri = services
+        .oauth
+        .authorize(&claims, request.try_into()?, &client)
+        .await?;
+    Ok(AuthorizeResponse { redirect_uri }.into())
+}
+
+/// Called by clients, which can authenticate either with HTTP Basic or in the form
+#[instrument(skip_all)]
+pub(super) async fn token(
+    State(services): State<Services>,
+    client: ClientInfo,
+    basic: Option<TypedHeader<Authorization<Basic>>>,
+    form: Result<Form<TokenRequest>, FormRejection>,
+) -> Result<Response, OAuthError> {
+    let Form(request) = form.map_err(|_| OAuthError::InvalidRequest)?;
+    let grant = request.grant()?;
+
+    let credentials = match basic {
+        Some(TypedHeader(Authorization(basic))) => ClientCredentials {
+            client_id: basic
+                .username()
+                .parse()
+                .map(ClientId)
+                .map_err(|_| OAuthError::InvalidClient)?,
+            client_secret: Some(ClientSecret::new(basic.password().into())),
+        },
+        None => ClientCredentials {
+            client_id: request.client_id.ok_or(OAuthError::InvalidClient)?,
+            client_secret: request.client_secret(),
+        },
+    };
+
+    let tokens = services.oauth.token(credentials, grant, &client).await?;
+    Ok((
+        [(CACHE_CONTROL, "no-store"), (PRAGMA, "no-cache")],
+        Json(TokenResponse::from(tokens)),
+    )
+        .into_response())
+}
+
+/// Takes any of our tokens, rather than only those allowed the `api` scope
+#[instrument(skip_all)]
+pub(super) async fn userinfo(
+    State(services): State<Services>,
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+) -> ApiResponse<UserInfo> {
+    let TypedHeader(Authorization(bearer)) = bearer.ok_or(ApiError::Auth)?;
+    let jwt = Jwt::new(bearer.token().to_string());
+
+    let info = services.oauth.userinfo(&jwt).await?;
+    Ok(info.into())
+}
+
+/// For a client to check the token it got for itself with the client credentials grant
+#[instrument(skip_all, fields(client_id = %acting.client.id.0))]
+pub(super) async fn client(acting: ActingClient) -> ApiResponse<ClientResponse> {
+    Ok(ClientResponse::from(acting).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{
+        header::{AUTHORIZATION, CACHE_CONTROL, CONTENT_TYPE},
+        StatusCode,
+    };
+    use base64::{engine::general_purpose::STANDARD, Engine};
+
+    use serde_json::{json, Value};
+    use url::{form_urlencoded, Url};
+
+    use crate::{
+        model::types::mock::{ADMIN_EMAIL, DEFAULT_EMAIL, DEFAULT_USER_ID},
+        state::identity::oidc::code_challenge,
+        testing::{bearer, test_client_with, test_data::TEST_DATA},
+    };
+
+    const VERIFIER: &str = "a verifier that is long enough to be a real one";
+
+    fn form(pairs: &[(&str, &str)]) -> String {
+        form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(pairs)
+            .finish()
+    }
+
+    #[tokio::test]
+    async fn can_authorize_an_app() {
+        let (client, services) = test_client_with(TEST_DATA.clone());
+        let admin = bearer(&services, &ADMIN_EMAIL).await;
+        let user = bearer(&services, &DEFAULT_EMAIL).await;
+
+        let body = json!({
+            "name": "app",
+            "redirect_uri": "https://app.example.com/callback",
+            "scopes": "email api",
+        });
+        let resp = client
+            .post("/admin/oauth-clients")
+            .header(AUTHORIZATION, &admin)
+            .json(&body)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let registered: Value = resp.json().await;
+        assert_eq!(registered["scopes"], "api email");
+        assert_eq!(registered["public"], false);
+        let client_id = registered["id"].as_str().unwrap().to_string();
+        let client_secret = registered["client_secret"].as_str().unwrap().to_string();
+
+        let challenge = code_challenge(VERIFIER);
+        let params = [
+            ("response_type", "code"),
+            ("client_id", &client_id),
+            ("redirect_uri", "https://app.example.com/callback"),
+            ("scope", "email"),
+            ("state", "xyz"),
+            ("code_challenge", &challenge),
+            ("code_challenge_method", "S256"),
+        ];
+        let resp = client
+            .get(&format!("/oauth/authorize?{}", form(&params)))
+            .header(AUTHORIZATION, &user)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let prompt: Value = resp.json().await;
+        assert_eq!(prompt["client_name"], "app");
+        assert_eq!(prompt["consented"], false);
+
+        let body: serde_json::Map<_, _> = params
+            .iter()
+            .map(|(key, value)| (key.to_string(), json!(value)))
+            .collect();
+        let resp = client
+            .post("/oauth/authorize")
+            .header(AUTHORIZATION, &user)
+            .json(&body)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let redirect: Value = resp.json().await;
+        let redirect: Url = redirect["redirect_uri"].as_str().unwrap().parse().unwrap();
+        let (_, code) = redirect
+            .query_pairs()
+            .find(|(key, _)| key == "code")
+            .unwrap();
+
+        let token = |basic: String| {
+            let body = form(&[
+                ("grant_type", "authorization_code"),
+                ("code", &code),
+                ("redirect_uri", "https://app.example.com/callback"),
+                ("code_verifier", VERIFIER),
+            ]);
+            client
+                .post("/oauth/token")
+                .header(AUTHORIZATION, format!("Basic {}", STANDARD.encode(basic)))
+                .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .body(body)
+                .send()
+        };
+        let resp = token(format!("{client_id}:wrong")).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            resp.json::<Value>().await,
+            json!({ "error": "invalid_client" })
+        );
+
+        let resp = token(format!("{client_id}:{client_secret}")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[CACHE_CONTROL], "no-store");
+        let tokens: Value = resp.json().await;
+        assert_eq!(tokens["token_type"], "Bearer");
+        assert_eq!(tokens["scope"], "email");
+        let access_token = format!("Bearer {}", tokens["access_token"].as_str().unwrap());
+
+        let resp = client
+            .get("/oauth/userinfo")
+            .header(AUTHORIZATION, &access_token)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.json::<Value>().await,
+            json!({ "sub": DEFAULT_USER_ID.0, "email": DEFAULT_EMAIL.0 })
+        );
+        // without the api scope, it's good for nothing else
+        let resp = client
+            .get("/me")
+            .header(AUTHORIZATION, &access_token)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let resp = client
+            .get("/me/oauth-consents")
+            .header(AUTHORIZATION, &user)
+            .send()
+            .await;
+        let consents: Value = resp.json().await;
+        assert_eq!(consents["consents"][0]["client_id"], client_id);
+        let uri = format!("/me/oauth-consents/{client_id}");
+        let resp = client
+            .delete(&uri)
+            .header(AUTHORIZATION, &user)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = client
+            .get("/oauth/userinfo")
+            .header(AUTHORIZATION, &access_token)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn clients_can_use_their_own_tokens() {
+        let (client, services) = test_client_with(TEST_DATA.clone());
+        let admin = bearer(&services, &ADMIN_EMAIL).await;
+
+        let body = json!({
+            "name": "service",
+            "redirect_uri": "https://service.example.com/callback",
+            "scopes": "profile",
+        });
+        let resp = client
+            .post("/admin/oauth-clients")
+            .header(AUTHORIZATION, &admin)
+            .json(&body)
+            .send()
+            .await;
+        let registered: Value = resp.json().await;
+        let client_id = registered["id"].as_str().unwrap().to_string();
+        let client_secret = registered["client_secret"].as_str().unwrap().to_string();
+
+        let resp = client
+            .post("/oauth/token")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(form(&[
+                ("grant_type", "client_credentials"),
+                ("client_id", &client_id),
+                ("client_secret", &client_secret),
+            ]))
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let tokens: Value = resp.json().await;
+        let access_token = format!("Bearer {}", tokens["access_token"].as_str().unwrap());
+
+        let resp = client
+            .get("/oauth/client")
+            .header(AUTHORIZATION, &access_token)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.json::<Value>().await,
+            json!({ "client_id": client_id, "client_name": "service", "scope": "profile" })
+        );
+        // a user's token isn't a client's
+        let resp = client
+            .get("/oauth/client")
+            .header(AUTHORIZATION, &admin)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let resp = client
+            .delete(&format!("/admin/oauth-clients/{client_id}"))
+            .header(AUTHORIZATION, &admin)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = client
+            .get("/oauth/client")
+            .header(AUTHORIZATION, &access_token)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn token_errors_are_what_oauth_clients_expect() {
+        let (client, _) = test_client_with(TEST_DATA.clone());
+
+        let resp = client
+            .post("/oauth/token")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(form(&[("grant_type", "password")]))
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            resp.json::<Value>().await,
+            json!({ "error": "unsupported_grant_type" })
+        );
+
+        let resp = client
+            .post("/oauth/token")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(form(&[("grant_type", "client_credentials")]))
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+}
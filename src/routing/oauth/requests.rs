@@ -0,0 +1,232 @@
+use microtype::{secrecy::ExposeSecret, SecretMicrotype};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+    model::{
+        oauth::Scopes,
+        types::{ClientId, ClientSecret, Token},
+    },
+    routing::errors::{ApiError, OAuthError},
+    state::{
+        jwt::Jwt,
+        oauth::{ActingClient, Authorization, AuthorizationPrompt, Grant, IssuedTokens},
+    },
+};
+
+/// What a client sends a user to `/oauth/authorize` with, which the frontend passes on as it is
+#[derive(Debug, Clone, Deserialize)]
+pub struct AuthorizeRequest {
+    /// Only `code` is supported
+    pub response_type: String,
+    pub client_id: ClientId,
+    pub redirect_uri: Url,
+    pub scope: Scopes,
+    pub state: Option<String>,
+    pub code_challenge: String,
+    /// Only `S256` is supported
+    pub code_challenge_method: String,
+}
+
+impl TryFrom<AuthorizeRequest> for Authorization {
+    type Error = ApiError;
+
+    fn try_from(request: AuthorizeRequest) -> Result<Self, Self::Error> {
+        if request.response_type != "code" {
+            return Err(ApiError::Invalid {
+                field: "response_type",
+            });
+        }
+        if request.code_challenge_method != "S256" {
+            return Err(ApiError::Invalid {
+                field: "code_challenge_method",
+            });
+        }
+
+        Ok(Self {
+            client_id: request.client_id,
+            redirect_uri: request.redirect_uri,
+            scope: request.scope,
+            state: request.state,
+            code_challenge: request.code_challenge,
+        })
+    }
+}
+
+/// What to ask the user to agree to
+#[derive(Debug, Clone, Serialize)]
+pub struct AuthorizationResponse {
+    pub client_id: ClientId,
+    pub client_name: String,
+    pub scope: Scopes,
+    /// Whether the user already agreed to all of `scope`, so needn't be asked again
+    pub consented: bool,
+}
+
+impl From<AuthorizationPrompt> for AuthorizationResponse {
+    fn from(prompt: AuthorizationPrompt) -> Self {
+        Self {
+            client_id: prompt.client.id,
+            client_name: prompt.client.name,
+            scope: prompt.scope,
+            consented: prompt.consented,
+        }
+    }
+}
+
+/// Who a client's own token says it is, and what it allows
+#[derive(Debug, Clone, Serialize)]
+pub struct ClientResponse {
+    pub client_id: ClientId,
+    pub client_name: String,
+    pub scope: Scopes,
+}
+
+impl From<ActingClient> for ClientResponse {
+    fn from(acting: ActingClient) -> Self {
+        Self {
+            client_id: acting.client.id,
+            client_name: acting.client.name,
+            scope: acting.scope,
+        }
+    }
+}
+
+/// Where to send the user back to the client
+#[derive(Debug, Clone, Serialize)]
+pub struct AuthorizeResponse {
+    pub redirect_uri: Url,
+}
+
+/// The form a client posts to `/oauth/token`, of which the fields needed depend on `grant_type`
+#[derive(Debug, Clone, Deserialize)]
+pub struct TokenRequest {
+    pub grant_type: String,
+    pub code: Option<String>,
+    pub redirect_uri: Option<Url>,
+    pub code_verifier: Option<String>,
+    pub refresh_token: Option<String>,
+    pub scope: Option<String>,
+    /// Only for clients that don't authenticate with HTTP Basic
+    pub client_id: Option<ClientId>,
+    pub client_secret: Option<String>,
+}
+
+impl TokenRequest {
+    pub fn grant(&self) -> Result<Grant, OAuthError> {
+        let scope = match &self.scope {
+            Some(scope) => Some(scope.parse().map_err(|_| OAuthError::InvalidScope)?),
+            None => None,
+        };
+
+        let grant = match self.grant_type.as_str() {
+            "authorization_code" => Grant::AuthorizationCode {
+                code: Token::new(required(&self.code)?.clone()),
+                redirect_uri: required(&self.redirect_uri)?.clone(),
+                code_verifier: required(&self.code_verifier)?.clone(),
+            },
+            "refresh_token" => Grant::RefreshToken {
+                refresh_token: Token::new(required(&self.refresh_token)?.clone()),
+                scope,
+            },
+            "client_credentials" => Grant::ClientCredentials { scope },
+            _ => return Err(OAuthError::UnsupportedGrantType),
+        };
+        Ok(grant)
+    }
+
+    pub fn client_secret(&self) -> Option<ClientSecret> {
+        self.client_secret.clone().map(ClientSecret::new)
+    }
+}
+
+fn required<T>(field: &Option<T>) -> Result<&T, OAuthError> {
+    field.as_ref().ok_or(OAuthError::InvalidRequest)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenResponse {
+    pub access_token: Jwt,
+    /// Always `Bearer`
+    pub token_type: &'static str,
+    /// Seconds until `access_token` expires
+    pub expires_in: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    pub scope: Scopes,
+}
+
+impl From<IssuedTokens> for TokenResponse {
+    fn from(tokens: IssuedTokens) -> Self {
+        Self {
+            access_token: tokens.access_token,
+            token_type: "Bearer",
+            expires_in: tokens.expires_in,
+            refresh_token: tokens
+                .refresh_token
+                .map(|token| token.expose_secret().clone()),
+            scope: tokens.scope,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{from_value, json, to_value};
+
+    use super::*;
+
+    #[test]
+    fn token_request_test() {
+        let request = json!({
+            "grant_type": "refresh_token",
+            "refresh_token": "foo",
+            "scope": "email",
+        });
+        let request = from_value::<TokenRequest>(request).unwrap();
+
+        let Grant::RefreshToken {
+            refresh_token,
+            scope,
+        } = request.grant().unwrap()
+        else {
+            panic!("wrong grant");
+        };
+        assert_eq!(refresh_token.expose_secret(), "foo");
+        assert_eq!(scope.unwrap().as_str(), "email");
+    }
+
+    #[test]
+    fn token_request_needs_what_its_grant_needs() {
+        let request = json!({ "grant_type": "authorization_code", "code": "foo" });
+        let request = from_value::<TokenRequest>(request).unwrap();
+        assert!(matches!(request.grant(), Err(OAuthError::InvalidRequest)));
+
+        let request = json!({ "grant_type": "password" });
+        let request = from_value::<TokenRequest>(request).unwrap();
+        assert!(matches!(
+            request.grant(),
+            Err(OAuthError::UnsupportedGrantType)
+        ));
+    }
+
+    #[test]
+    fn token_response_test() {
+        let response = TokenResponse::from(IssuedTokens {
+            access_token: Jwt::new("foo".into()),
+            expires_in: 1000,
+            refresh_token: None,
+            scope: "api".parse().unwrap(),
+        });
+
+        assert_eq!(
+            to_value(response).unwrap(),
+            json!({
+                "access_token": "foo",
+                "token_type": "Bearer",
+                "expires_in": 1000,
+                "scope": "api",
+            })
+        );
+    }
+}
@@ -0,0 +1,361 @@
+use axum::{
+    extract::{Path, Query, State},
+    response::Response,
+    Json,
+};
+use uuid::Uuid;
+
+use self::requests::{
+    AcceptInvitationRequest, ChangeRoleRequest, CreateOrgRequest, InvitationResponse,
+    InvitationsResponse, InviteRequest, MembersResponse, OrgResponse, OrgsResponse,
+    SwitchOrgRequest, SwitchOrgResponse,
+};
+use super::{
+    client::ClientInfo,
+    cookies::SessionQuery,
+    errors::{ApiError, ApiResponse},
+};
+use crate::{
+    model::{
+        organization::Membership,
+        types::{OrgId, UserId},
+    },
+    state::{
+        jwt::claims::{Claims, Validated},
+        principal::Principal,
+        Services,
+    },
+};
+
+pub mod requests;
+
+#[instrument(skip_all, fields(user_id = %principal.user_id.0))]
+pub(super) async fn organizations(
+    State(services): State<Services>,
+    principal: Principal,
+) -> ApiResponse<OrgsResponse> {
+    let organizations = services.org.organizations(&principal).await?;
+    let organizations = organizations.into_iter().map(OrgResponse::from).collect();
+    Ok(OrgsResponse { organizations }.into())
+}
+
+#[instrument(skip_all, fields(user_id = %principal.user_id.0))]
+pub(super) async fn create(
+    State(services): State<Services>,
+    principal: Principal,
+    client: ClientInfo,
+    Json(CreateOrgRequest { name }): Json<CreateOrgRequest>,
+) -> ApiResponse<OrgResponse> {
+    let created = services.org.create(&principal, name, &client).await?;
+    Ok(OrgResponse::from(created).into())
+}
+
+/// The organization the request's token is acting in
+#[instrument(skip_all, fields(user_id = %claims.subject.0))]
+pub(super) async fn current(
+    State(services): State<Services>,
+    claims: Claims<Validated>,
+) -> ApiResponse<OrgResponse> {
+    let current = services.org.current(&claims).await?;
+    Ok(OrgResponse::from(current).into())
+}
+
+/// Swaps the request's token for one acting in another organization, in the same session
+#[instrument(skip_all, fields(user_id = %claims.subject.0))]
+pub(super) async fn switch(
+    State(services): State<Services>,
+    claims: Claims<Validated>,
+    Query(SessionQuery { session }): Query<SessionQuery>,
+    Json(SwitchOrgRequest { org_id }): Json<SwitchOrgRequest>,
+) -> Result<Response, ApiError> {
+    let jwt = services.org.switch(&claims, org_id).await?;
+    session.respond(&services, jwt, |jwt| SwitchOrgResponse { jwt })
+}
+
+#[instrument(skip_all, fields(user_id = %principal.user_id.0))]
+pub(super) async fn accept_invitation(
+    State(services): State<Services>,
+    principal: Principal,
+    client: ClientInfo,
+    Json(AcceptInvitationRequest { token }): Json<AcceptInvitationRequest>,
+) -> ApiResponse<OrgResponse> {
+    let joined = services
+        .org
+        .accept_invitation(&principal, token, &client)
+        .await?;
+    Ok(OrgResponse::from(joined).into())
+}
+
+#[instrument(skip_all, fields(user_id = %principal.user_id.0, org_id = %org_id.0))]
+pub(super) async fn organization(
+    State(services): State<Services>,
+    principal: Principal,
+    Path(org_id): Path<OrgId>,
+) -> ApiResponse<OrgResponse> {
+    let organization = services.org.organization(&principal, org_id).await?;
+    Ok(OrgResponse::from(organization).into())
+}
+
+#[instrument(skip_all, fields(user_id = %principal.user_id.0, org_id = %org_id.0))]
+pub(super) async fn delete(
+    State(services): State<Services>,
+    principal: Principal,
+    client: ClientInfo,
+    Path(org_id): Path<OrgId>,
+) -> ApiResponse<()> {
+    services.org.delete(&principal, org_id, &client).await?;
+    Ok(Json(()))
+}
+
+#[instrument(skip_all, fields(user_id = %principal.user_id.0, org_id = %org_id.0))]
+pub(super) async fn members(
+    State(services): State<Services>,
+    principal: Principal,
+    Path(org_id): Path<OrgId>,
+) -> ApiResponse<MembersResponse> {
+    let members = services.org.members(&principal, org_id).await?;
+    Ok(MembersResponse { members }.into())
+}
+
+#[instrument(skip_all, fields(user_id = %principal.user_id.0, org_id = %org_id.0, member_id = %user_id.0))]
+pub(super) async fn change_role(
+    State(services): State<Services>,
+    principal: Principal,
+    client: ClientInfo,
+    Path((org_id, user_id)): Path<(OrgId, UserId)>,
+    Json(ChangeRoleRequest { role }): Json<ChangeRoleRequest>,
+) -> ApiResponse<Membership> {
+    let membership = services
+        .org
+        .change_role(&principal, org_id, user_id, role, &client)
+        .await?;
+    Ok(membership.into())
+}
+
+/// Also how members leave, by removing themselves
+#[instrument(skip_all, fields(user_id = %principal.user_id.0, org_id = %org_id.0, member_id = %user_id.0))]
+pub(super) async fn remove_member(
+    State(services): State<Services>,
+    principal: Principal,
+    client: ClientInfo,
+    Path((org_id, user_id)): Path<(OrgId, UserId)>,
+) -> ApiResponse<()> {
+    services
+        .org
+        .remove_member(&principal, org_id, user_id, &client)
+        .await?;
+    Ok(Json(()))
+}
+
+#[instrument(skip_all, fields(user_id = %principal.user_id.0, org_id = %org_id.0))]
+pub(super) async fn invitations(
+    State(services): State<Services>,
+    principal: Principal,
+    Path(org_id): Path<OrgId>,
+) -> ApiResponse<InvitationsResponse> {
+    let invitations = services.org.invitations(&principal, org_id).await?;
+    let invitations = invitations
+        .into_iter()
+        .map(InvitationResponse::from)
+        .collect();
+    Ok(InvitationsResponse { invitations }.into())
+}
+
+#[instrument(skip_all, fields(user_id = %principal.user_id.0, org_id = %org_id.0))]
+pub(super) async fn invite(
+    State(services): State<Services>,
+    principal: Principal,
+    client: ClientInfo,
+    Path(org_id): Path<OrgId>,
+    Json(InviteRequest { email, role }): Json<InviteRequest>,
+) -> ApiResponse<InvitationResponse> {
+    let invitation = services
+        .org
+        .invite(&principal, org_id, email, role, &client)
+        .await?;
+    Ok(InvitationResponse::from(invitation).into())
+}
+
+#[instrument(skip_all, fields(user_id = %principal.user_id.0, org_id = %org_id.0, invitation_id = %invitation_id))]
+pub(super) async fn revoke_invitation(
+    State(services): State<Services>,
+    principal: Principal,
+    client: ClientInfo,
+    Path((org_id, invitation_id)): Path<(OrgId, Uuid)>,
+) -> ApiResponse<()> {
+    services
+        .org
+        .revoke_invitation(&principal, org_id, invitation_id, &client)
+        .await?;
+    Ok(Json(()))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{header::AUTHORIZATION, StatusCode};
+    use chrono::Duration;
+    use microtype::secrecy::ExposeSecret;
+    use serde_json::{json, Value};
+
+    use crate::{
+        model::types::mock::{ADMIN_EMAIL, DEFAULT_EMAIL},
+        routing::client::ClientInfo,
+        state::{time::mock::DEFAULT_DATE_TIME, Services},
+        testing::{bearer, sign_in, test_client_with, test_data::TEST_DATA},
+    };
+
+    #[tokio::test]
+    async fn members_join_by_invitation_and_switch_to_the_org() {
+        let (client, services) = test_client_with(TEST_DATA.clone());
+        let owner = bearer(&services, &DEFAULT_EMAIL).await;
+        let invitee = bearer(&services, &ADMIN_EMAIL).await;
+
+        let resp = client
+            .post("/orgs")
+            .header(AUTHORIZATION, &owner)
+            .json(&json!({ "name": "Acme" }))
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let org: Value = resp.json().await;
+        assert_eq!(org["role"], "owner");
+        let org_uri = format!("/orgs/{}", org["id"].as_str().unwrap());
+
+        // to anyone outside it, the organization doesn't exist
+        let resp = client
+            .get(&org_uri)
+            .header(AUTHORIZATION, &invitee)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let resp = client
+            .post(&format!("{org_uri}/invitations"))
+            .header(AUTHORIZATION, &owner)
+            .json(&json!({ "email": ADMIN_EMAIL.as_str(), "role": "admin" }))
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let token = services.mailer.as_mock().token_sent_to(&ADMIN_EMAIL);
+
+        // only whoever it was sent to can accept it
+        let body = json!({ "token": token.expose_secret() });
+        let resp = client
+            .post("/orgs/invitations/accept")
+            .header(AUTHORIZATION, &owner)
+            .json(&body)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let resp = client
+            .post("/orgs/invitations/accept")
+            .header(AUTHORIZATION, &invitee)
+            .json(&body)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let joined: Value = resp.json().await;
+        assert_eq!(joined["role"], "admin");
+
+        let resp = client
+            .get(&format!("{org_uri}/members"))
+            .header(AUTHORIZATION, &invitee)
+            .send()
+            .await;
+        let members: Value = resp.json().await;
+        assert_eq!(members["members"].as_array().unwrap().len(), 2);
+
+        let resp = client
+            .get("/orgs/current")
+            .header(AUTHORIZATION, &invitee)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let resp = client
+            .post("/orgs/switch")
+            .header(AUTHORIZATION, &invitee)
+            .json(&json!({ "org_id": org["id"] }))
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let switched: Value = resp.json().await;
+        let switched = format!("Bearer {}", switched["jwt"].as_str().unwrap());
+
+        let resp = client
+            .get("/orgs/current")
+            .header(AUTHORIZATION, &switched)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let current: Value = resp.json().await;
+        assert_eq!(current["id"], org["id"]);
+        assert_eq!(current["name"], "Acme");
+    }
+
+    /// A key for the default user, named after the `scopes` it's for
+    async fn api_key(services: &Services, scopes: &str) -> String {
+        let claims = sign_in(services, &DEFAULT_EMAIL).await;
+        let created = services
+            .api_keys
+            .create(
+                &claims,
+                scopes.into(),
+                scopes.parse().unwrap(),
+                *DEFAULT_DATE_TIME + Duration::days(30),
+                &ClientInfo::default(),
+            )
+            .await
+            .unwrap();
+        format!("Bearer {}", created.secret.expose_secret())
+    }
+
+    #[tokio::test]
+    async fn api_keys_can_manage_organizations() {
+        let (client, services) = test_client_with(TEST_DATA.clone());
+        let key = api_key(&services, "api").await;
+
+        let resp = client
+            .post("/orgs")
+            .header(AUTHORIZATION, &key)
+            .json(&json!({ "name": "Acme" }))
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let org: Value = resp.json().await;
+        let org_uri = format!("/orgs/{}", org["id"].as_str().unwrap());
+
+        let resp = client
+            .post(&format!("{org_uri}/invitations"))
+            .header(AUTHORIZATION, &key)
+            .json(&json!({ "email": ADMIN_EMAIL.as_str(), "role": "member" }))
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = client
+            .get(&format!("{org_uri}/members"))
+            .header(AUTHORIZATION, &key)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // switching makes a new token for a session, which a key doesn't have
+        let resp = client
+            .post("/orgs/switch")
+            .header(AUTHORIZATION, &key)
+            .json(&json!({ "org_id": org["id"] }))
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let key = api_key(&services, "profile").await;
+        let resp = client.get("/orgs").header(AUTHORIZATION, &key).send().await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let resp = client
+            .get(&org_uri)
+            .header(AUTHORIZATION, &key)
+            .send()
+            .await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,118 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    model::{
+        organization::{Membership, OrgInvitation, OrgRole},
+        types::{Email, OrgId, Token},
+    },
+    state::{jwt::Jwt, organization::OrgMembership},
+};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateOrgRequest {
+    pub name: String,
+}
+
+/// An organization, and what the signed in user can do in it
+#[derive(Debug, Clone, Serialize)]
+pub struct OrgResponse {
+    pub id: OrgId,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub role: OrgRole,
+    pub joined_at: DateTime<Utc>,
+}
+
+impl From<OrgMembership> for OrgResponse {
+    fn from(
+        OrgMembership {
+            organization,
+            membership,
+        }: OrgMembership,
+    ) -> Self {
+        Self {
+            id: organization.id,
+            name: organization.name,
+            created_at: organization.created_at,
+            role: membership.role,
+            joined_at: membership.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OrgsResponse {
+    /// Oldest membership first
+    pub organizations: Vec<OrgResponse>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MembersResponse {
+    /// Longest standing first
+    pub members: Vec<Membership>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChangeRoleRequest {
+    pub role: OrgRole,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct InviteRequest {
+    pub email: Email,
+    #[serde(default = "member")]
+    pub role: OrgRole,
+}
+
+fn member() -> OrgRole {
+    OrgRole::Member
+}
+
+/// An invitation, without the hash of its token
+#[derive(Debug, Clone, Serialize)]
+pub struct InvitationResponse {
+    pub id: Uuid,
+    pub organization_id: OrgId,
+    pub email: Email,
+    pub role: OrgRole,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl From<OrgInvitation> for InvitationResponse {
+    fn from(invitation: OrgInvitation) -> Self {
+        Self {
+            id: invitation.id,
+            organization_id: invitation.organization_id,
+            email: invitation.email,
+            role: invitation.role,
+            created_at: invitation.created_at,
+            expires_at: invitation.expires_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InvitationsResponse {
+    /// Oldest first, including expired invitations
+    pub invitations: Vec<InvitationResponse>,
+}
+
+/// The token from an invitation link
+#[derive(Debug, Clone, Deserialize)]
+pub struct AcceptInvitationRequest {
+    pub token: Token,
+}
+
+/// `null` goes back to acting as just the user
+#[derive(Debug, Clone, Deserialize)]
+pub struct SwitchOrgRequest {
+    pub org_id: Option<OrgId>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SwitchOrgResponse {
+    pub jwt: Jwt,
+}
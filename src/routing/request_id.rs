@@ -0,0 +1,108 @@
+use axum::{
+    http::{HeaderName, HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+use microtype::{microtype, Microtype};
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::telemetry;
+
+pub const X_REQUEST_ID: &str = "x-request-id";
+
+/// Longest client-supplied request ID we accept, anything longer is replaced
+const MAX_LEN: usize = 128;
+
+microtype! {
+    #[derive(Debug, Clone, PartialEq)]
+    #[string]
+    pub String {
+        RequestId
+    }
+}
+
+/// Middleware that tags each request with an ID
+///
+/// The ID is taken from the `X-Request-Id` header if the client sent a sensible one, and
+/// generated otherwise. It's recorded on a span wrapping the whole request, made available to
+/// handlers as an `Extension<RequestId>`, and echoed back in the response headers. The span
+/// continues the caller's trace if they sent a `traceparent` header
+pub async fn request_id<B>(mut request: Request<B>, next: Next<B>) -> Response {
+    let id = request
+        .headers()
+        .get(X_REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_valid(id))
+        .map(String::from)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    let id = RequestId::new(id);
+
+    let span = info_span!(
+        "request",
+        request_id = %id,
+        method = %request.method(),
+        path = %request.uri().path(),
+    );
+
+    telemetry::set_remote_parent(&span, request.headers());
+
+    request.extensions_mut().insert(id.clone());
+    let mut response = next.run(request).instrument(span).await;
+
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response
+            .headers_mut()
+            .insert(HeaderName::from_static(X_REQUEST_ID), value);
+    }
+
+    response
+}
+
+fn is_valid(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_LEN && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+
+    use crate::testing::default_test_client;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn generates_request_id() {
+        let (client, _) = default_test_client();
+        let resp = client.get("/health").send().await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let id = resp.headers().get(X_REQUEST_ID).unwrap().to_str().unwrap();
+        assert!(Uuid::parse_str(id).is_ok());
+    }
+
+    #[tokio::test]
+    async fn echoes_request_id() {
+        let (client, _) = default_test_client();
+        let resp = client
+            .get("/health")
+            .header(X_REQUEST_ID, "abc-123")
+            .send()
+            .await;
+
+        assert_eq!(resp.headers().get(X_REQUEST_ID).unwrap(), "abc-123");
+    }
+
+    #[tokio::test]
+    async fn replaces_invalid_request_id() {
+        let (client, _) = default_test_client();
+        let resp = client
+            .get("/health")
+            .header(X_REQUEST_ID, "a".repeat(MAX_LEN + 1))
+            .send()
+            .await;
+
+        let id = resp.headers().get(X_REQUEST_ID).unwrap().to_str().unwrap();
+        assert!(Uuid::parse_str(id).is_ok());
+    }
+}
@@ -0,0 +1,374 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, Utc};
+use microtype::{secrecy::ExposeSecret, SecretMicrotype};
+use tracing::{field, Span};
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    db::Db,
+    model::{
+        api_key::ApiKey,
+        audit::AuditAction,
+        oauth::Scopes,
+        types::{ApiKeySecret, UserId},
+    },
+    routing::{client::ClientInfo, errors::ApiError},
+    state::jwt::claims::{Claims, Validated},
+};
+
+use super::{
+    audit::AuditService, auth::not_found_if_unmodified, hasher::hash_token, principal::Principal,
+    random::Random, time::Time, Common,
+};
+
+/// What every API key starts with, so that it can be told apart from a JWT, and recognised by
+/// secret scanners if it's leaked
+pub const API_KEY_PREFIX: &str = "ebk_";
+
+/// How much of a key, after `API_KEY_PREFIX`, is kept in the clear to look it up by
+const LOOKUP_PREFIX_LEN: usize = 12;
+
+/// How stale a key's `last_used_at` may get before a request updates it
+const KEY_TOUCH_INTERVAL_SECONDS: i64 = 60;
+
+/// A newly created key, along with the only copy of it
+#[derive(Debug, Clone)]
+pub struct CreatedApiKey {
+    pub key: ApiKey,
+    pub secret: ApiKeySecret,
+}
+
+/// Long-lived, scoped keys users make so that scripts needn't sign in with their password
+///
+/// A key looks like `ebk_<prefix>_<secret>`. The prefix is stored in the clear to find the key by,
+/// and only a hash of the whole key is stored to check it against
+#[derive(Debug, Clone)]
+pub struct ApiKeyService {
+    time: Arc<dyn Time>,
+    random: Arc<dyn Random>,
+    db: Arc<dyn Db>,
+    audit: AuditService,
+    config: Arc<Config>,
+}
+
+impl ApiKeyService {
+    pub fn new(common: Common) -> Self {
+        let Common {
+            time,
+            random,
+            db,
+            audit,
+            config,
+        } = common;
+
+        Self {
+            time,
+            random,
+            db,
+            audit,
+            config,
+        }
+    }
+
+    /// Make a key for the signed in user, which works until `expires_at`
+    #[instrument(skip_all, fields(user_id = %claims.subject.0))]
+    pub async fn create(
+        &self,
+        claims: &Claims<Validated>,
+        name: String,
+        scopes: Scopes,
+        expires_at: DateTime<Utc>,
+        client: &ClientInfo,
+    ) -> Result<CreatedApiKey, ApiError> {
+        // otherwise an app could give itself a credential that outlives the user's consent
+        if claims.client_id.is_some() {
+            return Err(ApiError::Auth);
+        }
+
+        if name.trim().is_empty() {
+            return Err(ApiError::Invalid { field: "name" });
+        }
+        if scopes.is_empty() {
+            return Err(ApiError::Invalid { field: "scopes" });
+        }
+        let now = self.time.now();
+        if expires_at <= now || expires_at > now + self.config.accounts.api_key_max_ttl() {
+            return Err(ApiError::Invalid {
+                field: "expires_at",
+            });
+        }
+
+        let prefix = self.random.uuid().simple().to_string()[..LOOKUP_PREFIX_LEN].to_string();
+        let secret = ApiKeySecret::new(format!(
+            "{API_KEY_PREFIX}{prefix}_{}",
+            self.random.token().expose_secret()
+        ));
+        let key = ApiKey {
+            id: self.random.uuid(),
+            user_id: claims.subject,
+            name,
+            prefix,
+            key_hash: hash_token(&secret),
+            scopes,
+            created_at: now,
+            expires_at,
+            last_used_at: None,
+        };
+        self.db.create_api_key(key.clone()).await?;
+
+        let user_id = claims.subject;
+        self.audit
+            .record(
+                AuditAction::ApiKeyCreated,
+                Some(user_id),
+                Some(user_id),
+                client,
+            )
+            .await;
+        Ok(CreatedApiKey { key, secret })
+    }
+
+    /// The signed in user's keys, expired or not, oldest first
+    #[instrument(skip_all, fields(user_id = %claims.subject.0))]
+    pub async fn keys(&self, claims: &Claims<Validated>) -> Result<Vec<ApiKey>, ApiError> {
+        Ok(self.db.api_keys_for_user(claims.subject).await?)
+    }
+
+    #[instrument(skip_all, fields(user_id = %claims.subject.0, key_id = %key_id))]
+    pub async fn revoke(
+        &self,
+        claims: &Claims<Validated>,
+        key_id: Uuid,
+        client: &ClientInfo,
+    ) -> Result<(), ApiError> {
+        let user_id = claims.subject;
+        let result = self.db.delete_api_key(user_id, key_id).await;
+        not_found_if_unmodified(result)?;
+
+        self.audit
+            .record(
+                AuditAction::ApiKeyRevoked,
+                Some(user_id),
+                Some(user_id),
+                client,
+            )
+            .await;
+        Ok(())
+    }
+
+    /// Check a key someone sent, and that its owner still has an account
+    #[instrument(skip_all, fields(user_id = field::Empty))]
+    pub async fn authenticate(&self, secret: &ApiKeySecret) -> Result<Principal, ApiError> {
+        let Some(prefix) = lookup_prefix(secret) else {
+            return Err(ApiError::Auth);
+        };
+        let key = match self.db.api_key_by_prefix(prefix.into()).await? {
+            Some(key) if key.key_hash == hash_token(secret) => key,
+            _ => return Err(ApiError::Auth),
+        };
+        Span::current().record("user_id", field::display(key.user_id.0));
+
+        let now = self.time.now();
+        if key.expires_at <= now || !self.user_exists(key.user_id).await? {
+            return Err(ApiError::Auth);
+        }
+
+        let touched = key.last_used_at.is_some_and(|last_used_at| {
+            now - last_used_at < Duration::seconds(KEY_TOUCH_INTERVAL_SECONDS)
+        });
+        if !touched {
+            self.db.touch_api_key(key.id, now).await?;
+        }
+        Ok(Principal::with_scope(key.user_id, key.scopes))
+    }
+
+    async fn user_exists(&self, user_id: UserId) -> Result<bool, ApiError> {
+        Ok(self.db.user_by_id(user_id).await?.is_some())
+    }
+}
+
+/// The part of a key it's stored under, if it looks like a key at all
+fn lookup_prefix(secret: &ApiKeySecret) -> Option<&str> {
+    let (prefix, _) = secret
+        .expose_secret()
+        .strip_prefix(API_KEY_PREFIX)?
+        .split_once('_')?;
+    Some(prefix).filter(|prefix| prefix.len() == LOOKUP_PREFIX_LEN)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use microtype::{secrecy::ExposeSecret, SecretMicrotype};
+
+    use crate::{
+        db::sql::DbError,
+        model::{
+            api_key::ApiKey,
+            oauth::Scope,
+            types::{
+                mock::{DEFAULT_EMAIL, DEFAULT_USER_ID},
+                ApiKeySecret,
+            },
+        },
+        routing::{client::ClientInfo, errors::ApiError},
+        state::{hasher::hash_token, time::mock::DEFAULT_DATE_TIME, Services},
+        testing::{sign_in, test_data::TEST_DATA, test_services_with},
+    };
+
+    use super::CreatedApiKey;
+
+    async fn create(services: &Services, name: &str, scopes: &str) -> CreatedApiKey {
+        let claims = sign_in(services, &DEFAULT_EMAIL).await;
+        services
+            .api_keys
+            .create(
+                &claims,
+                name.into(),
+                scopes.parse().unwrap(),
+                *DEFAULT_DATE_TIME + Duration::days(30),
+                &ClientInfo::default(),
+            )
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn authenticates_with_new_key() {
+        let services = test_services_with(TEST_DATA.clone());
+        let created = create(&services, "ci", "profile").await;
+
+        let secret = created.secret.expose_secret();
+        assert!(secret.starts_with(&format!("ebk_{}_", created.key.prefix)));
+        assert_eq!(created.key.key_hash, hash_token(&created.secret));
+
+        let principal = services
+            .api_keys
+            .authenticate(&created.secret)
+            .await
+            .unwrap();
+        assert_eq!(principal.user_id, *DEFAULT_USER_ID);
+        assert!(principal.allows(Scope::Profile));
+        assert!(!principal.allows(Scope::Api));
+
+        let claims = sign_in(&services, &DEFAULT_EMAIL).await;
+        let keys = services.api_keys.keys(&claims).await.unwrap();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].name, "ci");
+        assert_eq!(keys[0].last_used_at, Some(*DEFAULT_DATE_TIME));
+    }
+
+    #[tokio::test]
+    async fn rejects_wrong_expired_and_revoked_keys() {
+        let services = test_services_with(TEST_DATA.clone());
+        let created = create(&services, "ci", "api").await;
+        let authenticate = |secret: String| {
+            let api_keys = services.api_keys.clone();
+            async move {
+                api_keys
+                    .authenticate(&ApiKeySecret::new(secret))
+                    .await
+                    .map(|_| ())
+            }
+        };
+
+        let guessed = format!("ebk_{}_guess", created.key.prefix);
+        assert!(matches!(authenticate(guessed).await, Err(ApiError::Auth)));
+        assert!(matches!(
+            authenticate("ebk_nonsense".into()).await,
+            Err(ApiError::Auth)
+        ));
+
+        let expired = ApiKey {
+            id: uuid::Uuid::new_v4(),
+            name: "expired".into(),
+            prefix: "0123456789ab".into(),
+            key_hash: hash_token(&ApiKeySecret::new("ebk_0123456789ab_secret".into())),
+            expires_at: *DEFAULT_DATE_TIME,
+            ..created.key.clone()
+        };
+        services.db.create_api_key(expired).await.unwrap();
+        assert!(matches!(
+            authenticate("ebk_0123456789ab_secret".into()).await,
+            Err(ApiError::Auth)
+        ));
+
+        let claims = sign_in(&services, &DEFAULT_EMAIL).await;
+        services
+            .api_keys
+            .revoke(&claims, created.key.id, &ClientInfo::default())
+            .await
+            .unwrap();
+        let secret = created.secret.expose_secret().clone();
+        assert!(matches!(authenticate(secret).await, Err(ApiError::Auth)));
+        let again = services
+            .api_keys
+            .revoke(&claims, created.key.id, &ClientInfo::default())
+            .await;
+        assert!(matches!(again, Err(ApiError::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn keys_stop_working_when_their_user_is_deleted() {
+        let services = test_services_with(TEST_DATA.clone());
+        let created = create(&services, "ci", "api").await;
+
+        let claims = sign_in(&services, &DEFAULT_EMAIL).await;
+        services
+            .auth
+            .delete_user(&claims, &ClientInfo::default())
+            .await
+            .unwrap();
+
+        let result = services.api_keys.authenticate(&created.secret).await;
+        assert!(matches!(result, Err(ApiError::Auth)));
+    }
+
+    #[tokio::test]
+    async fn validates_new_keys() {
+        let services = test_services_with(TEST_DATA.clone());
+        let claims = sign_in(&services, &DEFAULT_EMAIL).await;
+        create(&services, "ci", "api").await;
+
+        let year = Duration::days(365);
+        let cases = [
+            (" ", "api", Duration::days(1), "name"),
+            ("deploy", "", Duration::days(1), "scopes"),
+            ("deploy", "api", Duration::zero(), "expires_at"),
+            ("deploy", "api", year + Duration::days(1), "expires_at"),
+        ];
+        for (name, scopes, expires_in, expected) in cases {
+            let result = services
+                .api_keys
+                .create(
+                    &claims,
+                    name.into(),
+                    scopes.parse().unwrap(),
+                    *DEFAULT_DATE_TIME + expires_in,
+                    &ClientInfo::default(),
+                )
+                .await;
+            assert!(
+                matches!(result, Err(ApiError::Invalid { field }) if field == expected),
+                "{name:?} {scopes:?} {expires_in}"
+            );
+        }
+
+        let duplicate = services
+            .api_keys
+            .create(
+                &claims,
+                "ci".into(),
+                "api".parse().unwrap(),
+                *DEFAULT_DATE_TIME + year,
+                &ClientInfo::default(),
+            )
+            .await;
+        assert!(matches!(
+            duplicate,
+            Err(ApiError::Db(DbError::AlreadyExists { .. }))
+        ));
+    }
+}
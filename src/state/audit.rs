@@ -0,0 +1,71 @@
+use std::sync::Arc;
+
+use crate::{
+    db::{audit::AuditFilter, Db},
+    model::{
+        audit::{AuditAction, AuditEvent},
+        types::UserId,
+    },
+    routing::{client::ClientInfo, errors::ApiError},
+};
+
+use super::{random::Random, time::Time};
+
+/// How many events a query returns if it doesn't ask for a number
+pub const DEFAULT_AUDIT_LIMIT: i64 = 100;
+
+/// The most events a single query can return
+const MAX_AUDIT_LIMIT: i64 = 1000;
+
+/// Records security-relevant events to an append-only log
+#[derive(Debug, Clone)]
+pub struct AuditService {
+    time: Arc<dyn Time>,
+    random: Arc<dyn Random>,
+    db: Arc<dyn Db>,
+}
+
+impl AuditService {
+    pub fn new(time: Arc<dyn Time>, random: Arc<dyn Random>, db: Arc<dyn Db>) -> Self {
+        Self { time, random, db }
+    }
+
+    /// Add an event to the log
+    ///
+    /// Failing to record an event is logged rather than returned, so that it can't stop whatever
+    /// is being audited from going ahead
+    #[instrument(skip_all, fields(action = action.as_str()))]
+    pub async fn record(
+        &self,
+        action: AuditAction,
+        actor_id: Option<UserId>,
+        subject_id: Option<UserId>,
+        client: &ClientInfo,
+    ) {
+        let event = AuditEvent {
+            id: self.random.uuid(),
+            occurred_at: self.time.now(),
+            actor_id,
+            subject_id,
+            action,
+            ip: client.ip.map(|ip| ip.to_string()),
+            user_agent: client.user_agent.clone(),
+            request_id: client.request_id.clone(),
+        };
+
+        if let Err(e) = self.db.create_audit_event(event).await {
+            error!("failed to record audit event: {e}");
+        }
+    }
+
+    /// Events matching `filter`, most recent first, with the limit kept within bounds
+    #[instrument(skip_all)]
+    pub async fn events(&self, filter: AuditFilter) -> Result<Vec<AuditEvent>, ApiError> {
+        let filter = AuditFilter {
+            limit: filter.limit.clamp(1, MAX_AUDIT_LIMIT),
+            ..filter
+        };
+
+        Ok(self.db.audit_events(filter).await?)
+    }
+}
@@ -1,116 +1,656 @@
 use std::sync::Arc;
 
+use chrono::Duration;
+use futures::{future::BoxFuture, FutureExt};
+use microtype::{secrecy::ExposeSecret, SecretMicrotype};
+use tracing::{field, Span};
+
 use crate::{
-    db::Db,
+    config::Config,
+    db::{sql::DbError, transaction::Transaction, Db},
     model::{
-        types::{Email, Password, UserId},
+        audit::AuditAction,
+        oauth::{Scope, Scopes},
+        session::Session,
+        types::{ClientId, Email, OrgId, Password, PasswordHash, SessionId, UserId},
         user::User,
     },
-    routing::errors::ApiError,
+    routing::{client::ClientInfo, errors::ApiError},
 };
 
 use super::{
+    audit::AuditService,
     hasher::Hasher,
     jwt::{
         claims::{Claims, Validated},
         Jwt, JwtError, JwtService,
     },
+    limiter::{LimitKey, Limiter},
+    metrics::Metrics,
+    organization::delete_orphaned_organizations,
+    password::PasswordPolicy,
     random::Random,
     time::Time,
+    Common,
 };
 
+/// How stale a session's `last_seen_at` may get before a request updates it
+const SESSION_TOUCH_INTERVAL_SECONDS: i64 = 60;
+
 #[derive(Debug, Clone)]
 pub struct AuthService {
     time: Arc<dyn Time>,
     random: Arc<dyn Random>,
     hasher: Arc<dyn Hasher>,
+    passwords: Arc<PasswordPolicy>,
     jwt: Arc<JwtService>,
+    limiter: Limiter,
     db: Arc<dyn Db>,
+    audit: AuditService,
+    metrics: Arc<Metrics>,
+    config: Arc<Config>,
 }
 
 impl AuthService {
     pub fn new(
-        time: Arc<dyn Time>,
-        random: Arc<dyn Random>,
+        common: Common,
         hasher: Arc<dyn Hasher>,
+        passwords: Arc<PasswordPolicy>,
         jwt: Arc<JwtService>,
-        db: Arc<dyn Db>,
+        limiter: Limiter,
+        metrics: Arc<Metrics>,
     ) -> Self {
+        let Common {
+            time,
+            random,
+            db,
+            audit,
+            config,
+        } = common;
+
         Self {
             time,
             random,
             hasher,
+            passwords,
             jwt,
+            limiter,
             db,
+            audit,
+            metrics,
+            config,
         }
     }
 
-    #[instrument]
-    pub async fn login(&self, email: Email, password: Password) -> Result<Jwt, ApiError> {
-        let user = self.user_with_email(email).await?.ok_or(ApiError::Auth)?;
-        match self.hasher.verify(&password, &user.password_hash) {
-            true => Ok(self.jwt.create_jwt(user).map_err(|_| ApiError::Auth)?),
-            false => Err(ApiError::Auth),
+    #[instrument(skip_all, fields(email = %email.redacted(), user_id = field::Empty))]
+    pub async fn login(
+        &self,
+        email: Email,
+        password: Password,
+        client: &ClientInfo,
+    ) -> Result<Jwt, ApiError> {
+        let keys = LimitKey::for_attempt(Some(&email), client);
+        self.limiter.check(&keys)?;
+
+        let result = self.check_login(email, password, client).await;
+        if let Err(ApiError::Auth) = result {
+            self.limiter.record(&keys);
+        }
+        self.metrics.record_login(result.is_ok());
+        result
+    }
+
+    async fn check_login(
+        &self,
+        email: Email,
+        password: Password,
+        client: &ClientInfo,
+    ) -> Result<Jwt, ApiError> {
+        let Some(user) = self.user_with_email(email).await? else {
+            self.audit
+                .record(AuditAction::LoginFailed, None, None, client)
+                .await;
+            return Err(ApiError::Auth);
+        };
+        let user_id = user.id;
+        Span::current().record("user_id", field::display(user_id.0));
+
+        if !self.hasher.verify(&password, &user.password_hash) {
+            self.audit
+                .record(AuditAction::LoginFailed, None, Some(user_id), client)
+                .await;
+            return Err(ApiError::Auth);
         }
+
+        self.sign_in(user, client).await
+    }
+
+    /// Start a session for a user who has proven who they are
+    pub(super) async fn sign_in(&self, user: User, client: &ClientInfo) -> Result<Jwt, ApiError> {
+        let user_id = user.id;
+        let (jwt, session) = self.new_session(user, client)?;
+        self.db.create_session(session).await?;
+
+        self.audit
+            .record(AuditAction::Login, Some(user_id), Some(user_id), client)
+            .await;
+        Ok(jwt)
     }
 
-    #[instrument]
-    pub async fn create_user(&self, email: Email, password: Password) -> Result<Jwt, ApiError> {
+    #[instrument(skip_all, fields(email = %email.redacted(), user_id = field::Empty))]
+    pub async fn create_user(
+        &self,
+        email: Email,
+        password: Password,
+        client: &ClientInfo,
+    ) -> Result<Jwt, ApiError> {
+        self.check_password(&password, &email)?;
+        let (_, jwt) = self
+            .sign_up(email, &password, |_, _| async { Ok(()) }.boxed(), client)
+            .await?;
+        Ok(jwt)
+    }
+
+    /// Make an account for `email` that's signed in to some other way than with a password, such
+    /// as with a link or at an identity provider, saving whatever `inserts` saves alongside it
+    pub(super) async fn sign_up_without_password<F>(
+        &self,
+        email: Email,
+        inserts: F,
+        client: &ClientInfo,
+    ) -> Result<(UserId, Jwt), ApiError>
+    where
+        F: for<'t> Fn(&'t dyn Transaction, UserId) -> BoxFuture<'t, Result<(), DbError>>
+            + Send
+            + Sync,
+    {
+        // it has to have a password, so it gets one nobody knows
+        let password = Password::new(self.random.token().expose_secret().clone());
+        self.sign_up(email, &password, inserts, client).await
+    }
+
+    async fn sign_up<F>(
+        &self,
+        email: Email,
+        password: &Password,
+        inserts: F,
+        client: &ClientInfo,
+    ) -> Result<(UserId, Jwt), ApiError>
+    where
+        F: for<'t> Fn(&'t dyn Transaction, UserId) -> BoxFuture<'t, Result<(), DbError>>
+            + Send
+            + Sync,
+    {
         let id = self.random.user_id();
+        Span::current().record("user_id", field::display(id.0));
         let created_at = self.time.now();
-        let password_hash = self.hasher.hash(&password).map_err(|_| ApiError::Auth)?;
+        let password_hash = self.hasher.hash(password).map_err(|_| ApiError::Auth)?;
+        let user = User::new(id, email, password_hash, created_at);
 
-        let user = User {
-            id,
-            email,
-            password_hash,
-            created_at,
-        };
+        let (jwt, session) = self.new_session(user.clone(), client)?;
+        let inserts = &inserts;
+        self.db
+            .transaction(|tx| {
+                let (user, session) = (user.clone(), session.clone());
+                let inserts = inserts(tx, id);
+                async move {
+                    tx.create_user(user).await?;
+                    inserts.await?;
+                    tx.create_session(session).await
+                }
+                .boxed()
+            })
+            .await?;
+
+        self.audit
+            .record(AuditAction::UserCreated, Some(id), Some(id), client)
+            .await;
+        Ok((id, jwt))
+    }
 
-        self.db.create_user(user.clone()).await?;
+    /// Hold a password someone is choosing for the account at `email` to the configured policy
+    fn check_password(&self, password: &Password, email: &Email) -> Result<(), ApiError> {
+        self.passwords.check(password, email).map_err(|e| {
+            debug!(reason = %e, "password rejected");
+            e.into()
+        })
+    }
 
-        let jwt = self.jwt.create_jwt(user).map_err(|_| ApiError::Auth)?;
+    /// Hash a password someone is choosing for the account at `email`, once it's been held to the
+    /// configured policy
+    pub(super) fn hash_new_password(
+        &self,
+        password: &Password,
+        email: &Email,
+    ) -> Result<PasswordHash, ApiError> {
+        self.check_password(password, email)?;
+        self.hasher.hash(password).map_err(|_| ApiError::Auth)
+    }
 
-        Ok(jwt)
+    /// Issue a token for `user`, along with the session it belongs to, which is yet to be saved
+    pub(super) fn new_session(
+        &self,
+        user: User,
+        client: &ClientInfo,
+    ) -> Result<(Jwt, Session), ApiError> {
+        let (jwt, claims) = self.jwt.create_jwt(user).map_err(|_| ApiError::Auth)?;
+        Ok((jwt, self.session(&claims, client)))
     }
 
-    #[instrument]
+    /// Like `new_session`, but the token only lets `client_id` do what `scope` allows
+    pub(super) fn new_scoped_session(
+        &self,
+        user: User,
+        client_id: ClientId,
+        scope: Scopes,
+        client: &ClientInfo,
+    ) -> Result<(Jwt, Session), ApiError> {
+        let (jwt, claims) = self
+            .jwt
+            .create_scoped_jwt(user, client_id, scope)
+            .map_err(|_| ApiError::Auth)?;
+        Ok((jwt, self.session(&claims, client)))
+    }
+
+    fn session(&self, claims: &Claims<Validated>, client: &ClientInfo) -> Session {
+        let now = self.time.now();
+        Session {
+            id: session_id(claims),
+            user_id: claims.subject,
+            created_at: now,
+            last_seen_at: now,
+            user_agent: client.user_agent.clone(),
+            ip: client.ip.map(|ip| ip.to_string()),
+        }
+    }
+
+    #[instrument(skip_all)]
     pub fn validate_jwt(&self, jwt: &Jwt) -> Result<Claims<Validated>, JwtError> {
         self.jwt.validate(jwt)
     }
 
+    /// Validate a JWT, and check that its session hasn't been revoked
+    ///
+    /// Unlike `validate_jwt`, this rejects revoked tokens, and tokens for deleted accounts, straight
+    /// away, rather than only once they expire. Tokens issued to OAuth clients are only accepted if
+    /// they were given the `api` scope
+    pub async fn authenticate(&self, jwt: &Jwt) -> Result<Claims<Validated>, ApiError> {
+        let claims = self.authenticate_session(jwt).await?;
+        match claims.allows(Scope::Api) {
+            true => Ok(claims),
+            false => Err(ApiError::Auth),
+        }
+    }
+
+    /// Like `authenticate`, but whatever the token's scope
+    #[instrument(skip_all, fields(user_id = field::Empty))]
+    pub(super) async fn authenticate_session(
+        &self,
+        jwt: &Jwt,
+    ) -> Result<Claims<Validated>, ApiError> {
+        let claims = self.validate_jwt(jwt).map_err(|_| ApiError::Auth)?;
+        Span::current().record("user_id", field::display(claims.subject.0));
+
+        let session = match self.db.session_by_id(session_id(&claims)).await? {
+            Some(session) if session.user_id == claims.subject => session,
+            _ => return Err(ApiError::Auth),
+        };
+
+        let now = self.time.now();
+        if now - session.last_seen_at >= Duration::seconds(SESSION_TOUCH_INTERVAL_SECONDS) {
+            self.db.touch_session(session.id, now).await?;
+        }
+        Ok(claims)
+    }
+
+    /// A CSRF token for the session `jwt` belongs to, for browsers that keep it in a cookie
+    pub fn csrf_token(&self, jwt: &Jwt) -> Result<Jwt, ApiError> {
+        let claims = self.validate_jwt(jwt).map_err(|_| ApiError::Auth)?;
+        self.jwt
+            .create_csrf_token(&claims)
+            .map_err(|_| ApiError::Auth)
+    }
+
+    /// Whether `token` is the CSRF token for the session `jwt` belongs to
+    pub(super) fn check_csrf_token(&self, jwt: &Jwt, token: &str) -> bool {
+        match self.validate_jwt(jwt) {
+            Ok(claims) => self.jwt.check_csrf_token(&claims, token),
+            Err(_) => false,
+        }
+    }
+
+    /// Reissue the token `claims` came from to act in `org`, as part of the same session
+    pub(super) fn switch_org(
+        &self,
+        claims: &Claims<Validated>,
+        org: Option<OrgId>,
+    ) -> Result<Jwt, ApiError> {
+        let (jwt, _) = self
+            .jwt
+            .create_org_jwt(claims, org)
+            .map_err(|_| ApiError::Auth)?;
+        Ok(jwt)
+    }
+
+    /// How long a new session lasts
+    pub fn session_ttl(&self) -> Duration {
+        self.config.jwt.ttl()
+    }
+
+    /// The sessions a user's tokens haven't expired for yet, most recently seen first
+    #[instrument(skip_all, fields(user_id = %claims.subject.0))]
+    pub async fn sessions(&self, claims: &Claims<Validated>) -> Result<Vec<Session>, ApiError> {
+        let created_since = self.time.now() - self.config.jwt.ttl();
+        let sessions = self.db.sessions_for_user(claims.subject).await?;
+
+        Ok(sessions
+            .into_iter()
+            .filter(|session| session.created_at > created_since)
+            .collect())
+    }
+
+    #[instrument(skip_all, fields(user_id = %claims.subject.0, session_id = %session_id.0))]
+    pub async fn revoke_session(
+        &self,
+        claims: &Claims<Validated>,
+        session_id: SessionId,
+        client: &ClientInfo,
+    ) -> Result<(), ApiError> {
+        let user_id = claims.subject;
+        let result = self.db.delete_session(user_id, session_id).await;
+        not_found_if_unmodified(result)?;
+
+        self.audit
+            .record(
+                AuditAction::SessionRevoked,
+                Some(user_id),
+                Some(user_id),
+                client,
+            )
+            .await;
+        Ok(())
+    }
+
+    /// Sign a user out everywhere, including the session `claims` came from
+    #[instrument(skip_all, fields(user_id = %claims.subject.0))]
+    pub async fn revoke_all_sessions(
+        &self,
+        claims: &Claims<Validated>,
+        client: &ClientInfo,
+    ) -> Result<usize, ApiError> {
+        let user_id = claims.subject;
+        let revoked = self.db.delete_sessions_for_user(user_id).await?;
+
+        self.audit
+            .record(
+                AuditAction::SessionsRevoked,
+                Some(user_id),
+                Some(user_id),
+                client,
+            )
+            .await;
+        Ok(revoked)
+    }
+
+    /// Change the signed in user's password, signing them out everywhere but the session `claims`
+    /// came from
+    ///
+    /// The current password is asked for again, so that a stolen JWT isn't enough to take over the
+    /// account
+    #[instrument(skip_all, fields(user_id = %claims.subject.0))]
+    pub async fn change_password(
+        &self,
+        claims: &Claims<Validated>,
+        password: Password,
+        new_password: Password,
+        client: &ClientInfo,
+    ) -> Result<(), ApiError> {
+        let user = self
+            .db
+            .user_by_id(claims.subject)
+            .await?
+            .ok_or(ApiError::NotFound)?;
+
+        if !self.hasher.verify(&password, &user.password_hash) {
+            return Err(ApiError::Auth);
+        }
+        let password_hash = self.hash_new_password(&new_password, &user.email)?;
+        let (user_id, current) = (user.id, session_id(claims));
+        self.db
+            .transaction(|tx| {
+                let (password_hash, current) = (password_hash.clone(), current.clone());
+                async move {
+                    tx.update_password(user_id, password_hash).await?;
+                    for session in tx.sessions_for_user(user_id).await? {
+                        if session.id != current {
+                            tx.delete_session(user_id, session.id).await?;
+                        }
+                    }
+                    Ok::<_, DbError>(())
+                }
+                .boxed()
+            })
+            .await?;
+
+        self.audit
+            .record(
+                AuditAction::PasswordChanged,
+                Some(user_id),
+                Some(user_id),
+                client,
+            )
+            .await;
+        Ok(())
+    }
+
+    pub fn is_admin(&self, claims: &Claims<Validated>) -> bool {
+        self.config.admins.contains(&claims.subject)
+    }
+
     #[allow(dead_code)]
-    #[instrument]
+    #[instrument(skip_all, fields(user_id = %user_id.0))]
     pub async fn user_with_id(&self, user_id: UserId) -> Result<Option<User>, ApiError> {
         Ok(self.db.user_by_id(user_id).await?)
     }
 
-    #[instrument]
+    #[instrument(skip_all, fields(email = %email.redacted()))]
     async fn user_with_email(&self, email: Email) -> Result<Option<User>, ApiError> {
         Ok(self.db.user_by_email(email).await?)
     }
 
-    #[instrument]
-    pub async fn delete_user(&self, claims: &Claims<Validated>) -> Result<(), ApiError> {
-        self.db.delete_user(claims.subject).await?;
+    #[instrument(skip_all, fields(user_id = %claims.subject.0))]
+    pub async fn delete_user(
+        &self,
+        claims: &Claims<Validated>,
+        client: &ClientInfo,
+    ) -> Result<(), ApiError> {
+        let (user_id, now) = (claims.subject, self.time.now());
+        let result = self
+            .db
+            .transaction(|tx| {
+                async move {
+                    tx.delete_user(user_id, now).await?;
+                    tx.delete_sessions_for_user(user_id).await?;
+                    tx.delete_refresh_tokens_for_user(user_id).await?;
+                    tx.delete_api_keys_for_user(user_id).await?;
+                    Ok(())
+                }
+                .boxed()
+            })
+            .await;
+        not_found_if_unmodified(result)?;
+
+        self.audit
+            .record(
+                AuditAction::UserDeleted,
+                Some(user_id),
+                Some(user_id),
+                client,
+            )
+            .await;
+        Ok(())
+    }
+
+    /// Undo a deletion on behalf of `admin`, as long as it's within the grace period and the user
+    /// hasn't been purged
+    #[instrument(skip_all, fields(user_id = %user_id.0, admin_id = %admin.subject.0))]
+    pub async fn restore_user(
+        &self,
+        admin: &Claims<Validated>,
+        user_id: UserId,
+        client: &ClientInfo,
+    ) -> Result<(), ApiError> {
+        let deleted_since = self.time.now() - self.config.accounts.deletion_grace_period();
+        let result = self.db.restore_user(user_id, deleted_since).await;
+        not_found_if_unmodified(result)?;
+
+        self.audit
+            .record(
+                AuditAction::UserRestored,
+                Some(admin.subject),
+                Some(user_id),
+                client,
+            )
+            .await;
         Ok(())
     }
+
+    /// Permanently remove users whose grace period has passed, along with any organizations that
+    /// would be left without an owner, returning how many users there were
+    #[instrument(skip_all, fields(purged = field::Empty))]
+    pub async fn purge_deleted_users(&self) -> Result<usize, DbError> {
+        let deleted_before = self.time.now() - self.config.accounts.deletion_grace_period();
+        let (purged, organizations) = self
+            .db
+            .transaction(|tx| {
+                async move {
+                    let user_ids = tx.users_deleted_before(deleted_before).await?;
+                    let organizations = delete_orphaned_organizations(tx, &user_ids).await?;
+                    let purged = tx.purge_deleted_users(deleted_before).await?;
+                    Ok::<_, DbError>((purged, organizations))
+                }
+                .boxed()
+            })
+            .await?;
+        Span::current().record("purged", purged);
+
+        for _ in &organizations {
+            self.audit
+                .record(
+                    AuditAction::OrganizationDeleted,
+                    None,
+                    None,
+                    &ClientInfo::default(),
+                )
+                .await;
+        }
+        if purged > 0 {
+            info!(
+                purged,
+                organizations = organizations.len(),
+                "purged deleted users"
+            );
+            self.audit
+                .record(AuditAction::UsersPurged, None, None, &ClientInfo::default())
+                .await;
+        }
+        Ok(purged)
+    }
+
+    /// Remove sessions whose tokens have expired, returning how many there were
+    #[instrument(skip_all, fields(purged = field::Empty))]
+    pub async fn purge_expired_sessions(&self) -> Result<usize, DbError> {
+        let created_before = self.time.now() - self.config.jwt.ttl();
+        let purged = self
+            .db
+            .delete_sessions_created_before(created_before)
+            .await?;
+        Span::current().record("purged", purged);
+
+        if purged > 0 {
+            info!(purged, "purged expired sessions");
+            self.audit
+                .record(
+                    AuditAction::SessionsPurged,
+                    None,
+                    None,
+                    &ClientInfo::default(),
+                )
+                .await;
+        }
+        Ok(purged)
+    }
+
+    /// Purge deleted users, expired sessions and spent sign in limits, returning how many users
+    /// and sessions there were
+    pub async fn purge_expired(&self) -> Result<usize, DbError> {
+        self.limiter.purge_expired();
+        let users = self.purge_deleted_users().await?;
+        let sessions = self.purge_expired_sessions().await?;
+        Ok(users + sessions)
+    }
+}
+
+/// An update that matched no rows means the user it was for doesn't exist, as far as callers are
+/// concerned
+pub(super) fn not_found_if_unmodified<T>(result: Result<T, DbError>) -> Result<T, ApiError> {
+    match result {
+        Err(DbError::RowsModified { actual: 0, .. }) => Err(ApiError::NotFound),
+        result => Ok(result?),
+    }
+}
+
+/// Sessions are keyed by the `jti` of the token they were issued with
+fn session_id(claims: &Claims<Validated>) -> SessionId {
+    SessionId(claims.jwt_id.0.clone())
 }
 
 #[cfg(test)]
 mod tests {
+    use chrono::Duration;
+    use microtype::SecretMicrotype;
+
     use crate::{
-        model::types::mock::{DEFAULT_EMAIL, DEFAULT_PASSWORD},
-        state::Services,
-        testing::{test_data::TEST_DATA, test_services, test_services_with},
+        model::{
+            audit::AuditAction,
+            session::Session,
+            types::{
+                mock::{
+                    ADMIN_EMAIL, ADMIN_USER_ID, DEFAULT_EMAIL, DEFAULT_PASSWORD, DEFAULT_USER_ID,
+                },
+                Email, Password, SessionId,
+            },
+        },
+        routing::{client::ClientInfo, errors::ApiError},
+        state::{
+            jwt::claims::{Claims, Validated},
+            password::PasswordError,
+            time::mock::DEFAULT_DATE_TIME,
+            Services,
+        },
+        testing::{
+            audited, login, sign_in, test_data::TEST_DATA, test_services, test_services_with,
+        },
     };
 
+    use super::session_id;
+
+    /// Claims for the admin, which are good for restoring users even once the admin is deleted
+    async fn admin(services: &Services) -> Claims<Validated> {
+        let jwt = login(services, &ADMIN_EMAIL).await;
+        services.auth.validate_jwt(&jwt).unwrap()
+    }
+
     #[tokio::test]
     async fn can_create_user() {
         let Services { auth, .. } = test_services();
 
         let jwt = auth
-            .create_user(DEFAULT_EMAIL.clone(), DEFAULT_PASSWORD.clone())
+            .create_user(
+                DEFAULT_EMAIL.clone(),
+                DEFAULT_PASSWORD.clone(),
+                &ClientInfo::default(),
+            )
             .await
             .unwrap();
 
@@ -126,15 +666,359 @@ mod tests {
         assert_eq!(user.email, claims.email)
     }
 
+    #[tokio::test]
+    async fn new_users_need_a_good_password() {
+        let Services { auth, .. } = test_services();
+
+        let rejected = [
+            ("", PasswordError::TooShort(8)),
+            ("password1", PasswordError::Breached),
+            ("default@email.com is me", PasswordError::ContainsEmail),
+        ];
+        for (password, reason) in rejected {
+            let result = auth
+                .create_user(
+                    DEFAULT_EMAIL.clone(),
+                    Password::new(password.into()),
+                    &ClientInfo::default(),
+                )
+                .await;
+            assert!(matches!(result, Err(ApiError::Password(e)) if e == reason));
+        }
+
+        let user = auth.user_with_email(DEFAULT_EMAIL.clone()).await.unwrap();
+        assert!(user.is_none());
+    }
+
     #[tokio::test]
     async fn can_login() {
         let Services { auth, .. } = test_services_with(TEST_DATA.clone());
         let jwt = auth
-            .login(DEFAULT_EMAIL.clone(), DEFAULT_PASSWORD.clone())
+            .login(
+                DEFAULT_EMAIL.clone(),
+                DEFAULT_PASSWORD.clone(),
+                &ClientInfo::default(),
+            )
             .await
             .unwrap();
 
         let claims = auth.jwt.validate(&jwt).unwrap();
         assert_eq!(claims.email, DEFAULT_EMAIL.clone());
     }
+
+    #[tokio::test]
+    async fn deleted_user_cannot_log_in_or_authenticate() {
+        let services = test_services_with(TEST_DATA.clone());
+        let auth = &services.auth;
+        let jwt = login(&services, &DEFAULT_EMAIL).await;
+        let claims = auth.authenticate(&jwt).await.unwrap();
+
+        auth.delete_user(&claims, &ClientInfo::default())
+            .await
+            .unwrap();
+
+        assert!(matches!(auth.authenticate(&jwt).await, Err(ApiError::Auth)));
+        let login = auth
+            .login(
+                DEFAULT_EMAIL.clone(),
+                DEFAULT_PASSWORD.clone(),
+                &ClientInfo::default(),
+            )
+            .await;
+        assert!(matches!(login, Err(ApiError::Auth)));
+        assert!(matches!(
+            auth.delete_user(&claims, &ClientInfo::default()).await,
+            Err(ApiError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn restores_within_grace_period() {
+        let services = test_services_with(TEST_DATA.clone());
+        let auth = &services.auth;
+        let db = &services.db;
+        let grace_period = auth.config.accounts.deletion_grace_period();
+        let admin = admin(&services).await;
+
+        db.delete_user(*DEFAULT_USER_ID, *DEFAULT_DATE_TIME - grace_period)
+            .await
+            .unwrap();
+        auth.restore_user(&admin, *DEFAULT_USER_ID, &ClientInfo::default())
+            .await
+            .unwrap();
+
+        db.delete_user(
+            *DEFAULT_USER_ID,
+            *DEFAULT_DATE_TIME - grace_period - Duration::seconds(1),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(
+            auth.restore_user(&admin, *DEFAULT_USER_ID, &ClientInfo::default())
+                .await,
+            Err(ApiError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn changing_password_checks_both_and_signs_out_elsewhere() {
+        let services = test_services_with(TEST_DATA.clone());
+        let auth = &services.auth;
+        let claims = sign_in(&services, &DEFAULT_EMAIL).await;
+        let elsewhere = login(&services, &DEFAULT_EMAIL).await;
+        let new_password = Password::new("correct horse battery staple 42".into());
+
+        let result = auth
+            .change_password(
+                &claims,
+                Password::new("wrong".into()),
+                new_password.clone(),
+                &ClientInfo::default(),
+            )
+            .await;
+        assert!(matches!(result, Err(ApiError::Auth)));
+        let result = auth
+            .change_password(
+                &claims,
+                DEFAULT_PASSWORD.clone(),
+                Password::new("short".into()),
+                &ClientInfo::default(),
+            )
+            .await;
+        assert!(matches!(
+            result,
+            Err(ApiError::Password(PasswordError::TooShort(_)))
+        ));
+
+        auth.change_password(
+            &claims,
+            DEFAULT_PASSWORD.clone(),
+            new_password.clone(),
+            &ClientInfo::default(),
+        )
+        .await
+        .unwrap();
+        assert!(auth.authenticate(&elsewhere).await.is_err());
+        assert_eq!(auth.sessions(&claims).await.unwrap().len(), 1);
+        assert!(auth
+            .login(
+                DEFAULT_EMAIL.clone(),
+                DEFAULT_PASSWORD.clone(),
+                &ClientInfo::default()
+            )
+            .await
+            .is_err());
+        auth.login(DEFAULT_EMAIL.clone(), new_password, &ClientInfo::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            audited(&services, AuditAction::PasswordChanged).await.len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn purges_after_grace_period() {
+        let services = test_services_with(TEST_DATA.clone());
+        let auth = &services.auth;
+        let db = &services.db;
+        let grace_period = auth.config.accounts.deletion_grace_period();
+        let admin = admin(&services).await;
+
+        db.delete_user(
+            *DEFAULT_USER_ID,
+            *DEFAULT_DATE_TIME - grace_period - Duration::seconds(1),
+        )
+        .await
+        .unwrap();
+        db.delete_user(*ADMIN_USER_ID, *DEFAULT_DATE_TIME - grace_period)
+            .await
+            .unwrap();
+
+        assert_eq!(auth.purge_deleted_users().await.unwrap(), 1);
+        assert!(matches!(
+            auth.restore_user(&admin, *DEFAULT_USER_ID, &ClientInfo::default())
+                .await,
+            Err(ApiError::NotFound)
+        ));
+        auth.restore_user(&admin, *ADMIN_USER_ID, &ClientInfo::default())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn login_starts_a_session() {
+        let services = test_services_with(TEST_DATA.clone());
+        let auth = &services.auth;
+        let jwt = login(&services, &DEFAULT_EMAIL).await;
+        let claims = auth.authenticate(&jwt).await.unwrap();
+
+        let sessions = auth.sessions(&claims).await.unwrap();
+
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].id, session_id(&claims));
+        assert_eq!(sessions[0].user_agent.as_deref(), Some("test"));
+        assert_eq!(sessions[0].ip.as_deref(), Some("127.0.0.1"));
+    }
+
+    #[tokio::test]
+    async fn revoked_session_cannot_authenticate() {
+        let services = test_services_with(TEST_DATA.clone());
+        let auth = &services.auth;
+        let revoked = login(&services, &DEFAULT_EMAIL).await;
+        let kept = login(&services, &DEFAULT_EMAIL).await;
+        let claims = auth.authenticate(&kept).await.unwrap();
+        let revoked_id = session_id(&auth.validate_jwt(&revoked).unwrap());
+
+        auth.revoke_session(&claims, revoked_id.clone(), &ClientInfo::default())
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            auth.authenticate(&revoked).await,
+            Err(ApiError::Auth)
+        ));
+        assert_eq!(auth.sessions(&claims).await.unwrap().len(), 1);
+        assert!(matches!(
+            auth.revoke_session(&claims, revoked_id, &ClientInfo::default())
+                .await,
+            Err(ApiError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn cannot_revoke_another_users_session() {
+        let services = test_services_with(TEST_DATA.clone());
+        let auth = &services.auth;
+        let user = sign_in(&services, &DEFAULT_EMAIL).await;
+        let admin = sign_in(&services, &ADMIN_EMAIL).await;
+
+        assert!(matches!(
+            auth.revoke_session(&admin, session_id(&user), &ClientInfo::default())
+                .await,
+            Err(ApiError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn revoking_all_sessions_signs_out_everywhere() {
+        let services = test_services_with(TEST_DATA.clone());
+        let auth = &services.auth;
+        let jwts = [
+            login(&services, &DEFAULT_EMAIL).await,
+            login(&services, &DEFAULT_EMAIL).await,
+        ];
+        let claims = auth.authenticate(&jwts[0]).await.unwrap();
+
+        assert_eq!(
+            auth.revoke_all_sessions(&claims, &ClientInfo::default())
+                .await
+                .unwrap(),
+            2
+        );
+
+        for jwt in &jwts {
+            assert!(matches!(auth.authenticate(jwt).await, Err(ApiError::Auth)));
+        }
+    }
+
+    #[tokio::test]
+    async fn expired_sessions_are_hidden_then_purged() {
+        let services = test_services_with(TEST_DATA.clone());
+        let auth = &services.auth;
+        let db = &services.db;
+        let claims = sign_in(&services, &DEFAULT_EMAIL).await;
+        let created_at = *DEFAULT_DATE_TIME - auth.config.jwt.ttl() - Duration::seconds(1);
+        db.create_session(Session {
+            id: SessionId("expired".into()),
+            user_id: *DEFAULT_USER_ID,
+            created_at,
+            last_seen_at: created_at,
+            user_agent: None,
+            ip: None,
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(auth.sessions(&claims).await.unwrap().len(), 1);
+        assert_eq!(auth.purge_expired_sessions().await.unwrap(), 1);
+        assert_eq!(
+            audited(&services, AuditAction::SessionsPurged).await.len(),
+            1
+        );
+        assert!(db
+            .session_by_id(SessionId("expired".into()))
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn logins_are_audited() {
+        let services = test_services_with(TEST_DATA.clone());
+        let auth = &services.auth;
+        let wrong_password = Password::new("wrong password".into());
+        let nobody = Email("nobody@email.com".into());
+
+        login(&services, &DEFAULT_EMAIL).await;
+        for (email, password) in [
+            (DEFAULT_EMAIL.clone(), wrong_password),
+            (nobody, DEFAULT_PASSWORD.clone()),
+        ] {
+            let result = auth.login(email, password, &ClientInfo::default()).await;
+            assert!(matches!(result, Err(ApiError::Auth)));
+        }
+
+        let logins = audited(&services, AuditAction::Login).await;
+        assert_eq!(logins.len(), 1);
+        assert_eq!(logins[0].actor_id, Some(*DEFAULT_USER_ID));
+        assert_eq!(logins[0].subject_id, Some(*DEFAULT_USER_ID));
+        assert_eq!(logins[0].occurred_at, *DEFAULT_DATE_TIME);
+        assert_eq!(logins[0].ip.as_deref(), Some("127.0.0.1"));
+        assert_eq!(logins[0].user_agent.as_deref(), Some("test"));
+        assert_eq!(logins[0].request_id.as_deref(), Some("request"));
+
+        let mut failures: Vec<_> = audited(&services, AuditAction::LoginFailed)
+            .await
+            .into_iter()
+            .map(|event| (event.actor_id, event.subject_id))
+            .collect();
+        failures.sort_by_key(|(_, subject_id)| subject_id.is_some());
+        assert_eq!(failures, [(None, None), (None, Some(*DEFAULT_USER_ID))]);
+    }
+
+    #[tokio::test]
+    async fn account_changes_are_audited() {
+        let services = test_services_with(TEST_DATA.clone());
+        let auth = &services.auth;
+        let admin = admin(&services).await;
+        let claims = sign_in(&services, &DEFAULT_EMAIL).await;
+
+        auth.revoke_all_sessions(&claims, &ClientInfo::default())
+            .await
+            .unwrap();
+        auth.delete_user(&claims, &ClientInfo::default())
+            .await
+            .unwrap();
+        auth.restore_user(&admin, *DEFAULT_USER_ID, &ClientInfo::default())
+            .await
+            .unwrap();
+
+        for action in [AuditAction::SessionsRevoked, AuditAction::UserDeleted] {
+            let events = audited(&services, action).await;
+            assert_eq!(events.len(), 1);
+            assert_eq!(events[0].actor_id, Some(*DEFAULT_USER_ID));
+        }
+        let restores = audited(&services, AuditAction::UserRestored).await;
+        assert_eq!(restores.len(), 1);
+        assert_eq!(restores[0].actor_id, Some(*ADMIN_USER_ID));
+        assert_eq!(restores[0].subject_id, Some(*DEFAULT_USER_ID));
+
+        // a failed restore changes nothing, so there's nothing to audit
+        let restore = auth
+            .restore_user(&admin, *DEFAULT_USER_ID, &ClientInfo::default())
+            .await;
+        assert!(matches!(restore, Err(ApiError::NotFound)));
+        assert_eq!(audited(&services, AuditAction::UserRestored).await.len(), 1);
+    }
 }
@@ -0,0 +1,420 @@
+use std::sync::Arc;
+
+use futures::FutureExt;
+use tracing::{field, Span};
+use url::Url;
+
+use crate::{
+    config::Config,
+    db::Db,
+    model::{
+        audit::AuditAction,
+        email_change::EmailChange,
+        types::{Email, Password, Token},
+    },
+    routing::{client::ClientInfo, errors::ApiError},
+    state::jwt::claims::{Claims, Validated},
+};
+
+use super::{
+    audit::AuditService,
+    auth::not_found_if_unmodified,
+    hasher::{hash_token, Hasher},
+    mailer::{Mailer, Message},
+    random::Random,
+    time::Time,
+    Common,
+};
+
+/// Changing a user's email address, which only happens once the new address has been confirmed
+///
+/// The old address is told about the change, with a link to cancel it
+#[derive(Debug, Clone)]
+pub struct EmailChangeService {
+    time: Arc<dyn Time>,
+    random: Arc<dyn Random>,
+    hasher: Arc<dyn Hasher>,
+    db: Arc<dyn Db>,
+    mailer: Arc<dyn Mailer>,
+    audit: AuditService,
+    config: Arc<Config>,
+}
+
+impl EmailChangeService {
+    pub fn new(common: Common, hasher: Arc<dyn Hasher>, mailer: Arc<dyn Mailer>) -> Self {
+        let Common {
+            time,
+            random,
+            db,
+            audit,
+            config,
+        } = common;
+
+        Self {
+            time,
+            random,
+            hasher,
+            db,
+            mailer,
+            audit,
+            config,
+        }
+    }
+
+    /// Start changing the signed in user's address to `new_email`, replacing any change they
+    /// already had pending
+    ///
+    /// The password is asked for again, so that a stolen JWT isn't enough to take over the account
+    #[instrument(skip_all, fields(user_id = %claims.subject.0, new_email = %new_email.redacted()))]
+    pub async fn start(
+        &self,
+        claims: &Claims<Validated>,
+        new_email: Email,
+        password: Password,
+        client: &ClientInfo,
+    ) -> Result<EmailChange, ApiError> {
+        let user = self
+            .db
+            .user_by_id(claims.subject)
+            .await?
+            .ok_or(ApiError::NotFound)?;
+
+        if !self.hasher.verify(&password, &user.password_hash) {
+            return Err(ApiError::Auth);
+        }
+        if new_email == user.email || self.db.user_by_email(new_email.clone()).await?.is_some() {
+            return Err(ApiError::Invalid { field: "email" });
+        }
+
+        let (confirm_token, cancel_token) = (self.random.token(), self.random.token());
+        let now = self.time.now();
+        let change = EmailChange {
+            id: self.random.uuid(),
+            user_id: user.id,
+            new_email: new_email.clone(),
+            confirm_token_hash: hash_token(&confirm_token),
+            cancel_token_hash: hash_token(&cancel_token),
+            created_at: now,
+            expires_at: now + self.config.accounts.email_change_ttl(),
+        };
+
+        self.db
+            .transaction(|tx| {
+                let change = change.clone();
+                async move {
+                    tx.delete_email_changes_for_user(change.user_id).await?;
+                    tx.create_email_change(change).await
+                }
+                .boxed()
+            })
+            .await?;
+
+        self.mailer
+            .send(Message {
+                to: new_email.clone(),
+                subject: "Confirm your new email address".into(),
+                body: format!(
+                    "Follow this link to start using this address for your account: {}",
+                    self.link("confirm", &confirm_token)
+                ),
+            })
+            .await?;
+        self.mailer
+            .send(Message {
+                to: user.email,
+                subject: "Your email address is being changed".into(),
+                body: format!(
+                    "Someone asked to change your account's email address to {}. If this wasn't \
+                     you, follow this link to cancel the change: {}",
+                    new_email.redacted(),
+                    self.link("cancel", &cancel_token)
+                ),
+            })
+            .await?;
+
+        self.audit
+            .record(
+                AuditAction::EmailChangeRequested,
+                Some(user.id),
+                Some(user.id),
+                client,
+            )
+            .await;
+        Ok(change)
+    }
+
+    /// Swap in the new address, signing the user out everywhere so no token holds the old one
+    #[instrument(skip_all, fields(user_id = field::Empty))]
+    pub async fn confirm(&self, token: Token, client: &ClientInfo) -> Result<(), ApiError> {
+        let change = self
+            .db
+            .email_change_by_confirm_token(hash_token(&token))
+            .await?
+            .filter(|change| change.expires_at > self.time.now())
+            .ok_or(ApiError::NotFound)?;
+        let user_id = change.user_id;
+        Span::current().record("user_id", field::display(user_id.0));
+
+        let result = self
+            .db
+            .transaction(|tx| {
+                let change = change.clone();
+                async move {
+                    tx.delete_email_change(change.id).await?;
+                    tx.update_email(change.user_id, change.new_email).await?;
+                    tx.delete_sessions_for_user(change.user_id).await?;
+                    Ok(())
+                }
+                .boxed()
+            })
+            .await;
+        not_found_if_unmodified(result)?;
+
+        self.audit
+            .record(
+                AuditAction::EmailChanged,
+                Some(user_id),
+                Some(user_id),
+                client,
+            )
+            .await;
+        Ok(())
+    }
+
+    /// Drop a pending change, which works even once it's expired
+    #[instrument(skip_all, fields(user_id = field::Empty))]
+    pub async fn cancel(&self, token: Token, client: &ClientInfo) -> Result<(), ApiError> {
+        let change = self
+            .db
+            .email_change_by_cancel_token(hash_token(&token))
+            .await?
+            .ok_or(ApiError::NotFound)?;
+        let user_id = change.user_id;
+        Span::current().record("user_id", field::display(user_id.0));
+
+        let result = self.db.delete_email_change(change.id).await;
+        not_found_if_unmodified(result)?;
+
+        self.audit
+            .record(
+                AuditAction::EmailChangeCancelled,
+                Some(user_id),
+                Some(user_id),
+                client,
+            )
+            .await;
+        Ok(())
+    }
+
+    fn link(&self, action: &str, token: &Token) -> Url {
+        self.config.email.link(&format!("email/{action}"), token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use microtype::SecretMicrotype;
+    use uuid::Uuid;
+
+    use crate::{
+        model::{
+            audit::AuditAction,
+            email_change::EmailChange,
+            types::{
+                mock::{ADMIN_EMAIL, DEFAULT_EMAIL, DEFAULT_PASSWORD, DEFAULT_USER_ID},
+                Email, Password, Token,
+            },
+        },
+        routing::{client::ClientInfo, errors::ApiError},
+        state::{
+            hasher::hash_token,
+            jwt::{
+                claims::{Claims, Validated},
+                Jwt,
+            },
+            time::mock::DEFAULT_DATE_TIME,
+            Services,
+        },
+        testing::{audited, login, sign_in, test_data::TEST_DATA, test_services_with},
+    };
+
+    fn new_email() -> Email {
+        Email("new@email.com".into())
+    }
+
+    /// Sign in as the default user and start changing their address to `new_email()`
+    async fn start(services: &Services) -> (Jwt, Claims<Validated>) {
+        let jwt = login(services, &DEFAULT_EMAIL).await;
+        let claims = services.auth.authenticate(&jwt).await.unwrap();
+        services
+            .email_change
+            .start(
+                &claims,
+                new_email(),
+                DEFAULT_PASSWORD.clone(),
+                &ClientInfo::default(),
+            )
+            .await
+            .unwrap();
+        (jwt, claims)
+    }
+
+    #[tokio::test]
+    async fn confirming_swaps_email_and_signs_out() {
+        let services = test_services_with(TEST_DATA.clone());
+        let (jwt, _) = start(&services).await;
+
+        let sent = services.mailer.as_mock().sent();
+        assert_eq!(sent.len(), 2);
+        assert!(sent[0]
+            .body
+            .contains("https://app.localhost/email/confirm?token="));
+        assert!(sent[1]
+            .body
+            .contains("https://app.localhost/email/cancel?token="));
+        assert_eq!(
+            audited(&services, AuditAction::EmailChangeRequested)
+                .await
+                .len(),
+            1
+        );
+
+        // nothing changes until the new address is confirmed
+        services.auth.authenticate(&jwt).await.unwrap();
+
+        let token = services.mailer.as_mock().token_sent_to(&new_email());
+        services
+            .email_change
+            .confirm(token.clone(), &ClientInfo::default())
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            services.auth.authenticate(&jwt).await,
+            Err(ApiError::Auth)
+        ));
+        let old = services
+            .auth
+            .login(
+                DEFAULT_EMAIL.clone(),
+                DEFAULT_PASSWORD.clone(),
+                &ClientInfo::default(),
+            )
+            .await;
+        assert!(matches!(old, Err(ApiError::Auth)));
+        let claims = sign_in(&services, &new_email()).await;
+        assert_eq!(claims.email, new_email());
+        assert_eq!(audited(&services, AuditAction::EmailChanged).await.len(), 1);
+
+        let again = services
+            .email_change
+            .confirm(token, &ClientInfo::default())
+            .await;
+        assert!(matches!(again, Err(ApiError::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn cancelling_keeps_old_email() {
+        let services = test_services_with(TEST_DATA.clone());
+        let (jwt, _) = start(&services).await;
+        let mailer = services.mailer.as_mock();
+
+        services
+            .email_change
+            .cancel(mailer.token_sent_to(&DEFAULT_EMAIL), &ClientInfo::default())
+            .await
+            .unwrap();
+
+        let confirm = services
+            .email_change
+            .confirm(mailer.token_sent_to(&new_email()), &ClientInfo::default())
+            .await;
+        assert!(matches!(confirm, Err(ApiError::NotFound)));
+        services.auth.authenticate(&jwt).await.unwrap();
+        assert_eq!(
+            audited(&services, AuditAction::EmailChangeCancelled)
+                .await
+                .len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn starting_again_replaces_pending_change() {
+        let services = test_services_with(TEST_DATA.clone());
+        let (_, claims) = start(&services).await;
+        let first = services.mailer.as_mock().token_sent_to(&new_email());
+
+        services
+            .email_change
+            .start(
+                &claims,
+                new_email(),
+                DEFAULT_PASSWORD.clone(),
+                &ClientInfo::default(),
+            )
+            .await
+            .unwrap();
+
+        let confirm = services
+            .email_change
+            .confirm(first, &ClientInfo::default())
+            .await;
+        assert!(matches!(confirm, Err(ApiError::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn expired_change_can_be_cancelled_but_not_confirmed() {
+        let services = test_services_with(TEST_DATA.clone());
+        let (confirm, cancel) = (Token::new("confirm".into()), Token::new("cancel".into()));
+        services
+            .db
+            .create_email_change(EmailChange {
+                id: Uuid::new_v4(),
+                user_id: *DEFAULT_USER_ID,
+                new_email: new_email(),
+                confirm_token_hash: hash_token(&confirm),
+                cancel_token_hash: hash_token(&cancel),
+                created_at: *DEFAULT_DATE_TIME - Duration::days(1),
+                expires_at: *DEFAULT_DATE_TIME,
+            })
+            .await
+            .unwrap();
+
+        let result = services
+            .email_change
+            .confirm(confirm, &ClientInfo::default())
+            .await;
+        assert!(matches!(result, Err(ApiError::NotFound)));
+        services
+            .email_change
+            .cancel(cancel, &ClientInfo::default())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_bad_requests() {
+        let services = test_services_with(TEST_DATA.clone());
+        let claims = sign_in(&services, &DEFAULT_EMAIL).await;
+        let wrong_password = Password::new("wrong password".into());
+
+        for (email, password, invalid) in [
+            (new_email(), wrong_password, false),
+            (DEFAULT_EMAIL.clone(), DEFAULT_PASSWORD.clone(), true),
+            (ADMIN_EMAIL.clone(), DEFAULT_PASSWORD.clone(), true),
+        ] {
+            let result = services
+                .email_change
+                .start(&claims, email, password, &ClientInfo::default())
+                .await;
+            match invalid {
+                true => assert!(matches!(result, Err(ApiError::Invalid { field: "email" }))),
+                false => assert!(matches!(result, Err(ApiError::Auth))),
+            }
+        }
+
+        assert!(services.mailer.as_mock().sent().is_empty());
+    }
+}
@@ -2,6 +2,7 @@ use std::fmt::Debug;
 
 use color_eyre::Result;
 use microtype::{secrecy::ExposeSecret, SecretMicrotype};
+use sha2::{Digest, Sha256};
 
 use crate::model::types::{Password, PasswordHash};
 
@@ -32,27 +33,19 @@ impl Hasher for BcryptHasher {
     }
 }
 
-#[cfg(test)]
-mod noop {
-
-    use super::*;
-
-    #[derive(Debug, Clone)]
-    pub struct NoopHasher;
-
-    impl Hasher for NoopHasher {
-        fn hash(&self, password: &Password) -> Result<PasswordHash> {
-            Ok(PasswordHash::new(password.expose_secret().clone()))
-        }
-
-        fn verify(&self, password: &Password, hash: &PasswordHash) -> bool {
-            password.expose_secret() == hash.expose_secret()
-        }
-    }
+/// Hash a token, or any other secret we generated, so it can be stored without the database being
+/// enough to use it
+///
+/// Unlike passwords, tokens are random enough that a fast, unsalted hash is safe, which also means
+/// they can be looked up by their hash
+pub fn hash_token(token: &impl ExposeSecret<String>) -> String {
+    hex::encode(Sha256::digest(token.expose_secret().as_bytes()))
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::model::types::Token;
+
     use super::*;
 
     #[test]
@@ -65,4 +58,14 @@ mod tests {
 
         assert!(!hasher.verify(&password, &PasswordHash::new("something".into())));
     }
+
+    #[test]
+    fn token_hashes_are_stable() {
+        let token = Token::new("abc".into());
+
+        assert_eq!(
+            hash_token(&token),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
 }
@@ -0,0 +1,127 @@
+use std::{sync::Arc, time::Duration};
+
+use serde::Serialize;
+use tokio::time::timeout;
+
+use crate::db::Db;
+
+use super::jwt::JwtService;
+
+/// How long a single dependency check may take before it is reported as failed
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone)]
+pub struct HealthService {
+    db: Arc<dyn Db>,
+    jwt: Arc<JwtService>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ComponentStatus {
+    Ok,
+    Error { error: String },
+}
+
+impl ComponentStatus {
+    pub fn is_ok(&self) -> bool {
+        matches!(self, ComponentStatus::Ok)
+    }
+
+    fn error(error: impl ToString) -> Self {
+        ComponentStatus::Error {
+            error: error.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReadinessReport {
+    pub ready: bool,
+    pub components: Components,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Components {
+    pub db: ComponentStatus,
+    pub migrations: ComponentStatus,
+    pub signing_keys: ComponentStatus,
+}
+
+impl HealthService {
+    pub fn new(db: Arc<dyn Db>, jwt: Arc<JwtService>) -> Self {
+        Self { db, jwt }
+    }
+
+    #[instrument(skip(self))]
+    pub async fn readiness(&self) -> ReadinessReport {
+        let (db, migrations) = tokio::join!(self.check_db(), self.check_migrations());
+        let signing_keys = self.check_signing_keys();
+
+        let ready = db.is_ok() && migrations.is_ok() && signing_keys.is_ok();
+
+        ReadinessReport {
+            ready,
+            components: Components {
+                db,
+                migrations,
+                signing_keys,
+            },
+        }
+    }
+
+    async fn check_db(&self) -> ComponentStatus {
+        match timeout(CHECK_TIMEOUT, self.db.ping()).await {
+            Ok(Ok(())) => ComponentStatus::Ok,
+            Ok(Err(e)) => ComponentStatus::error(e),
+            Err(_) => ComponentStatus::error("timed out"),
+        }
+    }
+
+    async fn check_migrations(&self) -> ComponentStatus {
+        match timeout(CHECK_TIMEOUT, self.db.has_pending_migrations()).await {
+            Ok(Ok(false)) => ComponentStatus::Ok,
+            Ok(Ok(true)) => ComponentStatus::error("pending migrations"),
+            Ok(Err(e)) => ComponentStatus::error(e),
+            Err(_) => ComponentStatus::error("timed out"),
+        }
+    }
+
+    fn check_signing_keys(&self) -> ComponentStatus {
+        match self.jwt.check_keys() {
+            Ok(()) => ComponentStatus::Ok,
+            Err(e) => ComponentStatus::error(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, to_value};
+
+    use crate::testing::test_services;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn ready_with_mock_db() {
+        let report = test_services().health.readiness().await;
+
+        assert!(report.ready);
+        assert!(report.components.db.is_ok());
+        assert!(report.components.migrations.is_ok());
+        assert!(report.components.signing_keys.is_ok());
+    }
+
+    #[test]
+    fn component_status_serializes() {
+        assert_eq!(
+            to_value(ComponentStatus::Ok).unwrap(),
+            json!({"status": "ok"})
+        );
+        assert_eq!(
+            to_value(ComponentStatus::error("timed out")).unwrap(),
+            json!({"status": "error", "error": "timed out"})
+        );
+    }
+}
@@ -0,0 +1,633 @@
+use std::{collections::BTreeMap, fmt::Debug, sync::Arc};
+
+use chrono::Duration;
+use color_eyre::Result;
+use jsonwebtoken::{crypto, decode, encode, Algorithm, Header, Validation};
+use serde::{Deserialize, Serialize};
+use tracing::{field, Span};
+use url::Url;
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    db::Db,
+    model::{
+        audit::AuditAction,
+        identity::UserIdentity,
+        types::{Email, Password, Token, UserId},
+    },
+    routing::{client::ClientInfo, errors::ApiError},
+    state::jwt::claims::{Claims, Validated},
+};
+
+use super::{
+    audit::AuditService,
+    auth::{not_found_if_unmodified, AuthService},
+    hasher::{hash_token, Hasher},
+    jwt::Jwt,
+    random::Random,
+    time::Time,
+    Common,
+};
+
+pub mod oidc;
+#[cfg(test)]
+pub mod stub;
+
+/// How long a user has to sign in at the provider before the `state` they left with expires
+pub const STATE_TTL_MINUTES: i64 = 10;
+
+/// The audience of `state` tokens, so that they can't be mistaken for anything else we sign
+const STATE_AUDIENCE: &str = "identity-state";
+
+/// Who a provider says a user is
+#[derive(Debug, Clone)]
+pub struct ExternalIdentity {
+    /// The provider's id for the user, which never changes
+    pub subject: String,
+    pub email: Option<Email>,
+    /// Whether the provider has checked that the user owns `email`
+    pub email_verified: bool,
+}
+
+/// What ties the user coming back from a provider to the sign in they left for it with
+#[derive(Debug, Clone)]
+pub struct AuthorizationRequest {
+    pub state: String,
+    pub nonce: String,
+    pub code_verifier: String,
+}
+
+/// Somewhere users can sign in instead of giving us a password
+#[axum::async_trait]
+pub trait IdentityProvider: Debug + Send + Sync + 'static {
+    /// Where to send the user to sign in, after which they're sent back with a code
+    async fn authorization_url(&self, request: &AuthorizationRequest) -> Result<Url>;
+
+    /// Swap the code the user was sent back with for who they are
+    async fn exchange(
+        &self,
+        code: &str,
+        request: &AuthorizationRequest,
+    ) -> Result<ExternalIdentity>;
+}
+
+/// A sign in at a provider that's under way, which only the browser given `binding` can finish
+#[derive(Debug, Clone)]
+pub struct StartedSignIn {
+    pub authorization_url: Url,
+    /// Kept in a cookie, so that a `code` and `state` someone else got can't be slipped into the
+    /// browser to sign it in to their account
+    pub binding: Token,
+}
+
+/// What `state` carries to the provider and back
+#[derive(Debug, Serialize, Deserialize)]
+struct StateClaims {
+    aud: String,
+    provider: String,
+    /// The user linking the identity, or `None` for signing in with it
+    link: Option<UserId>,
+    /// A hash of the `binding` the browser that started the sign in was given
+    binding: String,
+    exp: i64,
+    /// So that every sign in gets a verifier and nonce of its own
+    jti: Uuid,
+}
+
+/// Signing in with, and linking, accounts at external identity providers
+///
+/// Nothing is stored while the user is away at the provider. The `state` they're sent with is
+/// signed, and the PKCE verifier and nonce are derived from it with a key only we have, so the
+/// `state` they come back with, and the cookie that ties it to their browser, are all that's
+/// needed to carry on
+#[derive(Debug, Clone)]
+pub struct IdentityService {
+    time: Arc<dyn Time>,
+    random: Arc<dyn Random>,
+    hasher: Arc<dyn Hasher>,
+    db: Arc<dyn Db>,
+    auth: AuthService,
+    audit: AuditService,
+    config: Arc<Config>,
+    providers: Arc<BTreeMap<String, Arc<dyn IdentityProvider>>>,
+}
+
+impl IdentityService {
+    pub fn new(
+        common: Common,
+        hasher: Arc<dyn Hasher>,
+        auth: AuthService,
+        providers: BTreeMap<String, Arc<dyn IdentityProvider>>,
+    ) -> Self {
+        let Common {
+            time,
+            random,
+            db,
+            audit,
+            config,
+        } = common;
+
+        Self {
+            time,
+            random,
+            hasher,
+            db,
+            auth,
+            audit,
+            config,
+            providers: Arc::new(providers),
+        }
+    }
+
+    /// The names of the providers that users can sign in with
+    pub fn providers(&self) -> Vec<String> {
+        self.providers.keys().cloned().collect()
+    }
+
+    /// Where to send a user to sign in with `provider`, or to link their account there if
+    /// `linking` is set
+    #[instrument(skip_all, fields(provider = %provider))]
+    pub async fn start(
+        &self,
+        provider: &str,
+        linking: Option<&Claims<Validated>>,
+    ) -> Result<StartedSignIn, ApiError> {
+        let idp = self.provider(provider)?;
+
+        let binding = self.random.token();
+        let expires_at = self.time.now() + Duration::minutes(STATE_TTL_MINUTES);
+        let claims = StateClaims {
+            aud: STATE_AUDIENCE.into(),
+            provider: provider.into(),
+            link: linking.map(|claims| claims.subject),
+            binding: hash_token(&binding),
+            exp: expires_at.timestamp(),
+            jti: self.random.uuid(),
+        };
+        let state = encode(&Header::default(), &claims, self.config.jwt.key.encoding())
+            .map_err(|e| ApiError::Unknown(e.into()))?;
+
+        let authorization_url = idp.authorization_url(&self.request(state)?).await?;
+        Ok(StartedSignIn {
+            authorization_url,
+            binding,
+        })
+    }
+
+    /// Sign in with `provider`, creating an account if theirs isn't linked to one yet
+    ///
+    /// `binding` is what the browser was given when it started the sign in, if it has it
+    ///
+    /// A new account takes the address the provider has for the user, as long as the provider has
+    /// verified it and it isn't taken. An existing account isn't linked automatically, since then
+    /// controlling an address at any provider would be enough to take it over
+    #[instrument(skip_all, fields(provider = %provider, user_id = field::Empty))]
+    pub async fn login(
+        &self,
+        provider: &str,
+        code: &str,
+        state: &str,
+        binding: Option<&Token>,
+        client: &ClientInfo,
+    ) -> Result<Jwt, ApiError> {
+        let external = match self.identify(provider, code, state, binding, None).await {
+            Ok(external) => external,
+            Err(e) => {
+                self.audit
+                    .record(AuditAction::LoginFailed, None, None, client)
+                    .await;
+                return Err(e);
+            }
+        };
+
+        let linked = self
+            .db
+            .identity_by_subject(provider.into(), external.subject.clone())
+            .await?;
+        let Some(identity) = linked else {
+            return self.sign_up(provider, external, client).await;
+        };
+        Span::current().record("user_id", field::display(identity.user_id.0));
+
+        // the account may have been deleted since it was linked
+        let user = self
+            .db
+            .user_by_id(identity.user_id)
+            .await?
+            .ok_or(ApiError::Auth)?;
+        self.auth.sign_in(user, client).await
+    }
+
+    async fn sign_up(
+        &self,
+        provider: &str,
+        external: ExternalIdentity,
+        client: &ClientInfo,
+    ) -> Result<Jwt, ApiError> {
+        let email = match external.email {
+            Some(email) if external.email_verified => email,
+            _ => return Err(ApiError::Invalid { field: "email" }),
+        };
+
+        let now = self.time.now();
+        let identity = |user_id| UserIdentity {
+            id: self.random.uuid(),
+            user_id,
+            provider: provider.into(),
+            subject: external.subject.clone(),
+            email: Some(email.clone()),
+            created_at: now,
+        };
+        let (user_id, jwt) = self
+            .auth
+            .sign_up_without_password(
+                email.clone(),
+                |tx, user_id| tx.create_identity(identity(user_id)),
+                client,
+            )
+            .await?;
+
+        self.audit
+            .record(
+                AuditAction::IdentityLinked,
+                Some(user_id),
+                Some(user_id),
+                client,
+            )
+            .await;
+        Ok(jwt)
+    }
+
+    /// Link the signed in user's account at `provider`, which fails with `AlreadyExists` if it's
+    /// linked to someone already, or they've linked a different one there
+    #[instrument(skip_all, fields(user_id = %claims.subject.0, provider = %provider))]
+    pub async fn link(
+        &self,
+        claims: &Claims<Validated>,
+        provider: &str,
+        code: &str,
+        state: &str,
+        binding: Option<&Token>,
+        client: &ClientInfo,
+    ) -> Result<UserIdentity, ApiError> {
+        let user_id = claims.subject;
+        let external = self
+            .identify(provider, code, state, binding, Some(user_id))
+            .await?;
+
+        let identity = UserIdentity {
+            id: self.random.uuid(),
+            user_id,
+            provider: provider.into(),
+            subject: external.subject,
+            email: external.email,
+            created_at: self.time.now(),
+        };
+        self.db.create_identity(identity.clone()).await?;
+
+        self.audit
+            .record(
+                AuditAction::IdentityLinked,
+                Some(user_id),
+                Some(user_id),
+                client,
+            )
+            .await;
+        Ok(identity)
+    }
+
+    #[instrument(skip_all, fields(user_id = %claims.subject.0))]
+    pub async fn identities(
+        &self,
+        claims: &Claims<Validated>,
+    ) -> Result<Vec<UserIdentity>, ApiError> {
+        Ok(self.db.identities_for_user(claims.subject).await?)
+    }
+
+    /// Unlink the signed in user's account at `provider`
+    ///
+    /// The password is asked for again, which also means users who signed up through a provider,
+    /// and so don't know theirs, can't be left with no way to sign in
+    #[instrument(skip_all, fields(user_id = %claims.subject.0, provider = %provider))]
+    pub async fn unlink(
+        &self,
+        claims: &Claims<Validated>,
+        provider: &str,
+        password: Password,
+        client: &ClientInfo,
+    ) -> Result<(), ApiError> {
+        let user = self
+            .db
+            .user_by_id(claims.subject)
+            .await?
+            .ok_or(ApiError::NotFound)?;
+        if !self.hasher.verify(&password, &user.password_hash) {
+            return Err(ApiError::Auth);
+        }
+
+        let result = self.db.delete_identity(user.id, provider.into()).await;
+        not_found_if_unmodified(result)?;
+
+        self.audit
+            .record(
+                AuditAction::IdentityUnlinked,
+                Some(user.id),
+                Some(user.id),
+                client,
+            )
+            .await;
+        Ok(())
+    }
+
+    fn provider(&self, name: &str) -> Result<&dyn IdentityProvider, ApiError> {
+        self.providers
+            .get(name)
+            .map(AsRef::as_ref)
+            .ok_or(ApiError::NotFound)
+    }
+
+    /// Who the user that came back from `provider` with `code` is there, as long as `state` is one
+    /// we gave this browser for it, and for `link`ing to the same user if any
+    async fn identify(
+        &self,
+        provider: &str,
+        code: &str,
+        state: &str,
+        binding: Option<&Token>,
+        link: Option<UserId>,
+    ) -> Result<ExternalIdentity, ApiError> {
+        let idp = self.provider(provider)?;
+
+        let mut validation = Validation::default();
+        validation.set_audience(&[STATE_AUDIENCE]);
+        // checked against our own clock below
+        validation.validate_exp = false;
+        let claims: StateClaims = decode(state, self.config.jwt.key.decoding(), &validation)
+            .map_err(|_| ApiError::Auth)?
+            .claims;
+
+        let expired = self.time.now().timestamp() > claims.exp;
+        let bound = binding.is_some_and(|binding| hash_token(binding) == claims.binding);
+        if expired || !bound || claims.provider != provider || claims.link != link {
+            return Err(ApiError::Auth);
+        }
+
+        idp.exchange(code, &self.request(state.into())?)
+            .await
+            .map_err(|e| {
+                warn!("sign in with {provider} failed: {e}");
+                ApiError::Auth
+            })
+    }
+
+    fn request(&self, state: String) -> Result<AuthorizationRequest> {
+        Ok(AuthorizationRequest {
+            nonce: self.derive("nonce", &state)?,
+            code_verifier: self.derive("code_verifier", &state)?,
+            state,
+        })
+    }
+
+    /// A secret for `purpose` that only we can work out from `state`
+    fn derive(&self, purpose: &str, state: &str) -> Result<String> {
+        let message = format!("{purpose}.{state}");
+        let key = self.config.jwt.key.encoding();
+        Ok(crypto::sign(message.as_bytes(), key, Algorithm::HS256)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use microtype::SecretMicrotype;
+    use url::Url;
+
+    use crate::{
+        db::sql::DbError,
+        model::types::{
+            mock::{DEFAULT_EMAIL, DEFAULT_PASSWORD, DEFAULT_USER_ID},
+            Password,
+        },
+        routing::{client::ClientInfo, errors::ApiError},
+        state::{
+            identity::stub::{StubProvider, StubUser},
+            jwt::claims::{Claims, Validated},
+            Services,
+        },
+        testing::{sign_in, test_data::TEST_DATA, test_services_from},
+    };
+
+    async fn services_with_stub() -> (Services, StubProvider) {
+        let stub = StubProvider::start().await;
+        (test_services_from(stub.deps(), TEST_DATA.clone()), stub)
+    }
+
+    fn state(url: &Url) -> String {
+        let (_, state) = url.query_pairs().find(|(key, _)| key == "state").unwrap();
+        state.into_owned()
+    }
+
+    async fn login(
+        services: &Services,
+        stub: &StubProvider,
+        user: StubUser,
+    ) -> Result<Claims<Validated>, ApiError> {
+        let started = services.identity.start("stub", None).await?;
+        let url = started.authorization_url.clone();
+        let code = stub.authorize(&url, user);
+        let jwt = services
+            .identity
+            .login(
+                "stub",
+                &code,
+                &state(&url),
+                Some(&started.binding),
+                &ClientInfo::default(),
+            )
+            .await?;
+        Ok(services.auth.authenticate(&jwt).await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn signs_up_then_signs_in() {
+        let (services, stub) = services_with_stub().await;
+        assert_eq!(services.identity.providers(), vec!["stub".to_string()]);
+
+        let someone = StubUser::new("someone", "someone@email.com");
+        let signed_up = login(&services, &stub, someone.clone()).await.unwrap();
+        let user = services
+            .db
+            .user_by_id(signed_up.subject)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.email.0, "someone@email.com");
+
+        let signed_in = login(&services, &stub, someone).await.unwrap();
+        assert_eq!(signed_in.subject, signed_up.subject);
+        let identities = services.identity.identities(&signed_in).await.unwrap();
+        assert_eq!(identities.len(), 1);
+        assert_eq!(identities[0].subject, "someone");
+    }
+
+    #[tokio::test]
+    async fn needs_verified_unused_email_to_sign_up() {
+        let (services, stub) = services_with_stub().await;
+
+        let unverified = StubUser {
+            email_verified: false,
+            ..StubUser::new("someone", "someone@email.com")
+        };
+        let result = login(&services, &stub, unverified).await;
+        assert!(matches!(result, Err(ApiError::Invalid { field: "email" })));
+
+        let taken = StubUser::new("someone", &DEFAULT_EMAIL.0);
+        let result = login(&services, &stub, taken).await;
+        assert!(matches!(
+            result,
+            Err(ApiError::Db(DbError::AlreadyExists { .. }))
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_state_we_did_not_issue() {
+        let (services, stub) = services_with_stub().await;
+        let client = ClientInfo::default();
+
+        let started = services.identity.start("stub", None).await.unwrap();
+        let url = started.authorization_url.clone();
+        let code = stub.authorize(&url, StubUser::new("someone", "someone@email.com"));
+        let tampered = format!("{}x", state(&url));
+        let result = services
+            .identity
+            .login("stub", &code, &tampered, Some(&started.binding), &client)
+            .await;
+        assert!(matches!(result, Err(ApiError::Auth)));
+
+        let result = services.identity.start("nowhere", None).await;
+        assert!(matches!(result, Err(ApiError::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn states_only_work_in_the_browser_that_started_them() {
+        let (services, stub) = services_with_stub().await;
+        let client = ClientInfo::default();
+
+        let started = services.identity.start("stub", None).await.unwrap();
+        let url = started.authorization_url.clone();
+        let code = stub.authorize(&url, StubUser::new("someone", "someone@email.com"));
+
+        let elsewhere = services.identity.start("stub", None).await.unwrap();
+        for binding in [None, Some(&elsewhere.binding)] {
+            let result = services
+                .identity
+                .login("stub", &code, &state(&url), binding, &client)
+                .await;
+            assert!(matches!(result, Err(ApiError::Auth)));
+        }
+
+        let result = services
+            .identity
+            .login("stub", &code, &state(&url), Some(&started.binding), &client)
+            .await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn states_are_only_good_for_what_they_were_issued_for() {
+        let (services, stub) = services_with_stub().await;
+        let client = ClientInfo::default();
+        let claims = sign_in(&services, &DEFAULT_EMAIL).await;
+        let someone = StubUser::new("someone", "someone@email.com");
+
+        let started = services
+            .identity
+            .start("stub", Some(&claims))
+            .await
+            .unwrap();
+        let url = started.authorization_url.clone();
+        let code = stub.authorize(&url, someone.clone());
+        let result = services
+            .identity
+            .login("stub", &code, &state(&url), Some(&started.binding), &client)
+            .await;
+        assert!(matches!(result, Err(ApiError::Auth)));
+
+        let started = services.identity.start("stub", None).await.unwrap();
+        let url = started.authorization_url.clone();
+        let code = stub.authorize(&url, someone);
+        let result = services
+            .identity
+            .link(
+                &claims,
+                "stub",
+                &code,
+                &state(&url),
+                Some(&started.binding),
+                &client,
+            )
+            .await;
+        assert!(matches!(result, Err(ApiError::Auth)));
+    }
+
+    #[tokio::test]
+    async fn links_and_unlinks_identities() {
+        let (services, stub) = services_with_stub().await;
+        let client = ClientInfo::default();
+        let claims = sign_in(&services, &DEFAULT_EMAIL).await;
+        let someone = StubUser::new("someone", "someone@email.com");
+
+        let started = services
+            .identity
+            .start("stub", Some(&claims))
+            .await
+            .unwrap();
+        let url = started.authorization_url.clone();
+        let code = stub.authorize(&url, someone.clone());
+        let identity = services
+            .identity
+            .link(
+                &claims,
+                "stub",
+                &code,
+                &state(&url),
+                Some(&started.binding),
+                &client,
+            )
+            .await
+            .unwrap();
+        assert_eq!(identity.user_id, *DEFAULT_USER_ID);
+        assert_eq!(identity.email.unwrap().0, "someone@email.com");
+
+        let signed_in = login(&services, &stub, someone.clone()).await.unwrap();
+        assert_eq!(signed_in.subject, *DEFAULT_USER_ID);
+
+        let wrong = Password::new("wrong password".into());
+        let result = services
+            .identity
+            .unlink(&claims, "stub", wrong, &client)
+            .await;
+        assert!(matches!(result, Err(ApiError::Auth)));
+
+        services
+            .identity
+            .unlink(&claims, "stub", DEFAULT_PASSWORD.clone(), &client)
+            .await
+            .unwrap();
+        assert!(services
+            .identity
+            .identities(&claims)
+            .await
+            .unwrap()
+            .is_empty());
+        let result = services
+            .identity
+            .unlink(&claims, "stub", DEFAULT_PASSWORD.clone(), &client)
+            .await;
+        assert!(matches!(result, Err(ApiError::NotFound)));
+
+        // once unlinked, signing in with it makes an account of its own
+        let result = login(&services, &stub, someone).await.unwrap();
+        assert_ne!(result.subject, *DEFAULT_USER_ID);
+    }
+}
@@ -0,0 +1,267 @@
+use std::{sync::Arc, time::Duration};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use color_eyre::{
+    eyre::{bail, eyre},
+    Result,
+};
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, DecodingKey, Validation};
+use microtype::secrecy::ExposeSecret;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::{OnceCell, RwLock};
+use url::{form_urlencoded, Url};
+
+use crate::{config::OidcConfig, model::types::Email, state::time::Time};
+
+use super::{AuthorizationRequest, ExternalIdentity, IdentityProvider};
+
+/// How long a request to the provider can take before it's given up on
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How far the provider's clock may be ahead of ours when it comes to ID token expiry
+const LEEWAY_SECONDS: i64 = 60;
+
+/// The parts of the provider's discovery document that we use
+#[derive(Debug, Clone, Deserialize)]
+struct Metadata {
+    issuer: String,
+    authorization_endpoint: Url,
+    token_endpoint: Url,
+    jwks_uri: Url,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    exp: i64,
+    nonce: Option<String>,
+    email: Option<Email>,
+    #[serde(default)]
+    email_verified: bool,
+}
+
+/// Any provider that implements OpenID Connect's authorization code flow, which we use with PKCE
+///
+/// Its endpoints are discovered the first time they're needed, and its signing keys are fetched
+/// again whenever an ID token is signed with one we haven't seen, so that it can rotate them
+#[derive(Debug)]
+pub struct OidcProvider {
+    config: OidcConfig,
+    time: Arc<dyn Time>,
+    http: reqwest::Client,
+    metadata: OnceCell<Metadata>,
+    keys: RwLock<JwkSet>,
+}
+
+impl OidcProvider {
+    pub fn new(config: OidcConfig, time: Arc<dyn Time>) -> Result<Self> {
+        let http = reqwest::Client::builder().timeout(TIMEOUT).build()?;
+
+        Ok(Self {
+            config,
+            time,
+            http,
+            metadata: OnceCell::new(),
+            keys: RwLock::new(JwkSet { keys: Vec::new() }),
+        })
+    }
+
+    async fn metadata(&self) -> Result<&Metadata> {
+        self.metadata
+            .get_or_try_init(|| async {
+                let response = self
+                    .http
+                    .get(self.config.discovery_url.clone())
+                    .send()
+                    .await?;
+                Ok(response.error_for_status()?.json().await?)
+            })
+            .await
+    }
+
+    /// The key with id `kid`, fetching the provider's keys again if it's not one we have
+    async fn key(&self, kid: &str) -> Result<DecodingKey> {
+        if let Some(jwk) = self.keys.read().await.find(kid) {
+            return Ok(DecodingKey::from_jwk(jwk)?);
+        }
+
+        let jwks_uri = self.metadata().await?.jwks_uri.clone();
+        let keys: JwkSet = self
+            .http
+            .get(jwks_uri)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let key = keys.find(kid).map(DecodingKey::from_jwk).transpose()?;
+        *self.keys.write().await = keys;
+        key.ok_or_else(|| eyre!("provider has no key with id {kid}"))
+    }
+
+    /// Check an ID token's signature, and that it was issued to us for this sign in
+    async fn validate(&self, id_token: &str, nonce: &str) -> Result<IdTokenClaims> {
+        let header = decode_header(id_token)?;
+        let kid = header.kid.ok_or_else(|| eyre!("ID token has no key id"))?;
+        let key = self.key(&kid).await?;
+
+        // the key's family has to match the algorithm, so the header can't pick a weaker one
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&self.config.client_id]);
+        validation.set_issuer(&[&self.metadata().await?.issuer]);
+        // checked against our own clock below
+        validation.validate_exp = false;
+        let claims: IdTokenClaims = decode(id_token, &key, &validation)?.claims;
+
+        if self.time.now().timestamp() > claims.exp + LEEWAY_SECONDS {
+            bail!("ID token has expired");
+        }
+        if claims.nonce.as_deref() != Some(nonce) {
+            bail!("ID token is for a different sign in");
+        }
+        Ok(claims)
+    }
+
+    /// HTTP basic credentials for the token endpoint, which are form encoded first
+    fn credentials(&self) -> (String, String) {
+        let encode = |s: &str| form_urlencoded::byte_serialize(s.as_bytes()).collect();
+        (
+            encode(&self.config.client_id),
+            encode(self.config.client_secret.expose_secret()),
+        )
+    }
+}
+
+#[axum::async_trait]
+impl IdentityProvider for OidcProvider {
+    async fn authorization_url(&self, request: &AuthorizationRequest) -> Result<Url> {
+        let mut scopes = vec!["openid"];
+        scopes.extend(self.config.scopes.iter().map(String::as_str));
+
+        let mut url = self.metadata().await?.authorization_endpoint.clone();
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.config.client_id)
+            .append_pair("redirect_uri", self.config.redirect_url.as_str())
+            .append_pair("scope", &scopes.join(" "))
+            .append_pair("state", &request.state)
+            .append_pair("nonce", &request.nonce)
+            .append_pair("code_challenge", &code_challenge(&request.code_verifier))
+            .append_pair("code_challenge_method", "S256");
+        Ok(url)
+    }
+
+    async fn exchange(
+        &self,
+        code: &str,
+        request: &AuthorizationRequest,
+    ) -> Result<ExternalIdentity> {
+        let token_endpoint = self.metadata().await?.token_endpoint.clone();
+        let (client_id, client_secret) = self.credentials();
+        let form = [
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.config.redirect_url.as_str()),
+            ("code_verifier", &request.code_verifier),
+        ];
+
+        let response: TokenResponse = self
+            .http
+            .post(token_endpoint)
+            .basic_auth(client_id, Some(client_secret))
+            .form(&form)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let claims = self.validate(&response.id_token, &request.nonce).await?;
+
+        Ok(ExternalIdentity {
+            subject: claims.sub,
+            email: claims.email,
+            email_verified: claims.email_verified,
+        })
+    }
+}
+
+/// The S256 PKCE challenge for `verifier`
+pub(crate) fn code_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::state::{
+        identity::stub::{StubProvider, StubUser},
+        time::mock::{MockTime, DEFAULT_DATE_TIME},
+    };
+
+    use super::*;
+
+    #[test]
+    fn code_challenge_matches_rfc_7636() {
+        let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+        assert_eq!(
+            code_challenge(verifier),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+
+    fn request(nonce: &str) -> AuthorizationRequest {
+        AuthorizationRequest {
+            state: "state".into(),
+            nonce: nonce.into(),
+            code_verifier: "a".repeat(43),
+        }
+    }
+
+    #[tokio::test]
+    async fn exchanges_code_for_identity() {
+        let stub = StubProvider::start().await;
+        let provider = OidcProvider::new(stub.config(), Arc::new(MockTime::default())).unwrap();
+
+        let url = provider.authorization_url(&request("nonce")).await.unwrap();
+        let code = stub.authorize(&url, StubUser::new("someone", "someone@email.com"));
+        let identity = provider.exchange(&code, &request("nonce")).await.unwrap();
+
+        assert_eq!(identity.subject, "someone");
+        assert_eq!(identity.email.unwrap().0, "someone@email.com");
+        assert!(identity.email_verified);
+
+        // codes only work once
+        assert!(provider.exchange(&code, &request("nonce")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_tokens_for_other_sign_ins() {
+        let stub = StubProvider::start().await;
+        let provider = OidcProvider::new(stub.config(), Arc::new(MockTime::default())).unwrap();
+
+        let url = provider.authorization_url(&request("nonce")).await.unwrap();
+        let code = stub.authorize(&url, StubUser::new("someone", "someone@email.com"));
+        let result = provider.exchange(&code, &request("another")).await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_expired_tokens() {
+        // the stub issues tokens as of `DEFAULT_DATE_TIME`
+        let stub = StubProvider::start().await;
+        let time = MockTime(*DEFAULT_DATE_TIME + chrono::Duration::days(1));
+        let provider = OidcProvider::new(stub.config(), Arc::new(time)).unwrap();
+
+        let url = provider.authorization_url(&request("nonce")).await.unwrap();
+        let code = stub.authorize(&url, StubUser::new("someone", "someone@email.com"));
+
+        assert!(provider.exchange(&code, &request("nonce")).await.is_err());
+    }
+}
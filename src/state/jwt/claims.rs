@@ -5,7 +5,8 @@ use microtype::microtype;
 use serde::{Deserialize, Serialize};
 
 use crate::model::{
-    types::{Email, UserId},
+    oauth::{Scope, Scopes},
+    types::{ClientId, Email, OrgId, UserId},
     user::User,
 };
 
@@ -36,6 +37,26 @@ pub struct Claims<Validity> {
     pub jwt_id: JwtID,
 
     pub email: Email,
+
+    /// The client the token was issued to through OAuth, or `None` for one of our own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<ClientId>,
+    /// What the client was allowed to do, which is everything if `client_id` is `None`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<Scopes>,
+    /// The organization the user is acting in, which routes scope data to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub org: Option<OrgId>,
+}
+
+impl<Validity> Claims<Validity> {
+    /// Whether the token lets its bearer do what `scope` allows
+    pub fn allows(&self, scope: Scope) -> bool {
+        match &self.scope {
+            Some(scopes) => scopes.contains(scope),
+            None => true,
+        }
+    }
 }
 
 impl Claims<Validated> {
@@ -63,6 +84,35 @@ impl Claims<Validated> {
             not_before,
             jwt_id,
             email,
+            client_id: None,
+            scope: None,
+            org: None,
+        }
+    }
+
+    /// Restrict these claims to what a user let an OAuth client do
+    pub(super) fn for_client(self, client_id: ClientId, scope: Scopes) -> Self {
+        Self {
+            client_id: Some(client_id),
+            scope: Some(scope),
+            ..self
+        }
+    }
+
+    /// A copy of these claims acting in `org`, or as just the user if `None`
+    pub(super) fn for_org(&self, org: Option<OrgId>) -> Self {
+        Self {
+            _marker: PhantomData,
+            issuer: self.issuer.clone(),
+            subject: self.subject,
+            expiration: self.expiration,
+            not_before: self.not_before,
+            issued_at: self.issued_at,
+            jwt_id: self.jwt_id.clone(),
+            email: self.email.clone(),
+            client_id: self.client_id,
+            scope: self.scope.clone(),
+            org,
         }
     }
 }
@@ -82,6 +132,9 @@ impl Claims<Unvalidated> {
             issued_at,
             jwt_id,
             email,
+            client_id,
+            scope,
+            org,
         } = self;
         Claims {
             _marker: PhantomData,
@@ -92,10 +145,49 @@ impl Claims<Unvalidated> {
             issued_at,
             jwt_id,
             email,
+            client_id,
+            scope,
+            org,
         }
     }
 }
 
+/// The claims of a token an OAuth client gets for itself, rather than on behalf of a user
+///
+/// These have no `email`, so they can never be mistaken for a user's `Claims`, and have to have
+/// exactly these fields, so a user's claims can't be passed off as one of these
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ClientClaims {
+    #[serde(rename = "iss")]
+    pub issuer: Issuer,
+    #[serde(rename = "sub")]
+    pub subject: ClientId,
+    #[serde(rename = "exp")]
+    pub expiration: Expiration,
+    #[serde(rename = "nbf")]
+    pub not_before: NotBefore,
+    #[serde(rename = "iat")]
+    pub issued_at: IssuedAt,
+    #[serde(rename = "jti")]
+    pub jwt_id: JwtID,
+
+    pub client_id: ClientId,
+    pub scope: Scopes,
+}
+
+/// The claims of a CSRF token, which a browser signed in with a session cookie has to send back
+///
+/// These have no `sub`, so they can never be mistaken for a user's `Claims`, and a user's claims
+/// have no `csrf`, so they can't be passed off as one of these
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct CsrfClaims {
+    #[serde(rename = "exp")]
+    pub expiration: Expiration,
+    /// The `jti` of the session the token belongs to
+    pub csrf: JwtID,
+}
+
 microtype! {
     #[derive(Debug, Clone, PartialEq)]
     #[string]
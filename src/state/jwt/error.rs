@@ -11,3 +11,15 @@ pub enum JwtError {
     #[error("unknown error")]
     Unknown(#[from] jsonwebtoken::errors::Error),
 }
+
+impl JwtError {
+    /// A short, stable name for this error, suitable for use as a metric label
+    pub fn label(&self) -> &'static str {
+        match self {
+            JwtError::TooEarly => "too_early",
+            JwtError::TooLate => "too_late",
+            JwtError::InvalidSig => "invalid_sig",
+            JwtError::Unknown(_) => "unknown",
+        }
+    }
+}
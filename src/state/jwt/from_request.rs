@@ -1,13 +1,18 @@
 use axum::{
     extract::FromRequestParts,
-    headers::{authorization::Bearer, Authorization},
+    headers::{authorization::Bearer, Authorization, Cookie},
     http::request::Parts,
-    Extension, TypedHeader,
+    TypedHeader,
 };
-use color_eyre::eyre::eyre;
 use microtype::SecretMicrotype;
 
-use crate::{routing::errors::ApiError, state::Services};
+use crate::{
+    routing::{
+        cookies::{CSRF_HEADER, SESSION_COOKIE},
+        errors::ApiError,
+    },
+    state::Services,
+};
 
 use super::{
     claims::{Claims, Validated},
@@ -15,30 +20,50 @@ use super::{
 };
 
 type Header = TypedHeader<Authorization<Bearer>>;
+type Cookies = TypedHeader<Cookie>;
 
-#[axum::async_trait]
-impl<S> FromRequestParts<S> for Claims<Validated>
-where
-    S: Send + Sync,
-{
-    type Rejection = ApiError;
+/// The token a request was made with, from the `Authorization` header or else the session cookie
+///
+/// Browsers attach cookies to requests other sites make them send, so a request that relies on
+/// the cookie and could change something also has to carry the session's CSRF token
+pub(in crate::state) async fn request_token(
+    parts: &mut Parts,
+    services: &Services,
+) -> Result<String, ApiError> {
+    if let Ok(auth_header) = Header::from_request_parts(parts, services).await {
+        return Ok(auth_header.token().to_string());
+    }
 
-    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
-        let auth_header = Header::from_request_parts(parts, state)
-            .await
-            .map_err(|_| ApiError::Auth)?;
+    let cookies = Cookies::from_request_parts(parts, services)
+        .await
+        .map_err(|_| ApiError::Auth)?;
+    let token = cookies.get(SESSION_COOKIE).ok_or(ApiError::Auth)?;
+    let jwt = Jwt::new(token.to_string());
 
-        let Extension(services): Extension<Services> = Extension::from_request_parts(parts, state)
-            .await
-            .map_err(|_| ApiError::Unknown(eyre!("uh oh")))?;
+    if !parts.method.is_safe() {
+        let csrf_token = parts
+            .headers
+            .get(CSRF_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .ok_or(ApiError::Auth)?;
 
-        let jwt = Jwt::new(auth_header.token().to_string());
+        if !services.auth.check_csrf_token(&jwt, csrf_token) {
+            return Err(ApiError::Auth);
+        }
+    }
+    Ok(token.to_string())
+}
+
+#[axum::async_trait]
+impl FromRequestParts<Services> for Claims<Validated> {
+    type Rejection = ApiError;
 
-        let claims = services
-            .auth
-            .validate_jwt(&jwt)
-            .map_err(|_| ApiError::Auth)?;
+    async fn from_request_parts(
+        parts: &mut Parts,
+        services: &Services,
+    ) -> Result<Self, Self::Rejection> {
+        let jwt = Jwt::new(request_token(parts, services).await?);
 
-        Ok(claims)
+        services.auth.authenticate(&jwt).await
     }
 }
@@ -1,16 +1,27 @@
 use std::sync::Arc;
 
-use crate::{config::Config, model::user::User};
+use crate::{
+    config::Config,
+    model::{
+        oauth::Scopes,
+        types::{ClientId, OrgId},
+        user::User,
+    },
+};
 
-use self::claims::{Claims, Unvalidated, Validated};
+use self::claims::{
+    Claims, ClientClaims, CsrfClaims, Expiration, NotBefore, Unvalidated, Validated,
+};
 use jsonwebtoken::{decode, encode, Header, TokenData, Validation};
 use microtype::{secrecy::ExposeSecret, SecretMicrotype};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
 
-use super::{random::Random, time::Time};
+use super::{metrics::Metrics, random::Random, time::Time};
 
 pub mod claims;
 mod error;
-mod from_request;
+pub(super) mod from_request;
 
 pub use error::JwtError;
 
@@ -27,48 +38,154 @@ pub struct JwtService {
     time: Arc<dyn Time>,
     random: Arc<dyn Random>,
     config: Arc<Config>,
+    metrics: Arc<Metrics>,
 }
 
 impl JwtService {
-    pub fn new(time: Arc<dyn Time>, random: Arc<dyn Random>, config: Arc<Config>) -> Self {
+    pub fn new(
+        time: Arc<dyn Time>,
+        random: Arc<dyn Random>,
+        config: Arc<Config>,
+        metrics: Arc<Metrics>,
+    ) -> Self {
         Self {
             time,
             random,
             config,
+            metrics,
         }
     }
 
-    #[instrument]
+    #[instrument(skip_all)]
     pub fn validate(&self, jwt: &Jwt) -> Result<Claims<Validated>, JwtError> {
+        self.check_claims(jwt)
+            .inspect_err(|e| self.metrics.record_jwt_failure(e))
+    }
+
+    fn check_claims(&self, jwt: &Jwt) -> Result<Claims<Validated>, JwtError> {
+        let claims: Claims<Unvalidated> = self.decode(jwt)?;
+        self.check_times(claims.not_before, claims.expiration)?;
+        Ok(claims.insecure_assert_valid())
+    }
+
+    /// Check a token an OAuth client got for itself, which `validate` never accepts
+    #[instrument(skip_all)]
+    pub fn validate_client(&self, jwt: &Jwt) -> Result<ClientClaims, JwtError> {
+        self.check_client_claims(jwt)
+            .inspect_err(|e| self.metrics.record_jwt_failure(e))
+    }
+
+    fn check_client_claims(&self, jwt: &Jwt) -> Result<ClientClaims, JwtError> {
+        let claims: ClientClaims = self.decode(jwt)?;
+        self.check_times(claims.not_before, claims.expiration)?;
+        Ok(claims)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, jwt: &Jwt) -> Result<T, JwtError> {
         let mut validation = Validation::default();
         validation.validate_exp = false;
         validation.validate_nbf = false;
 
         let key = self.config.jwt.key.decoding();
-        let token: TokenData<Claims<Unvalidated>> =
+        let token: TokenData<T> =
             decode(jwt.expose_secret(), key, &validation).map_err(|_| JwtError::InvalidSig)?;
+        Ok(token.claims)
+    }
 
+    fn check_times(&self, not_before: NotBefore, expiration: Expiration) -> Result<(), JwtError> {
         let now = self.time.now();
 
-        let not_before = token.claims.not_before.as_date_time();
-        let expiration = token.claims.expiration.as_date_time();
-
-        if now < not_before {
+        if now < not_before.as_date_time() {
             return Err(JwtError::TooEarly);
         }
 
-        if now > expiration {
+        if now > expiration.as_date_time() {
             return Err(JwtError::TooLate);
         }
-        Ok(token.claims.insecure_assert_valid())
+        Ok(())
     }
 
-    pub fn create_jwt(&self, user: User) -> Result<Jwt, JwtError> {
+    /// Issue a token for `user`, returning it along with its claims
+    pub fn create_jwt(&self, user: User) -> Result<(Jwt, Claims<Validated>), JwtError> {
         let claims = self.claims_from_user(user);
-        let jwt = encode(&Header::default(), &claims, self.config.jwt.key.encoding())?;
+        Ok((self.sign(&claims)?, claims))
+    }
+
+    /// Issue a token for `user` that only lets `client_id` do what `scope` allows
+    pub fn create_scoped_jwt(
+        &self,
+        user: User,
+        client_id: ClientId,
+        scope: Scopes,
+    ) -> Result<(Jwt, Claims<Validated>), JwtError> {
+        let claims = self.claims_from_user(user).for_client(client_id, scope);
+        Ok((self.sign(&claims)?, claims))
+    }
+
+    /// Reissue the token `claims` came from to act in `org`, keeping its session and expiry
+    pub fn create_org_jwt(
+        &self,
+        claims: &Claims<Validated>,
+        org: Option<OrgId>,
+    ) -> Result<(Jwt, Claims<Validated>), JwtError> {
+        let claims = claims.for_org(org);
+        Ok((self.sign(&claims)?, claims))
+    }
+
+    /// Issue a token for an OAuth client to act as itself
+    pub fn create_client_jwt(&self, client_id: ClientId, scope: Scopes) -> Result<Jwt, JwtError> {
+        let now = self.time.now();
+        let claims = ClientClaims {
+            issuer: self.config.hostname.clone().into(),
+            subject: client_id,
+            expiration: (now + self.config.jwt.ttl()).timestamp().into(),
+            not_before: now.timestamp().into(),
+            issued_at: now.timestamp().into(),
+            jwt_id: self.random.uuid().to_string().into(),
+            client_id,
+            scope,
+        };
+        self.sign(&claims)
+    }
+
+    /// Issue a CSRF token for the session `claims` belong to, which expires along with it
+    pub fn create_csrf_token(&self, claims: &Claims<Validated>) -> Result<Jwt, JwtError> {
+        let claims = CsrfClaims {
+            expiration: claims.expiration,
+            csrf: claims.jwt_id.clone(),
+        };
+        self.sign(&claims)
+    }
+
+    /// Whether `token` is a CSRF token we issued for the session `claims` belong to
+    pub fn check_csrf_token(&self, claims: &Claims<Validated>, token: &str) -> bool {
+        let mut validation = Validation::default();
+        validation.validate_exp = false;
+
+        let key = self.config.jwt.key.decoding();
+        match decode::<CsrfClaims>(token, key, &validation) {
+            Ok(token) => token.claims.csrf == claims.jwt_id,
+            Err(_) => false,
+        }
+    }
+
+    fn sign(&self, claims: &impl Serialize) -> Result<Jwt, JwtError> {
+        let jwt = encode(&Header::default(), claims, self.config.jwt.key.encoding())?;
         Ok(Jwt::new(jwt))
     }
 
+    /// Check that the configured key pair can sign a token and verify the result
+    pub fn check_keys(&self) -> Result<(), JwtError> {
+        let probe = json!({ "exp": i64::MAX });
+        let key = &self.config.jwt.key;
+
+        let jwt = encode(&Header::default(), &probe, key.encoding())?;
+        decode::<Value>(&jwt, key.decoding(), &Validation::default())
+            .map_err(|_| JwtError::InvalidSig)?;
+
+        Ok(())
+    }
+
     fn claims_from_user(&self, user: User) -> Claims<Validated> {
         let ttl = self.config.jwt.ttl();
         let jwt_id = self.random.uuid().to_string().into();
@@ -85,8 +202,8 @@ mod tests {
 
     use crate::{
         config::testing::test_config,
-        model::{types::mock::DEFAULT_EMAIL, user::mock::default_user},
-        state::{random::mock::MockRandom, time::mock::MockTime},
+        model::{oauth::Scope, types::mock::DEFAULT_EMAIL, user::mock::default_user},
+        state::{metrics::Metrics, random::mock::MockRandom, time::mock::MockTime},
     };
 
     use super::*;
@@ -96,6 +213,7 @@ mod tests {
             time: Arc::new(MockTime::default()),
             random: Arc::new(MockRandom::new()),
             config: Arc::new(test_config()),
+            metrics: Arc::new(Metrics::new().unwrap()),
         }
     }
 
@@ -103,9 +221,88 @@ mod tests {
     fn can_create_and_validate_jwt() {
         let service = make_service();
         let user = default_user();
-        let jwt = service.create_jwt(user).unwrap();
+        let (jwt, _) = service.create_jwt(user).unwrap();
 
         let claims = service.validate(&jwt).unwrap();
         assert_eq!(claims.email.as_str(), DEFAULT_EMAIL.as_str());
     }
+
+    #[test]
+    fn scoped_jwt_carries_client_and_scope() {
+        let service = make_service();
+        let client_id = ClientId(uuid::Uuid::new_v4());
+        let scope: Scopes = "email".parse().unwrap();
+        let (jwt, _) = service
+            .create_scoped_jwt(default_user(), client_id, scope.clone())
+            .unwrap();
+
+        let claims = service.validate(&jwt).unwrap();
+        assert_eq!(claims.client_id, Some(client_id));
+        assert_eq!(claims.scope, Some(scope));
+        assert!(claims.allows(Scope::Email));
+        assert!(!claims.allows(Scope::Api));
+    }
+
+    #[test]
+    fn client_jwt_is_not_a_users() {
+        let service = make_service();
+        let jwt = service
+            .create_client_jwt(ClientId(uuid::Uuid::new_v4()), Scopes::default())
+            .unwrap();
+
+        assert!(service.validate(&jwt).is_err());
+    }
+
+    #[test]
+    fn client_jwt_validates_as_a_clients() {
+        let service = make_service();
+        let client_id = ClientId(uuid::Uuid::new_v4());
+        let scope: Scopes = "profile".parse().unwrap();
+        let jwt = service.create_client_jwt(client_id, scope.clone()).unwrap();
+
+        let claims = service.validate_client(&jwt).unwrap();
+        assert_eq!(claims.client_id, client_id);
+        assert_eq!(claims.scope, scope);
+
+        let (user_jwt, _) = service.create_jwt(default_user()).unwrap();
+        let (scoped_jwt, _) = service
+            .create_scoped_jwt(default_user(), client_id, scope)
+            .unwrap();
+        assert!(service.validate_client(&user_jwt).is_err());
+        assert!(service.validate_client(&scoped_jwt).is_err());
+    }
+
+    #[test]
+    fn csrf_token_is_bound_to_its_session() {
+        let service = make_service();
+        let (jwt, claims) = service.create_jwt(default_user()).unwrap();
+        let (_, other) = service.create_jwt(default_user()).unwrap();
+
+        let token = service.create_csrf_token(&claims).unwrap();
+        assert!(service.check_csrf_token(&claims, token.expose_secret()));
+        assert!(!service.check_csrf_token(&other, token.expose_secret()));
+        assert!(!service.check_csrf_token(&claims, jwt.expose_secret()));
+        assert!(service.validate(&token).is_err());
+    }
+
+    #[test]
+    fn org_jwt_is_the_same_session() {
+        let service = make_service();
+        let (_, claims) = service.create_jwt(default_user()).unwrap();
+        let org = OrgId(uuid::Uuid::new_v4());
+
+        let (jwt, _) = service.create_org_jwt(&claims, Some(org)).unwrap();
+        let switched = service.validate(&jwt).unwrap();
+        assert_eq!(switched.org, Some(org));
+        assert_eq!(switched.jwt_id, claims.jwt_id);
+        assert_eq!(switched.expiration, claims.expiration);
+
+        let (jwt, _) = service.create_org_jwt(&switched, None).unwrap();
+        assert_eq!(service.validate(&jwt).unwrap().org, None);
+    }
+
+    #[test]
+    fn check_keys_works() {
+        make_service().check_keys().unwrap();
+    }
 }
@@ -0,0 +1,151 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    config::LimitConfig,
+    model::types::Email,
+    routing::{client::ClientInfo, errors::ApiError},
+};
+
+use super::time::Time;
+
+/// What sign in attempts are counted against
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum LimitKey {
+    Email(String),
+    Ip(IpAddr),
+}
+
+impl LimitKey {
+    /// The keys for an attempt from `client`, for `email` if there is one
+    pub fn for_attempt(email: Option<&Email>, client: &ClientInfo) -> Vec<Self> {
+        let email = email.map(|email| Self::Email(email.0.to_lowercase()));
+        let ip = client.ip.map(Self::Ip);
+        email.into_iter().chain(ip).collect()
+    }
+}
+
+/// Turns sign in attempts away once there have been too many recently for an address, or from an
+/// IP, so that passwords and links can't be guessed and inboxes can't be flooded
+///
+/// Attempts are counted in memory, so each instance keeps a count of its own
+#[derive(Debug, Clone)]
+pub struct Limiter {
+    time: Arc<dyn Time>,
+    config: LimitConfig,
+    attempts: Arc<Mutex<HashMap<LimitKey, Vec<DateTime<Utc>>>>>,
+}
+
+impl Limiter {
+    pub fn new(time: Arc<dyn Time>, config: LimitConfig) -> Self {
+        Self {
+            time,
+            config,
+            attempts: Arc::default(),
+        }
+    }
+
+    /// Fail with `Limited` if any of `keys` has already had as many attempts as it's allowed
+    pub fn check(&self, keys: &[LimitKey]) -> Result<(), ApiError> {
+        let since = self.time.now() - self.config.window();
+        let attempts = self.attempts.lock().unwrap();
+
+        let limited = keys.iter().any(|key| {
+            let recent = attempts
+                .get(key)
+                .map_or(0, |times| times.iter().filter(|at| **at > since).count());
+            recent >= self.max(key)
+        });
+        match limited {
+            true => {
+                warn!("turned away a sign in attempt after too many");
+                Err(ApiError::Limited)
+            }
+            false => Ok(()),
+        }
+    }
+
+    /// Count an attempt against each of `keys`
+    pub fn record(&self, keys: &[LimitKey]) {
+        let now = self.time.now();
+        let since = now - self.config.window();
+        let mut attempts = self.attempts.lock().unwrap();
+
+        for key in keys {
+            let times = attempts.entry(key.clone()).or_default();
+            times.retain(|at| *at > since);
+            times.push(now);
+        }
+    }
+
+    /// Forget keys with no attempts that still count, returning how many there were
+    pub fn purge_expired(&self) -> usize {
+        let since = self.time.now() - self.config.window();
+        let mut attempts = self.attempts.lock().unwrap();
+
+        let before = attempts.len();
+        attempts.retain(|_, times| times.iter().any(|at| *at > since));
+        before - attempts.len()
+    }
+
+    fn max(&self, key: &LimitKey) -> usize {
+        match key {
+            LimitKey::Email(_) => self.config.per_email,
+            LimitKey::Ip(_) => self.config.per_ip,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use crate::{model::types::mock::DEFAULT_EMAIL, state::time::mock::MockTime};
+
+    use super::*;
+
+    fn limiter_at(time: MockTime) -> Limiter {
+        let config = LimitConfig {
+            per_email: 2,
+            per_ip: 3,
+            window_seconds: 60,
+        };
+        Limiter::new(Arc::new(time), config)
+    }
+
+    #[test]
+    fn limits_each_key_within_the_window() {
+        let time = MockTime::default();
+        let limiter = limiter_at(time);
+        let client = ClientInfo {
+            ip: Some([127, 0, 0, 1].into()),
+            ..ClientInfo::default()
+        };
+        let keys = LimitKey::for_attempt(Some(&DEFAULT_EMAIL), &client);
+        let ip_only = LimitKey::for_attempt(None, &client);
+
+        for _ in 0..2 {
+            limiter.check(&keys).unwrap();
+            limiter.record(&keys);
+        }
+        assert!(matches!(limiter.check(&keys), Err(ApiError::Limited)));
+
+        // the address is used up, but the IP has one attempt left
+        limiter.check(&ip_only).unwrap();
+        limiter.record(&ip_only);
+        assert!(matches!(limiter.check(&ip_only), Err(ApiError::Limited)));
+        assert_eq!(limiter.purge_expired(), 0);
+
+        let later = Limiter {
+            time: Arc::new(MockTime(time.0 + Duration::seconds(61))),
+            ..limiter.clone()
+        };
+        later.check(&keys).unwrap();
+        assert_eq!(later.purge_expired(), 2);
+    }
+}
@@ -0,0 +1,367 @@
+use std::sync::Arc;
+
+use futures::FutureExt;
+use tracing::{field, Span};
+use url::Url;
+
+use crate::{
+    config::Config,
+    db::{sql::DbError, Db},
+    model::{
+        audit::AuditAction,
+        magic_link::MagicLink,
+        types::{Email, Token},
+    },
+    routing::{client::ClientInfo, errors::ApiError},
+};
+
+use super::{
+    audit::AuditService,
+    auth::AuthService,
+    hasher::hash_token,
+    jwt::Jwt,
+    limiter::{LimitKey, Limiter},
+    mailer::{Mailer, Message},
+    metrics::Metrics,
+    random::Random,
+    time::Time,
+    Common,
+};
+
+/// Signing in by following a single-use link emailed to the account's address, instead of with a
+/// password
+///
+/// Failures are counted, audited and limited just as failed password logins are, and every link
+/// sent counts against the same per-address and per-IP limits. With `magic_link_signup`, links
+/// are also sent to addresses nobody has yet, and following one makes an account
+#[derive(Debug, Clone)]
+pub struct MagicLinkService {
+    time: Arc<dyn Time>,
+    random: Arc<dyn Random>,
+    db: Arc<dyn Db>,
+    mailer: Arc<dyn Mailer>,
+    auth: AuthService,
+    limiter: Limiter,
+    audit: AuditService,
+    metrics: Arc<Metrics>,
+    config: Arc<Config>,
+}
+
+impl MagicLinkService {
+    pub fn new(
+        common: Common,
+        mailer: Arc<dyn Mailer>,
+        auth: AuthService,
+        limiter: Limiter,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        let Common {
+            time,
+            random,
+            db,
+            audit,
+            config,
+        } = common;
+
+        Self {
+            time,
+            random,
+            db,
+            mailer,
+            auth,
+            limiter,
+            audit,
+            metrics,
+            config,
+        }
+    }
+
+    /// Email a sign in link to `email`, replacing any that was sent before
+    ///
+    /// Nothing is sent to an address nobody has unless signing up this way is allowed, but that
+    /// isn't reported, so that this can't be used to find out who has an account
+    #[instrument(skip_all, fields(email = %email.redacted()))]
+    pub async fn send(&self, email: Email, client: &ClientInfo) -> Result<(), ApiError> {
+        // every link sent counts, so that nobody's inbox can be flooded with them
+        let keys = LimitKey::for_attempt(Some(&email), client);
+        self.limiter.check(&keys)?;
+        self.limiter.record(&keys);
+
+        let user = self.db.user_by_email(email.clone()).await?;
+        if user.is_none() && !self.config.accounts.magic_link_signup {
+            return Ok(());
+        }
+
+        let token = self.random.token();
+        let now = self.time.now();
+        let link = MagicLink {
+            token_hash: hash_token(&token),
+            email: email.clone(),
+            created_at: now,
+            expires_at: now + self.config.accounts.magic_link_ttl(),
+        };
+        self.db
+            .transaction(|tx| {
+                let link = link.clone();
+                async move {
+                    tx.delete_magic_links_for_email(link.email.clone()).await?;
+                    tx.create_magic_link(link).await
+                }
+                .boxed()
+            })
+            .await?;
+
+        self.mailer
+            .send(Message {
+                to: email,
+                subject: "Your sign in link".into(),
+                body: format!(
+                    "If you didn't ask to sign in, you can ignore this email. Otherwise, follow \
+                     this link in the next {} minutes: {}",
+                    self.config.accounts.magic_link_ttl().num_minutes(),
+                    self.link(&token)
+                ),
+            })
+            .await?;
+
+        let user_id = user.map(|user| user.id);
+        self.audit
+            .record(AuditAction::MagicLinkSent, None, user_id, client)
+            .await;
+        Ok(())
+    }
+
+    /// Swap the token from a link for a JWT, which works once
+    #[instrument(skip_all, fields(user_id = field::Empty))]
+    pub async fn consume(&self, token: Token, client: &ClientInfo) -> Result<Jwt, ApiError> {
+        let keys = LimitKey::for_attempt(None, client);
+        self.limiter.check(&keys)?;
+
+        let result = self.check_link(token, client).await;
+        if let Err(ApiError::Auth) = result {
+            self.limiter.record(&keys);
+        }
+        self.metrics.record_login(result.is_ok());
+        result
+    }
+
+    async fn check_link(&self, token: Token, client: &ClientInfo) -> Result<Jwt, ApiError> {
+        let token_hash = hash_token(&token);
+        let link = self
+            .db
+            .magic_link_by_token(token_hash.clone())
+            .await?
+            .filter(|link| link.expires_at > self.time.now());
+        let Some(link) = link else {
+            self.audit
+                .record(AuditAction::LoginFailed, None, None, client)
+                .await;
+            return Err(ApiError::Auth);
+        };
+
+        match self.db.delete_magic_link(token_hash).await {
+            Ok(()) => {}
+            Err(DbError::RowsModified { .. }) => return Err(ApiError::Auth),
+            Err(e) => return Err(e.into()),
+        }
+
+        match self.db.user_by_email(link.email.clone()).await? {
+            Some(user) => {
+                Span::current().record("user_id", field::display(user.id.0));
+                self.auth.sign_in(user, client).await
+            }
+            None if self.config.accounts.magic_link_signup => {
+                let (_, jwt) = self
+                    .auth
+                    .sign_up_without_password(link.email, |_, _| async { Ok(()) }.boxed(), client)
+                    .await?;
+                Ok(jwt)
+            }
+            // the account was deleted after the link was sent
+            None => Err(ApiError::Auth),
+        }
+    }
+
+    /// Remove links that have expired, returning how many there were
+    #[instrument(skip_all, fields(purged = field::Empty))]
+    pub async fn purge_expired(&self) -> Result<usize, DbError> {
+        let purged = self.db.delete_expired_magic_links(self.time.now()).await?;
+        Span::current().record("purged", purged);
+        Ok(purged)
+    }
+
+    fn link(&self, token: &Token) -> Url {
+        self.config.email.link("magic-link", token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::Duration;
+    use microtype::SecretMicrotype;
+
+    use crate::{
+        config::testing::test_config,
+        model::{
+            audit::AuditAction,
+            magic_link::MagicLink,
+            types::{
+                mock::{DEFAULT_EMAIL, DEFAULT_PASSWORD, DEFAULT_USER_ID},
+                Email, Password, Token,
+            },
+        },
+        routing::{client::ClientInfo, errors::ApiError},
+        state::{hasher::hash_token, time::mock::DEFAULT_DATE_TIME, Dependencies, Services},
+        testing::{
+            audited, test_data::TEST_DATA, test_deps, test_services_from, test_services_with,
+        },
+    };
+
+    fn someone() -> Email {
+        Email("someone@email.com".into())
+    }
+
+    async fn send(services: &Services, email: &Email) -> Token {
+        services
+            .magic_link
+            .send(email.clone(), &ClientInfo::default())
+            .await
+            .unwrap();
+        services.mailer.as_mock().token_sent_to(email)
+    }
+
+    async fn consume(services: &Services, token: Token) -> Result<(), ApiError> {
+        let jwt = services
+            .magic_link
+            .consume(token, &ClientInfo::default())
+            .await?;
+        let claims = services.auth.authenticate(&jwt).await?;
+        assert_eq!(claims.subject, *DEFAULT_USER_ID);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn signs_in_with_link_once() {
+        let services = test_services_with(TEST_DATA.clone());
+        let token = send(&services, &DEFAULT_EMAIL).await;
+
+        consume(&services, token.clone()).await.unwrap();
+
+        let again = consume(&services, token).await;
+        assert!(matches!(again, Err(ApiError::Auth)));
+        assert_eq!(audited(&services, AuditAction::LoginFailed).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn new_link_replaces_old_one() {
+        let services = test_services_with(TEST_DATA.clone());
+        let first = send(&services, &DEFAULT_EMAIL).await;
+        let second = send(&services, &DEFAULT_EMAIL).await;
+
+        assert!(matches!(
+            consume(&services, first).await,
+            Err(ApiError::Auth)
+        ));
+        consume(&services, second).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn expired_link_does_not_sign_in() {
+        let services = test_services_with(TEST_DATA.clone());
+        let token = Token::new("expired".into());
+        let link = MagicLink {
+            token_hash: hash_token(&token),
+            email: DEFAULT_EMAIL.clone(),
+            created_at: *DEFAULT_DATE_TIME - Duration::minutes(15),
+            expires_at: *DEFAULT_DATE_TIME,
+        };
+        services.db.create_magic_link(link).await.unwrap();
+
+        assert!(matches!(
+            consume(&services, token).await,
+            Err(ApiError::Auth)
+        ));
+        assert_eq!(audited(&services, AuditAction::LoginFailed).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn sends_nothing_to_unknown_address() {
+        let services = test_services_with(TEST_DATA.clone());
+
+        services
+            .magic_link
+            .send(someone(), &ClientInfo::default())
+            .await
+            .unwrap();
+        assert!(services.mailer.as_mock().sent().is_empty());
+    }
+
+    #[tokio::test]
+    async fn signs_up_unknown_address_if_allowed() {
+        let mut config = test_config();
+        config.accounts.magic_link_signup = true;
+        let deps = Dependencies {
+            config: Arc::new(config),
+            ..test_deps()
+        };
+        let services = test_services_from(deps, TEST_DATA.clone());
+        let token = send(&services, &someone()).await;
+
+        let jwt = services
+            .magic_link
+            .consume(token, &ClientInfo::default())
+            .await
+            .unwrap();
+        let claims = services.auth.authenticate(&jwt).await.unwrap();
+        let user = services
+            .auth
+            .user_with_id(claims.subject)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.email, someone());
+    }
+
+    #[tokio::test]
+    async fn shares_limits_with_password_login() {
+        let services = test_services_with(TEST_DATA.clone());
+        let client = ClientInfo {
+            ip: Some([127, 0, 0, 1].into()),
+            ..ClientInfo::default()
+        };
+        let per_email = test_config().limits.per_email;
+
+        for _ in 0..per_email - 1 {
+            let result = services
+                .auth
+                .login(
+                    DEFAULT_EMAIL.clone(),
+                    Password::new("wrong password".into()),
+                    &client,
+                )
+                .await;
+            assert!(matches!(result, Err(ApiError::Auth)));
+        }
+        send(&services, &DEFAULT_EMAIL).await;
+
+        let result = services
+            .magic_link
+            .send(DEFAULT_EMAIL.clone(), &client)
+            .await;
+        assert!(matches!(result, Err(ApiError::Limited)));
+        let result = services
+            .auth
+            .login(DEFAULT_EMAIL.clone(), DEFAULT_PASSWORD.clone(), &client)
+            .await;
+        assert!(matches!(result, Err(ApiError::Limited)));
+
+        // other addresses, from elsewhere, are unaffected
+        services
+            .magic_link
+            .send(someone(), &ClientInfo::default())
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,148 @@
+use std::fmt::Debug;
+
+use color_eyre::Result;
+use lettre::{
+    message::{header::ContentType, Mailbox},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Tokio1Executor,
+};
+use microtype::secrecy::ExposeSecret;
+
+use crate::{
+    config::{EmailConfig, SmtpCredentials, SmtpTls},
+    model::types::Email,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub to: Email,
+    pub subject: String,
+    pub body: String,
+}
+
+#[axum::async_trait]
+pub trait Mailer: Debug + Send + Sync + 'static {
+    async fn send(&self, message: Message) -> Result<()>;
+
+    #[cfg(test)]
+    fn as_mock(&self) -> mock::MockMailer;
+}
+
+/// Delivers messages through an SMTP server
+#[derive(Debug, Clone)]
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn new(config: &EmailConfig) -> Result<Self> {
+        let smtp = &config.smtp;
+        let builder = match smtp.tls {
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&smtp.host),
+            SmtpTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&smtp.host)?,
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&smtp.host)?,
+        };
+        let builder = match smtp.port {
+            Some(port) => builder.port(port),
+            None => builder,
+        };
+        let builder = match &smtp.credentials {
+            Some(SmtpCredentials { username, password }) => builder.credentials(Credentials::new(
+                username.clone(),
+                password.expose_secret().clone(),
+            )),
+            None => builder,
+        };
+
+        Ok(Self {
+            transport: builder.build(),
+            from: config.from.parse()?,
+        })
+    }
+}
+
+#[axum::async_trait]
+impl Mailer for SmtpMailer {
+    #[instrument(skip_all, fields(to = %message.to.redacted(), subject = %message.subject))]
+    async fn send(&self, message: Message) -> Result<()> {
+        let email = lettre::Message::builder()
+            .from(self.from.clone())
+            .to(message.to.0.parse()?)
+            .subject(message.subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(message.body)?;
+        self.transport.send(email).await?;
+
+        Ok(())
+    }
+
+    #[cfg(test)]
+    fn as_mock(&self) -> mock::MockMailer {
+        unimplemented!()
+    }
+}
+
+#[cfg(test)]
+pub mod mock {
+    use std::sync::{Arc, Mutex};
+
+    use microtype::SecretMicrotype;
+
+    use crate::model::types::Token;
+
+    use super::*;
+
+    /// Keeps every message it's asked to send, so tests can read them
+    #[derive(Debug, Clone, Default)]
+    pub struct MockMailer(Arc<Mutex<Vec<Message>>>);
+
+    impl MockMailer {
+        pub fn sent(&self) -> Vec<Message> {
+            self.0.lock().unwrap().clone()
+        }
+
+        /// The token from the link in the most recent message sent to `to`
+        pub fn token_sent_to(&self, to: &Email) -> Token {
+            let message = self
+                .sent()
+                .into_iter()
+                .rev()
+                .find(|message| &message.to == to);
+            let body = message.expect("nothing was sent").body;
+            let (_, token) = body.split_once("token=").expect("no link was sent");
+            Token::new(token.split_whitespace().next().unwrap_or_default().into())
+        }
+    }
+
+    #[axum::async_trait]
+    impl Mailer for MockMailer {
+        async fn send(&self, message: Message) -> Result<()> {
+            self.0.lock().unwrap().push(message);
+            Ok(())
+        }
+
+        fn as_mock(&self) -> MockMailer {
+            self.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::testing::test_config;
+
+    use super::*;
+
+    #[test]
+    fn builds_from_config() {
+        let config = test_config().email;
+        assert!(SmtpMailer::new(&config).is_ok());
+
+        let config = EmailConfig {
+            from: "not an address".into(),
+            ..config
+        };
+        assert!(SmtpMailer::new(&config).is_err());
+    }
+}
@@ -0,0 +1,132 @@
+use std::time::Duration;
+
+use color_eyre::Result;
+use prometheus::{
+    core::Collector, Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, Opts,
+    Registry, TextEncoder,
+};
+
+use super::jwt::JwtError;
+
+/// Prometheus metrics for the whole application
+///
+/// Each instance owns its own `Registry`, so tests never share counters
+#[derive(Debug)]
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    logins: IntCounterVec,
+    jwt_validation_failures: IntCounterVec,
+    db_pool_wait: Histogram,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self> {
+        let registry = Registry::new();
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests handled"),
+            &["method", "route", "status"],
+        )?;
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time taken to handle HTTP requests",
+            ),
+            &["method", "route", "status"],
+        )?;
+        let logins = IntCounterVec::new(
+            Opts::new("auth_logins_total", "Login attempts by outcome"),
+            &["outcome"],
+        )?;
+        let jwt_validation_failures = IntCounterVec::new(
+            Opts::new(
+                "jwt_validation_failures_total",
+                "JWTs rejected during validation",
+            ),
+            &["reason"],
+        )?;
+        let db_pool_wait = Histogram::with_opts(HistogramOpts::new(
+            "db_pool_wait_seconds",
+            "Time spent waiting to check out a database connection",
+        ))?;
+
+        registry.register(Box::new(http_requests.clone()))?;
+        registry.register(Box::new(http_request_duration.clone()))?;
+        registry.register(Box::new(logins.clone()))?;
+        registry.register(Box::new(jwt_validation_failures.clone()))?;
+        registry.register(Box::new(db_pool_wait.clone()))?;
+
+        Ok(Self {
+            registry,
+            http_requests,
+            http_request_duration,
+            logins,
+            jwt_validation_failures,
+            db_pool_wait,
+        })
+    }
+
+    /// Register an additional collector, e.g. one that samples state at scrape time
+    pub fn register(&self, collector: Box<dyn Collector>) -> Result<()> {
+        self.registry.register(collector)?;
+        Ok(())
+    }
+
+    pub fn record_request(&self, method: &str, route: &str, status: u16, duration: Duration) {
+        let status = status.to_string();
+        let labels = [method, route, status.as_str()];
+
+        self.http_requests.with_label_values(&labels).inc();
+        self.http_request_duration
+            .with_label_values(&labels)
+            .observe(duration.as_secs_f64());
+    }
+
+    pub fn record_login(&self, success: bool) {
+        let outcome = match success {
+            true => "success",
+            false => "failure",
+        };
+        self.logins.with_label_values(&[outcome]).inc();
+    }
+
+    pub fn record_jwt_failure(&self, error: &JwtError) {
+        self.jwt_validation_failures
+            .with_label_values(&[error.label()])
+            .inc();
+    }
+
+    pub fn record_pool_wait(&self, duration: Duration) {
+        self.db_pool_wait.observe(duration.as_secs_f64());
+    }
+
+    /// Render all metrics in the Prometheus text exposition format
+    pub fn render(&self) -> Result<String> {
+        let mut buffer = vec![];
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_recorded_metrics() {
+        let metrics = Metrics::new().unwrap();
+        metrics.record_request("GET", "/health", 200, Duration::from_millis(5));
+        metrics.record_login(false);
+        metrics.record_jwt_failure(&JwtError::TooLate);
+
+        let text = metrics.render().unwrap();
+
+        assert!(
+            text.contains(r#"http_requests_total{method="GET",route="/health",status="200"} 1"#)
+        );
+        assert!(text.contains(r#"auth_logins_total{outcome="failure"} 1"#));
+        assert!(text.contains(r#"jwt_validation_failures_total{reason="too_late"} 1"#));
+    }
+}
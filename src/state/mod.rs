@@ -1,26 +1,57 @@
-use std::sync::Arc;
+use std::{collections::BTreeMap, sync::Arc};
 
 use color_eyre::Result;
 use static_assertions::assert_impl_all;
 
-#[cfg(test)]
-use crate::db::mock::MockDb;
+#[cfg(any(test, feature = "in-memory-db"))]
+use crate::db::memory::MemoryDb;
 use crate::{
     config::{Config, DbKind},
-    db::{sql::SqlDb, Db},
+    db::{sql::SqlDb, sqlite::SqliteDb, Db},
 };
 
 use self::{
+    api_key::ApiKeyService,
+    audit::AuditService,
     auth::AuthService,
+    email_change::EmailChangeService,
     hasher::{BcryptHasher, Hasher},
+    health::HealthService,
+    identity::{oidc::OidcProvider, IdentityProvider, IdentityService},
     jwt::JwtService,
+    limiter::Limiter,
+    magic_link::MagicLinkService,
+    mailer::{Mailer, SmtpMailer},
+    metrics::Metrics,
+    oauth::OAuthService,
+    organization::OrgService,
+    password::PasswordPolicy,
+    password_reset::PasswordResetService,
+    privacy::PrivacyService,
+    profile::ProfileService,
     random::{Random, SystemRandom},
     time::{SystemTime, Time},
 };
 
+pub mod api_key;
+pub mod audit;
 pub mod auth;
+pub mod email_change;
 pub mod hasher;
+pub mod health;
+pub mod identity;
 pub mod jwt;
+pub mod limiter;
+pub mod magic_link;
+pub mod mailer;
+pub mod metrics;
+pub mod oauth;
+pub mod organization;
+pub mod password;
+pub mod password_reset;
+pub mod principal;
+pub mod privacy;
+pub mod profile;
 pub mod random;
 pub mod time;
 
@@ -28,6 +59,7 @@ pub struct Dependencies {
     pub time: Arc<dyn Time>,
     pub random: Arc<dyn Random>,
     pub hasher: Arc<dyn Hasher>,
+    pub mailer: Arc<dyn Mailer>,
     pub config: Arc<Config>,
 }
 
@@ -39,6 +71,7 @@ impl Dependencies {
             time: Arc::new(SystemTime),
             random: Arc::new(SystemRandom),
             hasher: Arc::new(BcryptHasher),
+            mailer: Arc::new(SmtpMailer::new(&config.email)?),
             config: Arc::new(config),
         };
 
@@ -46,37 +79,153 @@ impl Dependencies {
     }
 }
 
+/// What most services are built from, passed to them together rather than one by one
+#[derive(Debug, Clone)]
+pub struct Common {
+    pub time: Arc<dyn Time>,
+    pub random: Arc<dyn Random>,
+    pub db: Arc<dyn Db>,
+    pub audit: AuditService,
+    pub config: Arc<Config>,
+}
+
 pub fn make_services(
     Dependencies {
         time,
         random,
         hasher,
+        mailer,
         config,
     }: Dependencies,
 ) -> Result<Services> {
+    let metrics = Arc::new(Metrics::new()?);
+
     let db: Arc<dyn Db> = match &config.db {
-        DbKind::Real(config) => Arc::new(SqlDb::connect(config.clone())?),
-        #[cfg(test)]
-        DbKind::InMemory => Arc::new(MockDb::new()),
+        DbKind::Real(config) => Arc::new(SqlDb::connect(config.clone(), metrics.clone())?),
+        DbKind::Sqlite { path } => Arc::new(SqliteDb::connect(path)?),
+        #[cfg(any(test, feature = "in-memory-db"))]
+        DbKind::InMemory { snapshot } => match snapshot {
+            Some(path) => Arc::new(MemoryDb::open(path.clone())?),
+            None => Arc::new(MemoryDb::new()),
+        },
     };
 
-    let jwt = JwtService::new(time.clone(), random.clone(), config);
+    let jwt = JwtService::new(time.clone(), random.clone(), config.clone(), metrics.clone());
     let jwt = Arc::new(jwt);
+    let passwords = Arc::new(PasswordPolicy::new(config.passwords.clone())?);
+
+    let health = HealthService::new(db.clone(), jwt.clone());
+    let profile = ProfileService::new(db.clone());
+    let audit = AuditService::new(time.clone(), random.clone(), db.clone());
+    let privacy = PrivacyService::new(time.clone(), db.clone(), audit.clone());
+    let common = Common {
+        time: time.clone(),
+        random,
+        db: db.clone(),
+        audit: audit.clone(),
+        config: config.clone(),
+    };
+    let email_change = EmailChangeService::new(common.clone(), hasher.clone(), mailer.clone());
+    let limiter = Limiter::new(time.clone(), config.limits.clone());
+    let auth = AuthService::new(
+        common.clone(),
+        hasher.clone(),
+        passwords,
+        jwt.clone(),
+        limiter.clone(),
+        metrics.clone(),
+    );
+    let magic_link = MagicLinkService::new(
+        common.clone(),
+        mailer.clone(),
+        auth.clone(),
+        limiter.clone(),
+        metrics.clone(),
+    );
+    let password_reset =
+        PasswordResetService::new(common.clone(), mailer.clone(), auth.clone(), limiter);
+    let api_keys = ApiKeyService::new(common.clone());
+    let oauth = OAuthService::new(common.clone(), jwt, auth.clone());
+    let org = OrgService::new(common.clone(), mailer.clone(), auth.clone());
 
-    let auth = AuthService::new(time, random, hasher, jwt, db.clone());
+    let providers = config
+        .oidc
+        .iter()
+        .map(|(name, oidc)| {
+            let provider = OidcProvider::new(oidc.clone(), time.clone())?;
+            Ok((
+                name.clone(),
+                Arc::new(provider) as Arc<dyn IdentityProvider>,
+            ))
+        })
+        .collect::<Result<BTreeMap<_, _>>>()?;
+    let identity = IdentityService::new(common, hasher, auth.clone(), providers);
 
     Ok(Services {
         auth,
-        #[cfg(test)]
+        audit,
+        profile,
+        email_change,
+        identity,
+        oauth,
+        api_keys,
+        magic_link,
+        password_reset,
+        org,
+        privacy,
+        health,
+        metrics,
         db,
+        #[cfg(test)]
+        mailer,
     })
 }
 
 #[derive(Debug, Clone)]
 pub struct Services {
     pub auth: AuthService,
-    #[cfg(test)]
+    pub audit: AuditService,
+    pub profile: ProfileService,
+    pub email_change: EmailChangeService,
+    pub identity: IdentityService,
+    pub oauth: OAuthService,
+    pub api_keys: ApiKeyService,
+    pub magic_link: MagicLinkService,
+    pub password_reset: PasswordResetService,
+    pub org: OrgService,
+    pub privacy: PrivacyService,
+    pub health: HealthService,
+    pub metrics: Arc<Metrics>,
     pub db: Arc<dyn Db>,
+    /// So tests can read what was sent
+    #[cfg(test)]
+    pub mailer: Arc<dyn Mailer>,
 }
 
 assert_impl_all!(Services: Send, Sync);
+
+impl Services {
+    /// Purge whatever has expired or been deleted for long enough, every `interval`, forever
+    pub async fn run_purges(self, interval: std::time::Duration) {
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.auth.purge_expired().await {
+                error!("failed to purge deleted users and expired sessions: {e}");
+            }
+            if let Err(e) = self.oauth.purge_expired().await {
+                error!("failed to purge expired oauth grants: {e}");
+            }
+            if let Err(e) = self.magic_link.purge_expired().await {
+                error!("failed to purge expired magic links: {e}");
+            }
+            if let Err(e) = self.password_reset.purge_expired().await {
+                error!("failed to purge expired password resets: {e}");
+            }
+            if let Err(e) = self.org.purge_expired().await {
+                error!("failed to purge expired organization invitations: {e}");
+            }
+        }
+    }
+}
@@ -0,0 +1,1178 @@
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{
+    extract::FromRequestParts,
+    headers::{authorization::Bearer, Authorization as AuthorizationHeader},
+    http::request::Parts,
+    TypedHeader,
+};
+use chrono::{DateTime, Utc};
+use futures::FutureExt;
+use microtype::{secrecy::ExposeSecret, SecretMicrotype};
+use serde::Serialize;
+use tracing::{field, Span};
+use url::Url;
+
+use crate::{
+    config::Config,
+    db::{sql::DbError, Db},
+    model::{
+        audit::AuditAction,
+        oauth::{AuthorizationCode, OAuthClient, OAuthConsent, RefreshToken, Scope, Scopes},
+        types::{ClientId, ClientSecret, Email, Token, UserId},
+        user::User,
+    },
+    routing::{
+        client::ClientInfo,
+        errors::{ApiError, OAuthError},
+    },
+    state::{
+        jwt::claims::{Claims, Validated},
+        Services,
+    },
+};
+
+use super::{
+    audit::AuditService,
+    auth::{not_found_if_unmodified, AuthService},
+    hasher::hash_token,
+    identity::oidc::code_challenge,
+    jwt::{Jwt, JwtService},
+    random::Random,
+    time::Time,
+    Common,
+};
+
+/// What a client asks a user to let it do, by sending them to `/oauth/authorize`
+#[derive(Debug, Clone)]
+pub struct Authorization {
+    pub client_id: ClientId,
+    /// Has to be exactly the one the client was registered with
+    pub redirect_uri: Url,
+    pub scope: Scopes,
+    /// Handed back to the client untouched, so it can tie the response to its request
+    pub state: Option<String>,
+    /// The S256 PKCE challenge, since every client has to use PKCE
+    pub code_challenge: String,
+}
+
+/// What the user is asked to agree to
+#[derive(Debug, Clone)]
+pub struct AuthorizationPrompt {
+    pub client: OAuthClient,
+    pub scope: Scopes,
+    /// Whether the user has already agreed to all of `scope`, so needn't be asked again
+    pub consented: bool,
+}
+
+/// What a client is swapping for tokens at `/oauth/token`
+#[derive(Debug, Clone)]
+pub enum Grant {
+    AuthorizationCode {
+        code: Token,
+        redirect_uri: Url,
+        code_verifier: String,
+    },
+    RefreshToken {
+        refresh_token: Token,
+        /// At most what the refresh token was issued for, which is also the default
+        scope: Option<Scopes>,
+    },
+    ClientCredentials {
+        /// At most what the client was registered for, which is also the default
+        scope: Option<Scopes>,
+    },
+}
+
+/// Who a client says it is, and its secret if it has one
+#[derive(Debug, Clone)]
+pub struct ClientCredentials {
+    pub client_id: ClientId,
+    pub client_secret: Option<ClientSecret>,
+}
+
+#[derive(Debug, Clone)]
+pub struct IssuedTokens {
+    pub access_token: Jwt,
+    pub expires_in: i64,
+    /// Only issued to clients acting for a user
+    pub refresh_token: Option<Token>,
+    pub scope: Scopes,
+}
+
+/// A newly registered client, along with the only copy of its secret
+#[derive(Debug, Clone)]
+pub struct RegisteredClient {
+    pub client: OAuthClient,
+    pub client_secret: Option<ClientSecret>,
+}
+
+/// What a client is told about a user, according to what it was allowed to know
+#[derive(Debug, Clone, Serialize)]
+pub struct UserInfo {
+    pub sub: UserId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<Email>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zoneinfo: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub picture: Option<String>,
+}
+
+/// A client acting as itself, with a token it got through the client credentials grant
+#[derive(Debug, Clone)]
+pub struct ActingClient {
+    pub client: OAuthClient,
+    /// What the token lets the client do, which is never more than it's registered for
+    pub scope: Scopes,
+}
+
+/// Only from the `Authorization` header, since clients have no use for cookies
+#[axum::async_trait]
+impl FromRequestParts<Services> for ActingClient {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        services: &Services,
+    ) -> Result<Self, Self::Rejection> {
+        let TypedHeader(AuthorizationHeader(bearer)) =
+            TypedHeader::<AuthorizationHeader<Bearer>>::from_request_parts(parts, services)
+                .await
+                .map_err(|_| ApiError::Auth)?;
+        let jwt = Jwt::new(bearer.token().to_string());
+
+        services.oauth.authenticate_client_token(&jwt).await
+    }
+}
+
+/// A client a user has let act for them
+#[derive(Debug, Clone, Serialize)]
+pub struct ConsentedClient {
+    pub client_id: ClientId,
+    pub client_name: String,
+    pub scope: Scopes,
+    pub granted_at: DateTime<Utc>,
+}
+
+/// Acting as an OAuth2 authorization server, so that apps can act for users without ever seeing
+/// their passwords
+///
+/// Codes, refresh tokens and client secrets are random tokens of which only hashes are stored.
+/// Access tokens are ordinary JWTs with sessions, restricted to what the user consented to
+#[derive(Debug, Clone)]
+pub struct OAuthService {
+    time: Arc<dyn Time>,
+    random: Arc<dyn Random>,
+    db: Arc<dyn Db>,
+    jwt: Arc<JwtService>,
+    auth: AuthService,
+    audit: AuditService,
+    config: Arc<Config>,
+}
+
+impl OAuthService {
+    pub fn new(common: Common, jwt: Arc<JwtService>, auth: AuthService) -> Self {
+        let Common {
+            time,
+            random,
+            db,
+            audit,
+            config,
+        } = common;
+
+        Self {
+            time,
+            random,
+            db,
+            jwt,
+            auth,
+            audit,
+            config,
+        }
+    }
+
+    /// Register a client on behalf of `admin`, with a secret unless it's `public`
+    #[instrument(skip_all, fields(admin_id = %admin.subject.0))]
+    pub async fn register_client(
+        &self,
+        admin: &Claims<Validated>,
+        name: String,
+        redirect_uri: Url,
+        scopes: Scopes,
+        public: bool,
+        client: &ClientInfo,
+    ) -> Result<RegisteredClient, ApiError> {
+        if name.trim().is_empty() {
+            return Err(ApiError::Invalid { field: "name" });
+        }
+        if scopes.is_empty() {
+            return Err(ApiError::Invalid { field: "scopes" });
+        }
+
+        let client_secret = match public {
+            true => None,
+            false => Some(ClientSecret::new(
+                self.random.token().expose_secret().clone(),
+            )),
+        };
+        let oauth_client = OAuthClient {
+            id: ClientId(self.random.uuid()),
+            name,
+            secret_hash: client_secret.as_ref().map(hash_token),
+            redirect_uri: redirect_uri.into(),
+            scopes,
+            created_at: self.time.now(),
+        };
+        self.db.create_client(oauth_client.clone()).await?;
+
+        self.audit
+            .record(
+                AuditAction::OAuthClientRegistered,
+                Some(admin.subject),
+                None,
+                client,
+            )
+            .await;
+        Ok(RegisteredClient {
+            client: oauth_client,
+            client_secret,
+        })
+    }
+
+    pub async fn clients(&self) -> Result<Vec<OAuthClient>, ApiError> {
+        Ok(self.db.clients().await?)
+    }
+
+    /// Remove a client on behalf of `admin`, along with everything users let it do
+    ///
+    /// Access tokens it already has for users keep working until they expire, but those it has for
+    /// itself stop working straight away
+    #[instrument(skip_all, fields(client_id = %client_id.0, admin_id = %admin.subject.0))]
+    pub async fn delete_client(
+        &self,
+        admin: &Claims<Validated>,
+        client_id: ClientId,
+        client: &ClientInfo,
+    ) -> Result<(), ApiError> {
+        let result = self.db.delete_client(client_id).await;
+        not_found_if_unmodified(result)?;
+
+        self.audit
+            .record(
+                AuditAction::OAuthClientDeleted,
+                Some(admin.subject),
+                None,
+                client,
+            )
+            .await;
+        Ok(())
+    }
+
+    /// Check what a client is asking a user for, so that they can be asked whether to allow it
+    #[instrument(skip_all, fields(user_id = %claims.subject.0, client_id = %request.client_id.0))]
+    pub async fn authorization(
+        &self,
+        claims: &Claims<Validated>,
+        request: &Authorization,
+    ) -> Result<AuthorizationPrompt, ApiError> {
+        let client = self.check_authorization(claims, request).await?;
+        let consent = self.db.consent(claims.subject, client.id).await?;
+
+        let consented = consent.is_some_and(|consent| request.scope.is_subset(&consent.scopes));
+        Ok(AuthorizationPrompt {
+            client,
+            scope: request.scope.clone(),
+            consented,
+        })
+    }
+
+    /// Let a client do what it asked for, returning where to send the user back to with a code
+    #[instrument(skip_all, fields(user_id = %claims.subject.0, client_id = %request.client_id.0))]
+    pub async fn authorize(
+        &self,
+        claims: &Claims<Validated>,
+        request: Authorization,
+        client: &ClientInfo,
+    ) -> Result<Url, ApiError> {
+        let oauth_client = self.check_authorization(claims, &request).await?;
+        let user_id = claims.subject;
+        let now = self.time.now();
+
+        let consented = self.db.consent(user_id, oauth_client.id).await?;
+        let consented = consented.map(|consent| consent.scopes).unwrap_or_default();
+        if !request.scope.is_subset(&consented) {
+            let consent = OAuthConsent {
+                user_id,
+                client_id: oauth_client.id,
+                scopes: consented.union(&request.scope),
+                granted_at: now,
+            };
+            self.db.save_consent(consent).await?;
+            self.audit
+                .record(
+                    AuditAction::OAuthConsentGranted,
+                    Some(user_id),
+                    Some(user_id),
+                    client,
+                )
+                .await;
+        }
+
+        let code = self.random.token();
+        let authorization_code = AuthorizationCode {
+            code_hash: hash_token(&code),
+            client_id: oauth_client.id,
+            user_id,
+            redirect_uri: oauth_client.redirect_uri,
+            scopes: request.scope,
+            code_challenge: request.code_challenge,
+            created_at: now,
+            expires_at: now + self.config.oauth.authorization_code_ttl(),
+        };
+        self.db
+            .create_authorization_code(authorization_code)
+            .await?;
+
+        let mut redirect_uri = request.redirect_uri;
+        redirect_uri
+            .query_pairs_mut()
+            .append_pair("code", code.expose_secret())
+            .extend_pairs(request.state.map(|state| ("state", state)));
+        Ok(redirect_uri)
+    }
+
+    /// The client being asked for, as long as it's being asked for something it can have, by a
+    /// user signed in to us directly
+    async fn check_authorization(
+        &self,
+        claims: &Claims<Validated>,
+        request: &Authorization,
+    ) -> Result<OAuthClient, ApiError> {
+        // otherwise one client could give itself, or others, more than it was given
+        if claims.client_id.is_some() {
+            return Err(ApiError::Auth);
+        }
+
+        let client = self
+            .db
+            .client_by_id(request.client_id)
+            .await?
+            .ok_or(ApiError::Invalid { field: "client_id" })?;
+        if request.redirect_uri.as_str() != client.redirect_uri {
+            return Err(ApiError::Invalid {
+                field: "redirect_uri",
+            });
+        }
+        if request.scope.is_empty() || !request.scope.is_subset(&client.scopes) {
+            return Err(ApiError::Invalid { field: "scope" });
+        }
+        if request.code_challenge.is_empty() {
+            return Err(ApiError::Invalid {
+                field: "code_challenge",
+            });
+        }
+        Ok(client)
+    }
+
+    /// Swap a grant for tokens, once the client has proven who it is
+    #[instrument(skip_all, fields(client_id = %credentials.client_id.0, user_id = field::Empty))]
+    pub async fn token(
+        &self,
+        credentials: ClientCredentials,
+        grant: Grant,
+        client: &ClientInfo,
+    ) -> Result<IssuedTokens, OAuthError> {
+        let oauth_client = self.authenticate_client(credentials).await?;
+
+        match grant {
+            Grant::AuthorizationCode {
+                code,
+                redirect_uri,
+                code_verifier,
+            } => {
+                self.exchange_code(oauth_client, code, redirect_uri, code_verifier, client)
+                    .await
+            }
+            Grant::RefreshToken {
+                refresh_token,
+                scope,
+            } => {
+                self.refresh(oauth_client, refresh_token, scope, client)
+                    .await
+            }
+            Grant::ClientCredentials { scope } => self.client_token(oauth_client, scope),
+        }
+    }
+
+    async fn authenticate_client(
+        &self,
+        credentials: ClientCredentials,
+    ) -> Result<OAuthClient, OAuthError> {
+        let client = self
+            .db
+            .client_by_id(credentials.client_id)
+            .await?
+            .ok_or(OAuthError::InvalidClient)?;
+
+        let secret_hash = credentials.client_secret.as_ref().map(hash_token);
+        match (&client.secret_hash, secret_hash) {
+            (None, None) => Ok(client),
+            (Some(expected), Some(given)) if *expected == given => Ok(client),
+            _ => Err(OAuthError::InvalidClient),
+        }
+    }
+
+    async fn exchange_code(
+        &self,
+        oauth_client: OAuthClient,
+        code: Token,
+        redirect_uri: Url,
+        code_verifier: String,
+        client: &ClientInfo,
+    ) -> Result<IssuedTokens, OAuthError> {
+        let code_hash = hash_token(&code);
+        let code = self
+            .db
+            .authorization_code_by_hash(code_hash.clone())
+            .await?
+            .ok_or(OAuthError::InvalidGrant)?;
+
+        // used up whether or not the exchange goes ahead, and only ever by one exchange
+        match self.db.delete_authorization_code(code_hash).await {
+            Err(DbError::RowsModified { actual: 0, .. }) => return Err(OAuthError::InvalidGrant),
+            result => result?,
+        }
+
+        let valid = code.client_id == oauth_client.id
+            && code.expires_at >= self.time.now()
+            && code.redirect_uri == redirect_uri.as_str()
+            && code.code_challenge == code_challenge(&code_verifier);
+        if !valid {
+            return Err(OAuthError::InvalidGrant);
+        }
+
+        let user = self.user(code.user_id).await?;
+        self.issue(user, oauth_client, code.scopes, client).await
+    }
+
+    /// Swap a refresh token for a new one, along with a new access token
+    ///
+    /// The session of the access token issued along with the old one is ended
+    async fn refresh(
+        &self,
+        oauth_client: OAuthClient,
+        refresh_token: Token,
+        scope: Option<Scopes>,
+        client: &ClientInfo,
+    ) -> Result<IssuedTokens, OAuthError> {
+        let token_hash = hash_token(&refresh_token);
+        let token = self
+            .db
+            .refresh_token_by_hash(token_hash.clone())
+            .await?
+            .ok_or(OAuthError::InvalidGrant)?;
+
+        match self.db.delete_refresh_token(token_hash).await {
+            Err(DbError::RowsModified { actual: 0, .. }) => return Err(OAuthError::InvalidGrant),
+            result => result?,
+        }
+        // it may have been revoked, or expired and been purged, already
+        match self
+            .db
+            .delete_session(token.user_id, token.session_id)
+            .await
+        {
+            Err(DbError::RowsModified { actual: 0, .. }) => {}
+            result => result?,
+        }
+
+        if token.client_id != oauth_client.id || token.expires_at < self.time.now() {
+            return Err(OAuthError::InvalidGrant);
+        }
+        let scope = scope.unwrap_or_else(|| token.scopes.clone());
+        if scope.is_empty() || !scope.is_subset(&token.scopes) {
+            return Err(OAuthError::InvalidScope);
+        }
+
+        let user = self.user(token.user_id).await?;
+        self.issue(user, oauth_client, scope, client).await
+    }
+
+    fn client_token(
+        &self,
+        oauth_client: OAuthClient,
+        scope: Option<Scopes>,
+    ) -> Result<IssuedTokens, OAuthError> {
+        // a public client can't prove who it is, so it can only act for a user
+        if oauth_client.secret_hash.is_none() {
+            return Err(OAuthError::UnauthorizedClient);
+        }
+        let scope = scope.unwrap_or_else(|| oauth_client.scopes.clone());
+        if scope.is_empty() || !scope.is_subset(&oauth_client.scopes) {
+            return Err(OAuthError::InvalidScope);
+        }
+
+        let access_token = self
+            .jwt
+            .create_client_jwt(oauth_client.id, scope.clone())
+            .map_err(|e| OAuthError::Server(ApiError::Unknown(e.into())))?;
+        Ok(IssuedTokens {
+            access_token,
+            expires_in: self.config.jwt.ttl_seconds,
+            refresh_token: None,
+            scope,
+        })
+    }
+
+    /// The user a grant was for, as long as they still exist
+    async fn user(&self, user_id: UserId) -> Result<User, OAuthError> {
+        Span::current().record("user_id", field::display(user_id.0));
+        self.db
+            .user_by_id(user_id)
+            .await?
+            .ok_or(OAuthError::InvalidGrant)
+    }
+
+    /// Start a session for `user` at `oauth_client`, with a refresh token to keep it going
+    async fn issue(
+        &self,
+        user: User,
+        oauth_client: OAuthClient,
+        scope: Scopes,
+        client: &ClientInfo,
+    ) -> Result<IssuedTokens, OAuthError> {
+        // the user may have taken back some of what they consented to since
+        let consent = self.db.consent(user.id, oauth_client.id).await?;
+        if !consent.is_some_and(|consent| scope.is_subset(&consent.scopes)) {
+            return Err(OAuthError::InvalidGrant);
+        }
+
+        let (user_id, client_id) = (user.id, oauth_client.id);
+        let (access_token, session) = self
+            .auth
+            .new_scoped_session(user, client_id, scope.clone(), client)
+            .map_err(OAuthError::Server)?;
+
+        let now = self.time.now();
+        let refresh_token = self.random.token();
+        let stored = RefreshToken {
+            token_hash: hash_token(&refresh_token),
+            client_id,
+            user_id,
+            scopes: scope.clone(),
+            session_id: session.id.clone(),
+            created_at: now,
+            expires_at: now + self.config.oauth.refresh_token_ttl(),
+        };
+        self.db
+            .transaction(|tx| {
+                let (session, stored) = (session.clone(), stored.clone());
+                async move {
+                    tx.create_session(session).await?;
+                    tx.create_refresh_token(stored).await
+                }
+                .boxed()
+            })
+            .await?;
+
+        Ok(IssuedTokens {
+            access_token,
+            expires_in: self.config.jwt.ttl_seconds,
+            refresh_token: Some(refresh_token),
+            scope,
+        })
+    }
+
+    /// The client that got `jwt` for itself, as long as it still exists and is still registered
+    /// for everything the token allows
+    #[instrument(skip_all, fields(client_id = field::Empty))]
+    pub async fn authenticate_client_token(&self, jwt: &Jwt) -> Result<ActingClient, ApiError> {
+        let claims = self.jwt.validate_client(jwt).map_err(|_| ApiError::Auth)?;
+        Span::current().record("client_id", field::display(claims.client_id.0));
+
+        let client = self
+            .db
+            .client_by_id(claims.client_id)
+            .await?
+            .ok_or(ApiError::Auth)?;
+        if !claims.scope.is_subset(&client.scopes) {
+            return Err(ApiError::Auth);
+        }
+        Ok(ActingClient {
+            client,
+            scope: claims.scope,
+        })
+    }
+
+    /// Who the bearer of `jwt` is, as much as its scope lets it know
+    #[instrument(skip_all)]
+    pub async fn userinfo(&self, jwt: &Jwt) -> Result<UserInfo, ApiError> {
+        let claims = self.auth.authenticate_session(jwt).await?;
+        let user = self
+            .db
+            .user_by_id(claims.subject)
+            .await?
+            .ok_or(ApiError::Auth)?;
+
+        let email = claims.allows(Scope::Email);
+        let profile = claims.allows(Scope::Profile);
+        Ok(UserInfo {
+            sub: user.id,
+            email: email.then_some(user.email),
+            name: user.display_name.filter(|_| profile),
+            locale: user.locale.filter(|_| profile),
+            zoneinfo: user.timezone.filter(|_| profile),
+            picture: user.avatar_url.filter(|_| profile),
+        })
+    }
+
+    /// The clients the signed in user has let act for them, oldest grant first
+    #[instrument(skip_all, fields(user_id = %claims.subject.0))]
+    pub async fn consents(
+        &self,
+        claims: &Claims<Validated>,
+    ) -> Result<Vec<ConsentedClient>, ApiError> {
+        let consents = self.db.consents_for_user(claims.subject).await?;
+        let names: HashMap<_, _> = self
+            .db
+            .clients()
+            .await?
+            .into_iter()
+            .map(|client| (client.id.0, client.name))
+            .collect();
+
+        Ok(consents
+            .into_iter()
+            .filter_map(|consent| {
+                Some(ConsentedClient {
+                    client_id: consent.client_id,
+                    client_name: names.get(&consent.client_id.0)?.clone(),
+                    scope: consent.scopes,
+                    granted_at: consent.granted_at,
+                })
+            })
+            .collect())
+    }
+
+    /// Stop a client acting for the signed in user, ending the sessions it started for them
+    #[instrument(skip_all, fields(user_id = %claims.subject.0, client_id = %client_id.0))]
+    pub async fn revoke_consent(
+        &self,
+        claims: &Claims<Validated>,
+        client_id: ClientId,
+        client: &ClientInfo,
+    ) -> Result<(), ApiError> {
+        let user_id = claims.subject;
+        let result = self
+            .db
+            .transaction(|tx| {
+                async move {
+                    tx.delete_consent(user_id, client_id).await?;
+
+                    let tokens = tx.refresh_tokens_for_user(user_id).await?;
+                    for token in tokens.into_iter().filter(|t| t.client_id == client_id) {
+                        match tx.delete_session(user_id, token.session_id).await {
+                            Err(DbError::RowsModified { actual: 0, .. }) => {}
+                            result => result?,
+                        }
+                    }
+                    tx.delete_refresh_tokens(user_id, client_id).await?;
+                    Ok(())
+                }
+                .boxed()
+            })
+            .await;
+        not_found_if_unmodified(result)?;
+
+        self.audit
+            .record(
+                AuditAction::OAuthConsentRevoked,
+                Some(user_id),
+                Some(user_id),
+                client,
+            )
+            .await;
+        Ok(())
+    }
+
+    /// Remove codes and refresh tokens that have expired, returning how many there were
+    #[instrument(skip_all, fields(purged = field::Empty))]
+    pub async fn purge_expired(&self) -> Result<usize, DbError> {
+        let purged = self.db.delete_expired_oauth_tokens(self.time.now()).await?;
+        Span::current().record("purged", purged);
+        Ok(purged)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use microtype::{secrecy::ExposeSecret, SecretMicrotype};
+    use url::Url;
+
+    use crate::{
+        model::{
+            oauth::{AuthorizationCode, Scope, Scopes},
+            types::{
+                mock::{ADMIN_EMAIL, DEFAULT_EMAIL, DEFAULT_USER_ID},
+                ClientSecret, Token,
+            },
+            user::ProfileChanges,
+        },
+        routing::{
+            client::ClientInfo,
+            errors::{ApiError, OAuthError},
+        },
+        state::{identity::oidc::code_challenge, time::mock::DEFAULT_DATE_TIME, Services},
+        testing::{sign_in, test_data::TEST_DATA, test_services_with},
+    };
+
+    use super::{Authorization, ClientCredentials, Grant, IssuedTokens, RegisteredClient};
+
+    const VERIFIER: &str = "a verifier that is long enough to be a real one";
+
+    async fn register(services: &Services, public: bool) -> RegisteredClient {
+        let admin = sign_in(services, &ADMIN_EMAIL).await;
+        services
+            .oauth
+            .register_client(
+                &admin,
+                "app".into(),
+                "https://app.example.com/callback".parse().unwrap(),
+                "api profile email".parse().unwrap(),
+                public,
+                &ClientInfo::default(),
+            )
+            .await
+            .unwrap()
+    }
+
+    fn credentials(registered: &RegisteredClient) -> ClientCredentials {
+        ClientCredentials {
+            client_id: registered.client.id,
+            client_secret: registered.client_secret.clone(),
+        }
+    }
+
+    fn authorization(registered: &RegisteredClient, scope: &str) -> Authorization {
+        Authorization {
+            client_id: registered.client.id,
+            redirect_uri: registered.client.redirect_uri.parse().unwrap(),
+            scope: scope.parse().unwrap(),
+            state: Some("xyz".into()),
+            code_challenge: code_challenge(VERIFIER),
+        }
+    }
+
+    fn code(redirect_uri: &Url) -> Token {
+        let (_, code) = redirect_uri
+            .query_pairs()
+            .find(|(key, _)| key == "code")
+            .unwrap();
+        Token::new(code.into_owned())
+    }
+
+    fn code_grant(code: Token, registered: &RegisteredClient) -> Grant {
+        Grant::AuthorizationCode {
+            code,
+            redirect_uri: registered.client.redirect_uri.parse().unwrap(),
+            code_verifier: VERIFIER.into(),
+        }
+    }
+
+    /// Have the default user let the client have `scope`, then exchange the code
+    async fn authorize(
+        services: &Services,
+        registered: &RegisteredClient,
+        scope: &str,
+    ) -> IssuedTokens {
+        let claims = sign_in(services, &DEFAULT_EMAIL).await;
+        let redirect_uri = services
+            .oauth
+            .authorize(
+                &claims,
+                authorization(registered, scope),
+                &ClientInfo::default(),
+            )
+            .await
+            .unwrap();
+
+        let grant = code_grant(code(&redirect_uri), registered);
+        services
+            .oauth
+            .token(credentials(registered), grant, &ClientInfo::default())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn authorization_code_flow_issues_scoped_tokens() {
+        let services = test_services_with(TEST_DATA.clone());
+        let registered = register(&services, false).await;
+        let claims = sign_in(&services, &DEFAULT_EMAIL).await;
+
+        let prompt = services
+            .oauth
+            .authorization(&claims, &authorization(&registered, "email"))
+            .await
+            .unwrap();
+        assert_eq!(prompt.client.name, "app");
+        assert!(!prompt.consented);
+
+        let redirect_uri = services
+            .oauth
+            .authorize(
+                &claims,
+                authorization(&registered, "email"),
+                &ClientInfo::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(redirect_uri.path(), "/callback");
+        assert!(redirect_uri
+            .query_pairs()
+            .any(|(key, value)| key == "state" && value == "xyz"));
+
+        let prompt = services
+            .oauth
+            .authorization(&claims, &authorization(&registered, "email"))
+            .await
+            .unwrap();
+        assert!(prompt.consented);
+
+        let grant = code_grant(code(&redirect_uri), &registered);
+        let tokens = services
+            .oauth
+            .token(
+                credentials(&registered),
+                grant.clone(),
+                &ClientInfo::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(tokens.scope.as_str(), "email");
+        assert!(tokens.refresh_token.is_some());
+
+        // only good for what it was given
+        let info = services.oauth.userinfo(&tokens.access_token).await.unwrap();
+        assert_eq!(info.sub, *DEFAULT_USER_ID);
+        assert_eq!(info.email, Some(DEFAULT_EMAIL.clone()));
+        assert!(matches!(
+            services.auth.authenticate(&tokens.access_token).await,
+            Err(ApiError::Auth)
+        ));
+
+        let reused = services
+            .oauth
+            .token(credentials(&registered), grant, &ClientInfo::default())
+            .await;
+        assert!(matches!(reused, Err(OAuthError::InvalidGrant)));
+    }
+
+    #[tokio::test]
+    async fn code_needs_its_verifier_and_client() {
+        let services = test_services_with(TEST_DATA.clone());
+        let registered = register(&services, false).await;
+        let other = register(&services, true).await;
+        let claims = sign_in(&services, &DEFAULT_EMAIL).await;
+        let mut codes = Vec::new();
+        for _ in 0..2 {
+            let redirect_uri = services
+                .oauth
+                .authorize(
+                    &claims,
+                    authorization(&registered, "api"),
+                    &ClientInfo::default(),
+                )
+                .await
+                .unwrap();
+            codes.push(code(&redirect_uri));
+        }
+
+        let wrong_verifier = Grant::AuthorizationCode {
+            code: codes[0].clone(),
+            redirect_uri: registered.client.redirect_uri.parse().unwrap(),
+            code_verifier: "something else".into(),
+        };
+        let result = services
+            .oauth
+            .token(
+                credentials(&registered),
+                wrong_verifier,
+                &ClientInfo::default(),
+            )
+            .await;
+        assert!(matches!(result, Err(OAuthError::InvalidGrant)));
+
+        let result = services
+            .oauth
+            .token(
+                credentials(&other),
+                code_grant(codes[1].clone(), &registered),
+                &ClientInfo::default(),
+            )
+            .await;
+        assert!(matches!(result, Err(OAuthError::InvalidGrant)));
+    }
+
+    #[tokio::test]
+    async fn clients_have_to_prove_who_they_are() {
+        let services = test_services_with(TEST_DATA.clone());
+        let registered = register(&services, false).await;
+        let grant = Grant::ClientCredentials { scope: None };
+
+        let wrong_secret = ClientCredentials {
+            client_secret: Some(ClientSecret::new("wrong".into())),
+            ..credentials(&registered)
+        };
+        let no_secret = ClientCredentials {
+            client_secret: None,
+            ..credentials(&registered)
+        };
+        for credentials in [wrong_secret, no_secret] {
+            let result = services
+                .oauth
+                .token(credentials, grant.clone(), &ClientInfo::default())
+                .await;
+            assert!(matches!(result, Err(OAuthError::InvalidClient)));
+        }
+
+        let tokens = services
+            .oauth
+            .token(
+                credentials(&registered),
+                grant.clone(),
+                &ClientInfo::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(tokens.scope, registered.client.scopes);
+        assert!(tokens.refresh_token.is_none());
+        // it isn't acting for anyone
+        assert!(services.oauth.userinfo(&tokens.access_token).await.is_err());
+        let acting = services
+            .oauth
+            .authenticate_client_token(&tokens.access_token)
+            .await
+            .unwrap();
+        assert_eq!(acting.client.id, registered.client.id);
+        assert_eq!(acting.scope, registered.client.scopes);
+
+        let public = register(&services, true).await;
+        assert!(public.client_secret.is_none());
+        let result = services
+            .oauth
+            .token(credentials(&public), grant, &ClientInfo::default())
+            .await;
+        assert!(matches!(result, Err(OAuthError::UnauthorizedClient)));
+    }
+
+    #[tokio::test]
+    async fn refresh_tokens_rotate_and_can_narrow_scope() {
+        let services = test_services_with(TEST_DATA.clone());
+        let registered = register(&services, true).await;
+        let first = authorize(&services, &registered, "api email").await;
+        let refresh_token = first.refresh_token.clone().unwrap();
+
+        let grant = |scope: Option<&str>| Grant::RefreshToken {
+            refresh_token: refresh_token.clone(),
+            scope: scope.map(|scope| scope.parse().unwrap()),
+        };
+        let widened = services
+            .oauth
+            .token(
+                credentials(&registered),
+                grant(Some("api email profile")),
+                &ClientInfo::default(),
+            )
+            .await;
+        assert!(matches!(widened, Err(OAuthError::InvalidScope)));
+        // a failed refresh still uses the token up
+        let result = services
+            .oauth
+            .token(
+                credentials(&registered),
+                grant(Some("email")),
+                &ClientInfo::default(),
+            )
+            .await;
+        assert!(matches!(result, Err(OAuthError::InvalidGrant)));
+
+        let second = authorize(&services, &registered, "api email").await;
+        let narrowed = services
+            .oauth
+            .token(
+                credentials(&registered),
+                Grant::RefreshToken {
+                    refresh_token: second.refresh_token.clone().unwrap(),
+                    scope: Some("email".parse().unwrap()),
+                },
+                &ClientInfo::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(narrowed.scope.as_str(), "email");
+        assert_ne!(
+            narrowed.refresh_token.unwrap().expose_secret(),
+            second.refresh_token.unwrap().expose_secret()
+        );
+
+        // the session of the access token it replaced is over
+        assert!(services.oauth.userinfo(&second.access_token).await.is_err());
+        services
+            .oauth
+            .userinfo(&narrowed.access_token)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn only_first_party_tokens_can_authorize() {
+        let services = test_services_with(TEST_DATA.clone());
+        let registered = register(&services, false).await;
+        let tokens = authorize(&services, &registered, "api").await;
+        let claims = services
+            .auth
+            .authenticate(&tokens.access_token)
+            .await
+            .unwrap();
+        assert!(claims.allows(Scope::Api));
+
+        let result = services
+            .oauth
+            .authorize(
+                &claims,
+                authorization(&registered, "api email"),
+                &ClientInfo::default(),
+            )
+            .await;
+        assert!(matches!(result, Err(ApiError::Auth)));
+    }
+
+    #[tokio::test]
+    async fn rejects_what_the_client_was_not_registered_for() {
+        let services = test_services_with(TEST_DATA.clone());
+        let admin = sign_in(&services, &ADMIN_EMAIL).await;
+        let registered = services
+            .oauth
+            .register_client(
+                &admin,
+                "app".into(),
+                "https://app.example.com/callback".parse().unwrap(),
+                "email".parse().unwrap(),
+                true,
+                &ClientInfo::default(),
+            )
+            .await
+            .unwrap();
+        let claims = sign_in(&services, &DEFAULT_EMAIL).await;
+
+        let too_much = authorization(&registered, "api");
+        let elsewhere = Authorization {
+            redirect_uri: "https://evil.example.com/callback".parse().unwrap(),
+            ..authorization(&registered, "email")
+        };
+        let nothing = Authorization {
+            scope: Scopes::default(),
+            ..authorization(&registered, "email")
+        };
+        for (request, field) in [
+            (too_much, "scope"),
+            (elsewhere, "redirect_uri"),
+            (nothing, "scope"),
+        ] {
+            let result = services.oauth.authorization(&claims, &request).await;
+            assert!(matches!(result, Err(ApiError::Invalid { field: f }) if f == field));
+        }
+    }
+
+    #[tokio::test]
+    async fn revoking_consent_ends_the_clients_sessions() {
+        let services = test_services_with(TEST_DATA.clone());
+        let registered = register(&services, false).await;
+        let tokens = authorize(&services, &registered, "profile").await;
+        let claims = sign_in(&services, &DEFAULT_EMAIL).await;
+
+        let consents = services.oauth.consents(&claims).await.unwrap();
+        assert_eq!(consents.len(), 1);
+        assert_eq!(consents[0].client_name, "app");
+        assert_eq!(consents[0].scope.as_str(), "profile");
+
+        services
+            .oauth
+            .revoke_consent(&claims, registered.client.id, &ClientInfo::default())
+            .await
+            .unwrap();
+
+        assert!(services.oauth.consents(&claims).await.unwrap().is_empty());
+        assert!(services.oauth.userinfo(&tokens.access_token).await.is_err());
+        let refresh = Grant::RefreshToken {
+            refresh_token: tokens.refresh_token.unwrap(),
+            scope: None,
+        };
+        let result = services
+            .oauth
+            .token(credentials(&registered), refresh, &ClientInfo::default())
+            .await;
+        assert!(matches!(result, Err(OAuthError::InvalidGrant)));
+        let result = services
+            .oauth
+            .revoke_consent(&claims, registered.client.id, &ClientInfo::default())
+            .await;
+        assert!(matches!(result, Err(ApiError::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn userinfo_only_shows_what_was_consented_to() {
+        let services = test_services_with(TEST_DATA.clone());
+        let registered = register(&services, false).await;
+        let changes = ProfileChanges {
+            display_name: Some(Some("Ada".into())),
+            ..ProfileChanges::default()
+        };
+        services
+            .db
+            .update_profile(*DEFAULT_USER_ID, changes)
+            .await
+            .unwrap();
+
+        let profile = authorize(&services, &registered, "profile").await;
+        let info = services
+            .oauth
+            .userinfo(&profile.access_token)
+            .await
+            .unwrap();
+        assert_eq!(info.name.as_deref(), Some("Ada"));
+        assert_eq!(info.email, None);
+    }
+
+    #[tokio::test]
+    async fn purges_expired_grants() {
+        let services = test_services_with(TEST_DATA.clone());
+        let registered = register(&services, false).await;
+        authorize(&services, &registered, "api").await;
+        let expired = AuthorizationCode {
+            code_hash: "expired".into(),
+            client_id: registered.client.id,
+            user_id: *DEFAULT_USER_ID,
+            redirect_uri: registered.client.redirect_uri.clone(),
+            scopes: "api".parse().unwrap(),
+            code_challenge: code_challenge(VERIFIER),
+            created_at: *DEFAULT_DATE_TIME - Duration::minutes(10),
+            expires_at: *DEFAULT_DATE_TIME - Duration::minutes(5),
+        };
+        services
+            .db
+            .create_authorization_code(expired)
+            .await
+            .unwrap();
+
+        assert_eq!(services.oauth.purge_expired().await.unwrap(), 1);
+        assert_eq!(services.oauth.purge_expired().await.unwrap(), 0);
+    }
+}
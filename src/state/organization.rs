@@ -0,0 +1,880 @@
+use std::sync::Arc;
+
+use futures::FutureExt;
+use tracing::{field, Span};
+use url::Url;
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    db::{sql::DbError, transaction::Transaction, Db},
+    model::{
+        audit::AuditAction,
+        oauth::Scope,
+        organization::{Membership, OrgInvitation, OrgRole, Organization},
+        types::{Email, OrgId, Token, UserId},
+    },
+    routing::{client::ClientInfo, errors::ApiError},
+    state::jwt::claims::{Claims, Validated},
+};
+
+use super::{
+    audit::AuditService,
+    auth::{not_found_if_unmodified, AuthService},
+    hasher::hash_token,
+    jwt::Jwt,
+    mailer::{Mailer, Message},
+    principal::Principal,
+    random::Random,
+    time::Time,
+    Common,
+};
+
+/// The longest name an organization can have, in characters
+const MAX_NAME_LEN: usize = 100;
+
+/// An organization, along with the signed in user's place in it
+#[derive(Debug, Clone)]
+pub struct OrgMembership {
+    pub organization: Organization,
+    pub membership: Membership,
+}
+
+/// Organizations several users share, which they join by invitation
+///
+/// Whoever makes an organization is its first owner, and there's always at least one. Admins can
+/// invite, remove and change the roles of members, but only owners can do that to other owners.
+/// To anyone who isn't a member, an organization doesn't exist
+#[derive(Debug, Clone)]
+pub struct OrgService {
+    time: Arc<dyn Time>,
+    random: Arc<dyn Random>,
+    db: Arc<dyn Db>,
+    mailer: Arc<dyn Mailer>,
+    auth: AuthService,
+    audit: AuditService,
+    config: Arc<Config>,
+}
+
+impl OrgService {
+    pub fn new(common: Common, mailer: Arc<dyn Mailer>, auth: AuthService) -> Self {
+        let Common {
+            time,
+            random,
+            db,
+            audit,
+            config,
+        } = common;
+
+        Self {
+            time,
+            random,
+            db,
+            mailer,
+            auth,
+            audit,
+            config,
+        }
+    }
+
+    /// Make an organization, with the signed in user as its owner
+    #[instrument(skip_all, fields(user_id = %principal.user_id.0))]
+    pub async fn create(
+        &self,
+        principal: &Principal,
+        name: String,
+        client: &ClientInfo,
+    ) -> Result<OrgMembership, ApiError> {
+        principal.require_scope(Scope::Api)?;
+        let name = name.trim();
+        if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+            return Err(ApiError::Invalid { field: "name" });
+        }
+
+        let now = self.time.now();
+        let organization = Organization {
+            id: OrgId(self.random.uuid()),
+            name: name.into(),
+            created_at: now,
+        };
+        let membership = Membership {
+            organization_id: organization.id,
+            user_id: principal.user_id,
+            role: OrgRole::Owner,
+            created_at: now,
+        };
+        self.db
+            .transaction(|tx| {
+                let (organization, membership) = (organization.clone(), membership.clone());
+                async move {
+                    tx.create_organization(organization).await?;
+                    tx.create_membership(membership).await
+                }
+                .boxed()
+            })
+            .await?;
+
+        self.audit
+            .record(
+                AuditAction::OrganizationCreated,
+                Some(principal.user_id),
+                None,
+                client,
+            )
+            .await;
+        Ok(OrgMembership {
+            organization,
+            membership,
+        })
+    }
+
+    /// Every organization the signed in user is in, oldest membership first
+    #[instrument(skip_all, fields(user_id = %principal.user_id.0))]
+    pub async fn organizations(
+        &self,
+        principal: &Principal,
+    ) -> Result<Vec<OrgMembership>, ApiError> {
+        principal.require_scope(Scope::Api)?;
+        let memberships = self.db.memberships_for_user(principal.user_id).await?;
+
+        let mut organizations = Vec::with_capacity(memberships.len());
+        for membership in memberships {
+            // deleted since the memberships were read
+            let Some(organization) = self
+                .db
+                .organization_by_id(membership.organization_id)
+                .await?
+            else {
+                continue;
+            };
+            organizations.push(OrgMembership {
+                organization,
+                membership,
+            });
+        }
+        Ok(organizations)
+    }
+
+    #[instrument(skip_all, fields(user_id = %principal.user_id.0, org_id = %org_id.0))]
+    pub async fn organization(
+        &self,
+        principal: &Principal,
+        org_id: OrgId,
+    ) -> Result<OrgMembership, ApiError> {
+        principal.require_scope(Scope::Api)?;
+        self.org_membership(principal.user_id, org_id).await
+    }
+
+    /// The organization the signed in user is acting in, for scoping what they see to it
+    ///
+    /// They may have been removed from it since switching to it, so this is checked every time
+    pub async fn current(&self, claims: &Claims<Validated>) -> Result<OrgMembership, ApiError> {
+        let org_id = claims.org.ok_or(ApiError::NotFound)?;
+        self.org_membership(claims.subject, org_id).await
+    }
+
+    /// Everyone in an organization, longest standing first
+    #[instrument(skip_all, fields(user_id = %principal.user_id.0, org_id = %org_id.0))]
+    pub async fn members(
+        &self,
+        principal: &Principal,
+        org_id: OrgId,
+    ) -> Result<Vec<Membership>, ApiError> {
+        principal.require_scope(Scope::Api)?;
+        self.membership(principal.user_id, org_id).await?;
+        Ok(self.db.memberships_for_organization(org_id).await?)
+    }
+
+    /// Delete an organization, along with its memberships and invitations, which only owners can do
+    #[instrument(skip_all, fields(user_id = %principal.user_id.0, org_id = %org_id.0))]
+    pub async fn delete(
+        &self,
+        principal: &Principal,
+        org_id: OrgId,
+        client: &ClientInfo,
+    ) -> Result<(), ApiError> {
+        principal.require_scope(Scope::Api)?;
+        self.require(principal, org_id, OrgRole::Owner).await?;
+        not_found_if_unmodified(self.db.delete_organization(org_id).await)?;
+
+        self.audit
+            .record(
+                AuditAction::OrganizationDeleted,
+                Some(principal.user_id),
+                None,
+                client,
+            )
+            .await;
+        Ok(())
+    }
+
+    /// Change what a member is allowed to do, leaving the organization with at least one owner
+    #[instrument(skip_all, fields(user_id = %principal.user_id.0, org_id = %org_id.0, member_id = %user_id.0))]
+    pub async fn change_role(
+        &self,
+        principal: &Principal,
+        org_id: OrgId,
+        user_id: UserId,
+        role: OrgRole,
+        client: &ClientInfo,
+    ) -> Result<Membership, ApiError> {
+        principal.require_scope(Scope::Api)?;
+        let actor = self.require(principal, org_id, OrgRole::Admin).await?.role;
+
+        let membership = self
+            .db
+            .transaction(|tx| {
+                async move {
+                    let Some(membership) = tx.membership(org_id, user_id).await? else {
+                        return Err(ApiError::NotFound);
+                    };
+                    let owners_affected =
+                        membership.role == OrgRole::Owner || role == OrgRole::Owner;
+                    if owners_affected && actor < OrgRole::Owner {
+                        return Err(ApiError::Auth);
+                    }
+                    if membership.role == OrgRole::Owner && role < OrgRole::Owner {
+                        check_other_owners(tx, org_id, "role").await?;
+                    }
+
+                    tx.update_membership_role(org_id, user_id, role).await?;
+                    Ok(Membership { role, ..membership })
+                }
+                .boxed()
+            })
+            .await?;
+
+        self.audit
+            .record(
+                AuditAction::OrgMemberRoleChanged,
+                Some(principal.user_id),
+                Some(user_id),
+                client,
+            )
+            .await;
+        Ok(membership)
+    }
+
+    /// Take someone out of an organization, or leave it, as long as an owner is left behind
+    ///
+    /// Anyone can leave, but only admins can remove others, and only owners can remove owners
+    #[instrument(skip_all, fields(user_id = %principal.user_id.0, org_id = %org_id.0, member_id = %user_id.0))]
+    pub async fn remove_member(
+        &self,
+        principal: &Principal,
+        org_id: OrgId,
+        user_id: UserId,
+        client: &ClientInfo,
+    ) -> Result<(), ApiError> {
+        principal.require_scope(Scope::Api)?;
+        let actor = self.membership(principal.user_id, org_id).await?.role;
+        let leaving = user_id == principal.user_id;
+        if !leaving && actor < OrgRole::Admin {
+            return Err(ApiError::Auth);
+        }
+
+        self.db
+            .transaction(|tx| {
+                async move {
+                    let Some(membership) = tx.membership(org_id, user_id).await? else {
+                        return Err(ApiError::NotFound);
+                    };
+                    if membership.role == OrgRole::Owner {
+                        if actor < OrgRole::Owner {
+                            return Err(ApiError::Auth);
+                        }
+                        check_other_owners(tx, org_id, "user_id").await?;
+                    }
+
+                    not_found_if_unmodified(tx.delete_membership(org_id, user_id).await)
+                }
+                .boxed()
+            })
+            .await?;
+
+        self.audit
+            .record(
+                AuditAction::OrgMemberRemoved,
+                Some(principal.user_id),
+                Some(user_id),
+                client,
+            )
+            .await;
+        Ok(())
+    }
+
+    /// Email `email` a link to join an organization as `role`
+    ///
+    /// Only admins can invite people, and only owners can invite other owners
+    #[instrument(skip_all, fields(user_id = %principal.user_id.0, org_id = %org_id.0, email = %email.redacted()))]
+    pub async fn invite(
+        &self,
+        principal: &Principal,
+        org_id: OrgId,
+        email: Email,
+        role: OrgRole,
+        client: &ClientInfo,
+    ) -> Result<OrgInvitation, ApiError> {
+        principal.require_scope(Scope::Api)?;
+        let actor = self.require(principal, org_id, OrgRole::Admin).await?;
+        if role > actor.role {
+            return Err(ApiError::Auth);
+        }
+        let organization = self
+            .db
+            .organization_by_id(org_id)
+            .await?
+            .ok_or(ApiError::NotFound)?;
+
+        let invitee = self.db.user_by_email(email.clone()).await?;
+        if let Some(invitee) = &invitee {
+            if self.db.membership(org_id, invitee.id).await?.is_some() {
+                return Err(ApiError::Invalid { field: "email" });
+            }
+        }
+
+        let token = self.random.token();
+        let now = self.time.now();
+        let invitation = OrgInvitation {
+            id: self.random.uuid(),
+            organization_id: org_id,
+            email: email.clone(),
+            role,
+            token_hash: hash_token(&token),
+            created_at: now,
+            expires_at: now + self.config.accounts.org_invitation_ttl(),
+        };
+        self.db.create_org_invitation(invitation.clone()).await?;
+
+        self.mailer
+            .send(Message {
+                to: email,
+                subject: format!("You're invited to join {}", organization.name),
+                body: format!(
+                    "You've been invited to join {} as {}. If you weren't expecting this, you can \
+                     ignore this email. Otherwise, follow this link in the next {} days: {}",
+                    organization.name,
+                    role.as_str(),
+                    self.config.accounts.org_invitation_ttl().num_days(),
+                    self.link(&token)
+                ),
+            })
+            .await?;
+
+        let invitee_id = invitee.map(|invitee| invitee.id);
+        self.audit
+            .record(
+                AuditAction::OrgMemberInvited,
+                Some(principal.user_id),
+                invitee_id,
+                client,
+            )
+            .await;
+        Ok(invitation)
+    }
+
+    /// The invitations an organization has out, expired or not, oldest first
+    #[instrument(skip_all, fields(user_id = %principal.user_id.0, org_id = %org_id.0))]
+    pub async fn invitations(
+        &self,
+        principal: &Principal,
+        org_id: OrgId,
+    ) -> Result<Vec<OrgInvitation>, ApiError> {
+        principal.require_scope(Scope::Api)?;
+        self.require(principal, org_id, OrgRole::Admin).await?;
+        Ok(self.db.org_invitations_for_organization(org_id).await?)
+    }
+
+    #[instrument(skip_all, fields(user_id = %principal.user_id.0, org_id = %org_id.0, invitation_id = %invitation_id))]
+    pub async fn revoke_invitation(
+        &self,
+        principal: &Principal,
+        org_id: OrgId,
+        invitation_id: Uuid,
+        client: &ClientInfo,
+    ) -> Result<(), ApiError> {
+        principal.require_scope(Scope::Api)?;
+        self.require(principal, org_id, OrgRole::Admin).await?;
+        let result = self.db.delete_org_invitation(org_id, invitation_id).await;
+        not_found_if_unmodified(result)?;
+
+        self.audit
+            .record(
+                AuditAction::OrgInvitationRevoked,
+                Some(principal.user_id),
+                None,
+                client,
+            )
+            .await;
+        Ok(())
+    }
+
+    /// Join the organization an invitation is for, which only works once, and only for the
+    /// address it was sent to
+    #[instrument(skip_all, fields(user_id = %principal.user_id.0, org_id = field::Empty))]
+    pub async fn accept_invitation(
+        &self,
+        principal: &Principal,
+        token: Token,
+        client: &ClientInfo,
+    ) -> Result<OrgMembership, ApiError> {
+        principal.require_scope(Scope::Api)?;
+        // the address may have changed since the token was issued
+        let user = self
+            .db
+            .user_by_id(principal.user_id)
+            .await?
+            .ok_or(ApiError::Auth)?;
+        let token_hash = hash_token(&token);
+        let invitation = self
+            .db
+            .org_invitation_by_token(token_hash)
+            .await?
+            .filter(|invitation| invitation.expires_at > self.time.now())
+            .filter(|invitation| invitation.email == user.email)
+            .ok_or(ApiError::Auth)?;
+        let (org_id, invitation_id) = (invitation.organization_id, invitation.id);
+        Span::current().record("org_id", field::display(org_id.0));
+
+        let membership = Membership {
+            organization_id: org_id,
+            user_id: user.id,
+            role: invitation.role,
+            created_at: self.time.now(),
+        };
+        self.db
+            .transaction(|tx| {
+                let membership = membership.clone();
+                async move {
+                    match tx.delete_org_invitation(org_id, invitation_id).await {
+                        Ok(()) => {}
+                        Err(DbError::RowsModified { .. }) => return Err(ApiError::Auth),
+                        Err(e) => return Err(e.into()),
+                    }
+                    tx.create_membership(membership).await?;
+                    Ok(())
+                }
+                .boxed()
+            })
+            .await?;
+
+        self.audit
+            .record(
+                AuditAction::OrgMemberJoined,
+                Some(user.id),
+                Some(user.id),
+                client,
+            )
+            .await;
+        self.org_membership(user.id, org_id).await
+    }
+
+    /// A token for the same session, acting in `org_id`, or as just the user if `None`
+    #[instrument(skip_all, fields(user_id = %claims.subject.0))]
+    pub async fn switch(
+        &self,
+        claims: &Claims<Validated>,
+        org_id: Option<OrgId>,
+    ) -> Result<Jwt, ApiError> {
+        if let Some(org_id) = org_id {
+            self.membership(claims.subject, org_id).await?;
+        }
+        self.auth.switch_org(claims, org_id)
+    }
+
+    /// Remove invitations that have expired, returning how many there were
+    #[instrument(skip_all, fields(purged = field::Empty))]
+    pub async fn purge_expired(&self) -> Result<usize, DbError> {
+        let purged = self
+            .db
+            .delete_expired_org_invitations(self.time.now())
+            .await?;
+        Span::current().record("purged", purged);
+        Ok(purged)
+    }
+
+    /// `user_id`'s membership, as if the organization didn't exist if they have none
+    async fn membership(&self, user_id: UserId, org_id: OrgId) -> Result<Membership, ApiError> {
+        self.db
+            .membership(org_id, user_id)
+            .await?
+            .ok_or(ApiError::NotFound)
+    }
+
+    /// An organization along with `user_id`'s membership of it
+    async fn org_membership(
+        &self,
+        user_id: UserId,
+        org_id: OrgId,
+    ) -> Result<OrgMembership, ApiError> {
+        let membership = self.membership(user_id, org_id).await?;
+        let organization = self
+            .db
+            .organization_by_id(org_id)
+            .await?
+            .ok_or(ApiError::NotFound)?;
+
+        Ok(OrgMembership {
+            organization,
+            membership,
+        })
+    }
+
+    /// The signed in user's membership, if it allows at least what `role` does
+    async fn require(
+        &self,
+        principal: &Principal,
+        org_id: OrgId,
+        role: OrgRole,
+    ) -> Result<Membership, ApiError> {
+        let membership = self.membership(principal.user_id, org_id).await?;
+        match membership.role >= role {
+            true => Ok(membership),
+            false => Err(ApiError::Auth),
+        }
+    }
+
+    fn link(&self, token: &Token) -> Url {
+        self.config.email.link("org-invitation", token)
+    }
+}
+
+/// Fail with `Invalid { field }` unless an organization has more than one owner, so that taking
+/// one away leaves it with another
+async fn check_other_owners(
+    tx: &dyn Transaction,
+    org_id: OrgId,
+    field: &'static str,
+) -> Result<(), ApiError> {
+    let owners = tx
+        .memberships_for_organization(org_id)
+        .await?
+        .into_iter()
+        .filter(|membership| membership.role == OrgRole::Owner)
+        .count();
+    match owners > 1 {
+        true => Ok(()),
+        false => Err(ApiError::Invalid { field }),
+    }
+}
+
+/// Delete the organizations that would have no owner left once `user_ids` are gone for good,
+/// returning their ids
+///
+/// Nobody else could manage or delete them otherwise
+pub(super) async fn delete_orphaned_organizations(
+    tx: &dyn Transaction,
+    user_ids: &[UserId],
+) -> Result<Vec<OrgId>, DbError> {
+    let mut deleted = vec![];
+    for &user_id in user_ids {
+        for membership in tx.memberships_for_user(user_id).await? {
+            let org_id = membership.organization_id;
+            if membership.role != OrgRole::Owner || deleted.contains(&org_id) {
+                continue;
+            }
+
+            let orphaned = tx
+                .memberships_for_organization(org_id)
+                .await?
+                .iter()
+                .filter(|membership| membership.role == OrgRole::Owner)
+                .all(|owner| user_ids.contains(&owner.user_id));
+            if orphaned {
+                tx.delete_organization(org_id).await?;
+                deleted.push(org_id);
+            }
+        }
+    }
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use microtype::SecretMicrotype;
+
+    use crate::{
+        model::{
+            organization::{OrgInvitation, OrgRole},
+            types::{
+                mock::{ADMIN_EMAIL, ADMIN_USER_ID, DEFAULT_EMAIL, DEFAULT_USER_ID},
+                Email, OrgId, Token,
+            },
+        },
+        routing::{client::ClientInfo, errors::ApiError},
+        state::{
+            hasher::hash_token, principal::Principal, time::mock::DEFAULT_DATE_TIME, Services,
+        },
+        testing::{sign_in, test_data::TEST_DATA, test_services_with},
+    };
+
+    async fn principal(services: &Services, email: Email) -> Principal {
+        sign_in(services, &email).await.into()
+    }
+
+    /// The default user owns a new organization, and the admin user has joined it as `role`
+    async fn org_with_member(services: &Services, role: OrgRole) -> (OrgId, Principal, Principal) {
+        let client = ClientInfo::default();
+        let owner = principal(services, DEFAULT_EMAIL.clone()).await;
+        let member = principal(services, ADMIN_EMAIL.clone()).await;
+
+        let org = services
+            .org
+            .create(&owner, "Acme".into(), &client)
+            .await
+            .unwrap();
+        let org_id = org.organization.id;
+        services
+            .org
+            .invite(&owner, org_id, ADMIN_EMAIL.clone(), role, &client)
+            .await
+            .unwrap();
+        let token = services.mailer.as_mock().token_sent_to(&ADMIN_EMAIL);
+        services
+            .org
+            .accept_invitation(&member, token, &client)
+            .await
+            .unwrap();
+
+        (org_id, owner, member)
+    }
+
+    #[tokio::test]
+    async fn names_must_be_given() {
+        let services = test_services_with(TEST_DATA.clone());
+        let owner = principal(&services, DEFAULT_EMAIL.clone()).await;
+
+        let result = services
+            .org
+            .create(&owner, "  ".into(), &ClientInfo::default())
+            .await;
+        assert!(matches!(result, Err(ApiError::Invalid { field: "name" })));
+    }
+
+    #[tokio::test]
+    async fn members_cannot_manage_others() {
+        let services = test_services_with(TEST_DATA.clone());
+        let (org_id, owner, member) = org_with_member(&services, OrgRole::Member).await;
+        let client = ClientInfo::default();
+
+        let result = services
+            .org
+            .change_role(&member, org_id, owner.user_id, OrgRole::Member, &client)
+            .await;
+        assert!(matches!(result, Err(ApiError::Auth)));
+        let result = services
+            .org
+            .remove_member(&member, org_id, owner.user_id, &client)
+            .await;
+        assert!(matches!(result, Err(ApiError::Auth)));
+        let result = services
+            .org
+            .invite(
+                &member,
+                org_id,
+                Email("a@email.com".into()),
+                OrgRole::Member,
+                &client,
+            )
+            .await;
+        assert!(matches!(result, Err(ApiError::Auth)));
+        let result = services.org.delete(&member, org_id, &client).await;
+        assert!(matches!(result, Err(ApiError::Auth)));
+
+        // but anyone can leave
+        services
+            .org
+            .remove_member(&member, org_id, member.user_id, &client)
+            .await
+            .unwrap();
+        let result = services.org.members(&member, org_id).await;
+        assert!(matches!(result, Err(ApiError::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn only_owners_manage_owners() {
+        let services = test_services_with(TEST_DATA.clone());
+        let (org_id, owner, admin) = org_with_member(&services, OrgRole::Admin).await;
+        let client = ClientInfo::default();
+
+        let result = services
+            .org
+            .change_role(&admin, org_id, admin.user_id, OrgRole::Owner, &client)
+            .await;
+        assert!(matches!(result, Err(ApiError::Auth)));
+        let result = services
+            .org
+            .remove_member(&admin, org_id, owner.user_id, &client)
+            .await;
+        assert!(matches!(result, Err(ApiError::Auth)));
+
+        let membership = services
+            .org
+            .change_role(&owner, org_id, admin.user_id, OrgRole::Owner, &client)
+            .await
+            .unwrap();
+        assert_eq!(membership.role, OrgRole::Owner);
+        services
+            .org
+            .remove_member(&admin, org_id, owner.user_id, &client)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn always_keeps_an_owner() {
+        let services = test_services_with(TEST_DATA.clone());
+        let (org_id, owner, _) = org_with_member(&services, OrgRole::Admin).await;
+        let client = ClientInfo::default();
+
+        let result = services
+            .org
+            .change_role(&owner, org_id, owner.user_id, OrgRole::Admin, &client)
+            .await;
+        assert!(matches!(result, Err(ApiError::Invalid { field: "role" })));
+        let result = services
+            .org
+            .remove_member(&owner, org_id, owner.user_id, &client)
+            .await;
+        assert!(matches!(
+            result,
+            Err(ApiError::Invalid { field: "user_id" })
+        ));
+
+        let members = services.org.members(&owner, org_id).await.unwrap();
+        assert_eq!(members[0].role, OrgRole::Owner);
+    }
+
+    #[tokio::test]
+    async fn invitations_work_once_and_expire() {
+        let services = test_services_with(TEST_DATA.clone());
+        let client = ClientInfo::default();
+        let (org_id, owner, member) = org_with_member(&services, OrgRole::Member).await;
+
+        // already used by `org_with_member`
+        let token = services.mailer.as_mock().token_sent_to(&ADMIN_EMAIL);
+        let result = services
+            .org
+            .accept_invitation(&member, token, &client)
+            .await;
+        assert!(matches!(result, Err(ApiError::Auth)));
+
+        services
+            .org
+            .remove_member(&owner, org_id, *ADMIN_USER_ID, &client)
+            .await
+            .unwrap();
+        let token = Token::new("expired".into());
+        let invitation = OrgInvitation {
+            id: uuid::Uuid::new_v4(),
+            organization_id: org_id,
+            email: ADMIN_EMAIL.clone(),
+            role: OrgRole::Member,
+            token_hash: hash_token(&token),
+            created_at: *DEFAULT_DATE_TIME - Duration::days(8),
+            expires_at: *DEFAULT_DATE_TIME - Duration::days(1),
+        };
+        services.db.create_org_invitation(invitation).await.unwrap();
+
+        let result = services
+            .org
+            .accept_invitation(&member, token, &client)
+            .await;
+        assert!(matches!(result, Err(ApiError::Auth)));
+
+        let purged = services.org.purge_expired().await.unwrap();
+        assert_eq!(purged, 1);
+        assert!(services
+            .org
+            .invitations(&owner, org_id)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn switching_needs_a_membership() {
+        let services = test_services_with(TEST_DATA.clone());
+        let (org_id, owner, _) = org_with_member(&services, OrgRole::Member).await;
+        let signed_in = sign_in(&services, &DEFAULT_EMAIL).await;
+        let outsider = OrgId(uuid::Uuid::new_v4());
+
+        let result = services.org.switch(&signed_in, Some(outsider)).await;
+        assert!(matches!(result, Err(ApiError::NotFound)));
+
+        let jwt = services.org.switch(&signed_in, Some(org_id)).await.unwrap();
+        let claims = services.auth.authenticate(&jwt).await.unwrap();
+        assert_eq!(claims.org, Some(org_id));
+        let current = services.org.current(&claims).await.unwrap();
+        assert_eq!(current.membership.role, OrgRole::Owner);
+
+        // once it's gone, the claim no longer counts for anything
+        services
+            .org
+            .delete(&owner, org_id, &ClientInfo::default())
+            .await
+            .unwrap();
+        let result = services.org.current(&claims).await;
+        assert!(matches!(result, Err(ApiError::NotFound)));
+    }
+
+    /// The default user co-owns one organization with the admin user, and solely owns another
+    async fn co_owned_and_solely_owned(services: &Services) -> (OrgId, OrgId) {
+        let (shared, owner, _) = org_with_member(services, OrgRole::Owner).await;
+        let solo = services
+            .org
+            .create(&owner, "Solo".into(), &ClientInfo::default())
+            .await
+            .unwrap();
+        (shared, solo.organization.id)
+    }
+
+    async fn exists(services: &Services, org_id: OrgId) -> bool {
+        services
+            .db
+            .organization_by_id(org_id)
+            .await
+            .unwrap()
+            .is_some()
+    }
+
+    #[tokio::test]
+    async fn erasing_the_only_owner_deletes_the_organization() {
+        let services = test_services_with(TEST_DATA.clone());
+        let (shared, solo) = co_owned_and_solely_owned(&services).await;
+        let admin = sign_in(&services, &ADMIN_EMAIL).await;
+
+        let report = services
+            .privacy
+            .erase_user(&admin, *DEFAULT_USER_ID, &ClientInfo::default())
+            .await
+            .unwrap();
+
+        assert_eq!(report.organizations, 1);
+        assert!(!exists(&services, solo).await);
+        assert!(exists(&services, shared).await);
+        let members = services.org.members(&admin.into(), shared).await.unwrap();
+        assert_eq!(members.len(), 1);
+        assert_eq!(members[0].role, OrgRole::Owner);
+    }
+
+    #[tokio::test]
+    async fn purging_the_only_owner_deletes_the_organization() {
+        let services = test_services_with(TEST_DATA.clone());
+        let (shared, solo) = co_owned_and_solely_owned(&services).await;
+        let grace_period = services.org.config.accounts.deletion_grace_period();
+        services
+            .db
+            .delete_user(
+                *DEFAULT_USER_ID,
+                *DEFAULT_DATE_TIME - grace_period - Duration::seconds(1),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(services.auth.purge_deleted_users().await.unwrap(), 1);
+        assert!(!exists(&services, solo).await);
+        assert!(exists(&services, shared).await);
+    }
+}
@@ -0,0 +1,340 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::{eyre::eyre, Result};
+use microtype::secrecy::ExposeSecret;
+use sha1::{Digest, Sha1};
+use thiserror::Error;
+
+use crate::{
+    config::PasswordConfig,
+    model::types::{Email, Password},
+};
+
+mod strength;
+
+/// Common passwords and keyboard patterns, most common first
+const COMMON_PASSWORDS: &str = include_str!("common.txt");
+/// SHA-1 hashes of passwords seen in public breaches
+const BREACHED_PASSWORDS: &str = include_str!("breached.txt");
+
+/// The length of the part of a SHA-1 hash breached passwords are grouped by, as in k-anonymity
+/// range lookups
+const HASH_PREFIX_LEN: usize = 5;
+
+/// The shortest local part of an email address that passwords aren't allowed to contain
+const MIN_EMAIL_PART_LEN: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CharClass {
+    Lowercase,
+    Uppercase,
+    Digit,
+    /// Anything that isn't a letter or a digit
+    Symbol,
+}
+
+impl CharClass {
+    pub const ALL: [CharClass; 4] = [
+        CharClass::Lowercase,
+        CharClass::Uppercase,
+        CharClass::Digit,
+        CharClass::Symbol,
+    ];
+
+    pub fn matches(self, c: char) -> bool {
+        match self {
+            CharClass::Lowercase => c.is_lowercase(),
+            CharClass::Uppercase => c.is_uppercase(),
+            CharClass::Digit => c.is_numeric(),
+            CharClass::Symbol => !c.is_alphanumeric(),
+        }
+    }
+
+    /// Roughly how many characters there are to choose from, at least on a keyboard
+    fn size(self) -> u32 {
+        match self {
+            CharClass::Lowercase | CharClass::Uppercase => 26,
+            CharClass::Digit => 10,
+            CharClass::Symbol => 33,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum PasswordError {
+    #[error("shorter than {0} characters")]
+    TooShort(usize),
+    #[error("longer than {0} bytes")]
+    TooLong(usize),
+    #[error("no {0:?} characters")]
+    Missing(CharClass),
+    #[error("strength {0} is too low")]
+    TooWeak(u8),
+    #[error("contains the email address")]
+    ContainsEmail,
+    #[error("seen in a breach")]
+    Breached,
+}
+
+impl PasswordError {
+    /// The reason given to whoever chose the password, so that they can pick a better one
+    pub fn key(&self) -> &'static str {
+        match self {
+            PasswordError::TooShort(_) => "too_short",
+            PasswordError::TooLong(_) => "too_long",
+            PasswordError::Missing(CharClass::Lowercase) => "missing_lowercase",
+            PasswordError::Missing(CharClass::Uppercase) => "missing_uppercase",
+            PasswordError::Missing(CharClass::Digit) => "missing_digit",
+            PasswordError::Missing(CharClass::Symbol) => "missing_symbol",
+            PasswordError::TooWeak(_) => "too_weak",
+            PasswordError::ContainsEmail => "contains_email",
+            PasswordError::Breached => "breached",
+        }
+    }
+}
+
+/// Checks passwords users pick against the configured rules
+#[derive(Debug)]
+pub struct PasswordPolicy {
+    config: PasswordConfig,
+    /// Lowercase common passwords, and how common they are, 0 being the most
+    dictionary: HashMap<String, usize>,
+    /// Breached password hashes, grouped by their first few hex digits
+    breached: HashMap<String, HashSet<String>>,
+    /// The configured directory of range files with more of them
+    ranges: Option<PathBuf>,
+}
+
+impl PasswordPolicy {
+    /// Fails if the configured breached password directory isn't one
+    pub fn new(config: PasswordConfig) -> Result<Self> {
+        let dictionary = lines(COMMON_PASSWORDS)
+            .enumerate()
+            .map(|(rank, word)| (word.to_lowercase(), rank))
+            .collect();
+
+        let mut breached = HashMap::new();
+        add_hashes(&mut breached, BREACHED_PASSWORDS)?;
+
+        let ranges = config.breached_passwords.clone();
+        if let Some(path) = &ranges {
+            if !path.is_dir() {
+                return Err(eyre!(
+                    "breached passwords need to be a directory, {} isn't one",
+                    path.display()
+                ));
+            }
+        }
+
+        Ok(Self {
+            config,
+            dictionary,
+            breached,
+            ranges,
+        })
+    }
+
+    /// Check that `password` is one the owner of `email` may choose
+    pub fn check(&self, password: &Password, email: &Email) -> Result<(), PasswordError> {
+        let config = &self.config;
+        let password = password.expose_secret();
+
+        if password.chars().count() < config.min_length {
+            return Err(PasswordError::TooShort(config.min_length));
+        }
+        if password.len() > config.max_length {
+            return Err(PasswordError::TooLong(config.max_length));
+        }
+
+        let required = [
+            (CharClass::Lowercase, config.require_lowercase),
+            (CharClass::Uppercase, config.require_uppercase),
+            (CharClass::Digit, config.require_digit),
+            (CharClass::Symbol, config.require_symbol),
+        ];
+        for (class, required) in required {
+            if required && !password.chars().any(|c| class.matches(c)) {
+                return Err(PasswordError::Missing(class));
+            }
+        }
+
+        if !config.allow_email && contains_email(password, email) {
+            return Err(PasswordError::ContainsEmail);
+        }
+
+        if self.is_breached(password) {
+            return Err(PasswordError::Breached);
+        }
+
+        let strength = strength::score(password, &self.dictionary);
+        if strength < config.min_strength {
+            return Err(PasswordError::TooWeak(strength));
+        }
+        Ok(())
+    }
+
+    fn is_breached(&self, password: &str) -> bool {
+        let hash = hex::encode_upper(Sha1::digest(password.as_bytes()));
+        let (prefix, suffix) = hash.split_at(HASH_PREFIX_LEN);
+
+        let bundled = self
+            .breached
+            .get(prefix)
+            .is_some_and(|suffixes| suffixes.contains(suffix));
+        bundled
+            || self
+                .ranges
+                .as_deref()
+                .is_some_and(|dir| in_range(dir, prefix, suffix))
+    }
+}
+
+/// Whether the range file in `dir` for `prefix` has `suffix` in it
+///
+/// A missing file means nothing with that prefix was breached, and one that can't be read is
+/// treated the same, so that a broken download doesn't stop anyone choosing a password
+fn in_range(dir: &Path, prefix: &str, suffix: &str) -> bool {
+    let path = dir.join(format!("{prefix}.txt"));
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return false,
+        Err(e) => {
+            warn!("couldn't read {}: {e}", path.display());
+            return false;
+        }
+    };
+
+    let found = lines(&contents).any(|line| {
+        let hash = line.split(':').next().unwrap_or_default();
+        hash.eq_ignore_ascii_case(suffix)
+    });
+    found
+}
+
+fn contains_email(password: &str, email: &Email) -> bool {
+    let password = password.to_lowercase();
+    let email = email.to_lowercase();
+    let local_part = email.split('@').next().unwrap_or_default();
+
+    password.contains(&email)
+        || (local_part.chars().count() >= MIN_EMAIL_PART_LEN && password.contains(local_part))
+}
+
+/// Parse `PREFIX:SUFFIX` lines of hex SHA-1 hashes
+fn add_hashes(breached: &mut HashMap<String, HashSet<String>>, contents: &str) -> Result<()> {
+    for line in lines(contents) {
+        let Some((prefix, suffix)) = line.split_once(':') else {
+            return Err(eyre!(
+                "breached password hashes need to be PREFIX:SUFFIX, got {line}"
+            ));
+        };
+        breached
+            .entry(prefix.to_uppercase())
+            .or_default()
+            .insert(suffix.to_uppercase());
+    }
+    Ok(())
+}
+
+/// Non-empty lines, without `#` comments
+fn lines(contents: &str) -> impl Iterator<Item = &str> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+}
+
+#[cfg(test)]
+mod tests {
+    use microtype::SecretMicrotype;
+    use uuid::Uuid;
+
+    use crate::model::types::mock::{DEFAULT_EMAIL, DEFAULT_PASSWORD};
+
+    use super::*;
+
+    fn check(config: PasswordConfig, password: &str) -> Result<(), PasswordError> {
+        let policy = PasswordPolicy::new(config).unwrap();
+        policy.check(&Password::new(password.into()), &DEFAULT_EMAIL)
+    }
+
+    #[test]
+    fn default_policy_allows_reasonable_passwords() {
+        let policy = PasswordPolicy::new(PasswordConfig::default()).unwrap();
+        policy.check(&DEFAULT_PASSWORD, &DEFAULT_EMAIL).unwrap();
+        check(PasswordConfig::default(), "staple horse battery").unwrap();
+    }
+
+    #[test]
+    fn enforces_length() {
+        let config = PasswordConfig::default;
+        assert_eq!(check(config(), "x7#q"), Err(PasswordError::TooShort(8)));
+        let long = "x7#q".repeat(20);
+        assert_eq!(check(config(), &long), Err(PasswordError::TooLong(72)));
+    }
+
+    #[test]
+    fn enforces_character_classes() {
+        let config = || PasswordConfig {
+            require_uppercase: true,
+            require_symbol: true,
+            ..PasswordConfig::default()
+        };
+        let missing = PasswordError::Missing(CharClass::Uppercase);
+        assert_eq!(check(config(), "staple horse battery"), Err(missing));
+        let missing = PasswordError::Missing(CharClass::Symbol);
+        assert_eq!(check(config(), "StapleHorseBattery"), Err(missing));
+        check(config(), "Staple horse battery").unwrap();
+    }
+
+    #[test]
+    fn rejects_weak_and_breached_passwords() {
+        let config = PasswordConfig::default;
+        assert_eq!(check(config(), "password1"), Err(PasswordError::Breached));
+        assert_eq!(
+            check(config(), "zzzzzzzzzz"),
+            Err(PasswordError::TooWeak(0))
+        );
+        assert_eq!(check(config(), "qwerty99"), Err(PasswordError::TooWeak(1)));
+    }
+
+    #[test]
+    fn rejects_the_email_address() {
+        let local_part = DEFAULT_EMAIL.split('@').next().unwrap().to_uppercase();
+        let password = format!("{local_part} is my password");
+        let err = check(PasswordConfig::default(), &password);
+        assert_eq!(err, Err(PasswordError::ContainsEmail));
+
+        let config = PasswordConfig {
+            allow_email: true,
+            ..PasswordConfig::default()
+        };
+        check(config, &password).unwrap();
+    }
+
+    #[test]
+    fn looks_up_more_breached_passwords_by_prefix() {
+        let hash = hex::encode_upper(Sha1::digest(b"staple horse battery"));
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        fs::create_dir(&dir).unwrap();
+        let range = format!("{}:3\n", hash[5..].to_lowercase());
+        fs::write(dir.join(format!("{}.txt", &hash[..5])), range).unwrap();
+
+        let config = || PasswordConfig {
+            breached_passwords: Some(dir.clone()),
+            ..PasswordConfig::default()
+        };
+        let breached = check(config(), "staple horse battery");
+        let other = check(config(), "horse battery staple");
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(breached, Err(PasswordError::Breached));
+        other.unwrap();
+
+        assert!(PasswordPolicy::new(config()).is_err());
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+
+use super::CharClass;
+
+/// The shortest common password or run worth guessing as one piece
+const MIN_PATTERN_LEN: usize = 3;
+
+/// A rough estimate of how hard a password is to guess, in the spirit of zxcvbn
+///
+/// This goes from 0, guessed in a few tries, to 4, very unlikely to be guessed, by zxcvbn's
+/// thresholds. The password is split into the pieces an attacker would guess separately: common
+/// passwords, runs like `aaaa` or `1234`, and otherwise single characters from the classes it uses.
+/// `dictionary` maps lowercase common passwords to how common they are, 0 being the most
+pub fn score(password: &str, dictionary: &HashMap<String, usize>) -> u8 {
+    match log10_guesses(password, dictionary) {
+        guesses if guesses < 3.0 => 0,
+        guesses if guesses < 6.0 => 1,
+        guesses if guesses < 8.0 => 2,
+        guesses if guesses < 10.0 => 3,
+        _ => 4,
+    }
+}
+
+fn log10_guesses(password: &str, dictionary: &HashMap<String, usize>) -> f64 {
+    let chars: Vec<char> = password.to_lowercase().chars().collect();
+    let per_char = f64::from(cardinality(password)).log10();
+
+    let mut guesses = 0.0;
+    let mut i = 0;
+    while i < chars.len() {
+        if let Some((len, rank)) = longest_word(&chars[i..], dictionary) {
+            // at least a few guesses, for where in the password the word goes
+            guesses += ((rank + 1) as f64).log10().max(1.0);
+            i += len;
+        } else if let Some(len) = run_length(&chars[i..]) {
+            guesses += per_char + (len as f64).log10();
+            i += len;
+        } else {
+            guesses += per_char;
+            i += 1;
+        }
+    }
+    guesses
+}
+
+/// The longest common password `chars` starts with, along with its rank
+fn longest_word(chars: &[char], dictionary: &HashMap<String, usize>) -> Option<(usize, usize)> {
+    (MIN_PATTERN_LEN..=chars.len()).rev().find_map(|len| {
+        let word: String = chars[..len].iter().collect();
+        dictionary.get(&word).map(|rank| (len, *rank))
+    })
+}
+
+/// How many characters from the start repeat, or count up or down, one at a time
+fn run_length(chars: &[char]) -> Option<usize> {
+    let step = |pair: &[char]| i64::from(u32::from(pair[1])) - i64::from(u32::from(pair[0]));
+
+    let first = step(chars.get(..2)?);
+    if first.abs() > 1 {
+        return None;
+    }
+    let len = 1 + chars
+        .windows(2)
+        .take_while(|pair| step(pair) == first)
+        .count();
+    (len >= MIN_PATTERN_LEN).then_some(len)
+}
+
+/// How many characters each one could have been, going by which classes the password uses
+fn cardinality(password: &str) -> u32 {
+    CharClass::ALL
+        .iter()
+        .filter(|class| password.chars().any(|c| class.matches(c)))
+        .map(|class| class.size())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dictionary() -> HashMap<String, usize> {
+        ["password", "qwerty", "dragon"]
+            .into_iter()
+            .enumerate()
+            .map(|(rank, word)| (word.to_string(), rank))
+            .collect()
+    }
+
+    #[test]
+    fn patterns_are_weak() {
+        for password in [
+            "",
+            "password",
+            "Password1",
+            "aaaaaaaaaaaa",
+            "12345678",
+            "qwerty99",
+        ] {
+            assert!(score(password, &dictionary()) < 2, "{password}");
+        }
+    }
+
+    #[test]
+    fn random_looking_passwords_are_strong() {
+        for password in ["correcthorsebattery", "Xk7#mQ2!vR9p", "bad password"] {
+            assert!(score(password, &dictionary()) >= 3, "{password}");
+        }
+    }
+
+    #[test]
+    fn finds_runs() {
+        let chars: Vec<char> = "4321x".chars().collect();
+        assert_eq!(run_length(&chars), Some(4));
+        let chars: Vec<char> = "zzz".chars().collect();
+        assert_eq!(run_length(&chars), Some(3));
+        let chars: Vec<char> = "ace".chars().collect();
+        assert_eq!(run_length(&chars), None);
+    }
+}
@@ -0,0 +1,325 @@
+use std::sync::Arc;
+
+use futures::FutureExt;
+use tracing::{field, Span};
+use url::Url;
+
+use crate::{
+    config::Config,
+    db::{sql::DbError, Db},
+    model::{
+        audit::AuditAction,
+        password_reset::PasswordReset,
+        types::{Email, Password, Token},
+    },
+    routing::{client::ClientInfo, errors::ApiError},
+};
+
+use super::{
+    audit::AuditService,
+    auth::AuthService,
+    hasher::hash_token,
+    limiter::{LimitKey, Limiter},
+    mailer::{Mailer, Message},
+    random::Random,
+    time::Time,
+    Common,
+};
+
+/// Choosing a new password for an account whose password was forgotten, by following a
+/// single-use link emailed to its address
+///
+/// The new password is held to the same policy as one chosen on sign up, and choosing it signs
+/// the account out everywhere. Links sent count against the same limits as sign in attempts
+#[derive(Debug, Clone)]
+pub struct PasswordResetService {
+    time: Arc<dyn Time>,
+    random: Arc<dyn Random>,
+    db: Arc<dyn Db>,
+    mailer: Arc<dyn Mailer>,
+    auth: AuthService,
+    limiter: Limiter,
+    audit: AuditService,
+    config: Arc<Config>,
+}
+
+impl PasswordResetService {
+    pub fn new(
+        common: Common,
+        mailer: Arc<dyn Mailer>,
+        auth: AuthService,
+        limiter: Limiter,
+    ) -> Self {
+        let Common {
+            time,
+            random,
+            db,
+            audit,
+            config,
+        } = common;
+
+        Self {
+            time,
+            random,
+            db,
+            mailer,
+            auth,
+            limiter,
+            audit,
+            config,
+        }
+    }
+
+    /// Email a reset link to the account at `email`, replacing any that was sent before
+    ///
+    /// Nothing is sent to an address nobody has, but that isn't reported, so that this can't be
+    /// used to find out who has an account
+    #[instrument(skip_all, fields(email = %email.redacted()))]
+    pub async fn send(&self, email: Email, client: &ClientInfo) -> Result<(), ApiError> {
+        let keys = LimitKey::for_attempt(Some(&email), client);
+        self.limiter.check(&keys)?;
+        self.limiter.record(&keys);
+
+        let Some(user) = self.db.user_by_email(email).await? else {
+            return Ok(());
+        };
+
+        let token = self.random.token();
+        let now = self.time.now();
+        let reset = PasswordReset {
+            token_hash: hash_token(&token),
+            user_id: user.id,
+            created_at: now,
+            expires_at: now + self.config.accounts.password_reset_ttl(),
+        };
+        self.db
+            .transaction(|tx| {
+                let reset = reset.clone();
+                async move {
+                    tx.delete_password_resets_for_user(reset.user_id).await?;
+                    tx.create_password_reset(reset).await
+                }
+                .boxed()
+            })
+            .await?;
+
+        self.mailer
+            .send(Message {
+                to: user.email,
+                subject: "Reset your password".into(),
+                body: format!(
+                    "If you didn't ask to reset your password, you can ignore this email. \
+                     Otherwise, follow this link in the next {} minutes to choose a new one: {}",
+                    self.config.accounts.password_reset_ttl().num_minutes(),
+                    self.link(&token)
+                ),
+            })
+            .await?;
+
+        self.audit
+            .record(
+                AuditAction::PasswordResetRequested,
+                None,
+                Some(user.id),
+                client,
+            )
+            .await;
+        Ok(())
+    }
+
+    /// Set the password of the account a link was sent to, which works once, and sign it out
+    /// everywhere
+    #[instrument(skip_all, fields(user_id = field::Empty))]
+    pub async fn reset(
+        &self,
+        token: Token,
+        new_password: Password,
+        client: &ClientInfo,
+    ) -> Result<(), ApiError> {
+        let keys = LimitKey::for_attempt(None, client);
+        self.limiter.check(&keys)?;
+
+        let result = self.check_reset(token, new_password, client).await;
+        if let Err(ApiError::Auth) = result {
+            self.limiter.record(&keys);
+        }
+        result
+    }
+
+    async fn check_reset(
+        &self,
+        token: Token,
+        new_password: Password,
+        client: &ClientInfo,
+    ) -> Result<(), ApiError> {
+        let token_hash = hash_token(&token);
+        let reset = self
+            .db
+            .password_reset_by_token(token_hash.clone())
+            .await?
+            .filter(|reset| reset.expires_at > self.time.now());
+        let Some(reset) = reset else {
+            return Err(ApiError::Auth);
+        };
+        let user_id = reset.user_id;
+        Span::current().record("user_id", field::display(user_id.0));
+
+        // the account was deleted after the link was sent
+        let user = self.db.user_by_id(user_id).await?.ok_or(ApiError::Auth)?;
+        let password_hash = self.auth.hash_new_password(&new_password, &user.email)?;
+
+        self.db
+            .transaction(|tx| {
+                let (token_hash, password_hash) = (token_hash.clone(), password_hash.clone());
+                async move {
+                    match tx.delete_password_reset(token_hash).await {
+                        Ok(()) => {}
+                        Err(DbError::RowsModified { .. }) => return Err(ApiError::Auth),
+                        Err(e) => return Err(e.into()),
+                    }
+                    tx.update_password(user_id, password_hash).await?;
+                    tx.delete_sessions_for_user(user_id).await?;
+                    Ok(())
+                }
+                .boxed()
+            })
+            .await?;
+
+        self.audit
+            .record(
+                AuditAction::PasswordReset,
+                Some(user_id),
+                Some(user_id),
+                client,
+            )
+            .await;
+        Ok(())
+    }
+
+    /// Remove links that have expired, returning how many there were
+    #[instrument(skip_all, fields(purged = field::Empty))]
+    pub async fn purge_expired(&self) -> Result<usize, DbError> {
+        let purged = self
+            .db
+            .delete_expired_password_resets(self.time.now())
+            .await?;
+        Span::current().record("purged", purged);
+        Ok(purged)
+    }
+
+    fn link(&self, token: &Token) -> Url {
+        self.config.email.link("password-reset", token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use microtype::SecretMicrotype;
+
+    use crate::{
+        model::{
+            password_reset::PasswordReset,
+            types::{
+                mock::{DEFAULT_EMAIL, DEFAULT_PASSWORD, DEFAULT_USER_ID},
+                Email, Password, Token,
+            },
+        },
+        routing::{client::ClientInfo, errors::ApiError},
+        state::{
+            hasher::hash_token, password::PasswordError, time::mock::DEFAULT_DATE_TIME, Services,
+        },
+        testing::{login, test_data::TEST_DATA, test_services_with},
+    };
+
+    fn new_password() -> Password {
+        Password::new("staple horse battery".into())
+    }
+
+    async fn send(services: &Services) -> Token {
+        services
+            .password_reset
+            .send(DEFAULT_EMAIL.clone(), &ClientInfo::default())
+            .await
+            .unwrap();
+        services.mailer.as_mock().token_sent_to(&DEFAULT_EMAIL)
+    }
+
+    async fn reset(services: &Services, token: Token, password: Password) -> Result<(), ApiError> {
+        services
+            .password_reset
+            .reset(token, password, &ClientInfo::default())
+            .await
+    }
+
+    #[tokio::test]
+    async fn resets_password_once_and_signs_out_everywhere() {
+        let services = test_services_with(TEST_DATA.clone());
+        let client = ClientInfo::default();
+        let jwt = login(&services, &DEFAULT_EMAIL).await;
+        let token = send(&services).await;
+
+        reset(&services, token.clone(), new_password())
+            .await
+            .unwrap();
+        let again = reset(&services, token, new_password()).await;
+        assert!(matches!(again, Err(ApiError::Auth)));
+
+        assert!(services.auth.authenticate(&jwt).await.is_err());
+        let old = services
+            .auth
+            .login(DEFAULT_EMAIL.clone(), DEFAULT_PASSWORD.clone(), &client)
+            .await;
+        assert!(matches!(old, Err(ApiError::Auth)));
+        let jwt = services
+            .auth
+            .login(DEFAULT_EMAIL.clone(), new_password(), &client)
+            .await
+            .unwrap();
+        let claims = services.auth.authenticate(&jwt).await.unwrap();
+        assert_eq!(claims.subject, *DEFAULT_USER_ID);
+    }
+
+    #[tokio::test]
+    async fn new_password_needs_to_be_a_good_one() {
+        let services = test_services_with(TEST_DATA.clone());
+        let token = send(&services).await;
+
+        let result = reset(&services, token.clone(), Password::new("password1".into())).await;
+        assert!(matches!(
+            result,
+            Err(ApiError::Password(PasswordError::Breached))
+        ));
+
+        // the link still works for a better one
+        reset(&services, token, new_password()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn expired_link_does_not_reset() {
+        let services = test_services_with(TEST_DATA.clone());
+        let token = Token::new("expired".into());
+        let reset_link = PasswordReset {
+            token_hash: hash_token(&token),
+            user_id: *DEFAULT_USER_ID,
+            created_at: *DEFAULT_DATE_TIME - Duration::hours(1),
+            expires_at: *DEFAULT_DATE_TIME,
+        };
+        services.db.create_password_reset(reset_link).await.unwrap();
+
+        let result = reset(&services, token, new_password()).await;
+        assert!(matches!(result, Err(ApiError::Auth)));
+    }
+
+    #[tokio::test]
+    async fn sends_nothing_to_unknown_address() {
+        let services = test_services_with(TEST_DATA.clone());
+
+        services
+            .password_reset
+            .send(Email("someone@email.com".into()), &ClientInfo::default())
+            .await
+            .unwrap();
+        assert!(services.mailer.as_mock().sent().is_empty());
+    }
+}
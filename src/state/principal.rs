@@ -0,0 +1,89 @@
+use axum::{extract::FromRequestParts, http::request::Parts};
+use microtype::SecretMicrotype;
+
+use crate::{
+    model::{
+        oauth::{Scope, Scopes},
+        types::{ApiKeySecret, UserId},
+    },
+    routing::errors::ApiError,
+    state::Services,
+};
+
+use super::{
+    api_key::API_KEY_PREFIX,
+    jwt::{
+        claims::{Claims, Validated},
+        from_request::request_token,
+        Jwt,
+    },
+};
+
+/// Whoever a request was made by, whether they sent a JWT or an API key
+///
+/// Routes for what scripts and CI have a use for, such as the profile and organizations, take
+/// this rather than `Claims`, and check its scope. Those that manage the account itself, its
+/// sessions, password, email address and keys, still take `Claims`, so that a leaked key can't
+/// be used to sign in elsewhere, lock its owner out or make more keys
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub user_id: UserId,
+    /// What the credential lets its bearer do, or `None` if it isn't restricted
+    scope: Option<Scopes>,
+}
+
+impl Principal {
+    /// Someone with an API key, who can only do what it was made for
+    pub(super) fn with_scope(user_id: UserId, scope: Scopes) -> Self {
+        Self {
+            user_id,
+            scope: Some(scope),
+        }
+    }
+
+    /// Whether the credential lets its bearer do what `scope` allows
+    pub fn allows(&self, scope: Scope) -> bool {
+        match &self.scope {
+            Some(scopes) => scopes.contains(scope),
+            None => true,
+        }
+    }
+
+    /// Fail with `Auth` unless the credential lets its bearer do what `scope` allows
+    pub fn require_scope(&self, scope: Scope) -> Result<(), ApiError> {
+        match self.allows(scope) {
+            true => Ok(()),
+            false => Err(ApiError::Auth),
+        }
+    }
+}
+
+impl From<Claims<Validated>> for Principal {
+    fn from(claims: Claims<Validated>) -> Self {
+        Self {
+            user_id: claims.subject,
+            scope: claims.scope,
+        }
+    }
+}
+
+#[axum::async_trait]
+impl FromRequestParts<Services> for Principal {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        services: &Services,
+    ) -> Result<Self, Self::Rejection> {
+        let token = request_token(parts, services).await?;
+
+        // a JWT's header is always encoded JSON, so starts with `eyJ` and can't be mistaken for a key
+        if token.starts_with(API_KEY_PREFIX) {
+            let key = ApiKeySecret::new(token);
+            return services.api_keys.authenticate(&key).await;
+        }
+
+        let claims = services.auth.authenticate_session(&Jwt::new(token)).await?;
+        Ok(claims.into())
+    }
+}
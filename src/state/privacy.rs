@@ -0,0 +1,391 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use futures::FutureExt;
+use serde::Serialize;
+
+use crate::{
+    db::{audit::AuditFilter, Db},
+    model::{
+        api_key::ApiKey,
+        audit::{AuditAction, AuditEvent},
+        email_change::EmailChange,
+        identity::UserIdentity,
+        magic_link::MagicLink,
+        oauth::OAuthConsent,
+        organization::{Membership, OrgInvitation},
+        session::Session,
+        types::UserId,
+        user::User,
+    },
+    routing::{client::ClientInfo, errors::ApiError},
+    state::jwt::claims::{Claims, Validated},
+};
+
+use super::{
+    audit::AuditService, auth::not_found_if_unmodified,
+    organization::delete_orphaned_organizations, time::Time,
+};
+
+/// Everything stored about a user
+#[derive(Debug, Clone)]
+pub struct UserExport {
+    pub exported_at: DateTime<Utc>,
+    pub user: User,
+    pub sessions: Vec<Session>,
+    pub email_change: Option<EmailChange>,
+    pub identities: Vec<UserIdentity>,
+    pub oauth_consents: Vec<OAuthConsent>,
+    pub api_keys: Vec<ApiKey>,
+    /// The sign in link out for the user's address, if there is one
+    pub magic_link: Option<MagicLink>,
+    pub org_memberships: Vec<Membership>,
+    /// Invitations out to the user's address that they're yet to accept
+    pub org_invitations: Vec<OrgInvitation>,
+    /// Every event the user is the actor or subject of, most recent first
+    pub audit_events: Vec<AuditEvent>,
+}
+
+/// What erasing a user removed, by table
+#[derive(Debug, Clone, Serialize)]
+pub struct ErasureReport {
+    pub user_id: UserId,
+    pub erased_at: DateTime<Utc>,
+    pub users: usize,
+    pub sessions: usize,
+    pub email_changes: usize,
+    pub identities: usize,
+    pub oauth_consents: usize,
+    pub oauth_refresh_tokens: usize,
+    pub api_keys: usize,
+    pub magic_links: usize,
+    /// Those the user was the only owner of, which would otherwise have been left with none
+    pub organizations: usize,
+    pub org_memberships: usize,
+    pub org_invitations: usize,
+    /// Kept, but with the user and their client details taken out
+    pub audit_events_anonymized: usize,
+}
+
+/// Answering data subject requests, for a copy of a user's data or for it to be erased
+#[derive(Debug, Clone)]
+pub struct PrivacyService {
+    time: Arc<dyn Time>,
+    db: Arc<dyn Db>,
+    audit: AuditService,
+}
+
+impl PrivacyService {
+    pub fn new(time: Arc<dyn Time>, db: Arc<dyn Db>, audit: AuditService) -> Self {
+        Self { time, db, audit }
+    }
+
+    #[instrument(skip_all, fields(user_id = %claims.subject.0))]
+    pub async fn export(
+        &self,
+        claims: &Claims<Validated>,
+        client: &ClientInfo,
+    ) -> Result<UserExport, ApiError> {
+        let user_id = claims.subject;
+        let user = self
+            .db
+            .user_by_id(user_id)
+            .await?
+            .ok_or(ApiError::NotFound)?;
+
+        // all of them, rather than the most an admin query can ask for
+        let filter = AuditFilter {
+            user_id: Some(user_id),
+            limit: i64::MAX,
+            ..AuditFilter::default()
+        };
+        let magic_link = self.db.magic_link_for_email(user.email.clone()).await?;
+        let org_invitations = self
+            .db
+            .org_invitations_for_email(user.email.clone())
+            .await?;
+        let export = UserExport {
+            exported_at: self.time.now(),
+            user,
+            sessions: self.db.sessions_for_user(user_id).await?,
+            email_change: self.db.email_change_for_user(user_id).await?,
+            identities: self.db.identities_for_user(user_id).await?,
+            oauth_consents: self.db.consents_for_user(user_id).await?,
+            api_keys: self.db.api_keys_for_user(user_id).await?,
+            magic_link,
+            org_memberships: self.db.memberships_for_user(user_id).await?,
+            org_invitations,
+            audit_events: self.db.audit_events(filter).await?,
+        };
+
+        self.audit
+            .record(
+                AuditAction::DataExported,
+                Some(user_id),
+                Some(user_id),
+                client,
+            )
+            .await;
+        Ok(export)
+    }
+
+    /// Remove every row tied to a user on behalf of `admin`, straight away and whether or not
+    /// they've been deleted, anonymizing the audit events that mention them
+    ///
+    /// Organizations the user is the only owner of are deleted too, since nobody else could run them
+    #[instrument(skip_all, fields(user_id = %user_id.0, admin_id = %admin.subject.0))]
+    pub async fn erase_user(
+        &self,
+        admin: &Claims<Validated>,
+        user_id: UserId,
+        client: &ClientInfo,
+    ) -> Result<ErasureReport, ApiError> {
+        let erased_at = self.time.now();
+        let result = self
+            .db
+            .transaction(|tx| {
+                async move {
+                    // links and invitations are sent to addresses rather than users, so go by the
+                    // user's address
+                    let email = tx
+                        .user_by_id_including_deleted(user_id)
+                        .await?
+                        .map(|user| user.email);
+
+                    let sessions = tx.delete_sessions_for_user(user_id).await?;
+                    let email_changes = tx.delete_email_changes_for_user(user_id).await?;
+                    let identities = tx.delete_identities_for_user(user_id).await?;
+                    let oauth_consents = tx.delete_consents_for_user(user_id).await?;
+                    let oauth_refresh_tokens = tx.delete_refresh_tokens_for_user(user_id).await?;
+                    let api_keys = tx.delete_api_keys_for_user(user_id).await?;
+                    let (magic_links, org_invitations) = match email {
+                        Some(email) => (
+                            tx.delete_magic_links_for_email(email.clone()).await?,
+                            tx.delete_org_invitations_for_email(email).await?,
+                        ),
+                        None => (0, 0),
+                    };
+                    let organizations = delete_orphaned_organizations(tx, &[user_id]).await?;
+                    let org_memberships = tx.delete_memberships_for_user(user_id).await?;
+                    let audit_events_anonymized = tx.anonymize_audit_events(user_id).await?;
+                    tx.erase_user(user_id).await?;
+
+                    let report = ErasureReport {
+                        user_id,
+                        erased_at,
+                        users: 1,
+                        sessions,
+                        email_changes,
+                        identities,
+                        oauth_consents,
+                        oauth_refresh_tokens,
+                        api_keys,
+                        magic_links,
+                        organizations: organizations.len(),
+                        org_memberships,
+                        org_invitations,
+                        audit_events_anonymized,
+                    };
+                    Ok((report, organizations))
+                }
+                .boxed()
+            })
+            .await;
+        let (report, organizations) = not_found_if_unmodified(result)?;
+        info!(?report, "erased user");
+
+        for _ in organizations {
+            self.audit
+                .record(
+                    AuditAction::OrganizationDeleted,
+                    Some(admin.subject),
+                    None,
+                    client,
+                )
+                .await;
+        }
+        self.audit
+            .record(AuditAction::UserErased, Some(admin.subject), None, client)
+            .await;
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use crate::{
+        db::audit::AuditFilter,
+        model::{
+            audit::AuditAction,
+            organization::OrgRole,
+            types::{
+                mock::{ADMIN_EMAIL, DEFAULT_EMAIL, DEFAULT_PASSWORD, DEFAULT_USER_ID},
+                Email,
+            },
+        },
+        routing::{client::ClientInfo, errors::ApiError},
+        state::{
+            jwt::claims::{Claims, Validated},
+            time::mock::DEFAULT_DATE_TIME,
+            Services,
+        },
+        testing::{sign_in, test_data::TEST_DATA, test_services_with},
+    };
+
+    /// Sign in as the default user, and start changing their email so there's one pending
+    async fn default_user_with_data(services: &Services) -> Claims<Validated> {
+        let claims = sign_in(services, &DEFAULT_EMAIL).await;
+        services
+            .email_change
+            .start(
+                &claims,
+                Email("new@email.com".into()),
+                DEFAULT_PASSWORD.clone(),
+                &ClientInfo::default(),
+            )
+            .await
+            .unwrap();
+        claims
+    }
+
+    /// Have the admin invite the default user to an organization of theirs
+    async fn invite_default_user(services: &Services) {
+        let admin = sign_in(services, &ADMIN_EMAIL).await.into();
+        let org = services
+            .org
+            .create(&admin, "org".into(), &ClientInfo::default())
+            .await
+            .unwrap();
+        services
+            .org
+            .invite(
+                &admin,
+                org.organization.id,
+                DEFAULT_EMAIL.clone(),
+                OrgRole::Member,
+                &ClientInfo::default(),
+            )
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn export_includes_everything() {
+        let services = test_services_with(TEST_DATA.clone());
+        let claims = default_user_with_data(&services).await;
+
+        services
+            .api_keys
+            .create(
+                &claims,
+                "ci".into(),
+                "api".parse().unwrap(),
+                *DEFAULT_DATE_TIME + Duration::days(1),
+                &ClientInfo::default(),
+            )
+            .await
+            .unwrap();
+        services
+            .magic_link
+            .send(DEFAULT_EMAIL.clone(), &ClientInfo::default())
+            .await
+            .unwrap();
+        invite_default_user(&services).await;
+
+        let export = services
+            .privacy
+            .export(&claims, &ClientInfo::default())
+            .await
+            .unwrap();
+
+        assert_eq!(export.user.id, *DEFAULT_USER_ID);
+        assert_eq!(export.sessions.len(), 1);
+        assert_eq!(export.email_change.unwrap().new_email.0, "new@email.com");
+        assert_eq!(export.api_keys[0].name, "ci");
+        assert_eq!(export.magic_link.unwrap().email, *DEFAULT_EMAIL);
+        assert_eq!(export.org_invitations.len(), 1);
+        let actions: Vec<_> = export
+            .audit_events
+            .iter()
+            .map(|event| event.action)
+            .collect();
+        assert!(actions.contains(&AuditAction::Login));
+        assert!(actions.contains(&AuditAction::EmailChangeRequested));
+
+        let filter = AuditFilter {
+            action: Some(AuditAction::DataExported),
+            limit: 10,
+            ..AuditFilter::default()
+        };
+        assert_eq!(services.audit.events(filter).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn erasure_removes_user_everywhere() {
+        let services = test_services_with(TEST_DATA.clone());
+        let admin = sign_in(&services, &ADMIN_EMAIL).await;
+        default_user_with_data(&services).await;
+        services
+            .magic_link
+            .send(DEFAULT_EMAIL.clone(), &ClientInfo::default())
+            .await
+            .unwrap();
+        invite_default_user(&services).await;
+
+        let report = services
+            .privacy
+            .erase_user(&admin, *DEFAULT_USER_ID, &ClientInfo::default())
+            .await
+            .unwrap();
+
+        assert_eq!(report.user_id, *DEFAULT_USER_ID);
+        assert_eq!(report.users, 1);
+        assert_eq!(report.sessions, 1);
+        assert_eq!(report.email_changes, 1);
+        assert_eq!(report.magic_links, 1);
+        assert_eq!(report.org_invitations, 1);
+        assert_eq!(report.audit_events_anonymized, 4);
+        assert!(services
+            .db
+            .magic_link_for_email(DEFAULT_EMAIL.clone())
+            .await
+            .unwrap()
+            .is_none());
+        assert!(services
+            .db
+            .org_invitations_for_email(DEFAULT_EMAIL.clone())
+            .await
+            .unwrap()
+            .is_empty());
+
+        assert!(services
+            .db
+            .user_by_id(*DEFAULT_USER_ID)
+            .await
+            .unwrap()
+            .is_none());
+        let mentions = AuditFilter {
+            user_id: Some(*DEFAULT_USER_ID),
+            limit: 10,
+            ..AuditFilter::default()
+        };
+        assert!(services.audit.events(mentions).await.unwrap().is_empty());
+
+        let erasures = AuditFilter {
+            action: Some(AuditAction::UserErased),
+            limit: 10,
+            ..AuditFilter::default()
+        };
+        let erasures = services.audit.events(erasures).await.unwrap();
+        assert_eq!(erasures.len(), 1);
+        assert_eq!(erasures[0].subject_id, None);
+
+        let again = services
+            .privacy
+            .erase_user(&admin, *DEFAULT_USER_ID, &ClientInfo::default())
+            .await;
+        assert!(matches!(again, Err(ApiError::NotFound)));
+    }
+}
@@ -0,0 +1,59 @@
+use std::sync::Arc;
+
+use crate::{
+    db::Db,
+    model::{
+        oauth::Scope,
+        user::{ProfileChanges, User},
+    },
+    routing::errors::ApiError,
+};
+
+use super::{auth::not_found_if_unmodified, principal::Principal};
+
+/// The signed in user's own account
+#[derive(Debug, Clone)]
+pub struct ProfileService {
+    db: Arc<dyn Db>,
+}
+
+impl ProfileService {
+    pub fn new(db: Arc<dyn Db>) -> Self {
+        Self { db }
+    }
+
+    #[instrument(skip_all, fields(user_id = %principal.user_id.0))]
+    pub async fn profile(&self, principal: &Principal) -> Result<User, ApiError> {
+        principal.require_scope(Scope::Profile)?;
+
+        self.db
+            .user_by_id(principal.user_id)
+            .await?
+            .ok_or(ApiError::NotFound)
+    }
+
+    /// Apply `changes`, returning the profile as it is afterwards
+    #[instrument(skip_all, fields(user_id = %principal.user_id.0))]
+    pub async fn update_profile(
+        &self,
+        principal: &Principal,
+        changes: ProfileChanges,
+    ) -> Result<User, ApiError> {
+        principal.require_scope(Scope::Api)?;
+        changes
+            .validate()
+            .map_err(|field| ApiError::Invalid { field })?;
+
+        if !changes.is_empty() {
+            let result = self.db.update_profile(principal.user_id, changes).await;
+            not_found_if_unmodified(result)?;
+        }
+
+        // `updated_at` was set by the database, so read it back from somewhere that's seen it
+        self.db
+            .read_your_writes()
+            .user_by_id(principal.user_id)
+            .await?
+            .ok_or(ApiError::NotFound)
+    }
+}
@@ -1,9 +1,9 @@
 use std::fmt::Debug;
 
-use microtype::Microtype;
+use microtype::{Microtype, SecretMicrotype};
 use uuid::Uuid;
 
-use crate::model::types::UserId;
+use crate::model::types::{Token, UserId};
 
 pub trait Random: Debug + Send + Sync + 'static {
     fn uuid(&self) -> Uuid;
@@ -11,6 +11,11 @@ pub trait Random: Debug + Send + Sync + 'static {
     fn user_id(&self) -> UserId {
         UserId::new(self.uuid())
     }
+
+    /// An unguessable secret for a one-time link
+    fn token(&self) -> Token {
+        Token::new(format!("{}{}", self.uuid().simple(), self.uuid().simple()))
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -0,0 +1,199 @@
+use axum::http::HeaderMap;
+use color_eyre::Result;
+use opentelemetry::{
+    propagation::{Extractor, TextMapPropagator},
+    trace::TracerProvider as _,
+    KeyValue,
+};
+use opentelemetry_otlp::{Protocol, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    runtime,
+    trace::{Sampler, TracerProvider},
+    Resource,
+};
+use tracing::{Span, Subscriber};
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::{fmt, prelude::*, registry::LookupSpan, EnvFilter};
+
+use crate::config::{Config, LogFormat, OtlpConfig};
+
+/// Handle to the installed tracing pipeline, which must be shut down to flush pending spans
+#[derive(Debug)]
+pub struct Telemetry {
+    provider: Option<TracerProvider>,
+}
+
+impl Telemetry {
+    pub fn shutdown(self) {
+        if let Some(provider) = self.provider {
+            if let Err(e) = provider.shutdown() {
+                error!("failed to shut down trace exporter: {e}");
+            }
+        }
+    }
+}
+
+/// Install the global tracing subscriber
+///
+/// `RUST_LOG` takes precedence over the configured filter, so verbosity can be raised for a
+/// single run without editing the config file
+pub fn init(config: &Config) -> Result<Telemetry> {
+    let filter = match EnvFilter::try_from_default_env() {
+        Ok(filter) => filter,
+        Err(_) => EnvFilter::try_new(&config.log.filter)?,
+    };
+
+    let (text, json) = match config.log.format {
+        LogFormat::Text => (Some(fmt::layer()), None),
+        LogFormat::Json => (
+            None,
+            Some(
+                fmt::layer()
+                    .json()
+                    .with_current_span(true)
+                    .with_span_list(true),
+            ),
+        ),
+    };
+
+    let provider = config.otlp.as_ref().map(tracer_provider).transpose()?;
+    let otel = provider.as_ref().map(otel_layer);
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(text)
+        .with(json)
+        .with(otel)
+        .try_init()?;
+
+    Ok(Telemetry { provider })
+}
+
+/// Build a tracer provider that batches spans and sends them to an OTLP/HTTP collector
+pub fn tracer_provider(config: &OtlpConfig) -> Result<TracerProvider> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_protocol(Protocol::HttpJson)
+        .with_endpoint(&config.endpoint)
+        .build()?;
+
+    let sampler = Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sampling_ratio)));
+    let resource = Resource::new([KeyValue::new("service.name", config.service_name.clone())]);
+
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_sampler(sampler)
+        .with_resource(resource)
+        .build();
+
+    Ok(provider)
+}
+
+fn otel_layer<S>(
+    provider: &TracerProvider,
+) -> OpenTelemetryLayer<S, opentelemetry_sdk::trace::Tracer>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let tracer = provider.tracer(env!("CARGO_PKG_NAME"));
+    tracing_opentelemetry::layer().with_tracer(tracer)
+}
+
+/// Continue a trace started by the caller, if they sent a W3C `traceparent` header
+pub fn set_remote_parent(span: &Span, headers: &HeaderMap) {
+    let context = TraceContextPropagator::new().extract(&HeaderExtractor(headers));
+    span.set_parent(context);
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{SocketAddr, TcpListener},
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{
+        body::{Body, Bytes},
+        extract::State,
+        http::Request,
+        routing::post,
+        Router, Server,
+    };
+    use tower::ServiceExt;
+    use tracing::instrument::WithSubscriber;
+
+    use crate::{make_app, testing::test_services};
+
+    use super::*;
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+    const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    type Received = Arc<Mutex<Vec<String>>>;
+
+    /// Stand-in for an OTLP collector, which records the body of every export request
+    fn collector() -> (String, Received) {
+        async fn traces(State(received): State<Received>, body: Bytes) {
+            let body = String::from_utf8(body.to_vec()).unwrap();
+            received.lock().unwrap().push(body);
+        }
+
+        let received = Received::default();
+        let router = Router::new()
+            .route("/v1/traces", post(traces))
+            .with_state(received.clone());
+
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Server::from_tcp(listener)
+            .unwrap()
+            .serve(router.into_make_service());
+        tokio::spawn(server);
+
+        (format!("http://{addr}/v1/traces"), received)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn exports_request_span_with_remote_parent() {
+        let (endpoint, received) = collector();
+        let config = OtlpConfig {
+            endpoint,
+            sampling_ratio: 1.0,
+            service_name: "test-service".into(),
+        };
+        let provider = tracer_provider(&config).unwrap();
+        let subscriber = tracing_subscriber::registry().with(otel_layer(&provider));
+
+        let request = Request::get("/health")
+            .header("traceparent", TRACEPARENT)
+            .body(Body::empty())
+            .unwrap();
+        make_app(test_services())
+            .oneshot(request)
+            .with_subscriber(subscriber)
+            .await
+            .unwrap();
+
+        tokio::task::spawn_blocking(move || provider.shutdown())
+            .await
+            .unwrap()
+            .unwrap();
+
+        let received = received.lock().unwrap().concat();
+        assert!(received.contains("test-service"));
+        assert!(received.contains(TRACE_ID));
+    }
+}
@@ -1,16 +1,32 @@
 use std::sync::Arc;
 
 use axum_test_helper::TestClient;
+use microtype::secrecy::ExposeSecret;
 
 mod serde;
+pub mod spans;
 pub mod test_data;
 
 use crate::{
     config::testing::test_config,
+    db::audit::AuditFilter,
     make_app,
-    model::user::User,
+    model::{
+        audit::{AuditAction, AuditEvent},
+        types::{mock::DEFAULT_PASSWORD, Email},
+        user::User,
+    },
+    routing::client::ClientInfo,
     state::{
-        hasher::BcryptHasher, make_services, random::mock::MockRandom, time::mock::MockTime,
+        hasher::BcryptHasher,
+        jwt::{
+            claims::{Claims, Validated},
+            Jwt,
+        },
+        mailer::mock::MockMailer,
+        make_services,
+        random::mock::MockRandom,
+        time::mock::MockTime,
         Dependencies, Services,
     },
 };
@@ -20,6 +36,7 @@ pub fn test_deps() -> Dependencies {
         time: Arc::new(MockTime::default()),
         random: Arc::new(MockRandom::new()),
         hasher: Arc::new(BcryptHasher), // uses lower cost modifier
+        mailer: Arc::new(MockMailer::default()),
         config: Arc::new(test_config()),
     }
 }
@@ -48,11 +65,14 @@ pub struct TestData {
     pub users: Vec<User>,
 }
 
-pub fn test_services_with(TestData { users }: TestData) -> Services {
-    let services = test_services();
+pub fn test_services_with(test_data: TestData) -> Services {
+    test_services_from(test_deps(), test_data)
+}
+
+pub fn test_services_from(deps: Dependencies, TestData { users }: TestData) -> Services {
+    let services = make_services(deps).unwrap();
 
-    let db = services.db.as_mock();
-    db.users.lock().unwrap().extend(users);
+    services.db.as_memory().seed(users);
 
     services
 }
@@ -61,3 +81,44 @@ pub fn test_client_with(test_data: TestData) -> (TestClient, Services) {
     let services = test_services_with(test_data);
     test_client_from_state(services)
 }
+
+pub fn test_client_from(deps: Dependencies, test_data: TestData) -> (TestClient, Services) {
+    let services = test_services_from(deps, test_data);
+    test_client_from_state(services)
+}
+
+/// Sign in as `email`, with the password every user in the test data has
+pub async fn login(services: &Services, email: &Email) -> Jwt {
+    let client = ClientInfo {
+        user_agent: Some("test".into()),
+        ip: Some([127, 0, 0, 1].into()),
+        request_id: Some("request".into()),
+    };
+    services
+        .auth
+        .login(email.clone(), DEFAULT_PASSWORD.clone(), &client)
+        .await
+        .unwrap()
+}
+
+/// Like `login`, but for the claims of the session that's started
+pub async fn sign_in(services: &Services, email: &Email) -> Claims<Validated> {
+    let jwt = login(services, email).await;
+    services.auth.authenticate(&jwt).await.unwrap()
+}
+
+/// Like `login`, but for an `Authorization` header carrying the token
+pub async fn bearer(services: &Services, email: &Email) -> String {
+    let jwt = login(services, email).await;
+    format!("Bearer {}", jwt.expose_secret())
+}
+
+/// The events recorded for `action`, newest first
+pub async fn audited(services: &Services, action: AuditAction) -> Vec<AuditEvent> {
+    let filter = AuditFilter {
+        action: Some(action),
+        limit: 10,
+        ..AuditFilter::default()
+    };
+    services.audit.events(filter).await.unwrap()
+}
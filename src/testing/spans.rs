@@ -0,0 +1,51 @@
+use std::{
+    fmt::{Debug, Write},
+    sync::{Arc, Mutex},
+};
+
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    Event, Subscriber,
+};
+use tracing_subscriber::{layer::Context, Layer};
+
+/// A layer that renders every span and event it sees into a string, so tests can make
+/// assertions about what would have been logged or exported
+#[derive(Debug, Clone, Default)]
+pub struct SpanCapture(Arc<Mutex<Vec<String>>>);
+
+impl SpanCapture {
+    /// Everything captured so far, one line per span, record or event
+    pub fn output(&self) -> String {
+        self.0.lock().unwrap().join("\n")
+    }
+
+    fn push(&self, name: &str, record: impl FnOnce(&mut dyn Visit)) {
+        let mut line = name.to_string();
+        record(&mut Visitor(&mut line));
+        self.0.lock().unwrap().push(line);
+    }
+}
+
+impl<S: Subscriber> Layer<S> for SpanCapture {
+    fn on_new_span(&self, attrs: &Attributes<'_>, _: &Id, _: Context<'_, S>) {
+        self.push(attrs.metadata().name(), |v| attrs.record(v));
+    }
+
+    fn on_record(&self, _: &Id, values: &Record<'_>, _: Context<'_, S>) {
+        self.push("record", |v| values.record(v));
+    }
+
+    fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+        self.push(event.metadata().name(), |v| event.record(v));
+    }
+}
+
+struct Visitor<'a>(&'a mut String);
+
+impl Visit for Visitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        write!(self.0, " {}={:?}", field.name(), value).unwrap();
+    }
+}